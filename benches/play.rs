@@ -0,0 +1,140 @@
+use std::{
+  collections::HashMap,
+  env,
+  time::{Duration, Instant},
+};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use evil_santa::{
+  api::games::OWNER_PERMISSION,
+  db::{
+    games::{self, CreateParams, Expected},
+    players, presents,
+  },
+  rules::GameRules,
+  MIGRATOR,
+};
+use sqlx::{postgres::PgPoolOptions, PgPool};
+use tokio::runtime::Runtime;
+use uuid::Uuid;
+
+const UID: &str = "bench";
+const PLAYERS: usize = 50;
+
+// a started game with as many presents as players
+async fn new_game(db: &PgPool) -> Uuid {
+  let users = HashMap::from([(UID.to_string(), OWNER_PERMISSION)]);
+  let game = games::create(
+    db,
+    CreateParams {
+      id: Uuid::new_v4(),
+      name: "bench",
+      images: Vec::new(),
+      users: &users,
+      rules: GameRules::default(),
+      created_by: UID,
+    },
+  )
+  .await
+  .unwrap();
+  let mut tx = db.begin().await.unwrap();
+  for i in 0..PLAYERS {
+    let player = players::CreateParams {
+      team_id: None,
+      name: format!("player {}", i),
+      images: Vec::new(),
+      uid: None,
+    };
+    players::create(&mut *tx, game.id, player, UID)
+      .await
+      .unwrap();
+    let present = presents::CreateParams {
+      name: format!("present {}", i),
+      description: None,
+      translations: None,
+      wrapped_images: None,
+      unwrapped_images: None,
+      price_cents: None,
+      currency: None,
+    };
+    presents::create(&mut tx, game.id, present, UID)
+      .await
+      .unwrap();
+  }
+  tx.commit().await.unwrap();
+  games::start(db, game.id, UID).await.unwrap();
+  game.id
+}
+
+async fn present(db: &PgPool, game_id: Uuid, held: bool) -> Option<i64> {
+  sqlx::query_scalar(
+    "SELECT id FROM presents WHERE game_id = $1 AND (player_id IS NOT NULL) = $2 ORDER BY id LIMIT 1",
+  )
+  .bind(game_id)
+  .bind(held)
+  .fetch_optional(db)
+  .await
+  .unwrap()
+}
+
+// time `iters` turns, starting the game over whenever everyone has a present
+async fn turns(db: &PgPool, game_id: Uuid, iters: u64, steal: bool) -> Duration {
+  let mut elapsed = Duration::ZERO;
+  for _ in 0..iters {
+    let Some(wrapped) = present(db, game_id, false).await else {
+      games::reset(db, game_id, UID).await.unwrap();
+      games::start(db, game_id, UID).await.unwrap();
+      continue;
+    };
+    let held = present(db, game_id, true).await;
+    let start = Instant::now();
+    games::roll(db, game_id, UID).await.unwrap();
+    games::pick(db, game_id, wrapped, Expected::default(), UID)
+      .await
+      .unwrap();
+    match held.filter(|_| steal) {
+      Some(held) => games::steal(db, game_id, held, Expected::default(), UID).await,
+      None => games::keep(db, game_id, UID).await,
+    }
+    .unwrap();
+    elapsed += start.elapsed();
+  }
+  elapsed
+}
+
+fn play(c: &mut Criterion) {
+  let Ok(url) = env::var("DATABASE_URL") else {
+    eprintln!("DATABASE_URL is not set, skipping the play benchmarks");
+    return;
+  };
+  let rt = Runtime::new().unwrap();
+  let (db, game_id) = rt.block_on(async {
+    let db = PgPoolOptions::new().connect(&url).await.unwrap();
+    MIGRATOR.run(&db).await.unwrap();
+    games::warmup(&db, 1).await.unwrap();
+    let game_id = new_game(&db).await;
+    (db, game_id)
+  });
+
+  let mut group = c.benchmark_group("turn");
+  group.bench_function("roll_pick_keep", |b| {
+    b.to_async(&rt)
+      .iter_custom(|iters| turns(&db, game_id, iters, false))
+  });
+  group.bench_function("roll_pick_steal", |b| {
+    b.to_async(&rt)
+      .iter_custom(|iters| turns(&db, game_id, iters, true))
+  });
+  group.finish();
+
+  rt.block_on(async {
+    sqlx::query("UPDATE games SET deleted_at = NOW() WHERE id = $1")
+      .bind(game_id)
+      .execute(&db)
+      .await
+      .unwrap();
+  });
+}
+
+criterion_group!(benches, play);
+criterion_main!(benches);
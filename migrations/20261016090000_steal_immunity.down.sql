@@ -0,0 +1,3 @@
+ALTER TABLE presents DROP column immune_until_turn;
+ALTER TABLE games DROP column rules;
+ALTER TABLE games DROP column turn;
@@ -0,0 +1,3 @@
+ALTER TABLE games ADD column turn INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE games ADD column rules JSONB NOT NULL DEFAULT '{}';
+ALTER TABLE presents ADD column immune_until_turn INTEGER;
@@ -0,0 +1,2 @@
+DROP TABLE guesses;
+ALTER TABLE presents DROP column price_cents;
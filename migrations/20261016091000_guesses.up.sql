@@ -0,0 +1,19 @@
+ALTER TABLE presents ADD column price_cents BIGINT CHECK (price_cents >= 0);
+
+--
+-- Tables
+--
+CREATE TABLE guesses (
+    id BIGSERIAL NOT NULL,
+    game_id uuid NOT NULL,
+    present_id BIGINT NOT NULL,
+    player_id BIGINT NOT NULL,
+    price_cents BIGINT NOT NULL CHECK (price_cents >= 0),
+    created_at timestamp NOT NULL DEFAULT now(),
+    updated_at timestamp,
+    PRIMARY KEY (id),
+    CONSTRAINT uq_guess UNIQUE (present_id, player_id),
+    CONSTRAINT fk_game FOREIGN KEY (game_id) REFERENCES games(id),
+    CONSTRAINT fk_present FOREIGN KEY (present_id) REFERENCES presents(id),
+    CONSTRAINT fk_player FOREIGN KEY (player_id) REFERENCES players(id)
+);
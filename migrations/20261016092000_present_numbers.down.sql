@@ -0,0 +1,2 @@
+ALTER TABLE presents DROP CONSTRAINT uq_present_number;
+ALTER TABLE presents DROP column number;
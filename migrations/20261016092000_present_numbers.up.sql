@@ -0,0 +1,8 @@
+ALTER TABLE presents ADD column number INTEGER;
+UPDATE presents SET number = numbered.n
+FROM (
+    SELECT id, ROW_NUMBER() OVER (PARTITION BY game_id ORDER BY id) AS n FROM presents
+) numbered
+WHERE presents.id = numbered.id;
+ALTER TABLE presents ALTER column number SET NOT NULL;
+ALTER TABLE presents ADD CONSTRAINT uq_present_number UNIQUE (game_id, number) DEFERRABLE INITIALLY DEFERRED;
@@ -0,0 +1,2 @@
+ALTER TABLE play_events DROP column reason;
+ALTER TABLE play_events DROP column kind;
@@ -0,0 +1,9 @@
+ALTER TABLE play_events ADD column kind TEXT;
+UPDATE play_events SET kind = CASE
+    WHEN present_id IS NULL THEN 'roll'
+    WHEN from_player_id IS NULL THEN 'pick'
+    WHEN from_player_id = player_id AND from_present_id = present_id THEN 'keep'
+    ELSE 'steal'
+END;
+ALTER TABLE play_events ALTER column kind SET NOT NULL;
+ALTER TABLE play_events ADD column reason TEXT;
@@ -0,0 +1,2 @@
+ALTER TABLE games DROP column viewer_limit;
+ALTER TABLE games DROP column share_token;
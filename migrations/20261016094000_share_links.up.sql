@@ -0,0 +1,2 @@
+ALTER TABLE games ADD column share_token TEXT UNIQUE;
+ALTER TABLE games ADD column viewer_limit INTEGER CHECK (viewer_limit >= 0);
@@ -0,0 +1 @@
+ALTER TABLE games ADD column theme JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,4 @@
+ALTER TABLE presents DROP column translations;
+ALTER TABLE presents DROP column description;
+ALTER TABLE games DROP column translations;
+ALTER TABLE games DROP column description;
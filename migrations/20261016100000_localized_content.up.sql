@@ -0,0 +1,4 @@
+ALTER TABLE games ADD column description TEXT;
+ALTER TABLE games ADD column translations JSONB NOT NULL DEFAULT '{}';
+ALTER TABLE presents ADD column description TEXT;
+ALTER TABLE presents ADD column translations JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,2 @@
+ALTER TABLE games DROP column nudged_turn;
+ALTER TABLE players DROP column uid;
@@ -0,0 +1,2 @@
+ALTER TABLE players ADD column uid TEXT;
+ALTER TABLE games ADD column nudged_turn INTEGER;
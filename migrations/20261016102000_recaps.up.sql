@@ -0,0 +1,7 @@
+CREATE TABLE recaps (
+    game_id uuid NOT NULL,
+    data JSONB NOT NULL,
+    created_at timestamp NOT NULL DEFAULT now(),
+    PRIMARY KEY (game_id),
+    CONSTRAINT fk_game FOREIGN KEY (game_id) REFERENCES games(id) ON DELETE CASCADE
+);
@@ -0,0 +1,8 @@
+DROP TRIGGER tr_snapshot_play_event_names ON play_events;
+DROP FUNCTION snapshot_play_event_names;
+ALTER TABLE play_events DROP column from_present_name;
+ALTER TABLE play_events DROP column from_player_name;
+ALTER TABLE play_events DROP column present_image;
+ALTER TABLE play_events DROP column present_name;
+ALTER TABLE play_events DROP column player_image;
+ALTER TABLE play_events DROP column player_name;
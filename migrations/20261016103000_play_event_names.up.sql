@@ -0,0 +1,45 @@
+ALTER TABLE play_events ADD column player_name TEXT;
+ALTER TABLE play_events ADD column player_image TEXT;
+ALTER TABLE play_events ADD column present_name TEXT;
+ALTER TABLE play_events ADD column present_image TEXT;
+ALTER TABLE play_events ADD column from_player_name TEXT;
+ALTER TABLE play_events ADD column from_present_name TEXT;
+
+--
+-- Snapshot names and thumbnails so history survives renames and deletes
+--
+CREATE FUNCTION snapshot_play_event_names()
+RETURNS trigger AS $$
+BEGIN
+    SELECT name, images[1] INTO NEW.player_name, NEW.player_image
+    FROM players WHERE id = NEW.player_id;
+    SELECT name, COALESCE(unwrapped_images[1], wrapped_images[1]) INTO NEW.present_name, NEW.present_image
+    FROM presents WHERE id = NEW.present_id;
+    SELECT name INTO NEW.from_player_name FROM players WHERE id = NEW.from_player_id;
+    SELECT name INTO NEW.from_present_name FROM presents WHERE id = NEW.from_present_id;
+    RETURN NEW;
+END;
+
+$$ LANGUAGE PLPGSQL;
+
+CREATE TRIGGER tr_snapshot_play_event_names
+BEFORE INSERT
+ON play_events
+FOR EACH ROW
+    EXECUTE PROCEDURE snapshot_play_event_names();
+
+UPDATE play_events SET
+    player_name = players.name,
+    player_image = players.images[1]
+FROM players WHERE players.id = play_events.player_id;
+
+UPDATE play_events SET
+    present_name = presents.name,
+    present_image = COALESCE(presents.unwrapped_images[1], presents.wrapped_images[1])
+FROM presents WHERE presents.id = play_events.present_id;
+
+UPDATE play_events SET from_player_name = players.name
+FROM players WHERE players.id = play_events.from_player_id;
+
+UPDATE play_events SET from_present_name = presents.name
+FROM presents WHERE presents.id = play_events.from_present_id;
@@ -0,0 +1,4 @@
+DROP TRIGGER tr_next_play_event_seq ON play_events;
+DROP FUNCTION next_play_event_seq;
+ALTER TABLE play_events DROP column seq;
+ALTER TABLE games DROP column event_seq;
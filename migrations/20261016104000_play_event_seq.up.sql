@@ -0,0 +1,31 @@
+ALTER TABLE games ADD column event_seq BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE play_events ADD column seq BIGINT;
+
+UPDATE play_events SET seq = numbered.seq
+FROM (
+    SELECT id, row_number() OVER (PARTITION BY game_id ORDER BY id) AS seq FROM play_events
+) numbered
+WHERE numbered.id = play_events.id;
+
+UPDATE games SET event_seq = COALESCE((SELECT MAX(seq) FROM play_events WHERE game_id = games.id), 0);
+
+ALTER TABLE play_events ALTER column seq SET NOT NULL;
+ALTER TABLE play_events ADD CONSTRAINT play_events_game_seq UNIQUE (game_id, seq);
+
+--
+-- Per-game sequence, never reset so clients can detect gaps
+--
+CREATE FUNCTION next_play_event_seq()
+RETURNS trigger AS $$
+BEGIN
+    UPDATE games SET event_seq = event_seq + 1 WHERE id = NEW.game_id RETURNING event_seq INTO NEW.seq;
+    RETURN NEW;
+END;
+
+$$ LANGUAGE PLPGSQL;
+
+CREATE TRIGGER tr_next_play_event_seq
+BEFORE INSERT
+ON play_events
+FOR EACH ROW
+    EXECUTE PROCEDURE next_play_event_seq();
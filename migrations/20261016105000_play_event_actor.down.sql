@@ -0,0 +1 @@
+ALTER TABLE play_events DROP column actor_uid;
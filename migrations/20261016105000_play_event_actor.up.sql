@@ -0,0 +1 @@
+ALTER TABLE play_events ADD column actor_uid TEXT;
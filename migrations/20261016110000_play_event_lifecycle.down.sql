@@ -0,0 +1,3 @@
+ALTER TABLE play_events DROP CONSTRAINT play_events_kind;
+DELETE FROM play_events WHERE player_id IS NULL;
+ALTER TABLE play_events ALTER column player_id SET NOT NULL;
@@ -0,0 +1,5 @@
+-- start and reset events have no player
+ALTER TABLE play_events ALTER column player_id DROP NOT NULL;
+ALTER TABLE play_events ADD CONSTRAINT play_events_kind CHECK (
+    kind IN ('start', 'roll', 'pick', 'keep', 'steal', 'assign', 'nudge', 'reset')
+);
@@ -0,0 +1,7 @@
+ALTER TABLE players ADD column position INTEGER;
+UPDATE players SET position = ordered.n
+FROM (
+    SELECT id, ROW_NUMBER() OVER (PARTITION BY game_id ORDER BY id) AS n FROM players
+) ordered
+WHERE players.id = ordered.id;
+ALTER TABLE players ALTER column position SET NOT NULL;
@@ -0,0 +1,5 @@
+DELETE FROM play_events WHERE kind = 'final_swap';
+ALTER TABLE play_events DROP CONSTRAINT play_events_kind;
+ALTER TABLE play_events ADD CONSTRAINT play_events_kind CHECK (
+    kind IN ('start', 'roll', 'pick', 'keep', 'steal', 'assign', 'nudge', 'reset')
+);
@@ -0,0 +1,6 @@
+DELETE FROM play_events WHERE kind = 'undo';
+ALTER TABLE play_events DROP CONSTRAINT play_events_kind;
+ALTER TABLE play_events ADD CONSTRAINT play_events_kind CHECK (
+    kind IN ('start', 'roll', 'pick', 'keep', 'steal', 'assign', 'nudge', 'final_swap', 'reset')
+);
+ALTER TABLE play_events DROP column undone_seq;
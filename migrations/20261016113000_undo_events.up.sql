@@ -0,0 +1,5 @@
+ALTER TABLE play_events ADD column undone_seq BIGINT;
+ALTER TABLE play_events DROP CONSTRAINT play_events_kind;
+ALTER TABLE play_events ADD CONSTRAINT play_events_kind CHECK (
+    kind IN ('start', 'roll', 'pick', 'keep', 'steal', 'assign', 'nudge', 'final_swap', 'undo', 'reset')
+);
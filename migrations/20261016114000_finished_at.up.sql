@@ -0,0 +1,6 @@
+ALTER TABLE games ADD column finished_at TIMESTAMP;
+UPDATE games SET finished_at = recaps.created_at FROM recaps WHERE recaps.game_id = games.id;
+ALTER TABLE play_events DROP CONSTRAINT play_events_kind;
+ALTER TABLE play_events ADD CONSTRAINT play_events_kind CHECK (
+    kind IN ('start', 'roll', 'pick', 'keep', 'steal', 'assign', 'nudge', 'final_swap', 'undo', 'finish', 'reset')
+);
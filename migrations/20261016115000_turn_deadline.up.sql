@@ -0,0 +1 @@
+ALTER TABLE games ADD column turn_deadline TIMESTAMP;
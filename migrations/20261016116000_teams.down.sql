@@ -0,0 +1,2 @@
+ALTER TABLE players DROP column team_id;
+DROP TABLE teams;
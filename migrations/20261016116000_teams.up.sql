@@ -0,0 +1,12 @@
+CREATE TABLE teams (
+    id BIGSERIAL NOT NULL,
+    game_id uuid NOT NULL,
+    name TEXT NOT NULL,
+    color TEXT,
+    created_at timestamp NOT NULL DEFAULT now(),
+    updated_at timestamp,
+    PRIMARY KEY (id),
+    CONSTRAINT fk_game FOREIGN KEY (game_id) REFERENCES games(id)
+);
+ALTER TABLE players ADD column team_id BIGINT;
+ALTER TABLE players ADD CONSTRAINT fk_team FOREIGN KEY (team_id) REFERENCES teams(id) ON DELETE SET NULL;
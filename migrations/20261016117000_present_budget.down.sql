@@ -0,0 +1,3 @@
+ALTER TABLE presents DROP column currency;
+ALTER TABLE games DROP column currency;
+ALTER TABLE games DROP column budget_cents;
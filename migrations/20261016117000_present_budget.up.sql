@@ -0,0 +1,3 @@
+ALTER TABLE games ADD column budget_cents BIGINT CHECK (budget_cents >= 0);
+ALTER TABLE games ADD column currency TEXT CHECK (currency ~ '^[A-Z]{3}$');
+ALTER TABLE presents ADD column currency TEXT CHECK (currency ~ '^[A-Z]{3}$');
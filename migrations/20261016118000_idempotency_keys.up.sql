@@ -0,0 +1,12 @@
+CREATE TABLE idempotency_keys (
+    uid TEXT NOT NULL,
+    key TEXT NOT NULL,
+    path TEXT NOT NULL,
+    request_body BYTEA NOT NULL,
+    status SMALLINT,
+    content_type TEXT,
+    response_body BYTEA,
+    created_at timestamp NOT NULL DEFAULT now(),
+    PRIMARY KEY (uid, key)
+);
+CREATE INDEX idempotency_keys_created_at ON idempotency_keys (created_at);
@@ -0,0 +1,2 @@
+DROP INDEX games_deleted_at_idx;
+ALTER TABLE games DROP column deleted_at;
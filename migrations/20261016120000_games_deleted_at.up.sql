@@ -0,0 +1,2 @@
+ALTER TABLE games ADD column deleted_at timestamp;
+CREATE INDEX games_deleted_at_idx ON games (deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,4 @@
+DROP TRIGGER tr_enqueue_play_webhooks ON play_events;
+DROP FUNCTION enqueue_play_webhooks();
+DROP TABLE webhook_deliveries;
+DROP TABLE webhooks;
@@ -0,0 +1,57 @@
+CREATE TABLE webhooks (
+    id BIGSERIAL NOT NULL,
+    game_id uuid NOT NULL,
+    url TEXT NOT NULL,
+    -- sealed, see crypto::Sealed
+    secret TEXT NOT NULL,
+    -- empty subscribes to everything
+    events TEXT[] NOT NULL DEFAULT '{}',
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at timestamp NOT NULL DEFAULT now(),
+    updated_at timestamp,
+    PRIMARY KEY (id),
+    CONSTRAINT fk_game FOREIGN KEY (game_id) REFERENCES games(id) ON DELETE CASCADE
+);
+CREATE INDEX webhooks_game_id ON webhooks (game_id);
+
+CREATE TABLE webhook_deliveries (
+    id BIGSERIAL NOT NULL,
+    webhook_id BIGINT NOT NULL,
+    event TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    -- the response status or error of the latest attempt
+    status SMALLINT,
+    error TEXT,
+    -- NULL once delivered or given up on
+    next_attempt_at timestamp DEFAULT now(),
+    delivered_at timestamp,
+    created_at timestamp NOT NULL DEFAULT now(),
+    PRIMARY KEY (id),
+    CONSTRAINT fk_webhook FOREIGN KEY (webhook_id) REFERENCES webhooks(id) ON DELETE CASCADE
+);
+CREATE INDEX webhook_deliveries_webhook_id ON webhook_deliveries (webhook_id, id);
+CREATE INDEX webhook_deliveries_due ON webhook_deliveries (next_attempt_at) WHERE next_attempt_at IS NOT NULL;
+CREATE INDEX webhook_deliveries_created_at ON webhook_deliveries (created_at);
+
+--
+-- Queue a delivery for every webhook subscribed to a new play event
+--
+CREATE FUNCTION enqueue_play_webhooks()
+RETURNS trigger AS $$
+BEGIN
+    INSERT INTO webhook_deliveries (webhook_id, event, payload)
+    SELECT id, 'play.' || NEW.kind, row_to_json(NEW)::jsonb
+    FROM webhooks
+    WHERE game_id = NEW.game_id AND active
+      AND (events = '{}' OR 'play' = ANY(events) OR 'play.' || NEW.kind = ANY(events));
+    RETURN NEW;
+END;
+
+$$ LANGUAGE PLPGSQL;
+
+CREATE TRIGGER tr_enqueue_play_webhooks
+AFTER INSERT
+ON play_events
+FOR EACH ROW
+    EXECUTE PROCEDURE enqueue_play_webhooks();
@@ -0,0 +1,4 @@
+DROP TRIGGER tr_enqueue_play_emails ON play_events;
+DROP FUNCTION enqueue_play_emails();
+DROP TABLE email_outbox;
+DROP TABLE user_preferences;
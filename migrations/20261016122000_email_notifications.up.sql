@@ -0,0 +1,47 @@
+CREATE TABLE user_preferences (
+    uid TEXT NOT NULL,
+    email_opt_out BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at timestamp NOT NULL DEFAULT now(),
+    updated_at timestamp,
+    PRIMARY KEY (uid)
+);
+
+CREATE TABLE email_outbox (
+    id BIGSERIAL NOT NULL,
+    uid TEXT NOT NULL,
+    game_id uuid NOT NULL,
+    -- invited, started or finished
+    kind TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    error TEXT,
+    -- NULL once sent, skipped or given up on
+    next_attempt_at timestamp DEFAULT now(),
+    sent_at timestamp,
+    created_at timestamp NOT NULL DEFAULT now(),
+    PRIMARY KEY (id),
+    CONSTRAINT fk_game FOREIGN KEY (game_id) REFERENCES games(id) ON DELETE CASCADE
+);
+CREATE INDEX email_outbox_due ON email_outbox (next_attempt_at) WHERE next_attempt_at IS NOT NULL;
+CREATE INDEX email_outbox_created_at ON email_outbox (created_at);
+
+--
+-- Queue an email to every user of a game when it starts or finishes
+--
+CREATE FUNCTION enqueue_play_emails()
+RETURNS trigger AS $$
+BEGIN
+    IF NEW.kind IN ('start', 'finish') THEN
+        INSERT INTO email_outbox (uid, game_id, kind)
+        SELECT jsonb_object_keys(users), id, CASE NEW.kind WHEN 'start' THEN 'started' ELSE 'finished' END
+        FROM games WHERE id = NEW.game_id;
+    END IF;
+    RETURN NEW;
+END;
+
+$$ LANGUAGE PLPGSQL;
+
+CREATE TRIGGER tr_enqueue_play_emails
+AFTER INSERT
+ON play_events
+FOR EACH ROW
+    EXECUTE PROCEDURE enqueue_play_emails();
@@ -0,0 +1,2 @@
+-- json posts signed event payloads, discord and slack post a chat message per play event
+ALTER TABLE webhooks ADD COLUMN format TEXT NOT NULL DEFAULT 'json';
@@ -0,0 +1 @@
+ALTER TABLE games DROP COLUMN scheduled_at;
@@ -0,0 +1,2 @@
+-- when the party is planned, in UTC, informational only
+ALTER TABLE games ADD COLUMN scheduled_at timestamp;
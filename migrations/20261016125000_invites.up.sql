@@ -0,0 +1,15 @@
+-- owner-issued invite links, the token itself is signed and only refers to a row here
+CREATE TABLE invites (
+    id BIGSERIAL NOT NULL,
+    game_id uuid NOT NULL,
+    -- granted on redeem, never lowers a permission the user already has
+    permission BIGINT NOT NULL,
+    created_by TEXT NOT NULL,
+    expires_at timestamp NOT NULL,
+    revoked_at timestamp,
+    accepted_count INTEGER NOT NULL DEFAULT 0,
+    created_at timestamp NOT NULL DEFAULT now(),
+    PRIMARY KEY (id),
+    CONSTRAINT fk_game FOREIGN KEY (game_id) REFERENCES games(id) ON DELETE CASCADE
+);
+CREATE INDEX invites_game_id ON invites (game_id);
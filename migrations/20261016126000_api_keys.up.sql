@@ -0,0 +1,18 @@
+-- keys for devices that can't sign in, e.g. a venue display, acting with fixed game permissions
+CREATE TABLE api_keys (
+    id BIGSERIAL NOT NULL,
+    name TEXT NOT NULL,
+    -- hex sha256 of the key, the key itself is only shown once
+    key_hash TEXT NOT NULL,
+    -- the first characters of the key, to tell keys apart
+    prefix TEXT NOT NULL,
+    -- game id => permission, like the custom claims of a user
+    games JSONB NOT NULL DEFAULT '{}',
+    created_by TEXT NOT NULL,
+    last_used_at timestamp,
+    revoked_at timestamp,
+    created_at timestamp NOT NULL DEFAULT now(),
+    PRIMARY KEY (id),
+    UNIQUE (key_hash)
+);
+CREATE INDEX api_keys_created_by ON api_keys (created_by);
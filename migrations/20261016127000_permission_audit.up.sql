@@ -0,0 +1,17 @@
+-- every change to who may access a game, newest last
+CREATE TABLE permission_audit (
+    id BIGSERIAL NOT NULL,
+    game_id uuid NOT NULL,
+    -- the user (or api key) making the change
+    actor_uid TEXT NOT NULL,
+    target_uid TEXT NOT NULL,
+    -- NULL when the target was added or removed
+    old_permission BIGINT,
+    new_permission BIGINT,
+    -- create, import, update, replace, members or invite
+    source TEXT NOT NULL,
+    created_at timestamp NOT NULL DEFAULT now(),
+    PRIMARY KEY (id),
+    CONSTRAINT fk_game FOREIGN KEY (game_id) REFERENCES games(id) ON DELETE CASCADE
+);
+CREATE INDEX permission_audit_game_id ON permission_audit (game_id, id);
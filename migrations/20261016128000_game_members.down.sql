@@ -0,0 +1,3 @@
+DROP TRIGGER tr_sync_game_members ON games;
+DROP FUNCTION sync_game_members();
+DROP TABLE game_members;
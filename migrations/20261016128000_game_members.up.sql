@@ -0,0 +1,34 @@
+-- authorization reads memberships from here, the games' users maps stay the one written to
+CREATE TABLE game_members (
+    game_id uuid NOT NULL,
+    uid TEXT NOT NULL,
+    permission BIGINT NOT NULL,
+    PRIMARY KEY (game_id, uid),
+    CONSTRAINT fk_game FOREIGN KEY (game_id) REFERENCES games(id) ON DELETE CASCADE
+);
+CREATE INDEX game_members_uid ON game_members (uid);
+
+INSERT INTO game_members (game_id, uid, permission)
+SELECT id, key, value::bigint FROM games, jsonb_each_text(users);
+
+--
+-- Mirror the users map of a game into game_members
+--
+CREATE FUNCTION sync_game_members()
+RETURNS trigger AS $$
+BEGIN
+    DELETE FROM game_members WHERE game_id = NEW.id AND NOT NEW.users ? uid;
+    INSERT INTO game_members (game_id, uid, permission)
+    SELECT NEW.id, key, value::bigint FROM jsonb_each_text(NEW.users)
+    ON CONFLICT (game_id, uid) DO UPDATE SET permission = EXCLUDED.permission
+    WHERE game_members.permission <> EXCLUDED.permission;
+    RETURN NEW;
+END;
+
+$$ LANGUAGE PLPGSQL;
+
+CREATE TRIGGER tr_sync_game_members
+AFTER INSERT OR UPDATE OF users
+ON games
+FOR EACH ROW
+    EXECUTE PROCEDURE sync_game_members();
@@ -0,0 +1,18 @@
+CREATE TABLE email_invites (
+    id BIGSERIAL NOT NULL,
+    game_id uuid NOT NULL,
+    -- lowercased, matched against the verified email of users signing in
+    email TEXT NOT NULL,
+    permission BIGINT NOT NULL,
+    created_by TEXT NOT NULL,
+    accepted_by TEXT,
+    accepted_at timestamp,
+    revoked_at timestamp,
+    created_at timestamp NOT NULL DEFAULT now(),
+    PRIMARY KEY (id),
+    CONSTRAINT fk_game FOREIGN KEY (game_id) REFERENCES games(id) ON DELETE CASCADE
+);
+CREATE UNIQUE INDEX email_invites_pending ON email_invites (game_id, email)
+    WHERE accepted_at IS NULL AND revoked_at IS NULL;
+CREATE INDEX email_invites_email ON email_invites (email)
+    WHERE accepted_at IS NULL AND revoked_at IS NULL;
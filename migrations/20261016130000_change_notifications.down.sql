@@ -0,0 +1,4 @@
+DROP TRIGGER tr_notify_game_change ON games;
+DROP TRIGGER tr_notify_present_change ON presents;
+DROP TRIGGER tr_notify_player_change ON players;
+DROP FUNCTION notify_change();
@@ -0,0 +1,42 @@
+--
+-- Tell listeners which player, present or game settings changed, they refetch what they show
+--
+CREATE FUNCTION notify_change()
+RETURNS trigger AS $$
+DECLARE
+    data jsonb;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        data := to_jsonb(OLD);
+    ELSE
+        data := to_jsonb(NEW);
+    END IF;
+    PERFORM pg_notify('changes', json_build_object(
+        'game_id', COALESCE(data->>'game_id', data->>'id'),
+        'entity', TG_ARGV[0],
+        'op', lower(TG_OP),
+        'id', CASE WHEN TG_ARGV[0] = 'game' THEN NULL ELSE (data->>'id')::bigint END
+    )::text);
+    RETURN NULL;
+END;
+
+$$ LANGUAGE PLPGSQL;
+
+CREATE TRIGGER tr_notify_player_change
+AFTER INSERT OR DELETE OR UPDATE
+ON players
+FOR EACH ROW
+    EXECUTE PROCEDURE notify_change('player');
+
+-- owners changing during play are already play events
+CREATE TRIGGER tr_notify_present_change
+AFTER INSERT OR DELETE OR UPDATE OF name, description, translations, wrapped_images, unwrapped_images, price_cents, currency, number
+ON presents
+FOR EACH ROW
+    EXECUTE PROCEDURE notify_change('present');
+
+CREATE TRIGGER tr_notify_game_change
+AFTER UPDATE OF name, description, translations, images, rules, theme, budget_cents, currency, scheduled_at, deleted_at
+ON games
+FOR EACH ROW
+    EXECUTE PROCEDURE notify_change('game');
@@ -0,0 +1,3 @@
+ALTER TABLE presents DROP COLUMN created_by, DROP COLUMN updated_by;
+ALTER TABLE players DROP COLUMN created_by, DROP COLUMN updated_by;
+ALTER TABLE games DROP COLUMN created_by, DROP COLUMN updated_by;
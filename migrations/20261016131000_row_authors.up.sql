@@ -0,0 +1,4 @@
+-- firebase uid of who created and last edited a row, unknown for rows from before
+ALTER TABLE games ADD COLUMN created_by TEXT, ADD COLUMN updated_by TEXT;
+ALTER TABLE players ADD COLUMN created_by TEXT, ADD COLUMN updated_by TEXT;
+ALTER TABLE presents ADD COLUMN created_by TEXT, ADD COLUMN updated_by TEXT;
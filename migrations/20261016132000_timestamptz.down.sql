@@ -0,0 +1,62 @@
+-- back to utc without an offset
+ALTER TABLE api_keys
+  ALTER COLUMN last_used_at TYPE timestamp USING last_used_at AT TIME ZONE 'UTC',
+  ALTER COLUMN revoked_at TYPE timestamp USING revoked_at AT TIME ZONE 'UTC',
+  ALTER COLUMN created_at TYPE timestamp USING created_at AT TIME ZONE 'UTC';
+ALTER TABLE email_invites
+  ALTER COLUMN accepted_at TYPE timestamp USING accepted_at AT TIME ZONE 'UTC',
+  ALTER COLUMN revoked_at TYPE timestamp USING revoked_at AT TIME ZONE 'UTC',
+  ALTER COLUMN created_at TYPE timestamp USING created_at AT TIME ZONE 'UTC';
+ALTER TABLE email_outbox
+  ALTER COLUMN next_attempt_at TYPE timestamp USING next_attempt_at AT TIME ZONE 'UTC',
+  ALTER COLUMN sent_at TYPE timestamp USING sent_at AT TIME ZONE 'UTC',
+  ALTER COLUMN created_at TYPE timestamp USING created_at AT TIME ZONE 'UTC';
+-- the change trigger depends on deleted_at and scheduled_at
+DROP TRIGGER tr_notify_game_change ON games;
+ALTER TABLE games
+  ALTER COLUMN created_at TYPE timestamp USING created_at AT TIME ZONE 'UTC',
+  ALTER COLUMN updated_at TYPE timestamp USING updated_at AT TIME ZONE 'UTC',
+  ALTER COLUMN started_at TYPE timestamp USING started_at AT TIME ZONE 'UTC',
+  ALTER COLUMN finished_at TYPE timestamp USING finished_at AT TIME ZONE 'UTC',
+  ALTER COLUMN turn_deadline TYPE timestamp USING turn_deadline AT TIME ZONE 'UTC',
+  ALTER COLUMN deleted_at TYPE timestamp USING deleted_at AT TIME ZONE 'UTC',
+  ALTER COLUMN scheduled_at TYPE timestamp USING scheduled_at AT TIME ZONE 'UTC';
+CREATE TRIGGER tr_notify_game_change
+AFTER UPDATE OF name, description, translations, images, rules, theme, budget_cents, currency, scheduled_at, deleted_at
+ON games
+FOR EACH ROW
+    EXECUTE PROCEDURE notify_change('game');
+ALTER TABLE guesses
+  ALTER COLUMN created_at TYPE timestamp USING created_at AT TIME ZONE 'UTC',
+  ALTER COLUMN updated_at TYPE timestamp USING updated_at AT TIME ZONE 'UTC';
+ALTER TABLE idempotency_keys
+  ALTER COLUMN created_at TYPE timestamp USING created_at AT TIME ZONE 'UTC';
+ALTER TABLE invites
+  ALTER COLUMN expires_at TYPE timestamp USING expires_at AT TIME ZONE 'UTC',
+  ALTER COLUMN revoked_at TYPE timestamp USING revoked_at AT TIME ZONE 'UTC',
+  ALTER COLUMN created_at TYPE timestamp USING created_at AT TIME ZONE 'UTC';
+ALTER TABLE permission_audit
+  ALTER COLUMN created_at TYPE timestamp USING created_at AT TIME ZONE 'UTC';
+ALTER TABLE play_events
+  ALTER COLUMN created_at TYPE timestamp USING created_at AT TIME ZONE 'UTC';
+ALTER TABLE players
+  ALTER COLUMN created_at TYPE timestamp USING created_at AT TIME ZONE 'UTC',
+  ALTER COLUMN updated_at TYPE timestamp USING updated_at AT TIME ZONE 'UTC';
+ALTER TABLE presents
+  ALTER COLUMN created_at TYPE timestamp USING created_at AT TIME ZONE 'UTC',
+  ALTER COLUMN updated_at TYPE timestamp USING updated_at AT TIME ZONE 'UTC';
+ALTER TABLE recaps
+  ALTER COLUMN created_at TYPE timestamp USING created_at AT TIME ZONE 'UTC';
+ALTER TABLE teams
+  ALTER COLUMN created_at TYPE timestamp USING created_at AT TIME ZONE 'UTC',
+  ALTER COLUMN updated_at TYPE timestamp USING updated_at AT TIME ZONE 'UTC';
+ALTER TABLE user_preferences
+  ALTER COLUMN created_at TYPE timestamp USING created_at AT TIME ZONE 'UTC',
+  ALTER COLUMN updated_at TYPE timestamp USING updated_at AT TIME ZONE 'UTC';
+ALTER TABLE webhook_deliveries
+  ALTER COLUMN next_attempt_at TYPE timestamp USING next_attempt_at AT TIME ZONE 'UTC',
+  ALTER COLUMN delivered_at TYPE timestamp USING delivered_at AT TIME ZONE 'UTC',
+  ALTER COLUMN created_at TYPE timestamp USING created_at AT TIME ZONE 'UTC';
+ALTER TABLE webhooks
+  ALTER COLUMN created_at TYPE timestamp USING created_at AT TIME ZONE 'UTC',
+  ALTER COLUMN updated_at TYPE timestamp USING updated_at AT TIME ZONE 'UTC';
@@ -0,0 +1,4 @@
+-- back to utc without an offset
+UPDATE recaps
+SET data = jsonb_set(data, '{generated_at}', to_jsonb((data->>'generated_at')::timestamptz AT TIME ZONE 'UTC'))
+WHERE data->>'generated_at' ~ '(Z|[+-]\d\d:\d\d)$';
@@ -0,0 +1,4 @@
+-- recaps stored before timestamptz have generated_at without an offset, it was utc
+UPDATE recaps
+SET data = jsonb_set(data, '{generated_at}', to_jsonb((data->>'generated_at')::timestamp AT TIME ZONE 'UTC'))
+WHERE data->>'generated_at' !~ '(Z|[+-]\d\d:\d\d)$';
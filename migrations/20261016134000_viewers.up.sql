@@ -0,0 +1,11 @@
+-- open event streams and sockets of every replica, so viewer limits hold across instances
+CREATE TABLE viewers (
+    id uuid NOT NULL,
+    game_id uuid NOT NULL,
+    kind TEXT NOT NULL CHECK (kind IN ('stream', 'socket', 'shared')),
+    -- refreshed while the connection is open, rows of a crashed replica go stale
+    seen_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (id),
+    CONSTRAINT fk_game FOREIGN KEY (game_id) REFERENCES games(id) ON DELETE CASCADE
+);
+CREATE INDEX viewers_game_id ON viewers (game_id);
@@ -0,0 +1,7 @@
+DROP INDEX email_invites_pending;
+DROP INDEX email_invites_email;
+ALTER TABLE email_invites DROP COLUMN email_index;
+CREATE UNIQUE INDEX email_invites_pending ON email_invites (game_id, email)
+    WHERE accepted_at IS NULL AND revoked_at IS NULL;
+CREATE INDEX email_invites_email ON email_invites (email)
+    WHERE accepted_at IS NULL AND revoked_at IS NULL;
@@ -0,0 +1,8 @@
+-- emails are sealed, so invites are matched by a keyed hash of the email. existing rows are hashed at startup
+ALTER TABLE email_invites ADD COLUMN email_index TEXT;
+DROP INDEX email_invites_pending;
+DROP INDEX email_invites_email;
+CREATE UNIQUE INDEX email_invites_pending ON email_invites (game_id, email_index)
+    WHERE accepted_at IS NULL AND revoked_at IS NULL;
+CREATE INDEX email_invites_email ON email_invites (email_index)
+    WHERE accepted_at IS NULL AND revoked_at IS NULL;
@@ -0,0 +1 @@
+ALTER TABLE permission_audit DROP COLUMN client_ip;
@@ -0,0 +1,2 @@
+-- where a permission change came from, NULL when the address wasn't known
+ALTER TABLE permission_audit ADD COLUMN client_ip inet;
@@ -0,0 +1,6 @@
+ALTER TABLE guesses DROP CONSTRAINT fk_game;
+ALTER TABLE guesses DROP CONSTRAINT fk_present;
+ALTER TABLE guesses DROP CONSTRAINT fk_player;
+ALTER TABLE guesses ADD CONSTRAINT fk_game FOREIGN KEY (game_id) REFERENCES games(id);
+ALTER TABLE guesses ADD CONSTRAINT fk_present FOREIGN KEY (present_id) REFERENCES presents(id);
+ALTER TABLE guesses ADD CONSTRAINT fk_player FOREIGN KEY (player_id) REFERENCES players(id);
@@ -0,0 +1,7 @@
+-- guesses mean nothing without their game, present or player
+ALTER TABLE guesses DROP CONSTRAINT fk_game;
+ALTER TABLE guesses DROP CONSTRAINT fk_present;
+ALTER TABLE guesses DROP CONSTRAINT fk_player;
+ALTER TABLE guesses ADD CONSTRAINT fk_game FOREIGN KEY (game_id) REFERENCES games(id) ON DELETE CASCADE;
+ALTER TABLE guesses ADD CONSTRAINT fk_present FOREIGN KEY (present_id) REFERENCES presents(id) ON DELETE CASCADE;
+ALTER TABLE guesses ADD CONSTRAINT fk_player FOREIGN KEY (player_id) REFERENCES players(id) ON DELETE CASCADE;
@@ -0,0 +1,2 @@
+ALTER TABLE teams DROP CONSTRAINT fk_game;
+ALTER TABLE teams ADD CONSTRAINT fk_game FOREIGN KEY (game_id) REFERENCES games(id);
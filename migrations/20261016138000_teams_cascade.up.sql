@@ -0,0 +1,3 @@
+-- teams go with their game, like webhooks, invites and members
+ALTER TABLE teams DROP CONSTRAINT fk_game;
+ALTER TABLE teams ADD CONSTRAINT fk_game FOREIGN KEY (game_id) REFERENCES games(id) ON DELETE CASCADE;
@@ -0,0 +1,6 @@
+ALTER TABLE players DROP CONSTRAINT fk_game;
+ALTER TABLE presents DROP CONSTRAINT fk_game;
+ALTER TABLE play_events DROP CONSTRAINT fk_game;
+ALTER TABLE players ADD CONSTRAINT fk_game FOREIGN KEY (game_id) REFERENCES games(id);
+ALTER TABLE presents ADD CONSTRAINT fk_game FOREIGN KEY (game_id) REFERENCES games(id);
+ALTER TABLE play_events ADD CONSTRAINT fk_game FOREIGN KEY (game_id) REFERENCES games(id);
@@ -0,0 +1,7 @@
+-- players, presents and events go with their game so deleted games can be purged
+ALTER TABLE players DROP CONSTRAINT fk_game;
+ALTER TABLE presents DROP CONSTRAINT fk_game;
+ALTER TABLE play_events DROP CONSTRAINT fk_game;
+ALTER TABLE players ADD CONSTRAINT fk_game FOREIGN KEY (game_id) REFERENCES games(id) ON DELETE CASCADE;
+ALTER TABLE presents ADD CONSTRAINT fk_game FOREIGN KEY (game_id) REFERENCES games(id) ON DELETE CASCADE;
+ALTER TABLE play_events ADD CONSTRAINT fk_game FOREIGN KEY (game_id) REFERENCES games(id) ON DELETE CASCADE;
@@ -0,0 +1,7 @@
+UPDATE games SET
+  player_id = NULL,
+  present_id = NULL,
+  turn_deadline = NULL,
+  updated_at = NOW()
+WHERE id = $1
+RETURNING updated_at
@@ -0,0 +1 @@
+INSERT INTO play_events (game_id, kind, player_id, present_id, from_player_id, from_present_id, actor_uid) VALUES ($1, 'keep', $2, $3, $4, $5, $6)
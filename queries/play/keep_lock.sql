@@ -0,0 +1 @@
+SELECT player_id, present_id FROM games WHERE id = $1 AND deleted_at IS NULL FOR UPDATE
@@ -0,0 +1 @@
+UPDATE presents SET player_id = $1, updated_at = NOW() WHERE id = $2
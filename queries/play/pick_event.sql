@@ -0,0 +1 @@
+INSERT INTO play_events (game_id, kind, player_id, present_id, actor_uid) VALUES ($1, 'pick', $2, $3, $4)
@@ -0,0 +1,6 @@
+UPDATE games SET
+  present_id = $1,
+  updated_at = NOW()
+WHERE present_id IS NULL
+  AND id = $2
+RETURNING player_id, updated_at
@@ -0,0 +1 @@
+INSERT INTO play_events (game_id, kind, player_id, actor_uid) VALUES ($1, 'roll', $2, $3)
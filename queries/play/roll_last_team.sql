@@ -0,0 +1,4 @@
+SELECT players.team_id FROM play_events
+JOIN players ON players.id = play_events.player_id
+WHERE play_events.game_id = $1 AND play_events.kind = 'roll'
+ORDER BY play_events.seq DESC LIMIT 1
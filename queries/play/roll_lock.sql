@@ -0,0 +1,3 @@
+SELECT turn, rules AS "rules: Json<GameRules>",
+  (SELECT COUNT(*) FROM players WHERE game_id = $1) AS "players!"
+FROM games WHERE id = $1 AND deleted_at IS NULL FOR UPDATE
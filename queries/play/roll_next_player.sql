@@ -0,0 +1,21 @@
+UPDATE games SET player_id = (
+  SELECT players.id
+  FROM players
+  WHERE id NOT IN (
+    SELECT player_id
+    FROM presents
+    WHERE game_id = $1
+    AND player_id IS NOT NULL)
+  AND game_id = $1
+  ORDER BY
+    CASE WHEN $4 THEN team_id <= $5 END,
+    CASE WHEN $4 THEN team_id END,
+    CASE WHEN $2 = 'random' THEN random() END,
+    CASE WHEN $2 = 'asc' THEN position END ASC,
+    CASE WHEN $2 = 'desc' THEN position END DESC,
+    id
+  LIMIT 1),
+  turn = turn + 1,
+  turn_deadline = NOW() + make_interval(secs => $3)
+WHERE player_id IS NULL
+AND id = $1 RETURNING player_id, turn, turn_deadline, updated_at
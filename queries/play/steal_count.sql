@@ -0,0 +1,2 @@
+SELECT COUNT(*) AS "count!" FROM play_events
+WHERE game_id = $1 AND kind = 'steal' AND from_present_id = $2
@@ -0,0 +1,3 @@
+SELECT from_player_id, from_present_id FROM play_events
+WHERE game_id = $1 AND kind = 'steal'
+ORDER BY seq DESC LIMIT 1
@@ -0,0 +1 @@
+SELECT player_id, immune_until_turn FROM presents WHERE id = $1
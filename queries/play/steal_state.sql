@@ -0,0 +1 @@
+SELECT player_id, present_id, turn, rules AS "rules: Json<GameRules>" FROM games WHERE id = $1
@@ -0,0 +1 @@
+UPDATE presents SET player_id = $1, immune_until_turn = $2, updated_at = NOW() WHERE id = $3
@@ -1,33 +1,91 @@
+use std::{sync::Arc, time::Duration};
+
 use axum::{
   async_trait,
-  extract::{FromRef, FromRequestParts, State},
-  http::{request::Parts, StatusCode},
+  body::to_bytes,
+  extract::{DefaultBodyLimit, FromRef, FromRequestParts, Request},
+  http::{header, request::Parts, HeaderValue, StatusCode},
+  middleware::{self, Next},
   response::{IntoResponse, Response},
-  routing::{get, post},
-  Router,
+  routing::{delete, get, patch, post, put},
+  Json, Router,
 };
 use axum_extra::{
   headers::{authorization::Bearer, Authorization},
   TypedHeader,
 };
-use firebase_auth::FirebaseAuth;
 use serde::Serialize;
+use tower_http::limit::RequestBodyLimitLayer;
+use utoipa::ToSchema;
+use validator::Validate;
 
 use crate::{
-  auth::{user::UserService, MyFirebaseUser},
-  db::{self, games::PlayStream},
+  auth::{permissions::Permissions, user::UserService, AuthProvider, MyFirebaseUser},
+  config::Config,
+  db::{
+    self,
+    games::{PlayStream, Streams},
+    repo::{GamesRepo, PlayersRepo, PresentsRepo, Repos},
+  },
+  error_code::ErrorCode,
+  i18n,
 };
 
+pub mod activity;
+pub mod admin;
+pub mod api_keys;
+pub mod changes;
+pub mod client_ip;
+pub mod csv;
+pub mod debug;
+pub mod email_invites;
 pub mod games;
+pub mod graphql;
+pub mod guesses;
+pub mod health;
+pub mod ics;
+pub mod idempotency;
+pub mod invites;
+pub mod maintenance;
+pub mod me;
+pub mod members;
+pub mod ndjson;
+pub mod openapi;
 pub mod players;
+pub mod presence;
 pub mod presents;
+pub mod quota;
+pub mod recaps;
+pub mod request_id;
+pub mod share;
+pub mod teams;
+pub mod turn_timer;
+pub mod tx;
+pub mod users;
+pub mod webhooks;
+pub mod ws;
+
+const MAX_ERROR_BODY: usize = 64 * 1024;
+// seconds to wait when a throttled or unavailable response has no better estimate
+const DEFAULT_RETRY_AFTER: u64 = 5;
 
 #[derive(Clone)]
 pub struct AppState {
   pub pool: sqlx::PgPool,
-  pub firebase_auth: FirebaseAuth<MyFirebaseUser>,
+  pub auth_provider: Arc<dyn AuthProvider>,
   pub claims_service: UserService,
   pub play_stream: PlayStream,
+  pub changes: changes::ChangeStream,
+  pub config: Config,
+  pub maintenance: maintenance::Maintenance,
+  pub quotas: quota::Quotas,
+  pub presence: presence::Presence,
+  pub activity: activity::ActivityStream,
+  pub readiness: health::Readiness,
+  pub invite_signer: invites::InviteSigner,
+  pub permissions: Permissions,
+  pub graphql: graphql::GraphqlSchema,
+  pub repos: Repos,
 }
 
 impl FromRef<AppState> for sqlx::PgPool {
@@ -36,6 +94,24 @@ impl FromRef<AppState> for sqlx::PgPool {
   }
 }
 
+impl FromRef<AppState> for Arc<dyn GamesRepo> {
+  fn from_ref(state: &AppState) -> Self {
+    state.repos.games.clone()
+  }
+}
+
+impl FromRef<AppState> for Arc<dyn PlayersRepo> {
+  fn from_ref(state: &AppState) -> Self {
+    state.repos.players.clone()
+  }
+}
+
+impl FromRef<AppState> for Arc<dyn PresentsRepo> {
+  fn from_ref(state: &AppState) -> Self {
+    state.repos.presents.clone()
+  }
+}
+
 pub struct Server {
   pub router: Router,
 }
@@ -43,23 +119,84 @@ pub struct Server {
 impl Server {
   pub fn new(
     pool: sqlx::PgPool,
-    firebase_auth: FirebaseAuth<MyFirebaseUser>,
+    auth_provider: Arc<dyn AuthProvider>,
     claims_service: UserService,
-    play_stream: PlayStream,
+    streams: Streams,
+    readiness: health::Readiness,
+    config: Config,
   ) -> Self {
+    let debug_responses = config.debug_responses;
+    let max_body_bytes = config.max_body_bytes;
+    let maintenance =
+      maintenance::Maintenance::new(config.maintenance_mode, config.maintenance_retry_after);
+    let quotas = quota::Quotas::new(
+      config.game_requests_per_minute,
+      config.game_event_polls_per_minute,
+      config.ip_requests_per_minute,
+      Duration::from_millis(config.play_action_interval_ms),
+    );
+    let invite_signer = invites::InviteSigner::new(&config.invite_secret);
+    let permissions = Permissions::new(pool.clone());
+    let repos = Repos::postgres(pool.clone());
+    let presence = presence::Presence::new(pool.clone());
+    presence.keep_alive();
     let app_state = AppState {
       pool,
-      firebase_auth,
+      auth_provider,
       claims_service,
-      play_stream,
+      play_stream: streams.play,
+      changes: streams.changes,
+      config,
+      maintenance,
+      quotas,
+      presence,
+      activity: streams.activity,
+      readiness,
+      invite_signer,
+      permissions,
+      graphql: graphql::schema(),
+      repos,
     };
 
-    let router = axum::Router::new()
+    let mut router = axum::Router::new()
       .route("/", get(home))
-      .route("/health", get(health))
+      .route("/healthz", get(health::healthz))
+      .route("/readyz", get(health::readyz))
+      .route(
+        "/admin/maintenance",
+        get(maintenance::get).put(maintenance::set),
+      )
+      .route("/admin/games", get(admin::list_games))
+      .route("/admin/games/:game_id", delete(admin::delete_game))
+      .route(
+        "/admin/games/:game_id/events/:event_id/reemit",
+        post(admin::reemit_event),
+      )
+      .route(
+        "/admin/users/:uid/permissions",
+        get(admin::user_permissions),
+      )
+      .route("/me", get(me::profile))
+      .route("/users/lookup", get(users::lookup))
+      .route("/me/export", get(me::export))
+      .route("/me/api-keys", get(api_keys::list).post(api_keys::create))
+      .route("/me/api-keys/:key_id", delete(api_keys::revoke))
+      .route(
+        "/me/notifications",
+        get(me::notifications).put(me::set_notifications),
+      )
+      .route("/rule-presets", get(games::rule_presets))
       .route("/games", get(games::list).post(games::create))
-      .route("/accept/:game_id", get(games::accept_invitation))
-      .route("/play/:game_id", post(games::play))
+      .route("/games/import", post(games::import))
+      .route("/invites/accept", post(invites::accept))
+      .route("/play/:game_id/start", post(games::start))
+      .route("/play/:game_id/reset", post(games::reset))
+      .route("/play/:game_id/roll", post(games::roll))
+      .route("/play/:game_id/pick", post(games::pick))
+      .route("/play/:game_id/keep", post(games::keep))
+      .route("/play/:game_id/steal", post(games::steal))
+      .route("/play/:game_id/assign", post(games::assign))
+      .route("/play/:game_id/undo", post(games::undo))
       .route(
         "/games/:game_id",
         get(games::get)
@@ -68,11 +205,64 @@ impl Server {
           .delete(games::delete),
       )
       .route("/games/:game_id/events", get(games::list_events))
+      .route("/games/:game_id/events/poll", get(games::poll_events))
+      .route(
+        "/games/:game_id/events.csv",
+        get(games::events_csv_download),
+      )
+      .route("/games/:game_id/nudge", post(games::nudge))
+      .route("/games/:game_id/restore", post(games::restore))
+      .route("/games/:game_id/export", get(games::export))
+      .route("/games/:game_id/calendar.ics", get(games::calendar))
+      .route(
+        "/games/:game_id/invites",
+        get(invites::list).post(invites::create),
+      )
+      .route(
+        "/games/:game_id/invites/:invite_id",
+        delete(invites::revoke),
+      )
+      .route(
+        "/games/:game_id/members",
+        get(members::list).post(members::add),
+      )
+      .route(
+        "/games/:game_id/members/:uid",
+        patch(members::update).delete(members::remove),
+      )
+      .route("/games/:game_id/audit", get(members::audit))
+      .route(
+        "/games/:game_id/email-invites",
+        get(email_invites::list).post(email_invites::create),
+      )
+      .route(
+        "/games/:game_id/email-invites/:invite_id",
+        delete(email_invites::revoke),
+      )
+      .route("/games/:game_id/activity", post(activity::signal))
+      .route("/games/:game_id/recap", get(recaps::get))
+      .route("/games/:game_id/summary", get(recaps::summary))
+      .route("/games/:game_id/stats", get(games::stats))
+      .route("/games/:game_id/state", get(games::state))
       .route("/games/:game_id/stream", get(games::events))
+      .route("/games/:game_id/ws", get(ws::connect))
+      .route("/games/:game_id/presence", get(presence::get))
+      .route("/graphql", post(graphql::execute))
+      .route("/graphql/ws", get(graphql::subscribe))
+      .route(
+        "/games/:game_id/share",
+        get(share::get)
+          .put(share::update)
+          .post(share::rotate)
+          .delete(share::revoke),
+      )
+      .route("/share/:token", get(share::view))
+      .route("/share/:token/stream", get(share::stream))
       .route(
         "/games/:game_id/players",
         get(players::list).post(players::create),
       )
+      .route("/games/:game_id/players/batch", post(players::create_many))
       .route(
         "/games/:game_id/players/:player_id",
         get(players::get)
@@ -80,10 +270,48 @@ impl Server {
           .put(players::replace)
           .delete(players::delete),
       )
+      .route(
+        "/games/:game_id/teams",
+        get(teams::list).post(teams::create),
+      )
+      .route(
+        "/games/:game_id/webhooks",
+        get(webhooks::list).post(webhooks::create),
+      )
+      .route(
+        "/games/:game_id/webhooks/:webhook_id",
+        get(webhooks::get)
+          .patch(webhooks::update)
+          .delete(webhooks::delete),
+      )
+      .route(
+        "/games/:game_id/webhooks/:webhook_id/deliveries",
+        get(webhooks::deliveries),
+      )
+      .route(
+        "/games/:game_id/teams/:team_id",
+        get(teams::get)
+          .patch(teams::update)
+          .put(teams::replace)
+          .delete(teams::delete),
+      )
       .route(
         "/games/:game_id/presents",
         get(presents::list).post(presents::create),
       )
+      .route(
+        "/games/:game_id/presents/batch",
+        post(presents::create_many)
+          .patch(games::assign_many)
+          .delete(presents::delete_many),
+      )
+      .route("/games/:game_id/presents/shuffle", post(presents::shuffle))
+      .route("/games/:game_id/guesses", get(guesses::list))
+      .route("/games/:game_id/guesses/scores", get(guesses::scores))
+      .route(
+        "/games/:game_id/presents/:present_id/guesses",
+        put(guesses::upsert),
+      )
       .route(
         "/games/:game_id/presents/:present_id",
         get(presents::get)
@@ -91,7 +319,33 @@ impl Server {
           .put(presents::replace)
           .delete(presents::delete),
       )
-      .with_state(app_state);
+      .layer(middleware::from_fn(tx::manage))
+      .layer(middleware::from_fn_with_state(
+        app_state.clone(),
+        quota::enforce,
+      ))
+      .layer(middleware::from_fn_with_state(
+        app_state.clone(),
+        idempotency::replay,
+      ))
+      .layer(middleware::from_fn_with_state(
+        app_state.clone(),
+        maintenance::guard,
+      ))
+      .layer(middleware::from_fn_with_state(
+        app_state.clone(),
+        client_ip::restrict_admin,
+      ))
+      .with_state(app_state)
+      .merge(openapi::routes())
+      .layer(DefaultBodyLimit::disable())
+      .layer(RequestBodyLimitLayer::new(max_body_bytes))
+      .layer(middleware::from_fn(normalize_errors))
+      .layer(middleware::from_fn(i18n::localize));
+
+    if debug_responses {
+      router = router.layer(middleware::from_fn(debug::diagnostics));
+    }
 
     Self { router }
   }
@@ -102,21 +356,210 @@ async fn home() -> &'static str {
   "Hello, World!"
 }
 
-// check health
-async fn health(State(db): State<sqlx::PgPool>) -> (StatusCode, &'static str) {
-  match db::health(&db).await {
-    Ok(()) => (StatusCode::OK, "👍 Healthy!"),
-    _ => (StatusCode::INTERNAL_SERVER_ERROR, "😭 Degraded!"),
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub struct ApiError {
+  #[serde(skip)]
+  pub status: StatusCode,
+  pub code: ErrorCode,
+  pub message: String,
+  // seconds, also sent as the Retry-After header
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub retry_after: Option<u64>,
+  // quote this when reporting a problem, also sent as X-Request-Id
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub request_id: Option<String>,
+  // extra fields depending on the code
+  #[serde(flatten)]
+  #[schema(ignore)]
+  pub details: Option<serde_json::Value>,
+}
+
+impl ApiError {
+  pub fn new(status: StatusCode, code: ErrorCode, message: impl Into<String>) -> Self {
+    Self {
+      status,
+      code,
+      message: message.into(),
+      retry_after: None,
+      request_id: None,
+      details: None,
+    }
+  }
+
+  pub fn with_retry_after(mut self, seconds: u64) -> Self {
+    self.retry_after = Some(seconds);
+    self
+  }
+
+  pub fn with_details<T: Serialize>(mut self, details: T) -> Self {
+    self.details = serde_json::to_value(details).ok();
+    self
+  }
+}
+
+impl IntoResponse for ApiError {
+  fn into_response(mut self) -> Response {
+    // throttled and unavailable responses always tell clients when to come back
+    if matches!(
+      self.status,
+      StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+    ) {
+      self.retry_after.get_or_insert(DEFAULT_RETRY_AFTER);
+    }
+    let mut res = (self.status, Json(&self)).into_response();
+    if let Some(retry_after) = self.retry_after {
+      res
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+    }
+    // keep the error around so later layers (i18n) can rewrite the body
+    res.extensions_mut().insert(self);
+    res
   }
 }
 
+// give bare error responses (plain status codes, extractor rejections) an ApiError body
+async fn normalize_errors(req: Request, next: Next) -> Response {
+  let res = next.run(req).await;
+  let status = res.status();
+  if !(status.is_client_error() || status.is_server_error())
+    || res.extensions().get::<ApiError>().is_some()
+  {
+    return res;
+  }
+  let (parts, body) = res.into_parts();
+  let text = to_bytes(body, MAX_ERROR_BODY)
+    .await
+    .map(|bytes| String::from_utf8_lossy(&bytes).trim().to_string())
+    .unwrap_or_default();
+  let message = if text.is_empty() {
+    status
+      .canonical_reason()
+      .unwrap_or(status.as_str())
+      .to_string()
+  } else {
+    text
+  };
+
+  let mut normalized =
+    ApiError::new(status, ErrorCode::from_status(status), message).into_response();
+  let own = normalized.headers().clone();
+  for (name, value) in parts.headers.iter() {
+    let replaced = name == header::CONTENT_TYPE || name == header::CONTENT_LENGTH;
+    if !replaced && !own.contains_key(name) {
+      normalized.headers_mut().append(name, value.clone());
+    }
+  }
+  normalized
+}
+
+#[derive(Serialize)]
+pub struct ParamError {
+  pub param: &'static str,
+  pub value: String,
+  pub allowed: Vec<String>,
+  pub examples: Vec<String>,
+}
+
+pub const INTERNAL_ERROR_MESSAGE: &str = "Something went wrong on our side, try again later";
+
+// unexpected failures are only logged, their text can hold sql and other internals
+pub fn internal_error(err: impl std::fmt::Display) -> ApiError {
+  tracing::error!("Internal error: {}", err);
+  ApiError::new(
+    StatusCode::INTERNAL_SERVER_ERROR,
+    ErrorCode::InternalError,
+    INTERNAL_ERROR_MESSAGE,
+  )
+}
+
+// the error handle_db_error responds with, for replies that aren't plain http responses
+pub fn db_api_error(err: db::Error) -> ApiError {
+  handle_db_error(err)
+    .extensions()
+    .get::<ApiError>()
+    .cloned()
+    .unwrap_or(ApiError::new(
+      StatusCode::INTERNAL_SERVER_ERROR,
+      ErrorCode::InternalError,
+      INTERNAL_ERROR_MESSAGE,
+    ))
+}
+
 pub fn handle_db_error(err: db::Error) -> Response {
+  let code = err.code();
+  let message = err.to_string();
   match err {
-    db::Error::Empty | db::Error::InvalidOrder => {
-      (StatusCode::BAD_REQUEST, err.to_string()).into_response()
+    db::Error::Empty | db::Error::CursorUnsupported => {
+      ApiError::new(StatusCode::BAD_REQUEST, code, message).into_response()
     }
-    db::Error::NotFound => StatusCode::NOT_FOUND.into_response(),
-    _ => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    db::Error::InvalidOrder { column, allowed } => {
+      let examples = match allowed.first() {
+        Some(c) => vec![c.clone(), format!("-{}", c)],
+        None => Vec::new(),
+      };
+      ApiError::new(StatusCode::BAD_REQUEST, code, message)
+        .with_details(ParamError {
+          param: "order",
+          value: column,
+          allowed,
+          examples,
+        })
+        .into_response()
+    }
+    db::Error::NotFound => ApiError::new(StatusCode::NOT_FOUND, code, message).into_response(),
+    db::Error::NotOwnPlayer => ApiError::new(StatusCode::FORBIDDEN, code, message).into_response(),
+    db::Error::VersionMismatch => {
+      ApiError::new(StatusCode::PRECONDITION_FAILED, code, message).into_response()
+    }
+    db::Error::OverBudget { budget_cents } => ApiError::new(StatusCode::BAD_REQUEST, code, message)
+      .with_details(serde_json::json!({ "budget_cents": budget_cents }))
+      .into_response(),
+    db::Error::CurrencyMismatch { currency } => {
+      ApiError::new(StatusCode::BAD_REQUEST, code, message)
+        .with_details(serde_json::json!({ "currency": currency }))
+        .into_response()
+    }
+    db::Error::GameStarted
+    | db::Error::StealingDisabled
+    | db::Error::StealBackForbidden
+    | db::Error::GuessingDisabled
+    | db::Error::GuessingClosed
+    | db::Error::NoActivePlayer
+    | db::Error::NothingToUndo
+    | db::Error::AlreadyNudged
+    | db::Error::AlreadyMember
+    | db::Error::LastOwner => ApiError::new(StatusCode::CONFLICT, code, message).into_response(),
+    db::Error::PresentImmune { until_turn } => ApiError::new(StatusCode::CONFLICT, code, message)
+      .with_details(serde_json::json!({ "immune_until_turn": until_turn }))
+      .into_response(),
+    db::Error::PlayerHasPresent { present_id } => {
+      ApiError::new(StatusCode::CONFLICT, code, message)
+        .with_details(serde_json::json!({ "present_id": present_id }))
+        .into_response()
+    }
+    db::Error::StealLimitReached { limit } => ApiError::new(StatusCode::CONFLICT, code, message)
+      .with_details(serde_json::json!({ "max_steals_per_present": limit }))
+      .into_response(),
+    db::Error::PlayerNotIdle { retry_after } => ApiError::new(StatusCode::CONFLICT, code, message)
+      .with_retry_after(retry_after.max(1) as u64)
+      .into_response(),
+    db::Error::StateChanged {
+      player_id,
+      event_seq,
+    } => ApiError::new(StatusCode::CONFLICT, code, message)
+      .with_details(serde_json::json!({ "player_id": player_id, "event_seq": event_seq }))
+      .into_response(),
+    db::Error::Sqlx(sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed) => {
+      tracing::warn!("Database unavailable: {}", message);
+      ApiError::new(
+        StatusCode::SERVICE_UNAVAILABLE,
+        ErrorCode::DatabaseUnavailable,
+        "The database is overloaded, try again shortly",
+      )
+      .into_response()
+    }
+    _ => internal_error(message).into_response(),
   }
 }
 
@@ -127,47 +570,131 @@ pub fn make_json_response<T: Serialize>(res: Result<T, db::Error>) -> Response {
   }
 }
 
+// 422 listing the invalid fields of a request body
+pub fn check_fields(body: &impl Validate) -> Result<(), ApiError> {
+  body
+    .validate()
+    .map_err(|errors| invalid_fields(serde_json::json!({ "fields": errors })))
+}
+
+// the first invalid item of a batch
+pub fn check_items<T: Validate>(items: &[T]) -> Result<(), ApiError> {
+  for (index, item) in items.iter().enumerate() {
+    item
+      .validate()
+      .map_err(|errors| invalid_fields(serde_json::json!({ "index": index, "fields": errors })))?;
+  }
+  Ok(())
+}
+
+fn invalid_fields(details: serde_json::Value) -> ApiError {
+  ApiError::new(
+    StatusCode::UNPROCESSABLE_ENTITY,
+    ErrorCode::ValidationFailed,
+    "Some fields are invalid",
+  )
+  .with_details(details)
+}
+
+// 201 with the new entity, so clients don't have to fetch it from the Location right away
+pub fn make_created_response<T: Serialize>(location: String, entity: T) -> Response {
+  (
+    StatusCode::CREATED,
+    [(header::LOCATION, location)],
+    make_json_response(Ok(entity)),
+  )
+    .into_response()
+}
+
+// lists stay bare arrays, the paging details go in headers
+pub fn make_page_response<T: Serialize>(res: Result<db::Page<T>, db::Error>) -> Response {
+  let page = match res {
+    Ok(page) => page,
+    Err(err) => return handle_db_error(err),
+  };
+  let mut response = make_json_response(Ok(&page.items));
+  let headers = response.headers_mut();
+  headers.insert("x-total-count", HeaderValue::from(page.total_count));
+  headers.insert("x-offset", HeaderValue::from(page.offset));
+  headers.insert("x-limit", HeaderValue::from(page.limit));
+  if let Some(cursor) = page.next_cursor {
+    headers.insert("x-next-cursor", HeaderValue::from(cursor));
+  }
+  headers.insert(
+    "x-has-more",
+    HeaderValue::from_static(if page.has_more() { "true" } else { "false" }),
+  );
+  response
+}
+
 #[async_trait]
 impl<S> FromRequestParts<S> for MyFirebaseUser
 where
   S: Send + Sync,
   AppState: FromRef<S>,
 {
-  type Rejection = (StatusCode, String);
+  type Rejection = ApiError;
 
   async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+    // the quota middleware already resolved the caller of game routes
+    if let Some(user) = parts.extensions.get::<MyFirebaseUser>() {
+      return Ok(user.clone());
+    }
+    let app_state = AppState::from_ref(state);
+    // devices that can't sign in send an api key instead of a token
+    if let Some(key) = parts.headers.get(api_keys::API_KEY_HEADER) {
+      let key = key
+        .to_str()
+        .map_err(|_| http_error(StatusCode::UNAUTHORIZED, ErrorCode::Unauthorized))?;
+      return api_keys::authenticate(&app_state.pool, key).await;
+    }
+    // spectators without an account send the share token of the game
+    if let Some(token) = parts.headers.get(share::SHARE_TOKEN_HEADER) {
+      let path = parts.uri.path();
+      return share::authenticate(&app_state.pool, &parts.method, path, token).await;
+    }
+
     let TypedHeader(Authorization(bearer)) =
       TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state)
         .await
-        .map_err(http_error_handler(StatusCode::BAD_REQUEST))?;
+        .map_err(http_error_handler(
+          StatusCode::BAD_REQUEST,
+          ErrorCode::MissingToken,
+        ))?;
 
-    let app_state = AppState::from_ref(state);
-    app_state
-      .firebase_auth
+    let mut user = app_state
+      .auth_provider
       .verify(bearer.token())
-      .map_err(|_| http_error(StatusCode::UNAUTHORIZED))
+      .await
+      .map_err(|_| http_error(StatusCode::UNAUTHORIZED, ErrorCode::Unauthorized))?;
+    // the claims can't hold every game of a user, the memberships in the db are what counts
+    let client_ip = parts
+      .extensions
+      .get::<client_ip::ClientIp>()
+      .and_then(|ip| ip.known());
+    user.games = app_state
+      .permissions
+      .games(&user, client_ip)
+      .await
+      .map_err(internal_error)?;
+    Ok(user)
   }
 }
 
-fn http_error_handler<E>(status: StatusCode) -> impl Fn(E) -> (StatusCode, String)
+fn http_error_handler<E>(status: StatusCode, code: ErrorCode) -> impl Fn(E) -> ApiError
 where
   E: std::error::Error,
 {
-  move |err: E| -> (StatusCode, String) { (status, err.to_string()) }
+  move |err: E| -> ApiError { ApiError::new(status, code, err.to_string()) }
 }
-fn http_error(status: StatusCode) -> (StatusCode, String) {
-  (
+fn http_error(status: StatusCode, code: ErrorCode) -> ApiError {
+  ApiError::new(
     status,
-    String::from(status.canonical_reason().unwrap_or(&status.to_string())),
+    code,
+    status.canonical_reason().unwrap_or(status.as_str()),
   )
 }
 
-impl FromRef<AppState> for FirebaseAuth<MyFirebaseUser> {
-  fn from_ref(state: &AppState) -> Self {
-    state.firebase_auth.clone()
-  }
-}
-
 pub struct UnauthorizedResponse {
   msg: String,
 }
@@ -183,3 +710,9 @@ impl FromRef<AppState> for UserService {
     state.claims_service.clone()
   }
 }
+
+impl FromRef<AppState> for Permissions {
+  fn from_ref(state: &AppState) -> Self {
+    state.permissions.clone()
+  }
+}
@@ -0,0 +1,105 @@
+use axum::{
+  extract::{FromRef, Path, State},
+  http::StatusCode,
+  response::{sse::Event, IntoResponse, Response},
+  Json,
+};
+use chrono::{DateTime, Utc};
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, Sender};
+use tokio_stream::wrappers::BroadcastStream;
+use uuid::Uuid;
+
+use crate::{auth::MyFirebaseUser, db::activity};
+
+use super::AppState;
+
+// what the current player is doing right now
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivityKind {
+  BrowsingPresents,
+  ViewingPresent,
+  ConsideringSteal,
+  Unwrapping,
+}
+
+// ephemeral signal, sent to every replica over NOTIFY activity but never stored
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Activity {
+  pub game_id: Uuid,
+  pub kind: ActivityKind,
+  pub player_id: Option<i64>,
+  pub present_id: Option<i64>,
+  pub at: DateTime<Utc>,
+}
+
+#[derive(Clone)]
+pub struct ActivityStream(Sender<Activity>);
+
+impl Default for ActivityStream {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl ActivityStream {
+  pub fn new() -> Self {
+    Self(broadcast::channel(64).0)
+  }
+
+  pub fn send(&self, activity: Activity) {
+    // nobody listening is fine
+    let _ = self.0.send(activity);
+  }
+
+  // named "activity" events for one game, missed signals are simply skipped
+  pub fn subscribe(&self, game_id: Uuid) -> impl Stream<Item = Result<Event, anyhow::Error>> {
+    BroadcastStream::new(self.0.subscribe()).filter_map(move |message| async move {
+      let activity = message.ok().filter(|a| a.game_id == game_id)?;
+      Some(
+        serde_json::to_string(&activity)
+          .map(|data| Event::default().event("activity").data(data))
+          .map_err(anyhow::Error::from),
+      )
+    })
+  }
+}
+
+impl FromRef<AppState> for ActivityStream {
+  fn from_ref(state: &AppState) -> Self {
+    state.activity.clone()
+  }
+}
+
+#[derive(Deserialize)]
+pub struct ActivityData {
+  pub kind: ActivityKind,
+  pub player_id: Option<i64>,
+  pub present_id: Option<i64>,
+}
+
+// tell spectators what the current player is up to
+pub async fn signal(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+  Json(data): Json<ActivityData>,
+) -> Response {
+  if !user.can_play(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  let activity = Activity {
+    game_id,
+    kind: data.kind,
+    player_id: data.player_id,
+    present_id: data.present_id,
+    at: Utc::now(),
+  };
+  // the listener of each replica hands it to its subscribers, the signal is fire and forget
+  if let Err(err) = activity::notify(&db, &activity).await {
+    tracing::warn!("Failed to send activity of game {}: {}", game_id, err);
+  }
+  StatusCode::ACCEPTED.into_response()
+}
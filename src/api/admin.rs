@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+
+use axum::{
+  async_trait,
+  extract::{FromRef, FromRequestParts, Path, Query, State},
+  http::{request::Parts, StatusCode},
+  response::{IntoResponse, Response},
+  Json,
+};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+  auth::{user::UserService, MyFirebaseUser},
+  db::{games, members, ListParams},
+  error_code::ErrorCode,
+};
+
+use super::{handle_db_error, http_error, make_page_response, ApiError, AppState};
+
+// a signed-in support staff member, everyone else gets a 403
+pub struct AdminUser(pub MyFirebaseUser);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AdminUser
+where
+  S: Send + Sync,
+  AppState: FromRef<S>,
+{
+  type Rejection = ApiError;
+
+  async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+    let user = MyFirebaseUser::from_request_parts(parts, state).await?;
+    if !user.is_superadmin() {
+      return Err(http_error(
+        StatusCode::FORBIDDEN,
+        ErrorCode::PermissionDenied,
+      ));
+    }
+    Ok(Self(user))
+  }
+}
+
+#[derive(Serialize)]
+pub struct UserPermissions {
+  pub uid: String,
+  pub superadmin: bool,
+  pub games: HashMap<String, i64>,
+}
+
+// all games across users
+pub async fn list_games(
+  State(db): State<sqlx::PgPool>,
+  _admin: AdminUser,
+  Query(p): Query<ListParams>,
+) -> Response {
+  make_page_response(games::list_all(&db, p).await)
+}
+
+// delete any game for good, without the name confirmation owners need
+pub async fn delete_game(
+  State(db): State<sqlx::PgPool>,
+  AdminUser(user): AdminUser,
+  Path(game_id): Path<Uuid>,
+) -> Result<StatusCode, Response> {
+  games::destroy(&db, game_id)
+    .await
+    .map_err(handle_db_error)?;
+  tracing::warn!("Game {} force-deleted by {}", game_id, user.sub);
+  Ok(StatusCode::ACCEPTED)
+}
+
+// the games a user is a member of, as authorization sees them, and their superadmin claim
+pub async fn user_permissions(
+  State(db): State<sqlx::PgPool>,
+  State(claims_service): State<UserService>,
+  _admin: AdminUser,
+  Path(uid): Path<String>,
+) -> Response {
+  let games = match members::games_of(&db, &uid).await {
+    Ok(games) => games,
+    Err(err) => return handle_db_error(err),
+  };
+  match claims_service.lookup(&uid).await {
+    Ok(found) => Json(UserPermissions {
+      uid: found.localId,
+      superadmin: found.customAttributes.superadmin,
+      games,
+    })
+    .into_response(),
+    Err(err) => {
+      tracing::warn!("Failed to look up user {}: {}", uid, err);
+      ApiError::new(
+        StatusCode::BAD_GATEWAY,
+        ErrorCode::InternalError,
+        "Failed to look up the user's account",
+      )
+      .into_response()
+    }
+  }
+}
+
+// send a play event to the listeners again, for clients stuck on a missed event
+pub async fn reemit_event(
+  State(db): State<sqlx::PgPool>,
+  AdminUser(user): AdminUser,
+  Path((game_id, event_id)): Path<(Uuid, i64)>,
+) -> Result<StatusCode, Response> {
+  games::reemit_event(&db, game_id, event_id)
+    .await
+    .map_err(handle_db_error)?;
+  tracing::info!(
+    "Event {} of game {} re-emitted by {}",
+    event_id,
+    game_id,
+    user.sub
+  );
+  Ok(StatusCode::ACCEPTED)
+}
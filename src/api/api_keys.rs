@@ -0,0 +1,122 @@
+use axum::{
+  extract::{Path, Query, State},
+  http::{HeaderName, StatusCode},
+  response::{IntoResponse, Response},
+  Json,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+  auth::MyFirebaseUser,
+  db::{
+    self,
+    api_keys::{self, ApiKey, CreateParams},
+    ListParams,
+  },
+  error_code::ErrorCode,
+};
+
+use super::{
+  check_fields, handle_db_error, internal_error, make_created_response, make_page_response,
+  ApiError,
+};
+
+pub const API_KEY_HEADER: HeaderName = HeaderName::from_static("x-api-key");
+const KEY_PREFIX: &str = "esk_";
+// characters of the key stored in clear, enough to tell keys apart
+const SHOWN_LEN: usize = KEY_PREFIX.len() + 8;
+
+fn hash(key: &str) -> String {
+  hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+// the key is only ever returned here
+#[derive(Serialize)]
+pub struct CreatedApiKey {
+  #[serde(flatten)]
+  pub api_key: ApiKey,
+  pub key: String,
+}
+
+// the user an api key acts as, revoked and unknown keys are rejected
+pub async fn authenticate(db: &sqlx::PgPool, key: &str) -> Result<MyFirebaseUser, ApiError> {
+  match api_keys::find_active(db, &hash(key)).await {
+    Ok(found) => {
+      if found.stale {
+        if let Err(err) = api_keys::touch(db, found.id).await {
+          tracing::warn!("Failed to update last use of api key {}: {}", found.id, err);
+        }
+      }
+      Ok(MyFirebaseUser::for_api_key(found.id, found.games))
+    }
+    Err(db::Error::NotFound) => Err(ApiError::new(
+      StatusCode::UNAUTHORIZED,
+      ErrorCode::Unauthorized,
+      "Unknown or revoked api key",
+    )),
+    Err(err) => Err(internal_error(err)),
+  }
+}
+
+// list the api keys of the signed-in user
+pub async fn list(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Query(p): Query<ListParams>,
+) -> Response {
+  if user.is_api_key() {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  make_page_response(api_keys::list(&db, &user.sub, p).await)
+}
+
+// create an api key for games the user owns, granting at most the user's own permission
+pub async fn create(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Json(p): Json<CreateParams>,
+) -> Response {
+  if user.is_api_key() {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  if let Err(err) = check_fields(&p) {
+    return err.into_response();
+  }
+  let allowed = p.games.iter().all(|(game_id, permission)| {
+    Uuid::parse_str(game_id)
+      .is_ok_and(|id| user.can_edit(id) && *permission <= user.permission_level(id))
+  });
+  if !allowed {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  let key = format!(
+    "{}{}{}",
+    KEY_PREFIX,
+    Uuid::new_v4().simple(),
+    Uuid::new_v4().simple()
+  );
+  match api_keys::create(&db, &hash(&key), &key[..SHOWN_LEN], &user.sub, p).await {
+    Ok(api_key) => make_created_response(
+      format!("/me/api-keys/{}", api_key.id),
+      CreatedApiKey { api_key, key },
+    ),
+    Err(err) => handle_db_error(err),
+  }
+}
+
+// revoke an api key
+pub async fn revoke(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Path(key_id): Path<i64>,
+) -> Result<StatusCode, Response> {
+  if user.is_api_key() {
+    return Err(StatusCode::FORBIDDEN.into_response());
+  }
+  api_keys::revoke(&db, &user.sub, key_id)
+    .await
+    .map_err(handle_db_error)?;
+  Ok(StatusCode::ACCEPTED)
+}
@@ -0,0 +1,58 @@
+use axum::{extract::FromRef, response::sse::Event};
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, Sender};
+use tokio_stream::wrappers::BroadcastStream;
+use uuid::Uuid;
+
+use super::AppState;
+
+// a player, present or the game's settings changed, as sent over NOTIFY changes
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Change {
+  pub game_id: Uuid,
+  // player, present or game
+  pub entity: String,
+  // insert, update or delete
+  pub op: String,
+  // the player or present, None for the game itself
+  pub id: Option<i64>,
+}
+
+#[derive(Clone)]
+pub struct ChangeStream(Sender<Change>);
+
+impl Default for ChangeStream {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl ChangeStream {
+  pub fn new() -> Self {
+    Self(broadcast::channel(256).0)
+  }
+
+  pub fn send(&self, change: Change) {
+    // nobody listening is fine
+    let _ = self.0.send(change);
+  }
+
+  // named "change" events for one game, clients refetch what changed
+  pub fn subscribe(&self, game_id: Uuid) -> impl Stream<Item = Result<Event, anyhow::Error>> {
+    BroadcastStream::new(self.0.subscribe()).filter_map(move |message| async move {
+      let change = message.ok().filter(|c| c.game_id == game_id)?;
+      Some(
+        serde_json::to_string(&change)
+          .map(|data| Event::default().event("change").data(data))
+          .map_err(anyhow::Error::from),
+      )
+    })
+  }
+}
+
+impl FromRef<AppState> for ChangeStream {
+  fn from_ref(state: &AppState) -> Self {
+    state.changes.clone()
+  }
+}
@@ -0,0 +1,165 @@
+use std::{
+  convert::Infallible,
+  net::{IpAddr, Ipv4Addr, SocketAddr},
+  sync::Arc,
+};
+
+use axum::{
+  async_trait,
+  extract::{ConnectInfo, FromRequestParts, Request, State},
+  http::{header::FORWARDED, request::Parts, HeaderMap, StatusCode},
+  middleware::Next,
+  response::{IntoResponse, Response},
+};
+use ipnet::IpNet;
+
+use crate::error_code::ErrorCode;
+
+use super::{ApiError, AppState};
+
+// operator-only routes, guarded by ADMIN_ALLOWED_IPS
+const ADMIN_PREFIXES: [&str; 2] = ["/admin", "/metrics"];
+
+// the address of the end user, resolved through trusted proxies
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub IpAddr);
+
+impl ClientIp {
+  // None when the request didn't come in over a socket, e.g. in tests
+  pub fn known(self) -> Option<IpAddr> {
+    Some(self.0).filter(|ip| !ip.is_unspecified())
+  }
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct TrustedProxies(Arc<Vec<IpNet>>);
+
+impl TrustedProxies {
+  pub fn new(nets: Vec<IpNet>) -> Self {
+    Self(Arc::new(nets))
+  }
+
+  pub fn contains(&self, ip: &IpAddr) -> bool {
+    self.0.iter().any(|net| net.contains(ip))
+  }
+}
+
+// accepts both CIDR ranges and bare addresses
+pub fn parse_net(s: &str) -> Option<IpNet> {
+  s.parse::<IpNet>()
+    .ok()
+    .or_else(|| s.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+// `for=` values of a Forwarded header, e.g. `for=192.0.2.60;proto=http, for="[2001:db8::1]:4711"`
+fn forwarded_for(headers: &HeaderMap) -> Vec<IpAddr> {
+  headers
+    .get_all(FORWARDED)
+    .iter()
+    .filter_map(|v| v.to_str().ok())
+    .flat_map(|v| v.split(','))
+    .filter_map(|element| {
+      element.split(';').find_map(|pair| {
+        let (key, value) = pair.trim().split_once('=')?;
+        if !key.eq_ignore_ascii_case("for") {
+          return None;
+        }
+        parse_node(value.trim().trim_matches('"'))
+      })
+    })
+    .collect()
+}
+
+fn x_forwarded_for(headers: &HeaderMap) -> Vec<IpAddr> {
+  headers
+    .get_all("x-forwarded-for")
+    .iter()
+    .filter_map(|v| v.to_str().ok())
+    .flat_map(|v| v.split(','))
+    .filter_map(|node| parse_node(node.trim()))
+    .collect()
+}
+
+fn parse_node(node: &str) -> Option<IpAddr> {
+  node
+    .parse::<IpAddr>()
+    .ok()
+    .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+    .or_else(|| {
+      let host = node.strip_prefix('[')?.split(']').next()?;
+      host.parse().ok()
+    })
+}
+
+// walk the forwarding chain right to left, the first untrusted hop is the client
+pub fn resolve_client_ip(peer: IpAddr, headers: &HeaderMap, trusted: &TrustedProxies) -> IpAddr {
+  if !trusted.contains(&peer) {
+    return peer;
+  }
+  let mut chain = forwarded_for(headers);
+  if chain.is_empty() {
+    chain = x_forwarded_for(headers);
+  }
+  let mut client = peer;
+  for hop in chain.into_iter().rev() {
+    client = hop;
+    if !trusted.contains(&hop) {
+      break;
+    }
+  }
+  client
+}
+
+// attach the resolved ClientIp to the request
+pub async fn resolve(
+  State(trusted): State<TrustedProxies>,
+  mut req: Request,
+  next: Next,
+) -> Response {
+  if let Some(ConnectInfo(peer)) = req.extensions().get::<ConnectInfo<SocketAddr>>() {
+    let ip = resolve_client_ip(peer.ip(), req.headers(), &trusted);
+    req.extensions_mut().insert(ClientIp(ip));
+  }
+  next.run(req).await
+}
+
+// reject operator routes from outside the allowlist, an empty list allows everyone
+pub async fn restrict_admin(State(state): State<AppState>, req: Request, next: Next) -> Response {
+  let allowed = &state.config.admin_allowed_ips;
+  let path = req.uri().path();
+  let is_admin = ADMIN_PREFIXES
+    .iter()
+    .any(|prefix| path == *prefix || path.starts_with(&format!("{}/", prefix)));
+  if !is_admin || allowed.is_empty() {
+    return next.run(req).await;
+  }
+  let ip = req.extensions().get::<ClientIp>().map(|ip| ip.0);
+  if ip.is_some_and(|ip| allowed.iter().any(|net| net.contains(&ip))) {
+    return next.run(req).await;
+  }
+  tracing::warn!("Blocked {} from {:?}", path, ip);
+  ApiError::new(
+    StatusCode::FORBIDDEN,
+    ErrorCode::PermissionDenied,
+    "Not allowed from this address",
+  )
+  .into_response()
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+  S: Send + Sync,
+{
+  type Rejection = Infallible;
+
+  async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    Ok(
+      parts
+        .extensions
+        .get::<ClientIp>()
+        .copied()
+        .unwrap_or(ClientIp(IpAddr::V4(Ipv4Addr::UNSPECIFIED))),
+    )
+  }
+}
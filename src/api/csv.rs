@@ -0,0 +1,84 @@
+use std::convert::Infallible;
+
+use axum::{
+  body::Body,
+  http::{header, HeaderMap},
+  response::{IntoResponse, Response},
+};
+use futures_util::{future, stream, Stream, StreamExt};
+
+use crate::db;
+
+pub const CONTENT_TYPE: &str = "text/csv";
+
+// whether the client asked for csv
+pub fn accepted(headers: &HeaderMap) -> bool {
+  headers
+    .get(header::ACCEPT)
+    .and_then(|v| v.to_str().ok())
+    .is_some_and(|accept| {
+      accept
+        .split(',')
+        .any(|t| t.trim().starts_with(CONTENT_TYPE))
+    })
+}
+
+// quote fields with separators, and defuse text spreadsheets would run as a formula
+fn field(value: &str) -> String {
+  let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+    format!("'{}", value)
+  } else {
+    value.to_string()
+  };
+  if value.contains([',', '"', '\n', '\r']) {
+    format!("\"{}\"", value.replace('"', "\"\""))
+  } else {
+    value
+  }
+}
+
+fn line(record: &[String]) -> Vec<u8> {
+  let mut line = record
+    .iter()
+    .map(|value| field(value))
+    .collect::<Vec<_>>()
+    .join(",");
+  line.push_str("\r\n");
+  line.into_bytes()
+}
+
+// write the header and then one line per record as records arrive, an error ends the file early
+pub fn response<S>(filename: &str, columns: &[&str], records: S) -> Response
+where
+  S: Stream<Item = Result<Vec<String>, db::Error>> + Send + 'static,
+{
+  let columns: Vec<String> = columns.iter().map(|c| c.to_string()).collect();
+  let lines = records
+    .scan((), |_, record| {
+      future::ready(match record {
+        Ok(record) => Some(record),
+        Err(err) => {
+          tracing::error!("CSV stream failed: {}", err);
+          None
+        }
+      })
+    })
+    .map(|record| line(&record));
+  let body = stream::once(future::ready(line(&columns)))
+    .chain(lines)
+    .map(Ok::<_, Infallible>);
+  (
+    [
+      (
+        header::CONTENT_TYPE,
+        format!("{}; charset=utf-8", CONTENT_TYPE),
+      ),
+      (
+        header::CONTENT_DISPOSITION,
+        format!("attachment; filename=\"{}\"", filename),
+      ),
+    ],
+    Body::from_stream(body),
+  )
+    .into_response()
+}
@@ -0,0 +1,139 @@
+use std::{cell::RefCell, fmt::Debug, time::Instant};
+
+use axum::{
+  body::{self, Body},
+  extract::{Query, Request},
+  http::{header, HeaderValue},
+  middleware::Next,
+  response::Response,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{
+  field::{Field, Visit},
+  Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+
+tokio::task_local! {
+  static QUERIES: RefCell<Vec<QueryDiagnostic>>;
+}
+
+#[derive(Serialize, Clone, Default, Debug)]
+pub struct QueryDiagnostic {
+  pub sql: String,
+  pub rows_returned: u64,
+  pub elapsed_ms: f64,
+}
+
+impl Visit for QueryDiagnostic {
+  fn record_str(&mut self, field: &Field, value: &str) {
+    match field.name() {
+      "db.statement" if !value.trim().is_empty() => self.sql = value.trim().to_string(),
+      "summary" if self.sql.is_empty() => self.sql = value.to_string(),
+      _ => {}
+    }
+  }
+
+  fn record_u64(&mut self, field: &Field, value: u64) {
+    if field.name() == "rows_returned" {
+      self.rows_returned = value;
+    }
+  }
+
+  fn record_f64(&mut self, field: &Field, value: f64) {
+    if field.name() == "elapsed_secs" {
+      self.elapsed_ms = value * 1000.0;
+    }
+  }
+
+  fn record_debug(&mut self, _field: &Field, _value: &dyn Debug) {}
+}
+
+// collects the statements sqlx logs under the `sqlx::query` target for the current request
+pub struct SqlCapture;
+
+impl<S: Subscriber> Layer<S> for SqlCapture {
+  fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+    let mut query = QueryDiagnostic::default();
+    event.record(&mut query);
+    let _ = QUERIES.try_with(|q| q.borrow_mut().push(query));
+  }
+}
+
+#[derive(Deserialize, Default)]
+struct DebugParams {
+  #[serde(default)]
+  pretty: bool,
+  #[serde(default)]
+  debug: bool,
+}
+
+#[derive(Serialize)]
+struct Diagnostics {
+  duration_ms: f64,
+  queries: Vec<QueryDiagnostic>,
+}
+
+fn millis(value: f64) -> HeaderValue {
+  HeaderValue::from_str(&format!("{:.2}", value)).unwrap_or(HeaderValue::from_static("0"))
+}
+
+// adds timing headers, `?pretty=true` indentation and a `?debug=true` `_debug` field
+pub async fn diagnostics(req: Request, next: Next) -> Response {
+  let params = Query::<DebugParams>::try_from_uri(req.uri())
+    .map(|q| q.0)
+    .unwrap_or_default();
+
+  let started = Instant::now();
+  let (mut res, queries) = QUERIES
+    .scope(RefCell::new(Vec::new()), async move {
+      let res = next.run(req).await;
+      (res, QUERIES.with(|q| q.take()))
+    })
+    .await;
+  let duration_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+  let headers = res.headers_mut();
+  headers.insert("x-debug-duration-ms", millis(duration_ms));
+  headers.insert("x-debug-sql-count", HeaderValue::from(queries.len()));
+  headers.insert(
+    "x-debug-sql-ms",
+    millis(queries.iter().map(|q| q.elapsed_ms).sum()),
+  );
+
+  let is_stream = matches!(
+    res.headers().get(header::CONTENT_TYPE),
+    Some(v) if v.as_bytes().starts_with(b"text/event-stream")
+  );
+  if !(params.pretty || params.debug) || is_stream {
+    return res;
+  }
+
+  let (mut parts, body) = res.into_parts();
+  let bytes = match body::to_bytes(body, usize::MAX).await {
+    Ok(bytes) => bytes,
+    Err(_) => return Response::from_parts(parts, Body::empty()),
+  };
+  let Ok(mut value) = serde_json::from_slice::<serde_json::Value>(&bytes) else {
+    return Response::from_parts(parts, Body::from(bytes));
+  };
+  if params.debug {
+    if let Some(obj) = value.as_object_mut() {
+      let diagnostics = Diagnostics {
+        duration_ms,
+        queries,
+      };
+      obj.insert(
+        String::from("_debug"),
+        serde_json::to_value(diagnostics).unwrap_or_default(),
+      );
+    }
+  }
+  let out = if params.pretty {
+    serde_json::to_vec_pretty(&value)
+  } else {
+    serde_json::to_vec(&value)
+  };
+  parts.headers.remove(header::CONTENT_LENGTH);
+  Response::from_parts(parts, Body::from(out.unwrap_or_default()))
+}
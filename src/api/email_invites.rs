@@ -0,0 +1,133 @@
+use axum::{
+  extract::{Path, Query, State},
+  http::StatusCode,
+  response::{IntoResponse, Response},
+  Json,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+  auth::{permissions::Permissions, user::UserService, MyFirebaseUser},
+  db::{
+    audit::{self, Actor},
+    email_invites, ListParams,
+  },
+  validation::permission_level,
+};
+
+use super::{
+  check_fields, client_ip::ClientIp, games::PLAY_PERMISSION, handle_db_error,
+  make_created_response, make_page_response, members::sync_claims, tx::Tx,
+};
+
+#[derive(Deserialize, Validate)]
+pub struct CreateParams {
+  #[validate(email, length(max = 254))]
+  pub email: String,
+  #[serde(default = "default_permission")]
+  #[validate(custom(function = permission_level))]
+  pub permission: i64,
+}
+
+fn default_permission() -> i64 {
+  PLAY_PERMISSION
+}
+
+// list the email invites of a game
+pub async fn list(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Query(p): Query<ListParams>,
+  Path(game_id): Path<Uuid>,
+) -> Response {
+  if user.can_edit(game_id) {
+    make_page_response(email_invites::list(&db, game_id, p).await)
+  } else {
+    StatusCode::FORBIDDEN.into_response()
+  }
+}
+
+// invite someone by email, they join once they sign in with it verified.
+// people who already have an account join right away
+pub async fn create(
+  mut tx: Tx,
+  user: MyFirebaseUser,
+  client_ip: ClientIp,
+  State(claims_service): State<UserService>,
+  State(permissions): State<Permissions>,
+  Path(game_id): Path<Uuid>,
+  Json(p): Json<CreateParams>,
+) -> Response {
+  if !user.can_edit(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  if let Err(err) = check_fields(&p) {
+    return err.into_response();
+  }
+  let email = p.email.trim().to_lowercase();
+  let invite =
+    match email_invites::create(tx.conn(), game_id, &email, p.permission, &user.sub).await {
+      Ok(invite) => invite,
+      Err(err) => return handle_db_error(err),
+    };
+
+  let account = match claims_service.lookup_by_email(&email).await {
+    Ok(account) => account.filter(|account| account.emailVerified && !account.disabled),
+    Err(err) => {
+      // they can still join when they next sign in
+      tracing::warn!("Failed to look up the account of an invited email: {}", err);
+      None
+    }
+  };
+  let Some(account) = account else {
+    return make_created_response(
+      format!("/games/{}/email-invites/{}", game_id, invite.id),
+      invite,
+    );
+  };
+  let uid = account.localId;
+  let actor = Actor {
+    uid: &user.sub,
+    client_ip: client_ip.known(),
+    source: audit::INVITE,
+  };
+  let joined = match email_invites::redeem(tx.conn(), &actor, &uid, &email, Some(game_id)).await {
+    Ok(joined) => joined,
+    Err(err) => return handle_db_error(err),
+  };
+  let invite = match email_invites::get(tx.conn(), game_id, invite.id).await {
+    Ok(invite) => invite,
+    Err(err) => return handle_db_error(err),
+  };
+  for (game_id, permission) in joined {
+    sync_claims(
+      &claims_service,
+      &permissions,
+      &uid,
+      game_id,
+      Some(permission),
+    )
+    .await;
+  }
+  make_created_response(
+    format!("/games/{}/email-invites/{}", game_id, invite.id),
+    invite,
+  )
+}
+
+// revoke a pending email invite
+pub async fn revoke(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Path((game_id, invite_id)): Path<(Uuid, i64)>,
+) -> Result<StatusCode, Response> {
+  if !user.can_edit(game_id) {
+    return Err(StatusCode::FORBIDDEN.into_response());
+  }
+  email_invites::revoke(&db, game_id, invite_id)
+    .await
+    .map_err(handle_db_error)?;
+  Ok(StatusCode::ACCEPTED)
+}
@@ -1,240 +1,1346 @@
-use std::{collections::HashMap, time::Duration};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use axum::{
   extract::{Path, Query, State},
-  http::StatusCode,
+  http::{header, HeaderMap, HeaderValue, StatusCode},
   response::{sse::Event, IntoResponse, Response, Sse},
   Json,
 };
-use chrono::NaiveDateTime;
-use futures_util::Stream;
-use futures_util::StreamExt;
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures_util::{stream, Stream, StreamExt};
 use serde::Deserialize;
-use serde::Serialize;
-use tokio_stream::wrappers::BroadcastStream;
+use serde_json::json;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream, IntervalStream};
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
+use validator::Validate;
 
 use crate::{
-  auth::{user::UserService, CustomClaims, MyFirebaseUser},
+  auth::{permissions::Permissions, user::UserService, MyFirebaseUser},
   db::{
-    games::{self, PlayStream, ReplaceParams, UpdateData},
-    ListParams,
+    self,
+    audit::{self, Actor},
+    events::{EventNames, GameEvent, PlayEvent, ReplayState},
+    export::{self, GameExport},
+    games::{
+      self, Assignment, Expected, Game, GameStateUpdateResult, GameStats, NudgeResult, PlayStream,
+      ReplaceParams, UpdateData,
+    },
+    import::{self, ImportData, ImportResult},
+    is_currency_code, notifications,
+    repo::GamesRepo,
+    webhooks::{GAME_DELETED, GAME_RESTORED, GAME_UPDATED},
+    BulkParams, BulkResult, ListParams, UpdateResult,
   },
+  error_code::ErrorCode,
+  i18n::Locale,
+  notify,
+  rules::{GameRules, RulePreset, RulePresetInfo},
+  validation::{image_urls, MAX_IMAGES, MAX_NAME_LEN},
 };
 
-use super::{handle_db_error, make_json_response};
+use super::{
+  activity::ActivityStream,
+  changes::ChangeStream,
+  check_fields,
+  client_ip::ClientIp,
+  csv, handle_db_error, ics, make_created_response, make_json_response, make_page_response,
+  members, ndjson,
+  presence::{Kind, Presence},
+  quota::{self, Quotas},
+  share::{self, SharedGame},
+  turn_timer,
+  tx::Tx,
+  webhooks, ApiError, AppState,
+};
 
 pub const OWNER_PERMISSION: i64 = 0xff;
 pub const PLAY_PERMISSION: i64 = 0x2;
 pub const VIEW_PERMISSION: i64 = 0x1;
 
-// list games
+/// list games
+#[utoipa::path(
+  get,
+  operation_id = "list_games",
+  path = "/games",
+  tag = "games",
+  params(ListParams),
+  responses(
+    (status = 200, body = Vec<Game>, headers(
+      ("X-Total-Count" = i64, description = "size of the whole list"),
+      ("X-Offset" = i64),
+      ("X-Limit" = i64),
+      ("X-Next-Cursor" = i64, description = "after_id of the next page"),
+      ("X-Has-More" = bool),
+    )),
+    (status = 400, body = ApiError),
+  )
+)]
 pub async fn list(
-  State(db): State<sqlx::PgPool>,
+  State(repo): State<Arc<dyn GamesRepo>>,
   user: MyFirebaseUser,
+  locale: Locale,
   Query(p): Query<ListParams>,
 ) -> Response {
-  make_json_response(games::list(&db, &user.sub, p).await)
+  let res = repo.list(&user.sub, p).await;
+  make_page_response(res.map(|page| page.map(|g| g.localize(locale))))
 }
 
-// get a game
+/// get a game
+#[utoipa::path(
+  get,
+  operation_id = "get_game",
+  path = "/games/{game_id}",
+  tag = "games",
+  params(("game_id" = Uuid, Path)),
+  responses(
+    (status = 200, body = Game, headers(("ETag" = String, description = "send back in If-Match to update"))),
+    (status = 403, body = ApiError),
+    (status = 404, body = ApiError),
+  )
+)]
 pub async fn get(
-  State(db): State<sqlx::PgPool>,
+  State(repo): State<Arc<dyn GamesRepo>>,
   user: MyFirebaseUser,
+  locale: Locale,
   Path(game_id): Path<Uuid>,
 ) -> Response {
   if !user.can_view(game_id) {
     return StatusCode::FORBIDDEN.into_response();
   }
-  make_json_response(games::get(&db, game_id).await)
+  match repo.get(game_id).await {
+    // spectators don't get to see who the members are
+    Ok(game) if user.is_share_token() => {
+      let version = game.version();
+      let game = SharedGame::from(game.localize(locale));
+      with_etag(make_json_response(Ok(game)), version)
+    }
+    Ok(game) => {
+      let version = game.version();
+      with_etag(make_json_response(Ok(game.localize(locale))), version)
+    }
+    Err(err) => handle_db_error(err),
+  }
 }
 
-#[derive(Deserialize)]
+/// describe the built-in rule presets
+#[utoipa::path(
+  get,
+  operation_id = "list_rule_presets",
+  path = "/rule-presets",
+  tag = "games",
+  security(()),
+  responses(
+    (status = 200, body = Vec<RulePresetInfo>),
+  )
+)]
+pub async fn rule_presets() -> Json<Vec<RulePresetInfo>> {
+  Json(RulePreset::ALL.iter().map(RulePreset::info).collect())
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+#[schema(as = GameCreateParams)]
 pub struct CreateParams {
+  #[validate(length(min = 1, max = MAX_NAME_LEN))]
   pub name: String,
+  #[validate(length(max = MAX_IMAGES), custom(function = image_urls))]
   pub images: Option<Vec<String>>,
   pub users: Option<HashMap<String, i64>>,
+  pub rules: Option<GameRules>,
+  pub preset: Option<RulePreset>,
 }
 
-#[derive(Serialize)]
-pub struct GameCreated {
-  id: Uuid,
-  users: HashMap<String, i64>,
-  created_at: NaiveDateTime,
-}
-
-// create a game
+/// create a game
+#[utoipa::path(
+  post,
+  operation_id = "create_game",
+  path = "/games",
+  tag = "games",
+  request_body = CreateParams,
+  responses(
+    (status = 201, body = Game, headers(("Location" = String))),
+    (status = 400, body = ApiError),
+    (status = 403, body = ApiError, description = "the user owns too many unfinished games"),
+    (status = 422, body = ApiError, description = "some fields are invalid"),
+  )
+)]
 pub async fn create(
-  State(db): State<sqlx::PgPool>,
+  mut tx: Tx,
   user: MyFirebaseUser,
-  State(mut claims_service): State<UserService>,
+  client_ip: ClientIp,
+  State(claims_service): State<UserService>,
+  State(permissions): State<Permissions>,
+  State(state): State<AppState>,
   Json(p): Json<CreateParams>,
 ) -> Response {
+  // api keys have fixed games, they can't own new ones
+  if user.is_api_key() {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  if let Err(err) = check_fields(&p) {
+    return err.into_response();
+  }
+  let rules = p
+    .rules
+    .or(p.preset.map(|preset| preset.rules()))
+    .unwrap_or_default();
+  if let Err(err) = check_rules(&rules) {
+    return err.into_response();
+  }
+  let limit = state.config.max_active_games;
+  if let Err(err) = check_active_games(tx.conn(), &user, limit).await {
+    return err;
+  }
   let id = Uuid::new_v4();
   let permission = OWNER_PERMISSION;
-  let mut claims = user.custom_claims();
-  claims.games.insert(id.to_string(), permission);
+  let mut users = p.users.unwrap_or_default();
+  users.insert(user.sub.clone(), permission);
+
+  let res = games::create(
+    tx.conn(),
+    games::CreateParams {
+      id,
+      name: &p.name,
+      images: p.images.unwrap_or_default(),
+      users: &users,
+      rules,
+      created_by: &user.sub,
+    },
+  )
+  .await;
+  let game = match res {
+    Ok(game) => game,
+    Err(err) => return handle_db_error(err),
+  };
+  let invited: Vec<String> = users
+    .keys()
+    .filter(|uid| **uid != user.sub)
+    .cloned()
+    .collect();
+  if let Err(err) = notifications::enqueue_invites(tx.conn(), id, &invited).await {
+    return handle_db_error(err);
+  }
+  let changes = audit::diff(&HashMap::new(), &users);
+  let actor = Actor {
+    uid: &user.sub,
+    client_ip: client_ip.known(),
+    source: audit::CREATE,
+  };
+  if let Err(err) = audit::record(tx.conn(), id, &actor, &changes).await {
+    return handle_db_error(err);
+  }
 
-  match claims_service
+  hint_owner(&claims_service, &permissions, &user, id).await;
+  make_created_response(format!("/games/{}", id), game)
+}
+
+// the owner can use a new game right away, the claims only hint at it
+async fn hint_owner(
+  claims_service: &UserService,
+  permissions: &Permissions,
+  user: &MyFirebaseUser,
+  game_id: Uuid,
+) {
+  permissions.forget(&user.sub);
+  let mut claims = user.custom_claims();
+  claims.games.insert(game_id.to_string(), OWNER_PERMISSION);
+  if let Err(err) = claims_service
     .set_custom_attributes(&user.sub, claims)
     .await
   {
-    Ok(()) => {
-      let mut users = p.users.unwrap_or_default();
-      users.insert(user.sub, permission);
-      let res = games::create(
-        &db,
-        games::CreateParams {
-          id,
-          name: &p.name,
-          images: p.images.unwrap_or_default(),
-          users: &users,
-        },
-      );
-      make_json_response(res.await.map(|res| GameCreated {
-        id,
-        users,
-        created_at: res.created_at,
-      }))
-    }
-    Err(err) => (
-      StatusCode::INTERNAL_SERVER_ERROR,
-      format!("Error update claims: {}", err),
-    )
-      .into_response(),
+    tracing::warn!("Failed to update claims of {}: {}", user.sub, err);
   }
 }
 
-// update a game
+/// update a game
+#[utoipa::path(
+  patch,
+  operation_id = "update_game",
+  path = "/games/{game_id}",
+  tag = "games",
+  params(("game_id" = Uuid, Path), ("If-Match" = String, Header, description = "ETag of the game, or *")),
+  request_body = UpdateData,
+  responses(
+    (status = 200, body = UpdateResult, headers(("ETag" = String))),
+    (status = 400, body = ApiError),
+    (status = 403, body = ApiError),
+    (status = 404, body = ApiError),
+    (status = 412, body = ApiError, description = "the game changed since it was read"),
+    (status = 428, body = ApiError, description = "If-Match is missing"),
+    (status = 422, body = ApiError, description = "some fields are invalid"),
+  )
+)]
 pub async fn update(
-  State(db): State<sqlx::PgPool>,
+  State(state): State<AppState>,
   user: MyFirebaseUser,
+  client_ip: ClientIp,
+  headers: HeaderMap,
   Path(game_id): Path<Uuid>,
   data: Option<Json<UpdateData>>,
 ) -> Response {
   if !user.can_edit(game_id) {
     return StatusCode::FORBIDDEN.into_response();
   }
+  let db = &state.pool;
+  let expected = match if_match(&headers) {
+    Ok(expected) => expected,
+    Err(err) => return err.into_response(),
+  };
   let data = data.unwrap_or_default().0;
+  if let Err(err) = check_fields(&data) {
+    return err.into_response();
+  }
   if let Some(users) = &data.users {
     if matches!(users.get(&user.sub), Some(p) if p.lt(&OWNER_PERMISSION)) {
       return StatusCode::BAD_REQUEST.into_response();
     }
   }
-  make_json_response(games::update(&db, game_id, data).await)
+  if let Some(rules) = &data.rules {
+    if let Err(err) = check_rules(rules) {
+      return err.into_response();
+    }
+  }
+  if !data.currency.as_deref().is_none_or(is_currency_code) {
+    return StatusCode::BAD_REQUEST.into_response();
+  }
+  if let Some(theme) = &data.theme {
+    if let Err(err) = theme.validate() {
+      return ApiError::new(
+        StatusCode::BAD_REQUEST,
+        ErrorCode::InvalidTheme,
+        err.to_string(),
+      )
+      .with_details(err)
+      .into_response();
+    }
+  }
+  let previous = match &data.users {
+    Some(_) => previous_users(db, game_id).await,
+    None => None,
+  };
+  let res = games::update(db, game_id, data, expected.as_deref(), &user.sub).await;
+  if res.is_ok() {
+    let actor = Actor {
+      uid: &user.sub,
+      client_ip: client_ip.known(),
+      source: audit::UPDATE,
+    };
+    notify_updated(
+      db,
+      &state.claims_service,
+      &state.permissions,
+      actor,
+      game_id,
+      previous,
+    )
+    .await;
+  }
+  versioned_response(res)
+}
+
+// the users of a game before a change, to invite the ones it adds
+async fn previous_users(db: &sqlx::PgPool, game_id: Uuid) -> Option<HashMap<String, i64>> {
+  games::get(db, game_id).await.ok().map(|game| game.users)
 }
 
-#[derive(Deserialize, Default, Debug)]
-pub struct PlayParams {
-  pub action: String,
+// webhooks get the whole game after a settings change, users it added get an invitation
+async fn notify_updated(
+  db: &sqlx::PgPool,
+  claims_service: &UserService,
+  permissions: &Permissions,
+  actor: Actor<'_>,
+  game_id: Uuid,
+  previous_users: Option<HashMap<String, i64>>,
+) {
+  if let Ok(game) = games::get(db, game_id).await {
+    if let Some(previous) = previous_users {
+      let users = &game.users;
+      sync_users(
+        db,
+        claims_service,
+        permissions,
+        actor,
+        game_id,
+        &previous,
+        users,
+      )
+      .await;
+    }
+    let data = serde_json::to_value(&game).unwrap_or_default();
+    webhooks::notify(db, game_id, GAME_UPDATED, data).await;
+  }
+}
+
+// the claims of users whose permission changed follow the users map,
+// removed users lose the game so they can't keep viewing or playing it
+async fn sync_users(
+  db: &sqlx::PgPool,
+  claims_service: &UserService,
+  permissions: &Permissions,
+  actor: Actor<'_>,
+  game_id: Uuid,
+  previous: &HashMap<String, i64>,
+  current: &HashMap<String, i64>,
+) {
+  let added = current
+    .keys()
+    .filter(|uid| !previous.contains_key(*uid))
+    .cloned()
+    .collect();
+  notify::invite(db, game_id, added).await;
+
+  let changes = audit::diff(previous, current);
+  if let Err(err) = audit::record(db, game_id, &actor, &changes).await {
+    tracing::error!("Failed to audit users of game {}: {}", game_id, err);
+  }
+  for change in changes {
+    let permission = change.new_permission;
+    members::sync_claims(
+      claims_service,
+      permissions,
+      &change.uid,
+      game_id,
+      permission,
+    )
+    .await;
+  }
+}
+
+// strong validator of a game version, e.g. "1703437200123456"
+fn etag(version: DateTime<Utc>) -> String {
+  format!("\"{}\"", version.timestamp_micros())
+}
+
+fn with_etag(mut res: Response, version: DateTime<Utc>) -> Response {
+  if let Ok(value) = HeaderValue::from_str(&etag(version)) {
+    res.headers_mut().insert(header::ETAG, value);
+  }
+  res
+}
+
+fn versioned_response(res: Result<UpdateResult, db::Error>) -> Response {
+  match res {
+    Ok(updated) => with_etag(make_json_response(Ok(&updated)), updated.updated_at),
+    Err(err) => handle_db_error(err),
+  }
+}
+
+// game versions named in If-Match, None for `*`, writes without it would overwrite co-hosts
+fn if_match(headers: &HeaderMap) -> Result<Option<Vec<DateTime<Utc>>>, ApiError> {
+  let Some(value) = headers.get(header::IF_MATCH) else {
+    return Err(ApiError::new(
+      StatusCode::PRECONDITION_REQUIRED,
+      ErrorCode::IfMatchRequired,
+      "Send the ETag of the game in the If-Match header",
+    ));
+  };
+  let value = value.to_str().unwrap_or_default().trim();
+  if value == "*" {
+    return Ok(None);
+  }
+  // weak and unknown tags never match, so they end up rejected as stale
+  let versions = value
+    .split(',')
+    .filter_map(|tag| {
+      tag
+        .trim()
+        .strip_prefix('"')?
+        .strip_suffix('"')?
+        .parse()
+        .ok()
+    })
+    .filter_map(DateTime::from_timestamp_micros)
+    .collect();
+  Ok(Some(versions))
+}
+
+// a user can only own so many unfinished games at once, 0 means no limit
+async fn check_active_games(
+  conn: &mut sqlx::PgConnection,
+  user: &MyFirebaseUser,
+  limit: i64,
+) -> Result<(), Response> {
+  if limit == 0 {
+    return Ok(());
+  }
+  let active = games::count_active_owned(conn, &user.sub)
+    .await
+    .map_err(handle_db_error)?;
+  if active < limit {
+    return Ok(());
+  }
+  let err = ApiError::new(
+    StatusCode::FORBIDDEN,
+    ErrorCode::ActiveGameLimit,
+    format!(
+      "You already own {} unfinished games, finish or delete one first",
+      limit
+    ),
+  )
+  .with_details(json!({ "limit": limit }));
+  Err(err.into_response())
+}
+
+fn check_rules(rules: &GameRules) -> Result<(), ApiError> {
+  rules.validate().map_err(|err| {
+    ApiError::new(
+      StatusCode::BAD_REQUEST,
+      ErrorCode::InvalidRules,
+      err.to_string(),
+    )
+    .with_details(err)
+  })
+}
+
+#[derive(Deserialize, Default, ToSchema)]
+pub struct PresentData {
+  pub present_id: i64,
+  // optional compare-and-set
+  pub expected_player_id: Option<i64>,
+  pub expected_event_seq: Option<i64>,
+}
+
+impl PresentData {
+  pub fn expected(&self) -> Expected {
+    Expected {
+      player_id: self.expected_player_id,
+      event_seq: self.expected_event_seq,
+    }
+  }
 }
 
-#[derive(Deserialize, Default)]
-pub struct PlayData {
+#[derive(Deserialize, Default, ToSchema)]
+pub struct AssignData {
   pub present_id: i64,
+  pub player_id: i64,
+  pub reason: Option<String>,
 }
 
-// update a game
-pub async fn play(
+/// start a game
+#[utoipa::path(
+  post,
+  operation_id = "start",
+  path = "/play/{game_id}/start",
+  tag = "play",
+  params(("game_id" = Uuid, Path)),
+  responses(
+    (status = 200, body = GameStateUpdateResult),
+    (status = 403, body = ApiError),
+    (status = 409, body = ApiError, description = "the game moved on or the rules forbid the action"),
+  )
+)]
+pub async fn start(
+  State(db): State<sqlx::PgPool>,
+  State(quotas): State<Quotas>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+) -> Response {
+  if !user.can_edit(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  if let Err(wait) = quotas.throttle_play(game_id) {
+    return quota::play_throttled(wait).into_response();
+  }
+  make_json_response(games::start(&db, game_id, &user.sub).await)
+}
+
+/// reset a game to before it started
+#[utoipa::path(
+  post,
+  operation_id = "reset",
+  path = "/play/{game_id}/reset",
+  tag = "play",
+  params(("game_id" = Uuid, Path)),
+  responses(
+    (status = 200, body = GameStateUpdateResult),
+    (status = 403, body = ApiError),
+  )
+)]
+pub async fn reset(
+  State(db): State<sqlx::PgPool>,
+  State(quotas): State<Quotas>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+) -> Response {
+  if !user.can_edit(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  if let Err(wait) = quotas.throttle_play(game_id) {
+    return quota::play_throttled(wait).into_response();
+  }
+  make_json_response(games::reset(&db, game_id, &user.sub).await)
+}
+
+/// take back the latest play action
+#[utoipa::path(
+  post,
+  operation_id = "undo",
+  path = "/play/{game_id}/undo",
+  tag = "play",
+  params(("game_id" = Uuid, Path)),
+  responses(
+    (status = 200, body = GameStateUpdateResult),
+    (status = 403, body = ApiError),
+    (status = 409, body = ApiError, description = "the game moved on or the rules forbid the action"),
+  )
+)]
+pub async fn undo(
   State(db): State<sqlx::PgPool>,
+  State(quotas): State<Quotas>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+) -> Response {
+  if !user.can_edit(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  if let Err(wait) = quotas.throttle_play(game_id) {
+    return quota::play_throttled(wait).into_response();
+  }
+  let result = games::undo(&db, game_id, &user.sub).await;
+  turn_timer::schedule_roll(&db, game_id, &result);
+  make_json_response(result)
+}
+
+/// roll the dice for the next player
+#[utoipa::path(
+  post,
+  operation_id = "roll",
+  path = "/play/{game_id}/roll",
+  tag = "play",
+  params(("game_id" = Uuid, Path)),
+  responses(
+    (status = 200, body = GameStateUpdateResult),
+    (status = 403, body = ApiError),
+    (status = 409, body = ApiError, description = "the game moved on or the rules forbid the action"),
+  )
+)]
+pub async fn roll(
+  State(db): State<sqlx::PgPool>,
+  State(quotas): State<Quotas>,
   user: MyFirebaseUser,
   Path(game_id): Path<Uuid>,
-  Query(q): Query<PlayParams>,
-  data: Option<Json<PlayData>>,
 ) -> Response {
   if !user.can_play(game_id) {
     return StatusCode::FORBIDDEN.into_response();
   }
-  match q.action.as_str() {
-    "start" => games::start(&db, game_id)
-      .await
-      .map_err(handle_db_error)
-      .into_response(),
-    "reset" => games::reset(&db, game_id)
-      .await
-      .map_err(handle_db_error)
-      .into_response(),
-    "roll" => games::roll(&db, game_id)
-      .await
-      .map_err(handle_db_error)
-      .into_response(),
-    "pick" => match data {
-      Some(data) => games::pick(&db, game_id, data.present_id)
-        .await
-        .map_err(handle_db_error)
-        .into_response(),
-      None => StatusCode::BAD_REQUEST.into_response(),
-    },
-    "keep" => games::keep(&db, game_id)
-      .await
-      .map_err(handle_db_error)
-      .into_response(),
-    "steal" => match data {
-      Some(data) => games::steal(&db, game_id, data.present_id)
-        .await
-        .map_err(handle_db_error)
-        .into_response(),
-      None => StatusCode::BAD_REQUEST.into_response(),
-    },
-    _ => StatusCode::BAD_REQUEST.into_response(),
+  if let Err(wait) = quotas.throttle_play(game_id) {
+    return quota::play_throttled(wait).into_response();
   }
+  let result = games::roll(&db, game_id, &user.sub).await;
+  turn_timer::schedule_roll(&db, game_id, &result);
+  make_json_response(result)
 }
 
-// replace a game
-pub async fn replace(
+/// unwrap a present from the pile
+#[utoipa::path(
+  post,
+  operation_id = "pick",
+  path = "/play/{game_id}/pick",
+  tag = "play",
+  params(("game_id" = Uuid, Path)),
+  request_body = PresentData,
+  responses(
+    (status = 200, body = GameStateUpdateResult),
+    (status = 403, body = ApiError),
+    (status = 409, body = ApiError, description = "the game moved on or the rules forbid the action"),
+  )
+)]
+pub async fn pick(
   State(db): State<sqlx::PgPool>,
+  State(quotas): State<Quotas>,
   user: MyFirebaseUser,
   Path(game_id): Path<Uuid>,
+  Json(data): Json<PresentData>,
+) -> Response {
+  if !user.can_play(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  if let Err(wait) = quotas.throttle_play(game_id) {
+    return quota::play_throttled(wait).into_response();
+  }
+  make_json_response(games::pick(&db, game_id, data.present_id, data.expected(), &user.sub).await)
+}
+
+/// keep the unwrapped present
+#[utoipa::path(
+  post,
+  operation_id = "keep",
+  path = "/play/{game_id}/keep",
+  tag = "play",
+  params(("game_id" = Uuid, Path)),
+  responses(
+    (status = 200, body = GameStateUpdateResult),
+    (status = 403, body = ApiError),
+    (status = 409, body = ApiError, description = "the game moved on or the rules forbid the action"),
+  )
+)]
+pub async fn keep(
+  State(db): State<sqlx::PgPool>,
+  State(quotas): State<Quotas>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+) -> Response {
+  if !user.can_play(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  if let Err(wait) = quotas.throttle_play(game_id) {
+    return quota::play_throttled(wait).into_response();
+  }
+  make_json_response(games::keep(&db, game_id, &user.sub).await)
+}
+
+/// swap the unwrapped present for another player's
+#[utoipa::path(
+  post,
+  operation_id = "steal",
+  path = "/play/{game_id}/steal",
+  tag = "play",
+  params(("game_id" = Uuid, Path)),
+  request_body = PresentData,
+  responses(
+    (status = 200, body = GameStateUpdateResult),
+    (status = 403, body = ApiError),
+    (status = 409, body = ApiError, description = "the game moved on or the rules forbid the action"),
+  )
+)]
+pub async fn steal(
+  State(db): State<sqlx::PgPool>,
+  State(quotas): State<Quotas>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+  Json(data): Json<PresentData>,
+) -> Response {
+  if !user.can_play(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  if let Err(wait) = quotas.throttle_play(game_id) {
+    return quota::play_throttled(wait).into_response();
+  }
+  make_json_response(games::steal(&db, game_id, data.present_id, data.expected(), &user.sub).await)
+}
+
+/// hand a present to a player outside of normal play, owners only
+#[utoipa::path(
+  post,
+  operation_id = "assign",
+  path = "/play/{game_id}/assign",
+  tag = "play",
+  params(("game_id" = Uuid, Path)),
+  request_body = AssignData,
+  responses(
+    (status = 200, body = GameStateUpdateResult),
+    (status = 400, body = ApiError),
+    (status = 403, body = ApiError),
+    (status = 409, body = ApiError, description = "the game moved on or the rules forbid the action"),
+  )
+)]
+pub async fn assign(
+  State(db): State<sqlx::PgPool>,
+  State(quotas): State<Quotas>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+  Json(data): Json<AssignData>,
+) -> Response {
+  if !user.can_edit(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  if let Err(wait) = quotas.throttle_play(game_id) {
+    return quota::play_throttled(wait).into_response();
+  }
+  let reason = data.reason.unwrap_or_default();
+  if reason.trim().is_empty() {
+    return ApiError::new(
+      StatusCode::BAD_REQUEST,
+      ErrorCode::ReasonRequired,
+      "Assigning a present requires a reason",
+    )
+    .into_response();
+  }
+  make_json_response(
+    games::assign(
+      &db,
+      game_id,
+      data.present_id,
+      data.player_id,
+      reason.trim(),
+      &user.sub,
+    )
+    .await,
+  )
+}
+
+/// hand many presents to players, ?mode=partial keeps going past failures
+#[utoipa::path(
+  patch,
+  operation_id = "assign_presents",
+  path = "/games/{game_id}/presents/batch",
+  tag = "play",
+  params(("game_id" = Uuid, Path), BulkParams),
+  request_body = Vec<AssignData>,
+  responses(
+    (status = 200, body = BulkResult),
+    (status = 400, body = ApiError),
+    (status = 403, body = ApiError),
+    (status = 409, body = ApiError, description = "the game moved on or the rules forbid the action"),
+  )
+)]
+pub async fn assign_many(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+  Query(q): Query<BulkParams>,
+  Json(items): Json<Vec<AssignData>>,
+) -> Response {
+  if !user.can_edit(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  let mut assignments = Vec::with_capacity(items.len());
+  for item in items {
+    let reason = item.reason.unwrap_or_default();
+    if reason.trim().is_empty() {
+      return ApiError::new(
+        StatusCode::BAD_REQUEST,
+        ErrorCode::ReasonRequired,
+        "Assigning a present requires a reason",
+      )
+      .into_response();
+    }
+    assignments.push(Assignment {
+      present_id: item.present_id,
+      player_id: item.player_id,
+      reason: reason.trim().to_string(),
+    });
+  }
+  make_json_response(games::assign_many(&db, game_id, assignments, q.mode, &user.sub).await)
+}
+
+/// ask the current player to hurry up
+#[utoipa::path(
+  post,
+  operation_id = "nudge",
+  path = "/games/{game_id}/nudge",
+  tag = "play",
+  params(("game_id" = Uuid, Path)),
+  responses(
+    (status = 200, body = NudgeResult),
+    (status = 403, body = ApiError),
+    (status = 409, body = ApiError, description = "the game moved on or the rules forbid the action"),
+  )
+)]
+pub async fn nudge(
+  State(state): State<AppState>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+) -> Response {
+  if !user.can_play(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  make_json_response(
+    games::nudge(
+      &state.pool,
+      game_id,
+      state.config.nudge_idle_seconds,
+      &user.sub,
+    )
+    .await,
+  )
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StateParams {
+  // id of the last event to replay, the current state when omitted
+  pub at_event: Option<i64>,
+}
+
+/// ownership of the presents as it was right after an event, for replays
+#[utoipa::path(
+  get,
+  operation_id = "get_game_state",
+  path = "/games/{game_id}/state",
+  tag = "games",
+  params(("game_id" = Uuid, Path), StateParams),
+  responses(
+    (status = 200, body = ReplayState),
+    (status = 403, body = ApiError),
+    (status = 404, body = ApiError),
+  )
+)]
+pub async fn state(
+  State(repo): State<Arc<dyn GamesRepo>>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+  Query(p): Query<StateParams>,
+) -> Response {
+  if !user.can_view(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  make_json_response(repo.state_at(game_id, p.at_event).await)
+}
+
+/// steal statistics of a game so far
+#[utoipa::path(
+  get,
+  operation_id = "get_game_stats",
+  path = "/games/{game_id}/stats",
+  tag = "games",
+  params(("game_id" = Uuid, Path)),
+  responses(
+    (status = 200, body = GameStats),
+    (status = 403, body = ApiError),
+  )
+)]
+pub async fn stats(
+  State(repo): State<Arc<dyn GamesRepo>>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+) -> Response {
+  if !user.can_view(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  make_json_response(repo.stats(game_id).await)
+}
+
+/// replace a game
+#[utoipa::path(
+  put,
+  operation_id = "replace_game",
+  path = "/games/{game_id}",
+  tag = "games",
+  params(("game_id" = Uuid, Path), ("If-Match" = String, Header, description = "ETag of the game, or *")),
+  request_body = ReplaceParams,
+  responses(
+    (status = 200, body = UpdateResult, headers(("ETag" = String))),
+    (status = 400, body = ApiError),
+    (status = 403, body = ApiError),
+    (status = 404, body = ApiError),
+    (status = 412, body = ApiError, description = "the game changed since it was read"),
+    (status = 428, body = ApiError, description = "If-Match is missing"),
+    (status = 422, body = ApiError, description = "some fields are invalid"),
+  )
+)]
+pub async fn replace(
+  State(state): State<AppState>,
+  user: MyFirebaseUser,
+  client_ip: ClientIp,
+  headers: HeaderMap,
+  Path(game_id): Path<Uuid>,
   Json(p): Json<ReplaceParams>,
 ) -> Response {
   if !user.can_edit(game_id) {
     return StatusCode::FORBIDDEN.into_response();
   }
-  make_json_response(games::replace(&db, game_id, p).await)
+  let db = &state.pool;
+  let expected = match if_match(&headers) {
+    Ok(expected) => expected,
+    Err(err) => return err.into_response(),
+  };
+  if let Err(err) = check_fields(&p) {
+    return err.into_response();
+  }
+  if let Some(rules) = &p.rules {
+    if let Err(err) = check_rules(rules) {
+      return err.into_response();
+    }
+  }
+  if !p.currency.as_deref().is_none_or(is_currency_code) {
+    return StatusCode::BAD_REQUEST.into_response();
+  }
+  let previous = previous_users(db, game_id).await;
+  let res = games::replace(db, game_id, p, expected.as_deref(), &user.sub).await;
+  if res.is_ok() {
+    let actor = Actor {
+      uid: &user.sub,
+      client_ip: client_ip.known(),
+      source: audit::REPLACE,
+    };
+    notify_updated(
+      db,
+      &state.claims_service,
+      &state.permissions,
+      actor,
+      game_id,
+      previous,
+    )
+    .await;
+  }
+  versioned_response(res)
 }
 
-// delete a game
+#[derive(Deserialize, Default, ToSchema)]
+pub struct ConfirmData {
+  // the exact name of the game
+  pub confirm: Option<String>,
+}
+
+// destructive operations must repeat the game name so a stray request can't wipe a party
+pub fn check_confirmation(name: &str, confirm: Option<&str>) -> Result<(), ApiError> {
+  match confirm {
+    None => Err(ApiError::new(
+      StatusCode::PRECONDITION_REQUIRED,
+      ErrorCode::ConfirmationRequired,
+      "Repeat the game name in the confirm field to continue",
+    )),
+    Some(confirm) if confirm != name => Err(ApiError::new(
+      StatusCode::UNPROCESSABLE_ENTITY,
+      ErrorCode::ConfirmationMismatch,
+      "The confirm field does not match the game name",
+    )),
+    Some(_) => Ok(()),
+  }
+}
+
+/// delete a game
+#[utoipa::path(
+  delete,
+  operation_id = "delete_game",
+  path = "/games/{game_id}",
+  tag = "games",
+  params(("game_id" = Uuid, Path)),
+  request_body = Option<ConfirmData>,
+  responses(
+    (status = 202),
+    (status = 403, body = ApiError),
+    (status = 404, body = ApiError),
+    (status = 422, body = ApiError),
+    (status = 428, body = ApiError),
+  )
+)]
 pub async fn delete(
   State(db): State<sqlx::PgPool>,
   user: MyFirebaseUser,
   Path(game_id): Path<Uuid>,
+  data: Option<Json<ConfirmData>>,
 ) -> Result<StatusCode, Response> {
   if !user.can_edit(game_id) {
     return Err(StatusCode::FORBIDDEN.into_response());
   }
+  let game = games::get(&db, game_id).await.map_err(handle_db_error)?;
+  let data = data.unwrap_or_default().0;
+  check_confirmation(&game.name, data.confirm.as_deref()).map_err(IntoResponse::into_response)?;
   games::delete(&db, game_id).await.map_err(handle_db_error)?;
+  webhooks::notify(&db, game_id, GAME_DELETED, json!({ "id": game_id })).await;
   Ok(StatusCode::ACCEPTED)
 }
 
-// accept view permission for the current user
-pub async fn accept_invitation(
+/// export a game with its players, presents and play events
+#[utoipa::path(
+  get,
+  operation_id = "export_game",
+  path = "/games/{game_id}/export",
+  tag = "games",
+  params(("game_id" = Uuid, Path)),
+  responses(
+    (status = 200, body = GameExport, headers(("Content-Disposition" = String))),
+    (status = 403, body = ApiError),
+    (status = 404, body = ApiError),
+  )
+)]
+pub async fn export(
   State(db): State<sqlx::PgPool>,
   user: MyFirebaseUser,
-  State(mut claims_service): State<UserService>,
   Path(game_id): Path<Uuid>,
-) -> Result<StatusCode, Response> {
-  let game = crate::db::games::get(&db, game_id)
-    .await
-    .map_err(handle_db_error)?;
+) -> Response {
+  if !user.can_view(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  match export::game(&db, game_id).await {
+    Ok(data) => {
+      let filename = format!(
+        "attachment; filename=\"evil-santa-game-{}-{}.json\"",
+        game_id,
+        data.exported_at.format("%Y-%m-%d")
+      );
+      ([(header::CONTENT_DISPOSITION, filename)], Json(data)).into_response()
+    }
+    Err(err) => handle_db_error(err),
+  }
+}
+
+/// the scheduled game as an iCalendar event, to send invites from a calendar app
+#[utoipa::path(
+  get,
+  operation_id = "game_calendar",
+  path = "/games/{game_id}/calendar.ics",
+  tag = "games",
+  params(("game_id" = Uuid, Path)),
+  responses(
+    (status = 200, content_type = "text/calendar", body = String),
+    (status = 403, body = ApiError),
+    (status = 404, body = ApiError, description = "the game is missing or not scheduled"),
+  )
+)]
+pub async fn calendar(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  locale: Locale,
+  Path(game_id): Path<Uuid>,
+) -> Response {
+  if !user.can_view(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  let game = match games::get(&db, game_id).await {
+    Ok(game) => game.localize(locale),
+    Err(err) => return handle_db_error(err),
+  };
+  let Some(start) = game.scheduled_at else {
+    return ApiError::new(
+      StatusCode::NOT_FOUND,
+      ErrorCode::GameNotScheduled,
+      "The game has no scheduled_at yet",
+    )
+    .into_response();
+  };
+  ics::response(
+    &format!("evil-santa-{}.ics", game_id),
+    ics::calendar(&game, start),
+  )
+}
 
-  let game_id_string = game_id.to_string();
-  if game.users.get(&user.sub).is_some() && user.games.get(&game_id_string).is_none() {
-    let mut new_games = user.games.clone();
-    new_games.insert(game_id_string, VIEW_PERMISSION);
-    match claims_service
-      .set_custom_attributes(&user.sub, CustomClaims { games: new_games })
-      .await
-    {
-      Ok(()) => Ok(StatusCode::OK),
-      Err(err) => Err((StatusCode::BAD_GATEWAY, err.to_string()).into_response()),
+/// import a game from an export as a new game
+#[utoipa::path(
+  post,
+  operation_id = "import_game",
+  path = "/games/import",
+  tag = "games",
+  request_body = ImportData,
+  responses(
+    (status = 201, body = ImportResult, headers(("Location" = String))),
+    (status = 400, body = ApiError),
+    (status = 403, body = ApiError, description = "the user owns too many unfinished games"),
+    (status = 422, body = ApiError, description = "some fields are invalid"),
+  )
+)]
+pub async fn import(
+  mut tx: Tx,
+  user: MyFirebaseUser,
+  client_ip: ClientIp,
+  State(claims_service): State<UserService>,
+  State(permissions): State<Permissions>,
+  State(state): State<AppState>,
+  Json(data): Json<ImportData>,
+) -> Response {
+  if user.is_api_key() {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  if let Err(err) = check_fields(&data) {
+    return err.into_response();
+  }
+  if let Err(err) = check_rules(&data.game.rules) {
+    return err.into_response();
+  }
+  if let Err(err) = data.game.theme.validate() {
+    return ApiError::new(
+      StatusCode::BAD_REQUEST,
+      ErrorCode::InvalidTheme,
+      err.to_string(),
+    )
+    .with_details(err)
+    .into_response();
+  }
+  let currencies = std::iter::once(&data.game.currency)
+    .chain(data.presents.iter().map(|p| &p.currency))
+    .all(|currency| currency.as_deref().is_none_or(is_currency_code));
+  if !currencies {
+    return StatusCode::BAD_REQUEST.into_response();
+  }
+
+  let limit = state.config.max_active_games;
+  if let Err(err) = check_active_games(tx.conn(), &user, limit).await {
+    return err;
+  }
+  let id = Uuid::new_v4();
+  let users = HashMap::from([(user.sub.clone(), OWNER_PERMISSION)]);
+  // like create, the game and its audit entries are committed together
+  let imported = match import::import(tx.conn(), id, &users, &user.sub, data).await {
+    Ok(imported) => imported,
+    Err(err) => return handle_db_error(err),
+  };
+  let changes = audit::diff(&HashMap::new(), &users);
+  let actor = Actor {
+    uid: &user.sub,
+    client_ip: client_ip.known(),
+    source: audit::IMPORT,
+  };
+  if let Err(err) = audit::record(tx.conn(), id, &actor, &changes).await {
+    return handle_db_error(err);
+  }
+
+  hint_owner(&claims_service, &permissions, &user, id).await;
+  make_created_response(format!("/games/{}", id), imported)
+}
+
+/// restore a deleted game
+#[utoipa::path(
+  post,
+  operation_id = "restore_game",
+  path = "/games/{game_id}/restore",
+  tag = "games",
+  params(("game_id" = Uuid, Path)),
+  responses(
+    (status = 200, body = Game),
+    (status = 403, body = ApiError),
+    (status = 404, body = ApiError, description = "the game isn't deleted, was already purged or isn't owned by the user"),
+  )
+)]
+pub async fn restore(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  locale: Locale,
+  Path(game_id): Path<Uuid>,
+) -> Response {
+  match games::restore(&db, game_id, &user.sub).await {
+    Ok(game) => {
+      let data = serde_json::to_value(&game).unwrap_or_default();
+      webhooks::notify(&db, game_id, GAME_RESTORED, data).await;
+      let version = game.version();
+      with_etag(make_json_response(Ok(game.localize(locale))), version)
     }
-  } else {
-    Ok(StatusCode::OK)
+    Err(err) => handle_db_error(err),
   }
 }
 
-// list games
+// deleted games can be restored for 30 days, then they are gone for good
+pub fn purge_deleted_hourly(db: sqlx::PgPool) {
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+    loop {
+      interval.tick().await;
+      match games::purge_deleted(&db).await {
+        Ok(0) => {}
+        Ok(purged) => tracing::info!("Purged {} deleted games", purged),
+        Err(err) => tracing::warn!("Failed to purge deleted games: {}", err),
+      }
+    }
+  });
+}
+
+/// list the play events of a game
+#[utoipa::path(
+  get,
+  operation_id = "list_events",
+  path = "/games/{game_id}/events",
+  tag = "games",
+  params(("game_id" = Uuid, Path), ListParams),
+  responses(
+    (
+      status = 200,
+      content(
+        (Vec<PlayEvent> = "application/json"),
+        (PlayEvent = "application/x-ndjson"),
+        (String = "text/csv"),
+      ),
+      headers(
+        ("X-Total-Count" = i64, description = "size of the whole list"),
+        ("X-Offset" = i64),
+        ("X-Limit" = i64),
+        ("X-Next-Cursor" = i64, description = "after_id of the next page"),
+        ("X-Has-More" = bool),
+      ),
+    ),
+    (status = 400, body = ApiError),
+    (status = 403, body = ApiError),
+  )
+)]
 pub async fn list_events(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  headers: HeaderMap,
+  Path(game_id): Path<Uuid>,
+  Query(p): Query<ListParams>,
+) -> Response {
+  if !user.can_view(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  if ndjson::accepted(&headers) {
+    return ndjson::response(games::stream_events(db, game_id, p));
+  }
+  if csv::accepted(&headers) {
+    return events_csv(db, game_id, p).await;
+  }
+  make_page_response(games::list_events(&db, game_id, p).await)
+}
+
+// events returned by one poll, the client polls again right away when there are more
+const POLL_LIMIT: i64 = 100;
+const DEFAULT_POLL_SECONDS: u64 = 25;
+const MAX_POLL_SECONDS: u64 = 30;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PollParams {
+  // id of the last event the client has, all events when omitted
+  pub after_id: Option<i64>,
+  // seconds to wait for a new event, at most 30
+  pub timeout: Option<u64>,
+}
+
+/// wait for play events after after_id, for networks that buffer server-sent events
+#[utoipa::path(
+  get,
+  operation_id = "poll_events",
+  path = "/games/{game_id}/events/poll",
+  tag = "games",
+  params(("game_id" = Uuid, Path), PollParams),
+  responses(
+    (status = 200, body = Vec<PlayEvent>, description = "empty when nothing happened before the timeout", headers(
+      ("X-Next-Cursor" = i64, description = "after_id of the next page"),
+      ("X-Has-More" = bool),
+    )),
+    (status = 403, body = ApiError),
+    (status = 429, body = ApiError),
+  )
+)]
+pub async fn poll_events(
+  State(repo): State<Arc<dyn GamesRepo>>,
+  State(play_stream): State<PlayStream>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+  Query(p): Query<PollParams>,
+) -> Response {
+  if !user.can_view(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  let params = || ListParams {
+    after_id: Some(p.after_id.unwrap_or(0)),
+    limit: Some(POLL_LIMIT),
+    ..Default::default()
+  };
+  // subscribe before reading, so an event can't slip in between
+  let mut rx = play_stream.subscribe(game_id);
+  let page = match repo.list_events(game_id, params()).await {
+    Ok(page) => page,
+    Err(err) => return handle_db_error(err),
+  };
+  if !page.items.is_empty() {
+    return make_page_response(Ok(page));
+  }
+  let timeout = Duration::from_secs(
+    p.timeout
+      .unwrap_or(DEFAULT_POLL_SECONDS)
+      .min(MAX_POLL_SECONDS),
+  );
+  if tokio::time::timeout(timeout, rx.recv()).await.is_err() {
+    return make_page_response(Ok(page));
+  }
+  // read the events back, the one that woke us up may not be the only one
+  make_page_response(repo.list_events(game_id, params()).await)
+}
+
+/// download the play events of a game as csv
+#[utoipa::path(
+  get,
+  operation_id = "export_events_csv",
+  path = "/games/{game_id}/events.csv",
+  tag = "games",
+  params(("game_id" = Uuid, Path), ListParams),
+  responses(
+    (status = 200, content_type = "text/csv", body = String),
+    (status = 400, body = ApiError),
+    (status = 403, body = ApiError),
+  )
+)]
+pub async fn events_csv_download(
   State(db): State<sqlx::PgPool>,
   user: MyFirebaseUser,
   Path(game_id): Path<Uuid>,
@@ -243,24 +1349,189 @@ pub async fn list_events(
   if !user.can_view(game_id) {
     return StatusCode::FORBIDDEN.into_response();
   }
-  make_json_response(games::list_events(&db, game_id, p).await)
+  events_csv(db, game_id, p).await
+}
+
+const EVENT_CSV_COLUMNS: [&str; 9] = [
+  "seq",
+  "time",
+  "kind",
+  "player",
+  "present",
+  "other_player",
+  "other_present",
+  "reason",
+  "undone_seq",
+];
+
+async fn events_csv(db: sqlx::PgPool, game_id: Uuid, p: ListParams) -> Response {
+  let names = match EventNames::load(&db, game_id).await {
+    Ok(names) => names,
+    Err(err) => return handle_db_error(err),
+  };
+  let records = games::stream_events(db, game_id, p)
+    .map(move |event| event.map(|event| csv_record(&names, &event)));
+  csv::response(
+    &format!("evil-santa-events-{}.csv", game_id),
+    &EVENT_CSV_COLUMNS,
+    records,
+  )
 }
 
+// one line per event, in the order of EVENT_CSV_COLUMNS
+fn csv_record(names: &EventNames, event: &PlayEvent) -> Vec<String> {
+  let kind = serde_json::to_value(&event.event)
+    .ok()
+    .and_then(|value| value["kind"].as_str().map(str::to_string))
+    .unwrap_or_default();
+  let (player, present, other_player, other_present, reason, undone_seq) = match &event.event {
+    GameEvent::Rolled { player } | GameEvent::Nudged { player } => {
+      (Some(player), None, None, None, None, None)
+    }
+    GameEvent::Picked { player, present }
+    | GameEvent::Kept { player, present }
+    | GameEvent::FinalSwap { player, present } => {
+      (Some(player), Some(present), None, None, None, None)
+    }
+    GameEvent::Stolen {
+      player,
+      present,
+      victim,
+      swapped,
+    } => (
+      Some(player),
+      Some(present),
+      Some(victim),
+      swapped.as_ref(),
+      None,
+      None,
+    ),
+    GameEvent::Assigned {
+      player,
+      present,
+      from_player,
+      reason,
+    } => (
+      Some(player),
+      Some(present),
+      from_player.as_ref(),
+      None,
+      reason.clone(),
+      None,
+    ),
+    GameEvent::Undone { undone_seq } => (None, None, None, None, None, Some(*undone_seq)),
+    GameEvent::Started | GameEvent::Finished | GameEvent::Reset => {
+      (None, None, None, None, None, None)
+    }
+  };
+  vec![
+    event.seq.to_string(),
+    event.created_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+    kind,
+    player.map(|p| names.player(p)).unwrap_or_default(),
+    present.map(|p| names.present(p)).unwrap_or_default(),
+    other_player.map(|p| names.player(p)).unwrap_or_default(),
+    other_present.map(|p| names.present(p)).unwrap_or_default(),
+    reason.unwrap_or_default(),
+    undone_seq.map(|seq| seq.to_string()).unwrap_or_default(),
+  ]
+}
+
+/// server-sent play events as they happen, after a "snapshot" event with the current state, with "activity", "change", "presence" and "resync" events and a heartbeat every second
+#[utoipa::path(
+  get,
+  operation_id = "stream_events",
+  path = "/games/{game_id}/stream",
+  tag = "games",
+  params(("game_id" = Uuid, Path)),
+  responses(
+    (status = 200, body = PlayEvent, content_type = "text/event-stream"),
+    (status = 403, body = ApiError),
+    (status = 404, body = ApiError),
+  )
+)]
 pub async fn events(
+  State(repo): State<Arc<dyn GamesRepo>>,
   State(play_stream): State<PlayStream>,
-) -> Sse<impl Stream<Item = Result<Event, anyhow::Error>>> {
-  let rx = play_stream.subscribe();
+  State(activity): State<ActivityStream>,
+  State(changes): State<ChangeStream>,
+  State(presence): State<Presence>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+) -> Response {
+  if !user.can_view(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  // subscribe before reading the snapshot, so no event falls in between
+  let rx = play_stream.subscribe(game_id);
+  let snapshot = match repo.snapshot(game_id).await {
+    Ok(snapshot) => snapshot,
+    Err(err) => return handle_db_error(err),
+  };
+  let seq = snapshot.event_seq;
+  let snapshot = serde_json::to_string(&snapshot)
+    .map(|data| Event::default().event("snapshot").data(data))
+    .map_err(anyhow::Error::from);
+  // share-link spectators count against the viewer limit, members don't
+  let viewer = if user.is_share_token() {
+    match share::join(&presence, game_id).await {
+      Ok(viewer) => viewer,
+      Err(err) => return err,
+    }
+  } else {
+    match presence.connect(game_id, Kind::Stream).await {
+      Ok(viewer) => viewer,
+      Err(err) => return handle_db_error(err),
+    }
+  };
+  let closed = viewer.closed();
+  let spectator = user.is_share_token();
 
   let receiver = BroadcastStream::new(rx);
-  let stream = receiver.map(|message| {
-    let message = message?;
-    let data = serde_json::to_string(&message)?;
-    Ok(Event::default().data(data))
+  let stream = receiver.filter_map(move |message| {
+    // the subscriber stays connected for as long as the stream is alive
+    let _ = &viewer;
+    let event = match message {
+      // already part of the snapshot
+      Ok(message) if message.seq <= seq => None,
+      message if spectator => Some(play_event(game_id, message.map(share::redact_event))),
+      message => Some(play_event(game_id, message)),
+    };
+    async move { event }
   });
+  let stream = stream::once(async { snapshot }).chain(stream);
 
-  Sse::new(stream).keep_alive(
-    axum::response::sse::KeepAlive::new()
-      .interval(Duration::from_secs(1))
-      .text("It's good to be alive!"),
-  )
+  let stream = stream::select(stream, activity.subscribe(game_id));
+  let stream = stream::select(stream, changes.subscribe(game_id));
+  let stream = stream::select(stream, presence.events(game_id));
+  Sse::new(stream::select(stream, heartbeat()).take_until(closed)).into_response()
+}
+
+// a play event off the broadcast channel, or a "resync" event when the subscriber fell behind
+// and should refetch the state, e.g. {"skipped": 3}
+pub fn play_event(
+  game_id: Uuid,
+  message: Result<PlayEvent, BroadcastStreamRecvError>,
+) -> Result<Event, anyhow::Error> {
+  match message {
+    Ok(message) => Ok(Event::default().data(serde_json::to_string(&message)?)),
+    Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+      tracing::warn!("Stream of game {} skipped {} events", game_id, skipped);
+      Ok(
+        Event::default()
+          .event("resync")
+          .data(json!({ "skipped": skipped }).to_string()),
+      )
+    }
+  }
+}
+
+// keep-alive comments carrying the server time, e.g. ": server_time=2024-12-24T18:00:00Z"
+pub fn heartbeat() -> impl Stream<Item = Result<Event, anyhow::Error>> {
+  IntervalStream::new(tokio::time::interval(Duration::from_secs(1))).map(|_| {
+    Ok(Event::default().comment(format!(
+      "server_time={}",
+      Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+    )))
+  })
 }
@@ -0,0 +1,361 @@
+use std::str::FromStr;
+
+use async_graphql::{
+  http::{WebSocket as GraphqlWebSocket, WebSocketProtocols, WsMessage, ALL_WEBSOCKET_PROTOCOLS},
+  ComplexObject, Context, Data, ErrorExtensions, Json, Object, Schema, Subscription,
+};
+use axum::{
+  extract::{
+    ws::{CloseFrame, Message, WebSocket},
+    State, WebSocketUpgrade,
+  },
+  http::{header, HeaderMap, StatusCode},
+  response::{IntoResponse, Response},
+};
+use futures_util::{Stream, StreamExt};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{BroadcastStream, UnboundedReceiverStream};
+use uuid::Uuid;
+
+use crate::{
+  auth::MyFirebaseUser,
+  db::{
+    events::PlayEvent,
+    games::{Game, GameStateUpdateResult},
+    players::Player,
+    presents::Present,
+    ListParams,
+  },
+  error_code::ErrorCode,
+  i18n::Locale,
+  rules::GameRules,
+  theme::GameTheme,
+};
+
+use super::{
+  db_api_error,
+  games::PresentData,
+  presence::Kind,
+  presents::redact,
+  ws::{self, Action},
+  ApiError, AppState,
+};
+
+// nested selections are shallow, game > players is as deep as it gets
+const MAX_DEPTH: usize = 8;
+
+pub type GraphqlSchema = Schema<Query, Mutation, Subscription>;
+
+// built once, the app state, user and locale are added to every request
+pub fn schema() -> GraphqlSchema {
+  Schema::build(Query, Mutation, Subscription)
+    .limit_depth(MAX_DEPTH)
+    .finish()
+}
+
+// errors carry the same code as the rest api, in their extensions
+fn error(err: ApiError) -> async_graphql::Error {
+  let code = serde_json::to_value(err.code).unwrap_or_default();
+  async_graphql::Error::new(err.message).extend_with(|_, extensions| {
+    extensions.set(
+      "code",
+      async_graphql::Value::from_json(code).unwrap_or_default(),
+    )
+  })
+}
+
+fn forbidden() -> async_graphql::Error {
+  error(ApiError::new(
+    StatusCode::FORBIDDEN,
+    ErrorCode::PermissionDenied,
+    "Not allowed to view this game",
+  ))
+}
+
+fn db_error(err: crate::db::Error) -> async_graphql::Error {
+  error(db_api_error(err))
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+  // games the signed-in user is a member of
+  async fn games(
+    &self,
+    ctx: &Context<'_>,
+    offset: Option<i64>,
+    limit: Option<i64>,
+  ) -> async_graphql::Result<Vec<Game>> {
+    let state = ctx.data_unchecked::<AppState>();
+    let user = ctx.data_unchecked::<MyFirebaseUser>();
+    let locale = *ctx.data_unchecked::<Locale>();
+    let p = ListParams {
+      offset,
+      limit,
+      ..Default::default()
+    };
+    let page = state
+      .repos
+      .games
+      .list(&user.sub, p)
+      .await
+      .map_err(db_error)?;
+    Ok(page.items.into_iter().map(|g| g.localize(locale)).collect())
+  }
+
+  async fn game(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<Game> {
+    let state = ctx.data_unchecked::<AppState>();
+    let user = ctx.data_unchecked::<MyFirebaseUser>();
+    if !user.can_view(id) {
+      return Err(forbidden());
+    }
+    let game = state.repos.games.get(id).await.map_err(db_error)?;
+    Ok(game.localize(*ctx.data_unchecked::<Locale>()))
+  }
+}
+
+// only reachable through Query::game and Query::games, which check the permission
+#[ComplexObject]
+impl Game {
+  async fn rules(&self) -> Json<GameRules> {
+    Json(self.rules.clone())
+  }
+
+  async fn theme(&self) -> Json<GameTheme> {
+    Json(self.theme.clone())
+  }
+
+  async fn players(
+    &self,
+    ctx: &Context<'_>,
+    offset: Option<i64>,
+    limit: Option<i64>,
+  ) -> async_graphql::Result<Vec<Player>> {
+    let state = ctx.data_unchecked::<AppState>();
+    let p = ListParams {
+      offset,
+      limit,
+      ..Default::default()
+    };
+    let page = state
+      .repos
+      .players
+      .list(self.id, p)
+      .await
+      .map_err(db_error)?;
+    Ok(page.items)
+  }
+
+  async fn presents(
+    &self,
+    ctx: &Context<'_>,
+    offset: Option<i64>,
+    limit: Option<i64>,
+  ) -> async_graphql::Result<Vec<Present>> {
+    let state = ctx.data_unchecked::<AppState>();
+    let user = ctx.data_unchecked::<MyFirebaseUser>();
+    let locale = *ctx.data_unchecked::<Locale>();
+    let p = ListParams {
+      offset,
+      limit,
+      ..Default::default()
+    };
+    let page = state
+      .repos
+      .presents
+      .list(self.id, p)
+      .await
+      .map_err(db_error)?;
+    Ok(
+      page
+        .items
+        .into_iter()
+        .map(|p| redact(p.localize(locale), user))
+        .collect(),
+    )
+  }
+}
+
+pub struct Mutation;
+
+// the play actions, with the same checks as the POST /play routes
+#[Object]
+impl Mutation {
+  async fn roll(
+    &self,
+    ctx: &Context<'_>,
+    game_id: Uuid,
+  ) -> async_graphql::Result<GameStateUpdateResult> {
+    play(ctx, game_id, Action::Roll).await
+  }
+
+  async fn pick(
+    &self,
+    ctx: &Context<'_>,
+    game_id: Uuid,
+    present_id: i64,
+    expected_player_id: Option<i64>,
+    expected_event_seq: Option<i64>,
+  ) -> async_graphql::Result<GameStateUpdateResult> {
+    let data = PresentData {
+      present_id,
+      expected_player_id,
+      expected_event_seq,
+    };
+    play(ctx, game_id, Action::Pick(data)).await
+  }
+
+  async fn keep(
+    &self,
+    ctx: &Context<'_>,
+    game_id: Uuid,
+  ) -> async_graphql::Result<GameStateUpdateResult> {
+    play(ctx, game_id, Action::Keep).await
+  }
+
+  async fn steal(
+    &self,
+    ctx: &Context<'_>,
+    game_id: Uuid,
+    present_id: i64,
+    expected_player_id: Option<i64>,
+    expected_event_seq: Option<i64>,
+  ) -> async_graphql::Result<GameStateUpdateResult> {
+    let data = PresentData {
+      present_id,
+      expected_player_id,
+      expected_event_seq,
+    };
+    play(ctx, game_id, Action::Steal(data)).await
+  }
+}
+
+async fn play(
+  ctx: &Context<'_>,
+  game_id: Uuid,
+  action: Action,
+) -> async_graphql::Result<GameStateUpdateResult> {
+  let state = ctx.data_unchecked::<AppState>();
+  let user = ctx.data_unchecked::<MyFirebaseUser>();
+  ws::act(state, user, game_id, action).await.map_err(error)
+}
+
+pub struct Subscription;
+
+#[Subscription]
+impl Subscription {
+  // play events of a game as they happen, events a slow client falls behind on are skipped
+  async fn play_events(
+    &self,
+    ctx: &Context<'_>,
+    game_id: Uuid,
+  ) -> async_graphql::Result<impl Stream<Item = Json<PlayEvent>>> {
+    let state = ctx.data_unchecked::<AppState>();
+    let user = ctx.data_unchecked::<MyFirebaseUser>();
+    if !user.can_view(game_id) {
+      return Err(forbidden());
+    }
+    let viewer = state
+      .presence
+      .connect(game_id, Kind::Socket)
+      .await
+      .map_err(db_error)?;
+    let receiver = BroadcastStream::new(state.play_stream.subscribe(game_id));
+    Ok(receiver.filter_map(move |message| {
+      // the subscriber stays connected for as long as the stream is alive
+      let _ = &viewer;
+      async move { message.ok().map(Json) }
+    }))
+  }
+}
+
+// queries and mutations over POST /graphql
+pub async fn execute(
+  State(state): State<AppState>,
+  user: MyFirebaseUser,
+  locale: Locale,
+  axum::Json(request): axum::Json<async_graphql::Request>,
+) -> Response {
+  let schema = state.graphql.clone();
+  let request = request.data(state).data(user).data(locale);
+  axum::Json(schema.execute(request).await).into_response()
+}
+
+// subscriptions over GET /graphql/ws, speaking graphql-transport-ws or the older graphql-ws
+pub async fn subscribe(
+  State(state): State<AppState>,
+  user: MyFirebaseUser,
+  locale: Locale,
+  headers: HeaderMap,
+  ws: WebSocketUpgrade,
+) -> Response {
+  let protocol = headers
+    .get(header::SEC_WEBSOCKET_PROTOCOL)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| {
+      value
+        .split(',')
+        .find_map(|protocol| WebSocketProtocols::from_str(protocol.trim()).ok())
+    });
+  let Some(protocol) = protocol else {
+    return ApiError::new(
+      StatusCode::BAD_REQUEST,
+      ErrorCode::BadRequest,
+      "Expected the graphql-transport-ws or graphql-ws protocol",
+    )
+    .into_response();
+  };
+  ws.protocols(ALL_WEBSOCKET_PROTOCOLS)
+    .on_upgrade(move |socket| session(socket, state, user, locale, protocol))
+}
+
+async fn session(
+  mut socket: WebSocket,
+  state: AppState,
+  user: MyFirebaseUser,
+  locale: Locale,
+  protocol: WebSocketProtocols,
+) {
+  let mut data = Data::default();
+  data.insert(state.clone());
+  data.insert(user);
+  data.insert(locale);
+  // client messages are handed over through a channel, so one loop can both read and write
+  let (tx, rx) = mpsc::unbounded_channel();
+  let mut replies = GraphqlWebSocket::new(
+    state.graphql.clone(),
+    UnboundedReceiverStream::new(rx),
+    protocol,
+  )
+  .connection_data(data);
+  loop {
+    tokio::select! {
+      message = socket.recv() => {
+        let bytes = match message {
+          Some(Ok(Message::Text(text))) => text.into_bytes(),
+          Some(Ok(Message::Binary(bytes))) => bytes,
+          Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+          Some(Ok(_)) => continue,
+        };
+        if tx.send(bytes).is_err() {
+          break;
+        }
+      }
+      reply = replies.next() => {
+        let message = match reply {
+          Some(WsMessage::Text(text)) => Message::Text(text),
+          Some(WsMessage::Close(code, reason)) => Message::Close(Some(CloseFrame {
+            code,
+            reason: reason.into(),
+          })),
+          None => break,
+        };
+        let closing = matches!(message, Message::Close(_));
+        if socket.send(message).await.is_err() || closing {
+          break;
+        }
+      }
+    }
+  }
+}
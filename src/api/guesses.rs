@@ -0,0 +1,62 @@
+use axum::{
+  extract::{Path, Query, State},
+  http::StatusCode,
+  response::{IntoResponse, Response},
+  Json,
+};
+use uuid::Uuid;
+
+use crate::{
+  auth::MyFirebaseUser,
+  db::{
+    guesses::{self, GuessParams},
+    ListParams,
+  },
+};
+
+use super::{make_json_response, make_page_response};
+
+// list guesses
+pub async fn list(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+  Query(p): Query<ListParams>,
+) -> Response {
+  if user.can_view(game_id) {
+    let res = guesses::list(&db, game_id, p);
+    make_page_response(res.await)
+  } else {
+    StatusCode::FORBIDDEN.into_response()
+  }
+}
+
+// guess the price of a wrapped present, hosts can guess for any player
+pub async fn upsert(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Path((game_id, present_id)): Path<(Uuid, i64)>,
+  Json(p): Json<GuessParams>,
+) -> Response {
+  if user.can_play(game_id) {
+    let uid = (!user.can_edit(game_id)).then_some(user.sub.as_str());
+    let res = guesses::upsert(&db, game_id, present_id, p, uid);
+    make_json_response(res.await)
+  } else {
+    StatusCode::FORBIDDEN.into_response()
+  }
+}
+
+// closest-guess winners
+pub async fn scores(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+) -> Response {
+  if user.can_view(game_id) {
+    let res = guesses::scores(&db, game_id);
+    make_json_response(res.await)
+  } else {
+    StatusCode::FORBIDDEN.into_response()
+  }
+}
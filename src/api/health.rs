@@ -0,0 +1,79 @@
+use std::sync::{
+  atomic::{AtomicBool, Ordering},
+  Arc,
+};
+
+use axum::{
+  extract::{FromRef, State},
+  http::StatusCode,
+  response::{IntoResponse, Response},
+  Json,
+};
+use serde::Serialize;
+
+use crate::{db, error_code::ErrorCode, MIGRATOR};
+
+use super::{ApiError, AppState};
+
+// dependencies that come up outside of a request, set by whoever owns them
+#[derive(Clone, Default)]
+pub struct Readiness {
+  listener: Arc<AtomicBool>,
+  firebase: Arc<AtomicBool>,
+}
+
+impl Readiness {
+  pub fn set_listener(&self, ready: bool) {
+    self.listener.store(ready, Ordering::Relaxed);
+  }
+
+  pub fn set_firebase(&self, ready: bool) {
+    self.firebase.store(ready, Ordering::Relaxed);
+  }
+}
+
+impl FromRef<AppState> for Readiness {
+  fn from_ref(state: &AppState) -> Self {
+    state.readiness.clone()
+  }
+}
+
+#[derive(Serialize)]
+struct ReadyStatus {
+  database: bool,
+  migrations: bool,
+  listener: bool,
+  firebase: bool,
+}
+
+// liveness, only fails when the process can't answer at all
+pub async fn healthz() -> (StatusCode, &'static str) {
+  (StatusCode::OK, "👍 Alive!")
+}
+
+// readiness, a failing dependency takes the pod out of rotation without restarting it
+pub async fn readyz(
+  State(db): State<sqlx::PgPool>,
+  State(readiness): State<Readiness>,
+) -> Response {
+  let database = db::health(&db).await.is_ok();
+  let latest = MIGRATOR.iter().map(|m| m.version).max().unwrap_or_default();
+  let migrations = database && db::migrated(&db, latest).await.unwrap_or(false);
+  let status = ReadyStatus {
+    database,
+    migrations,
+    listener: readiness.listener.load(Ordering::Relaxed),
+    firebase: readiness.firebase.load(Ordering::Relaxed),
+  };
+  let ready = status.database && status.migrations && status.listener && status.firebase;
+  if ready {
+    return Json(status).into_response();
+  }
+  ApiError::new(
+    StatusCode::SERVICE_UNAVAILABLE,
+    ErrorCode::NotReady,
+    "Not ready",
+  )
+  .with_details(serde_json::to_value(status).unwrap_or_default())
+  .into_response()
+}
@@ -0,0 +1,82 @@
+use axum::{
+  http::header,
+  response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+
+use crate::db::games::Game;
+
+pub const CONTENT_TYPE: &str = "text/calendar";
+
+// calendars need an end, parties run for about this long
+const DURATION: &str = "PT2H";
+
+// backslash, separators and newlines have to be escaped in text values
+fn text(value: &str) -> String {
+  value
+    .replace('\\', "\\\\")
+    .replace(';', "\\;")
+    .replace(',', "\\,")
+    .replace("\r\n", "\\n")
+    .replace('\n', "\\n")
+}
+
+fn time(value: DateTime<Utc>) -> String {
+  value.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+// lines longer than 75 octets continue on the next line after a space
+fn fold(line: &str) -> String {
+  let mut folded = String::new();
+  let mut len = 0;
+  for c in line.chars() {
+    if len + c.len_utf8() > 75 {
+      folded.push_str("\r\n ");
+      len = 1;
+    }
+    folded.push(c);
+    len += c.len_utf8();
+  }
+  folded.push_str("\r\n");
+  folded
+}
+
+// a calendar with one event for a scheduled game, the uid keeps re-imports from duplicating it
+pub fn calendar(game: &Game, start: DateTime<Utc>) -> String {
+  let mut lines = vec![
+    "BEGIN:VCALENDAR".to_string(),
+    "VERSION:2.0".to_string(),
+    "PRODID:-//Evil Santa//Evil Santa//EN".to_string(),
+    "CALSCALE:GREGORIAN".to_string(),
+    "METHOD:PUBLISH".to_string(),
+    "BEGIN:VEVENT".to_string(),
+    format!("UID:{}@evil-santa", game.id),
+    format!("DTSTAMP:{}", time(Utc::now())),
+    format!("DTSTART:{}", time(start)),
+    format!("DURATION:{}", DURATION),
+    format!("SUMMARY:{}", text(&game.name)),
+  ];
+  if let Some(description) = &game.description {
+    lines.push(format!("DESCRIPTION:{}", text(description)));
+  }
+  lines.push("END:VEVENT".to_string());
+  lines.push("END:VCALENDAR".to_string());
+  lines.iter().map(|line| fold(line)).collect()
+}
+
+pub fn response(filename: &str, calendar: String) -> Response {
+  (
+    [
+      (
+        header::CONTENT_TYPE,
+        format!("{}; charset=utf-8", CONTENT_TYPE),
+      ),
+      (
+        header::CONTENT_DISPOSITION,
+        format!("attachment; filename=\"{}\"", filename),
+      ),
+    ],
+    calendar,
+  )
+    .into_response()
+}
@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use axum::{
+  body::{to_bytes, Body},
+  extract::{FromRequestParts, Request, State},
+  http::{header, HeaderValue, Method, StatusCode},
+  middleware::Next,
+  response::{IntoResponse, Response},
+};
+use sqlx::PgPool;
+
+use crate::{
+  auth::MyFirebaseUser,
+  db::idempotency::{self, Claim, StoredResponse},
+  error_code::ErrorCode,
+};
+
+use super::{handle_db_error, ApiError, AppState};
+
+const KEY_HEADER: &str = "idempotency-key";
+const REPLAYED_HEADER: &str = "idempotent-replayed";
+const MAX_KEY_LEN: usize = 255;
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// a POST retried with the same Idempotency-Key gets the original response instead of
+// running again, only successful responses are kept so failed requests can be retried
+pub async fn replay(State(state): State<AppState>, req: Request, next: Next) -> Response {
+  if req.method() != Method::POST {
+    return next.run(req).await;
+  }
+  let Some(key) = req.headers().get(KEY_HEADER) else {
+    return next.run(req).await;
+  };
+  let key = match key.to_str() {
+    Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => key.to_string(),
+    _ => return StatusCode::BAD_REQUEST.into_response(),
+  };
+
+  let (mut parts, body) = req.into_parts();
+  // keys are per user, the handler rejects requests without one
+  let Ok(user) = MyFirebaseUser::from_request_parts(&mut parts, &state).await else {
+    return next.run(Request::from_parts(parts, body)).await;
+  };
+  // bounded by the global body limit
+  let Ok(body) = to_bytes(body, usize::MAX).await else {
+    return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+  };
+  let path = parts.uri.path().to_string();
+
+  match idempotency::claim(&state.pool, &user.sub, &key, &path, &body).await {
+    Ok(Claim::New) => {}
+    Ok(Claim::Replay(stored)) => return replayed(stored),
+    Ok(Claim::InProgress) => {
+      return ApiError::new(
+        StatusCode::CONFLICT,
+        ErrorCode::IdempotencyKeyInProgress,
+        "A request with this Idempotency-Key is still running",
+      )
+      .with_retry_after(1)
+      .into_response()
+    }
+    Ok(Claim::Mismatch) => {
+      return ApiError::new(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        ErrorCode::IdempotencyKeyReused,
+        "This Idempotency-Key was already used for another request",
+      )
+      .into_response()
+    }
+    Err(err) => return handle_db_error(err),
+  }
+
+  let res = next.run(Request::from_parts(parts, Body::from(body))).await;
+  if !res.status().is_success() {
+    release(&state.pool, &user.sub, &key).await;
+    return res;
+  }
+  let (parts, body) = res.into_parts();
+  let body = match to_bytes(body, usize::MAX).await {
+    Ok(body) => body,
+    Err(err) => {
+      tracing::error!("Failed to buffer response for idempotency key: {}", err);
+      release(&state.pool, &user.sub, &key).await;
+      return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+  };
+  let header_value = |name| {
+    parts
+      .headers
+      .get(name)
+      .and_then(|value| value.to_str().ok())
+      .map(str::to_string)
+  };
+  let stored = StoredResponse {
+    status: parts.status.as_u16() as i16,
+    content_type: header_value(header::CONTENT_TYPE),
+    location: header_value(header::LOCATION),
+    body: body.to_vec(),
+  };
+  if let Err(err) = idempotency::complete(&state.pool, &user.sub, &key, &stored).await {
+    tracing::error!("Failed to store idempotent response: {}", err);
+  }
+  Response::from_parts(parts, Body::from(body))
+}
+
+async fn release(db: &PgPool, uid: &str, key: &str) {
+  if let Err(err) = idempotency::release(db, uid, key).await {
+    tracing::error!("Failed to release idempotency key: {}", err);
+  }
+}
+
+fn replayed(stored: StoredResponse) -> Response {
+  let status = StatusCode::from_u16(stored.status as u16).unwrap_or(StatusCode::OK);
+  let mut res = (status, stored.body).into_response();
+  let headers = res.headers_mut();
+  match stored
+    .content_type
+    .and_then(|value| HeaderValue::from_str(&value).ok())
+  {
+    Some(content_type) => headers.insert(header::CONTENT_TYPE, content_type),
+    None => headers.remove(header::CONTENT_TYPE),
+  };
+  if let Some(location) = stored
+    .location
+    .and_then(|value| HeaderValue::from_str(&value).ok())
+  {
+    headers.insert(header::LOCATION, location);
+  }
+  headers.insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+  res
+}
+
+// drop expired keys every hour
+pub fn purge_hourly(db: PgPool) {
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+      interval.tick().await;
+      match idempotency::purge(&db).await {
+        Ok(0) => {}
+        Ok(purged) => tracing::info!("Purged {} expired idempotency keys", purged),
+        Err(err) => tracing::warn!("Failed to purge idempotency keys: {}", err),
+      }
+    }
+  });
+}
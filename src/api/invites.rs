@@ -0,0 +1,273 @@
+use std::sync::Arc;
+
+use axum::{
+  extract::{FromRef, Path, Query, State},
+  http::StatusCode,
+  response::{IntoResponse, Response},
+  Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+  auth::{permissions::Permissions, user::UserService, MyFirebaseUser},
+  db::{
+    self,
+    audit::{self, Actor, Change},
+    invites::{self, Invite},
+    ListParams,
+  },
+  error_code::ErrorCode,
+  validation::permission_level,
+};
+
+use super::{
+  check_fields, client_ip::ClientIp, games::PLAY_PERMISSION, handle_db_error,
+  make_created_response, make_page_response, tx::Tx, ApiError, AppState,
+};
+
+const DEFAULT_EXPIRES_IN_HOURS: i64 = 72;
+const MAX_EXPIRES_IN_HOURS: i64 = 30 * 24;
+
+// signs invite tokens, every instance needs the same secret to accept them
+#[derive(Clone)]
+pub struct InviteSigner {
+  key: Arc<Vec<u8>>,
+}
+
+impl InviteSigner {
+  // without a configured secret tokens only work until the next restart
+  pub fn new(secret: &str) -> Self {
+    let key = if secret.is_empty() {
+      tracing::warn!("INVITE_SECRET is empty, invite links stop working on restart");
+      [Uuid::new_v4().into_bytes(), Uuid::new_v4().into_bytes()].concat()
+    } else {
+      secret.as_bytes().to_vec()
+    };
+    Self { key: Arc::new(key) }
+  }
+
+  fn mac(&self, payload: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("hmac accepts keys of any size");
+    mac.update(payload.as_bytes());
+    mac
+  }
+
+  // <base64 claims>.<base64 hmac-sha256 of the claims>
+  fn sign(&self, claims: &InviteClaims) -> String {
+    let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).unwrap_or_default());
+    let signature = URL_SAFE_NO_PAD.encode(self.mac(&payload).finalize().into_bytes());
+    format!("{}.{}", payload, signature)
+  }
+
+  fn verify(&self, token: &str) -> Option<InviteClaims> {
+    let (payload, signature) = token.split_once('.')?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+    self.mac(payload).verify_slice(&signature).ok()?;
+    serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()
+  }
+}
+
+impl FromRef<AppState> for InviteSigner {
+  fn from_ref(state: &AppState) -> Self {
+    state.invite_signer.clone()
+  }
+}
+
+// what a token grants, the invite row is still checked so it can be revoked
+#[derive(Serialize, Deserialize)]
+struct InviteClaims {
+  #[serde(rename = "i")]
+  invite_id: i64,
+  #[serde(rename = "g")]
+  game_id: Uuid,
+  #[serde(rename = "p")]
+  permission: i64,
+  // unix seconds
+  #[serde(rename = "e")]
+  expires_at: i64,
+}
+
+fn invalid_invite() -> ApiError {
+  ApiError::new(
+    StatusCode::BAD_REQUEST,
+    ErrorCode::InvalidInvite,
+    "The invite is invalid or was revoked",
+  )
+}
+
+#[derive(Deserialize, Validate)]
+pub struct CreateParams {
+  #[serde(default = "default_permission")]
+  #[validate(custom(function = permission_level))]
+  pub permission: i64,
+  #[serde(default = "default_expires_in_hours")]
+  #[validate(range(min = 1, max = MAX_EXPIRES_IN_HOURS))]
+  pub expires_in_hours: i64,
+}
+
+fn default_permission() -> i64 {
+  PLAY_PERMISSION
+}
+
+fn default_expires_in_hours() -> i64 {
+  DEFAULT_EXPIRES_IN_HOURS
+}
+
+// the token is only ever returned here
+#[derive(Serialize)]
+pub struct CreatedInvite {
+  #[serde(flatten)]
+  pub invite: Invite,
+  pub token: String,
+}
+
+// list the invites of a game
+pub async fn list(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Query(p): Query<ListParams>,
+  Path(game_id): Path<Uuid>,
+) -> Response {
+  if user.can_edit(game_id) {
+    make_page_response(invites::list(&db, game_id, p).await)
+  } else {
+    StatusCode::FORBIDDEN.into_response()
+  }
+}
+
+// create an invite link token
+pub async fn create(
+  State(db): State<sqlx::PgPool>,
+  State(signer): State<InviteSigner>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+  p: Option<Json<CreateParams>>,
+) -> Response {
+  if !user.can_edit(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  let p = match p {
+    Some(Json(p)) => p,
+    None => CreateParams {
+      permission: default_permission(),
+      expires_in_hours: default_expires_in_hours(),
+    },
+  };
+  if let Err(err) = check_fields(&p) {
+    return err.into_response();
+  }
+  let expires_at = Utc::now() + Duration::hours(p.expires_in_hours);
+  match invites::create(&db, game_id, p.permission, &user.sub, expires_at).await {
+    Ok(invite) => {
+      let token = signer.sign(&InviteClaims {
+        invite_id: invite.id,
+        game_id,
+        permission: invite.permission,
+        expires_at: invite.expires_at.timestamp(),
+      });
+      make_created_response(
+        format!("/games/{}/invites/{}", game_id, invite.id),
+        CreatedInvite { invite, token },
+      )
+    }
+    Err(err) => handle_db_error(err),
+  }
+}
+
+// revoke an invite
+pub async fn revoke(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Path((game_id, invite_id)): Path<(Uuid, i64)>,
+) -> Result<StatusCode, Response> {
+  if !user.can_edit(game_id) {
+    return Err(StatusCode::FORBIDDEN.into_response());
+  }
+  invites::revoke(&db, game_id, invite_id)
+    .await
+    .map_err(handle_db_error)?;
+  Ok(StatusCode::ACCEPTED)
+}
+
+#[derive(Deserialize)]
+pub struct AcceptData {
+  pub token: String,
+}
+
+#[derive(Serialize)]
+pub struct Accepted {
+  pub game_id: Uuid,
+  pub permission: i64,
+}
+
+// redeem an invite token, joining the game's users and the user's claims
+pub async fn accept(
+  mut tx: Tx,
+  State(signer): State<InviteSigner>,
+  State(claims_service): State<UserService>,
+  State(permissions): State<Permissions>,
+  user: MyFirebaseUser,
+  client_ip: ClientIp,
+  Json(data): Json<AcceptData>,
+) -> Response {
+  // api keys have fixed games, they can't join more
+  if user.is_api_key() {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  let Some(claims) = signer.verify(&data.token) else {
+    return invalid_invite().into_response();
+  };
+  if claims.expires_at <= Utc::now().timestamp() {
+    return ApiError::new(
+      StatusCode::GONE,
+      ErrorCode::InviteExpired,
+      "The invite has expired",
+    )
+    .into_response();
+  }
+  let (previous, permission) =
+    match invites::redeem(tx.conn(), claims.game_id, claims.invite_id, &user.sub).await {
+      Ok(redeemed) => redeemed,
+      Err(db::Error::NotFound) => return invalid_invite().into_response(),
+      Err(err) => return handle_db_error(err),
+    };
+  if previous != Some(permission) {
+    let change = Change {
+      uid: user.sub.clone(),
+      old_permission: previous,
+      new_permission: Some(permission),
+    };
+    let actor = Actor {
+      uid: &user.sub,
+      client_ip: client_ip.known(),
+      source: audit::INVITE,
+    };
+    let res = audit::record(tx.conn(), claims.game_id, &actor, &[change]).await;
+    if let Err(err) = res {
+      return handle_db_error(err);
+    }
+  }
+
+  permissions.forget(&user.sub);
+  let mut custom_claims = user.custom_claims();
+  custom_claims
+    .games
+    .insert(claims.game_id.to_string(), permission);
+  if let Err(err) = claims_service
+    .set_custom_attributes(&user.sub, custom_claims)
+    .await
+  {
+    tracing::warn!("Failed to update claims of {}: {}", user.sub, err);
+  }
+  Json(Accepted {
+    game_id: claims.game_id,
+    permission,
+  })
+  .into_response()
+}
@@ -0,0 +1,92 @@
+use std::sync::{Arc, RwLock};
+
+use axum::{
+  extract::{FromRef, Request, State},
+  http::{Method, StatusCode},
+  middleware::Next,
+  response::{IntoResponse, Response},
+  Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::error_code::ErrorCode;
+
+use super::{admin::AdminUser, ApiError, AppState};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MaintenanceStatus {
+  pub enabled: bool,
+  pub retry_after: u64,
+  pub message: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct Maintenance(Arc<RwLock<MaintenanceStatus>>);
+
+impl Maintenance {
+  pub fn new(enabled: bool, retry_after: u64) -> Self {
+    Self(Arc::new(RwLock::new(MaintenanceStatus {
+      enabled,
+      retry_after,
+      message: None,
+    })))
+  }
+
+  pub fn status(&self) -> MaintenanceStatus {
+    self.0.read().unwrap().clone()
+  }
+
+  pub fn set(&self, status: MaintenanceStatus) {
+    *self.0.write().unwrap() = status;
+  }
+}
+
+impl FromRef<AppState> for Maintenance {
+  fn from_ref(state: &AppState) -> Self {
+    state.maintenance.clone()
+  }
+}
+
+// reject mutations while in maintenance, reads and streams keep working
+pub async fn guard(State(maintenance): State<Maintenance>, req: Request, next: Next) -> Response {
+  let read_only = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+  if read_only || req.uri().path().starts_with("/admin") {
+    return next.run(req).await;
+  }
+  let status = maintenance.status();
+  if !status.enabled {
+    return next.run(req).await;
+  }
+  let err = ApiError::new(
+    StatusCode::SERVICE_UNAVAILABLE,
+    ErrorCode::Maintenance,
+    status
+      .message
+      .unwrap_or(String::from("Service is in read-only maintenance mode")),
+  );
+  err.with_retry_after(status.retry_after).into_response()
+}
+
+// get maintenance status
+pub async fn get(State(maintenance): State<Maintenance>) -> Json<MaintenanceStatus> {
+  Json(maintenance.status())
+}
+
+// toggle maintenance mode, superadmins only
+pub async fn set(
+  State(maintenance): State<Maintenance>,
+  AdminUser(user): AdminUser,
+  Json(status): Json<MaintenanceStatus>,
+) -> Response {
+  tracing::warn!(
+    "Maintenance mode {} by {}",
+    if status.enabled {
+      "enabled"
+    } else {
+      "disabled"
+    },
+    user.sub
+  );
+  maintenance.set(status.clone());
+  Json(status).into_response()
+}
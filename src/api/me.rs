@@ -0,0 +1,109 @@
+use axum::{
+  extract::State,
+  http::header,
+  response::{IntoResponse, Response},
+  Json,
+};
+use serde::Serialize;
+
+use crate::{
+  auth::MyFirebaseUser,
+  db::{
+    export,
+    games::{self, Membership},
+    notifications::{self, Preferences},
+  },
+};
+
+use super::{
+  games::{OWNER_PERMISSION, PLAY_PERMISSION},
+  handle_db_error, make_json_response,
+};
+
+#[derive(Serialize)]
+pub struct Profile {
+  pub uid: String,
+  pub name: Option<String>,
+  pub email: Option<String>,
+  pub email_verified: Option<bool>,
+  pub picture: Option<String>,
+  pub superadmin: bool,
+  pub games: Vec<GameRole>,
+}
+
+#[derive(Serialize)]
+pub struct GameRole {
+  #[serde(flatten)]
+  pub membership: Membership,
+  // owner, player or viewer
+  pub role: &'static str,
+}
+
+fn role(permission: i64) -> &'static str {
+  if permission >= OWNER_PERMISSION {
+    "owner"
+  } else if permission >= PLAY_PERMISSION {
+    "player"
+  } else {
+    "viewer"
+  }
+}
+
+// the signed-in user with every game they are in, so clients don't decode the token
+pub async fn profile(State(db): State<sqlx::PgPool>, user: MyFirebaseUser) -> Response {
+  let memberships = match games::memberships(&db, &user.sub).await {
+    Ok(memberships) => memberships,
+    Err(err) => return handle_db_error(err),
+  };
+  let games = memberships
+    .into_iter()
+    .map(|membership| GameRole {
+      role: role(membership.permission),
+      membership,
+    })
+    .collect();
+  make_json_response(Ok(Profile {
+    uid: user.sub,
+    name: user.name,
+    email: user.email,
+    email_verified: user.email_verified,
+    picture: user.picture,
+    superadmin: user.superadmin,
+    games,
+  }))
+}
+
+// download everything stored about the signed-in user
+pub async fn export(State(db): State<sqlx::PgPool>, user: MyFirebaseUser) -> Response {
+  let email = user
+    .email
+    .as_deref()
+    .filter(|_| user.email_verified == Some(true));
+  match export::personal(&db, &user.sub, email).await {
+    Ok(data) => {
+      let filename = format!(
+        "attachment; filename=\"evil-santa-export-{}.json\"",
+        data.exported_at.format("%Y-%m-%d")
+      );
+      ([(header::CONTENT_DISPOSITION, filename)], Json(data)).into_response()
+    }
+    Err(err) => handle_db_error(err),
+  }
+}
+
+// the email preferences of the signed-in user
+pub async fn notifications(State(db): State<sqlx::PgPool>, user: MyFirebaseUser) -> Response {
+  make_json_response(notifications::preferences(&db, &user.sub).await)
+}
+
+// opt out of (or back into) emails about games
+pub async fn set_notifications(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Json(p): Json<Preferences>,
+) -> Response {
+  match notifications::set_preferences(&db, &user.sub, &p).await {
+    Ok(()) => make_json_response(Ok(p)),
+    Err(err) => handle_db_error(err),
+  }
+}
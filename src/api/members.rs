@@ -0,0 +1,234 @@
+use axum::{
+  extract::{Path, Query, State},
+  http::StatusCode,
+  response::{IntoResponse, Response},
+  Json,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+  auth::{permissions::Permissions, user::UserService, MyFirebaseUser},
+  db::{
+    audit::{self, Actor, Change},
+    members, notifications, ListParams,
+  },
+  validation::permission_level,
+};
+
+use super::{
+  check_fields, client_ip::ClientIp, handle_db_error, make_created_response, make_json_response,
+  make_page_response, tx::Tx,
+};
+
+#[derive(Deserialize, Validate)]
+pub struct AddParams {
+  #[validate(length(min = 1, max = 128))]
+  pub uid: String,
+  #[validate(custom(function = permission_level))]
+  pub permission: i64,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct UpdateParams {
+  #[validate(custom(function = permission_level))]
+  pub permission: i64,
+}
+
+// requests are authorized from game_members, so the member's next request sees the change
+// while their custom claims only mirror it as a hint, None removes the game
+pub async fn sync_claims(
+  claims_service: &UserService,
+  permissions: &Permissions,
+  uid: &str,
+  game_id: Uuid,
+  permission: Option<i64>,
+) {
+  permissions.forget(uid);
+  let res = match claims_service.lookup(uid).await {
+    Ok(user) => {
+      let mut claims = user.customAttributes;
+      match permission {
+        Some(permission) => claims.games.insert(game_id.to_string(), permission),
+        None => claims.games.remove(&game_id.to_string()),
+      };
+      claims_service.set_custom_attributes(uid, claims).await
+    }
+    Err(err) => Err(err),
+  };
+  if let Err(err) = res {
+    tracing::warn!(
+      "Failed to update claims of {} for game {}: {}",
+      uid,
+      game_id,
+      err
+    );
+  }
+}
+
+// list the members of a game
+pub async fn list(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+) -> Response {
+  if user.can_edit(game_id) {
+    make_json_response(members::list(&db, game_id).await)
+  } else {
+    StatusCode::FORBIDDEN.into_response()
+  }
+}
+
+// add a user to a game
+pub async fn add(
+  mut tx: Tx,
+  user: MyFirebaseUser,
+  client_ip: ClientIp,
+  State(claims_service): State<UserService>,
+  State(permissions): State<Permissions>,
+  Path(game_id): Path<Uuid>,
+  Json(p): Json<AddParams>,
+) -> Response {
+  if !user.can_edit(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  if let Err(err) = check_fields(&p) {
+    return err.into_response();
+  }
+  let member = match members::add(tx.conn(), game_id, &p.uid, p.permission).await {
+    Ok(member) => member,
+    Err(err) => return handle_db_error(err),
+  };
+  let invited = [member.uid.clone()];
+  if let Err(err) = notifications::enqueue_invites(tx.conn(), game_id, &invited).await {
+    return handle_db_error(err);
+  }
+  let change = Change {
+    uid: member.uid.clone(),
+    old_permission: None,
+    new_permission: Some(member.permission),
+  };
+  if let Err(err) = audit::record(
+    tx.conn(),
+    game_id,
+    &members_actor(&user, client_ip),
+    &[change],
+  )
+  .await
+  {
+    return handle_db_error(err);
+  }
+  sync_claims(
+    &claims_service,
+    &permissions,
+    &member.uid,
+    game_id,
+    Some(member.permission),
+  )
+  .await;
+  make_created_response(format!("/games/{}/members/{}", game_id, member.uid), member)
+}
+
+// change the permission of a member
+pub async fn update(
+  mut tx: Tx,
+  user: MyFirebaseUser,
+  client_ip: ClientIp,
+  State(claims_service): State<UserService>,
+  State(permissions): State<Permissions>,
+  Path((game_id, uid)): Path<(Uuid, String)>,
+  Json(p): Json<UpdateParams>,
+) -> Response {
+  if !user.can_edit(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  if let Err(err) = check_fields(&p) {
+    return err.into_response();
+  }
+  let (member, previous) = match members::update(tx.conn(), game_id, &uid, p.permission).await {
+    Ok(updated) => updated,
+    Err(err) => return handle_db_error(err),
+  };
+  if previous != member.permission {
+    let change = Change {
+      uid: uid.clone(),
+      old_permission: Some(previous),
+      new_permission: Some(member.permission),
+    };
+    if let Err(err) = audit::record(
+      tx.conn(),
+      game_id,
+      &members_actor(&user, client_ip),
+      &[change],
+    )
+    .await
+    {
+      return handle_db_error(err);
+    }
+  }
+  sync_claims(
+    &claims_service,
+    &permissions,
+    &uid,
+    game_id,
+    Some(member.permission),
+  )
+  .await;
+  make_json_response(Ok(member))
+}
+
+// remove a user from a game
+pub async fn remove(
+  mut tx: Tx,
+  user: MyFirebaseUser,
+  client_ip: ClientIp,
+  State(claims_service): State<UserService>,
+  State(permissions): State<Permissions>,
+  Path((game_id, uid)): Path<(Uuid, String)>,
+) -> Result<StatusCode, Response> {
+  if !user.can_edit(game_id) {
+    return Err(StatusCode::FORBIDDEN.into_response());
+  }
+  let previous = members::remove(tx.conn(), game_id, &uid)
+    .await
+    .map_err(handle_db_error)?;
+  let change = Change {
+    uid: uid.clone(),
+    old_permission: Some(previous),
+    new_permission: None,
+  };
+  audit::record(
+    tx.conn(),
+    game_id,
+    &members_actor(&user, client_ip),
+    &[change],
+  )
+  .await
+  .map_err(handle_db_error)?;
+  sync_claims(&claims_service, &permissions, &uid, game_id, None).await;
+  Ok(StatusCode::ACCEPTED)
+}
+
+// the permission changes of a game, newest first
+pub async fn audit(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+  Query(p): Query<ListParams>,
+) -> Response {
+  if user.can_edit(game_id) {
+    make_page_response(audit::list(&db, game_id, p).await)
+  } else {
+    StatusCode::FORBIDDEN.into_response()
+  }
+}
+
+// member changes are audited as made by the caller
+fn members_actor(user: &MyFirebaseUser, client_ip: ClientIp) -> Actor<'_> {
+  Actor {
+    uid: &user.sub,
+    client_ip: client_ip.known(),
+    source: audit::MEMBERS,
+  }
+}
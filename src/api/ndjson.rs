@@ -0,0 +1,60 @@
+use std::convert::Infallible;
+
+use axum::{
+  body::Body,
+  http::{header, HeaderMap},
+  response::{IntoResponse, Response},
+};
+use futures_util::{future, Stream, StreamExt};
+use serde::Serialize;
+
+use crate::{db, error_code::ErrorCode};
+
+use super::INTERNAL_ERROR_MESSAGE;
+
+pub const CONTENT_TYPE: &str = "application/x-ndjson";
+
+// whether the client asked for newline delimited json
+pub fn accepted(headers: &HeaderMap) -> bool {
+  headers
+    .get(header::ACCEPT)
+    .and_then(|v| v.to_str().ok())
+    .is_some_and(|accept| {
+      accept
+        .split(',')
+        .any(|t| t.trim().starts_with(CONTENT_TYPE))
+    })
+}
+
+// write one json document per row as rows arrive, an error ends the stream with an error line
+pub fn response<T, S>(rows: S) -> Response
+where
+  T: Serialize,
+  S: Stream<Item = Result<T, db::Error>> + Send + 'static,
+{
+  let lines = rows.scan(false, |failed, row| {
+    if *failed {
+      return future::ready(None);
+    }
+    let line = match row {
+      Ok(row) => serde_json::to_vec(&row).map_err(|err| err.to_string()),
+      Err(err) => Err(err.to_string()),
+    };
+    let mut line = line.unwrap_or_else(|err| {
+      *failed = true;
+      tracing::error!("NDJSON stream failed: {}", err);
+      serde_json::to_vec(&serde_json::json!({
+        "code": ErrorCode::InternalError,
+        "message": INTERNAL_ERROR_MESSAGE,
+      }))
+      .unwrap_or_default()
+    });
+    line.push(b'\n');
+    future::ready(Some(Ok::<_, Infallible>(line)))
+  });
+  (
+    [(header::CONTENT_TYPE, CONTENT_TYPE)],
+    Body::from_stream(lines),
+  )
+    .into_response()
+}
@@ -0,0 +1,96 @@
+use axum::Router;
+use utoipa::{
+  openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+  Modify, OpenApi,
+};
+use utoipa_swagger_ui::SwaggerUi;
+
+use super::{games, players, presents};
+
+// generated from the handler annotations, add new handlers to paths
+#[derive(OpenApi)]
+#[openapi(
+  info(
+    title = "Evil Santa API",
+    description = "Errors share the ApiError body, lists send their paging in X- headers"
+  ),
+  paths(
+    games::rule_presets,
+    games::list,
+    games::create,
+    games::get,
+    games::update,
+    games::replace,
+    games::delete,
+    games::restore,
+    games::export,
+    games::calendar,
+    games::import,
+    games::list_events,
+    games::poll_events,
+    games::events_csv_download,
+    games::events,
+    games::state,
+    games::stats,
+    games::start,
+    games::reset,
+    games::roll,
+    games::pick,
+    games::keep,
+    games::steal,
+    games::assign,
+    games::assign_many,
+    games::undo,
+    games::nudge,
+    players::list,
+    players::create,
+    players::create_many,
+    players::get,
+    players::update,
+    players::replace,
+    players::delete,
+    presents::list,
+    presents::create,
+    presents::create_many,
+    presents::delete_many,
+    presents::shuffle,
+    presents::get,
+    presents::update,
+    presents::replace,
+    presents::delete,
+  ),
+  modifiers(&FirebaseToken),
+  security(("firebase" = [])),
+  tags(
+    (name = "games", description = "Games, their settings and history"),
+    (name = "play", description = "Turn actions of a running game"),
+    (name = "players", description = "Players of a game"),
+    (name = "presents", description = "Presents of a game"),
+  )
+)]
+pub struct ApiDoc;
+
+// clients send their Firebase ID token as a bearer token
+struct FirebaseToken;
+
+impl Modify for FirebaseToken {
+  fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+    let components = openapi.components.get_or_insert_with(Default::default);
+    components.add_security_scheme(
+      "firebase",
+      SecurityScheme::Http(
+        HttpBuilder::new()
+          .scheme(HttpAuthScheme::Bearer)
+          .bearer_format("JWT")
+          .build(),
+      ),
+    );
+  }
+}
+
+// /openapi.json and a Swagger UI at /docs, bundled so it works without a CDN
+pub fn routes() -> Router {
+  SwaggerUi::new("/docs")
+    .url("/openapi.json", ApiDoc::openapi())
+    .into()
+}
@@ -3,100 +3,248 @@ use axum::{
   http::StatusCode,
   response::{IntoResponse, Response}, Json,
 };
+use std::sync::Arc;
+
 use uuid::Uuid;
 
 use crate::{
   auth::MyFirebaseUser,
   db::{
-    players::{self, CreateParams, ReplaceParams, UpdateParams},
-    ListParams,
+    players::{CreateParams, Player, ReplaceParams, UpdateParams},
+    repo::PlayersRepo,
+    BulkParams, BulkResult, ListParams, UpdateResult,
   },
 };
 
-use super::{handle_db_error, make_json_response};
+use super::{
+  check_fields, check_items, handle_db_error, make_created_response, make_json_response, make_page_response, ApiError,
+};
+
+// spectators with a share token don't get to see who plays or edits
+pub fn redact(mut player: Player, user: &MyFirebaseUser) -> Player {
+  if user.is_share_token() {
+    player.uid = None;
+    player.created_by = None;
+    player.updated_by = None;
+  }
+  player
+}
 
-// list players
+/// list players
+#[utoipa::path(
+  get,
+  operation_id = "list_players",
+  path = "/games/{game_id}/players",
+  tag = "players",
+  params(("game_id" = Uuid, Path), ListParams),
+  responses(
+    (status = 200, body = Vec<Player>, headers(
+      ("X-Total-Count" = i64, description = "size of the whole list"),
+      ("X-Offset" = i64),
+      ("X-Limit" = i64),
+      ("X-Next-Cursor" = i64, description = "after_id of the next page"),
+      ("X-Has-More" = bool),
+    )),
+    (status = 400, body = ApiError),
+    (status = 403, body = ApiError),
+  )
+)]
 pub async fn list(
-  State(db): State<sqlx::PgPool>,
+  State(repo): State<Arc<dyn PlayersRepo>>,
   user: MyFirebaseUser,
   Query(p): Query<ListParams>,
   Path(game_id): Path<Uuid>,
 ) -> Response {
   if user.can_view(game_id) {
-    let res = players::list(&db, game_id, p);
-    make_json_response(res.await)
+    let res = repo.list(game_id, p).await;
+    make_page_response(res.map(|page| page.map(|p| redact(p, &user))))
   } else {
     StatusCode::FORBIDDEN.into_response()
   }
 }
 
-// get a player
+/// get a player
+#[utoipa::path(
+  get,
+  operation_id = "get_player",
+  path = "/games/{game_id}/players/{player_id}",
+  tag = "players",
+  params(("game_id" = Uuid, Path), ("player_id" = i64, Path)),
+  responses(
+    (status = 200, body = Player),
+    (status = 403, body = ApiError),
+    (status = 404, body = ApiError),
+  )
+)]
 pub async fn get(
-  State(db): State<sqlx::PgPool>,
+  State(repo): State<Arc<dyn PlayersRepo>>,
   user: MyFirebaseUser,
   Path((game_id, player_id)): Path<(Uuid, i64)>,
 ) -> Response {
   if user.can_view(game_id) {
-    let res = players::get(&db, player_id);
-    make_json_response(res.await)
+    let res = repo.get(player_id).await;
+    make_json_response(res.map(|p| redact(p, &user)))
   } else {
     StatusCode::FORBIDDEN.into_response()
   }
 }
 
-// create a player
+/// create a player
+#[utoipa::path(
+  post,
+  operation_id = "create_player",
+  path = "/games/{game_id}/players",
+  tag = "players",
+  params(("game_id" = Uuid, Path)),
+  request_body = CreateParams,
+  responses(
+    (status = 201, body = Player, headers(("Location" = String))),
+    (status = 400, body = ApiError),
+    (status = 403, body = ApiError),
+    (status = 422, body = ApiError, description = "some fields are invalid"),
+  )
+)]
 pub async fn create(
-  State(db): State<sqlx::PgPool>,
+  State(repo): State<Arc<dyn PlayersRepo>>,
   user: MyFirebaseUser,
   Path(game_id): Path<Uuid>,
   Json(p): Json<CreateParams>,
 ) -> Response {
   if user.can_edit(game_id) {
-    let res = players::create(&db, game_id, p);
+    if let Err(err) = check_fields(&p) {
+      return err.into_response();
+    }
+    match repo.create(game_id, p, &user.sub).await {
+      Ok(player) => make_created_response(
+        format!("/games/{}/players/{}", game_id, player.id),
+        player,
+      ),
+      Err(err) => handle_db_error(err),
+    }
+  } else {
+    StatusCode::FORBIDDEN.into_response()
+  }
+}
+
+/// create many players, ?mode=partial keeps the valid ones when some fail
+#[utoipa::path(
+  post,
+  operation_id = "create_players",
+  path = "/games/{game_id}/players/batch",
+  tag = "players",
+  params(("game_id" = Uuid, Path), BulkParams),
+  request_body = Vec<CreateParams>,
+  responses(
+    (status = 200, body = BulkResult),
+    (status = 400, body = ApiError),
+    (status = 403, body = ApiError),
+    (status = 422, body = ApiError, description = "some fields are invalid"),
+  )
+)]
+pub async fn create_many(
+  State(repo): State<Arc<dyn PlayersRepo>>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+  Query(q): Query<BulkParams>,
+  Json(items): Json<Vec<CreateParams>>,
+) -> Response {
+  if user.can_edit(game_id) {
+    if let Err(err) = check_items(&items) {
+      return err.into_response();
+    }
+    let res = repo.create_many(game_id, items, q.mode, &user.sub);
     make_json_response(res.await)
   } else {
     StatusCode::FORBIDDEN.into_response()
   }
 }
 
-// update a player
+/// update a player
+#[utoipa::path(
+  patch,
+  operation_id = "update_player",
+  path = "/games/{game_id}/players/{player_id}",
+  tag = "players",
+  params(("game_id" = Uuid, Path), ("player_id" = i64, Path)),
+  request_body = UpdateParams,
+  responses(
+    (status = 200, body = UpdateResult),
+    (status = 400, body = ApiError),
+    (status = 403, body = ApiError),
+    (status = 404, body = ApiError),
+    (status = 422, body = ApiError, description = "some fields are invalid"),
+  )
+)]
 pub async fn update(
-  State(db): State<sqlx::PgPool>,
+  State(repo): State<Arc<dyn PlayersRepo>>,
   user: MyFirebaseUser,
   Path((game_id, player_id)): Path<(Uuid, i64)>,
   Json(p): Json<UpdateParams>,
 ) -> Response {
   if user.can_edit(game_id) {
-    let res = players::update(&db, player_id, p);
+    if let Err(err) = check_fields(&p) {
+      return err.into_response();
+    }
+    let res = repo.update(player_id, p, &user.sub);
     make_json_response(res.await)
   } else {
     StatusCode::FORBIDDEN.into_response()
   }
 }
 
-// replace a player
+/// replace a player
+#[utoipa::path(
+  put,
+  operation_id = "replace_player",
+  path = "/games/{game_id}/players/{player_id}",
+  tag = "players",
+  params(("game_id" = Uuid, Path), ("player_id" = i64, Path)),
+  request_body = ReplaceParams,
+  responses(
+    (status = 200, body = UpdateResult),
+    (status = 400, body = ApiError),
+    (status = 403, body = ApiError),
+    (status = 404, body = ApiError),
+    (status = 422, body = ApiError, description = "some fields are invalid"),
+  )
+)]
 pub async fn replace(
-  State(db): State<sqlx::PgPool>,
+  State(repo): State<Arc<dyn PlayersRepo>>,
   user: MyFirebaseUser,
   Path((game_id, player_id)): Path<(Uuid, i64)>,
   Json(p): Json<ReplaceParams>,
 ) -> Response {
   if user.can_edit(game_id) {
-    let res = players::replace(&db, player_id, p);
+    if let Err(err) = check_fields(&p) {
+      return err.into_response();
+    }
+    let res = repo.replace(player_id, p, &user.sub);
     make_json_response(res.await)
   } else {
     StatusCode::FORBIDDEN.into_response()
   }
 }
 
-// delete a player
+/// delete a player
+#[utoipa::path(
+  delete,
+  operation_id = "delete_player",
+  path = "/games/{game_id}/players/{player_id}",
+  tag = "players",
+  params(("game_id" = Uuid, Path), ("player_id" = i64, Path)),
+  responses(
+    (status = 202),
+    (status = 403, body = ApiError),
+    (status = 404, body = ApiError),
+  )
+)]
 pub async fn delete(
-  State(db): State<sqlx::PgPool>,
+  State(repo): State<Arc<dyn PlayersRepo>>,
   user: MyFirebaseUser,
   Path((game_id, player_id)): Path<(Uuid, i64)>,
 ) -> Result<StatusCode, Response> {
   if user.can_edit(game_id) {
-    players::delete(&db, player_id)
+    repo.delete(player_id)
       .await
       .map_err(handle_db_error)?;
     Ok(StatusCode::ACCEPTED)
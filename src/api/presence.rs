@@ -0,0 +1,278 @@
+use std::{
+  collections::HashMap,
+  future::Future,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+
+use axum::{
+  extract::{FromRef, Path, State},
+  http::StatusCode,
+  response::{sse::Event, IntoResponse, Response},
+};
+use futures_util::{Stream, StreamExt};
+use serde::Serialize;
+use sqlx::PgPool;
+use tokio::sync::watch;
+use tokio_stream::wrappers::IntervalStream;
+use uuid::Uuid;
+
+use crate::{
+  auth::MyFirebaseUser,
+  db::{self, games, viewers},
+};
+
+use super::{make_json_response, AppState};
+
+// how often subscribers of the event stream are told who is connected
+const EVENT_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+  // the event stream, e.g. a wall display
+  Stream,
+  // the websocket of a client that also plays
+  Socket,
+  // the event stream of a share link
+  Shared,
+}
+
+// connections per game, by how they're connected
+#[derive(Clone, Copy, Default, Serialize)]
+pub struct Counts {
+  pub streams: usize,
+  pub sockets: usize,
+  pub shared: usize,
+}
+
+impl Kind {
+  fn as_str(&self) -> &'static str {
+    match self {
+      Kind::Stream => "stream",
+      Kind::Socket => "socket",
+      Kind::Shared => "shared",
+    }
+  }
+
+  fn parse(value: &str) -> Option<Self> {
+    match value {
+      "stream" => Some(Kind::Stream),
+      "socket" => Some(Kind::Socket),
+      "shared" => Some(Kind::Shared),
+      _ => None,
+    }
+  }
+}
+
+impl Counts {
+  fn get_mut(&mut self, kind: Kind) -> &mut usize {
+    match kind {
+      Kind::Stream => &mut self.streams,
+      Kind::Socket => &mut self.sockets,
+      Kind::Shared => &mut self.shared,
+    }
+  }
+}
+
+#[derive(Serialize)]
+pub struct PresenceStatus {
+  #[serde(flatten)]
+  pub counts: Counts,
+  pub total: usize,
+}
+
+impl From<Counts> for PresenceStatus {
+  fn from(counts: Counts) -> Self {
+    PresenceStatus {
+      total: counts.streams + counts.sockets + counts.shared,
+      counts,
+    }
+  }
+}
+
+// a viewer connected to this replica, closed when its row is removed
+struct Local {
+  game_id: Uuid,
+  kind: Kind,
+  closed: watch::Sender<bool>,
+}
+
+// subscribers connected per game, kept in postgres so every replica sees the same counts
+#[derive(Clone)]
+pub struct Presence {
+  db: PgPool,
+  // the viewers connected to this replica, refreshed in the viewers table
+  local: Arc<Mutex<HashMap<Uuid, Local>>>,
+}
+
+impl Presence {
+  pub fn new(db: PgPool) -> Self {
+    Presence {
+      db,
+      local: Arc::default(),
+    }
+  }
+
+  pub async fn get(&self, game_id: Uuid) -> Result<Counts, db::Error> {
+    let mut counts = Counts::default();
+    for (kind, count) in viewers::counts(&self.db, game_id).await? {
+      if let Some(kind) = Kind::parse(&kind) {
+        *counts.get_mut(kind) = count as usize;
+      }
+    }
+    Ok(counts)
+  }
+
+  // number of share-link viewers, counted against the viewer limit
+  pub async fn count(&self, game_id: Uuid) -> Result<usize, db::Error> {
+    Ok(self.get(game_id).await?.shared)
+  }
+
+  // register a share-link viewer unless the game is already at its limit
+  pub async fn join(&self, game_id: Uuid) -> Result<Option<Viewer>, db::Error> {
+    let limit = games::get_share(&self.db, game_id).await?.viewer_limit;
+    let Some(limit) = limit else {
+      return self.connect(game_id, Kind::Shared).await.map(Some);
+    };
+    let id = Uuid::new_v4();
+    let kind = Kind::Shared.as_str();
+    if !viewers::insert_within(&self.db, id, game_id, kind, limit.max(0) as i64).await? {
+      return Ok(None);
+    }
+    Ok(Some(self.track(id, game_id, Kind::Shared)))
+  }
+
+  // register a subscriber, there's no limit for members of the game
+  pub async fn connect(&self, game_id: Uuid, kind: Kind) -> Result<Viewer, db::Error> {
+    let id = Uuid::new_v4();
+    viewers::insert(&self.db, id, game_id, kind.as_str()).await?;
+    Ok(self.track(id, game_id, kind))
+  }
+
+  fn track(&self, id: Uuid, game_id: Uuid, kind: Kind) -> Viewer {
+    let (closed, rx) = watch::channel(false);
+    let local = Local {
+      game_id,
+      kind,
+      closed,
+    };
+    self.local.lock().unwrap().insert(id, local);
+    Viewer {
+      presence: self.clone(),
+      id,
+      closed: rx,
+    }
+  }
+
+  // disconnect the share-link viewers of a game, on every replica.
+  // the others notice their rows are gone when they next refresh them
+  pub async fn close_shared(&self, game_id: Uuid) -> Result<(), db::Error> {
+    viewers::delete_kind(&self.db, game_id, Kind::Shared.as_str()).await?;
+    for local in self.local.lock().unwrap().values() {
+      if local.game_id == game_id && local.kind == Kind::Shared {
+        local.closed.send_replace(true);
+      }
+    }
+    Ok(())
+  }
+
+  fn leave(&self, id: Uuid) {
+    self.local.lock().unwrap().remove(&id);
+    let db = self.db.clone();
+    tokio::spawn(async move {
+      if let Err(err) = viewers::delete(&db, id).await {
+        tracing::warn!("Failed to remove viewer {}: {}", id, err);
+      }
+    });
+  }
+
+  // refresh the viewers of this replica, and drop the ones replicas left behind when they stopped
+  pub fn keep_alive(&self) {
+    let presence = self.clone();
+    tokio::spawn(async move {
+      let mut interval = tokio::time::interval(EVENT_INTERVAL);
+      loop {
+        interval.tick().await;
+        let ids: Vec<Uuid> = presence.local.lock().unwrap().keys().copied().collect();
+        match viewers::touch(&presence.db, &ids).await {
+          Ok(alive) => presence.close_missing(&ids, &alive),
+          Err(err) => tracing::warn!("Failed to refresh viewers: {}", err),
+        }
+        match viewers::purge(&presence.db).await {
+          Ok(0) => {}
+          Ok(purged) => tracing::info!("Purged {} stale viewers", purged),
+          Err(err) => tracing::warn!("Failed to purge viewers: {}", err),
+        }
+      }
+    });
+  }
+
+  // close the local viewers whose rows were removed, e.g. by close_shared on another replica
+  fn close_missing(&self, touched: &[Uuid], alive: &[Uuid]) {
+    let local = self.local.lock().unwrap();
+    for id in touched.iter().filter(|id| !alive.contains(id)) {
+      if let Some(local) = local.get(id) {
+        local.closed.send_replace(true);
+      }
+    }
+  }
+
+  // "presence" events with the current counts, sent periodically
+  pub fn events(&self, game_id: Uuid) -> impl Stream<Item = Result<Event, anyhow::Error>> {
+    let presence = self.clone();
+    IntervalStream::new(tokio::time::interval(EVENT_INTERVAL)).filter_map(move |_| {
+      let presence = presence.clone();
+      async move {
+        // a failed lookup skips one update, the next one comes soon enough
+        let counts = presence.get(game_id).await.ok()?;
+        let status = PresenceStatus::from(counts);
+        Some(
+          serde_json::to_string(&status)
+            .map(|data| Event::default().event("presence").data(data))
+            .map_err(anyhow::Error::from),
+        )
+      }
+    })
+  }
+}
+
+// a connected subscriber, removed from the counts when dropped
+pub struct Viewer {
+  presence: Presence,
+  id: Uuid,
+  closed: watch::Receiver<bool>,
+}
+
+impl Viewer {
+  // resolves once the viewer was disconnected, its stream should end then
+  pub fn closed(&self) -> impl Future<Output = ()> + Send + 'static {
+    let mut closed = self.closed.clone();
+    async move {
+      let _ = closed.wait_for(|closed| *closed).await;
+    }
+  }
+}
+
+impl Drop for Viewer {
+  fn drop(&mut self) {
+    self.presence.leave(self.id);
+  }
+}
+
+impl FromRef<AppState> for Presence {
+  fn from_ref(state: &AppState) -> Self {
+    state.presence.clone()
+  }
+}
+
+// who is connected to a game right now, e.g. before starting it
+pub async fn get(
+  State(presence): State<Presence>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+) -> Response {
+  if !user.can_view(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  make_json_response(presence.get(game_id).await.map(PresenceStatus::from))
+}
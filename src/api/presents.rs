@@ -1,102 +1,307 @@
+use std::sync::Arc;
+
 use axum::{
   extract::{Path, Query, State},
   http::StatusCode,
   response::{IntoResponse, Response}, Json,
 };
+use serde::Deserialize;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
   auth::MyFirebaseUser,
   db::{
-    presents::{self, CreateParams, ReplaceParams, UpdateParams},
-    ListParams,
+    presents::{CreateParams, Present, PresentNumber, ReplaceParams, UpdateParams},
+    repo::PresentsRepo,
+    is_currency_code, BulkParams, BulkResult, ListParams, UpdateResult,
   },
+  i18n::Locale,
+};
+
+use super::{
+  check_fields, check_items, handle_db_error, make_created_response, make_json_response, make_page_response, ApiError,
 };
 
-use super::{handle_db_error, make_json_response};
+// prices stay hidden from players so they can be guessed, and authors from spectators
+pub fn redact(mut present: Present, user: &MyFirebaseUser) -> Present {
+  if !user.can_edit(present.game_id) {
+    present.price_cents = None;
+  }
+  if user.is_share_token() {
+    present.created_by = None;
+    present.updated_by = None;
+  }
+  present
+}
 
-// list presents
+/// list presents
+#[utoipa::path(
+  get,
+  operation_id = "list_presents",
+  path = "/games/{game_id}/presents",
+  tag = "presents",
+  params(("game_id" = Uuid, Path), ListParams),
+  responses(
+    (status = 200, body = Vec<Present>, headers(
+      ("X-Total-Count" = i64, description = "size of the whole list"),
+      ("X-Offset" = i64),
+      ("X-Limit" = i64),
+      ("X-Next-Cursor" = i64, description = "after_id of the next page"),
+      ("X-Has-More" = bool),
+    )),
+    (status = 400, body = ApiError),
+    (status = 403, body = ApiError),
+  )
+)]
 pub async fn list(
-  State(db): State<sqlx::PgPool>,
+  State(repo): State<Arc<dyn PresentsRepo>>,
   user: MyFirebaseUser,
+  locale: Locale,
   Path(game_id): Path<Uuid>,
   Query(p): Query<ListParams>,
 ) -> Response {
   if user.can_view(game_id) {
-    let res = presents::list(&db, game_id, p);
-    make_json_response(res.await)
+    let res = repo.list(game_id, p).await;
+    make_page_response(res.map(|page| page.map(|p| redact(p.localize(locale), &user))))
   } else {
     StatusCode::FORBIDDEN.into_response()
   }
 }
 
-// get a present
+/// get a present
+#[utoipa::path(
+  get,
+  operation_id = "get_present",
+  path = "/games/{game_id}/presents/{present_id}",
+  tag = "presents",
+  params(("game_id" = Uuid, Path), ("present_id" = i64, Path)),
+  responses(
+    (status = 200, body = Present),
+    (status = 403, body = ApiError),
+    (status = 404, body = ApiError),
+  )
+)]
 pub async fn get(
-  State(db): State<sqlx::PgPool>,
+  State(repo): State<Arc<dyn PresentsRepo>>,
   user: MyFirebaseUser,
+  locale: Locale,
   Path((game_id, present_id)): Path<(Uuid, i64)>,
 ) -> Response {
   if user.can_view(game_id) {
-    let res = presents::get(&db, present_id);
-    make_json_response(res.await)
+    let res = repo.get(present_id).await;
+    make_json_response(res.map(|p| redact(p.localize(locale), &user)))
   } else {
     StatusCode::FORBIDDEN.into_response()
   }
 }
 
-// create a present
+/// create a present
+#[utoipa::path(
+  post,
+  operation_id = "create_present",
+  path = "/games/{game_id}/presents",
+  tag = "presents",
+  params(("game_id" = Uuid, Path)),
+  request_body = CreateParams,
+  responses(
+    (status = 201, body = Present, headers(("Location" = String))),
+    (status = 400, body = ApiError),
+    (status = 403, body = ApiError),
+    (status = 422, body = ApiError, description = "some fields are invalid"),
+  )
+)]
 pub async fn create(
-  State(db): State<sqlx::PgPool>,
+  State(repo): State<Arc<dyn PresentsRepo>>,
   user: MyFirebaseUser,
+  locale: Locale,
   Path(game_id): Path<Uuid>,
   Json(p): Json<CreateParams>,
 ) -> Response {
   if user.can_edit(game_id) {
-    let res = presents::create(&db, game_id, p);
+    if let Err(err) = check_fields(&p) {
+      return err.into_response();
+    }
+    if !p.currency.as_deref().is_none_or(is_currency_code) {
+      return StatusCode::BAD_REQUEST.into_response();
+    }
+    match repo.create(game_id, p, &user.sub).await {
+      Ok(present) => make_created_response(
+        format!("/games/{}/presents/{}", game_id, present.id),
+        present.localize(locale),
+      ),
+      Err(err) => handle_db_error(err),
+    }
+  } else {
+    StatusCode::FORBIDDEN.into_response()
+  }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct DeleteManyData {
+  pub ids: Vec<i64>,
+}
+
+/// create many presents, ?mode=partial keeps the valid ones when some fail
+#[utoipa::path(
+  post,
+  operation_id = "create_presents",
+  path = "/games/{game_id}/presents/batch",
+  tag = "presents",
+  params(("game_id" = Uuid, Path), BulkParams),
+  request_body = Vec<CreateParams>,
+  responses(
+    (status = 200, body = BulkResult),
+    (status = 400, body = ApiError),
+    (status = 403, body = ApiError),
+    (status = 422, body = ApiError, description = "some fields are invalid"),
+  )
+)]
+pub async fn create_many(
+  State(repo): State<Arc<dyn PresentsRepo>>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+  Query(q): Query<BulkParams>,
+  Json(items): Json<Vec<CreateParams>>,
+) -> Response {
+  if user.can_edit(game_id) {
+    if let Err(err) = check_items(&items) {
+      return err.into_response();
+    }
+    if !items
+      .iter()
+      .all(|p| p.currency.as_deref().is_none_or(is_currency_code))
+    {
+      return StatusCode::BAD_REQUEST.into_response();
+    }
+    let res = repo.create_many(game_id, items, q.mode, &user.sub);
     make_json_response(res.await)
   } else {
     StatusCode::FORBIDDEN.into_response()
   }
 }
 
-// update a present
+/// delete many presents, ?mode=partial keeps going past failures
+#[utoipa::path(
+  delete,
+  operation_id = "delete_presents",
+  path = "/games/{game_id}/presents/batch",
+  tag = "presents",
+  params(("game_id" = Uuid, Path), BulkParams),
+  request_body = DeleteManyData,
+  responses(
+    (status = 200, body = BulkResult),
+    (status = 400, body = ApiError),
+    (status = 403, body = ApiError),
+  )
+)]
+pub async fn delete_many(
+  State(repo): State<Arc<dyn PresentsRepo>>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+  Query(q): Query<BulkParams>,
+  Json(data): Json<DeleteManyData>,
+) -> Response {
+  if user.can_edit(game_id) {
+    let res = repo.delete_many(game_id, data.ids, q.mode);
+    make_json_response(res.await)
+  } else {
+    StatusCode::FORBIDDEN.into_response()
+  }
+}
+
+/// update a present
+#[utoipa::path(
+  patch,
+  operation_id = "update_present",
+  path = "/games/{game_id}/presents/{present_id}",
+  tag = "presents",
+  params(("game_id" = Uuid, Path), ("present_id" = i64, Path)),
+  request_body = UpdateParams,
+  responses(
+    (status = 200, body = UpdateResult),
+    (status = 400, body = ApiError),
+    (status = 403, body = ApiError),
+    (status = 404, body = ApiError),
+    (status = 422, body = ApiError, description = "some fields are invalid"),
+  )
+)]
 pub async fn update(
-  State(db): State<sqlx::PgPool>,
+  State(repo): State<Arc<dyn PresentsRepo>>,
   user: MyFirebaseUser,
   Path((game_id, present_id)): Path<(Uuid, i64)>,
   Json(p): Json<UpdateParams>,
 ) -> Response {
   if user.can_edit(game_id) {
-    let res = presents::update(&db, present_id, p);
+    if let Err(err) = check_fields(&p) {
+      return err.into_response();
+    }
+    if !p.currency.as_deref().is_none_or(is_currency_code) {
+      return StatusCode::BAD_REQUEST.into_response();
+    }
+    let res = repo.update(present_id, p, &user.sub);
     make_json_response(res.await)
   } else {
     StatusCode::FORBIDDEN.into_response()
   }
 }
 
-// replace a present
+/// replace a present
+#[utoipa::path(
+  put,
+  operation_id = "replace_present",
+  path = "/games/{game_id}/presents/{present_id}",
+  tag = "presents",
+  params(("game_id" = Uuid, Path), ("present_id" = i64, Path)),
+  request_body = ReplaceParams,
+  responses(
+    (status = 200, body = UpdateResult),
+    (status = 400, body = ApiError),
+    (status = 403, body = ApiError),
+    (status = 404, body = ApiError),
+    (status = 422, body = ApiError, description = "some fields are invalid"),
+  )
+)]
 pub async fn replace(
-  State(db): State<sqlx::PgPool>,
+  State(repo): State<Arc<dyn PresentsRepo>>,
   user: MyFirebaseUser,
   Path((game_id, present_id)): Path<(Uuid, i64)>,
   Json(p): Json<ReplaceParams>,
 ) -> Response {
   if user.can_edit(game_id) {
-    let res = presents::replace(&db, present_id, p);
+    if let Err(err) = check_fields(&p) {
+      return err.into_response();
+    }
+    if !p.currency.as_deref().is_none_or(is_currency_code) {
+      return StatusCode::BAD_REQUEST.into_response();
+    }
+    let res = repo.replace(present_id, p, &user.sub);
     make_json_response(res.await)
   } else {
     StatusCode::FORBIDDEN.into_response()
   }
 }
 
-// delete a present
+/// delete a present
+#[utoipa::path(
+  delete,
+  operation_id = "delete_present",
+  path = "/games/{game_id}/presents/{present_id}",
+  tag = "presents",
+  params(("game_id" = Uuid, Path), ("present_id" = i64, Path)),
+  responses(
+    (status = 202),
+    (status = 403, body = ApiError),
+    (status = 404, body = ApiError),
+  )
+)]
 pub async fn delete(
-  State(db): State<sqlx::PgPool>,
+  State(repo): State<Arc<dyn PresentsRepo>>,
   user: MyFirebaseUser,
   Path((game_id, present_id)): Path<(Uuid, i64)>,
 ) -> Result<StatusCode, Response> {
   if user.can_edit(game_id) {
-    presents::delete(&db, present_id)
+    repo.delete(present_id)
       .await
       .map_err(handle_db_error)?;
     Ok(StatusCode::ACCEPTED)
@@ -104,3 +309,29 @@ pub async fn delete(
     Err(StatusCode::FORBIDDEN.into_response())
   }
 }
+
+/// shuffle present numbers before the game starts
+#[utoipa::path(
+  post,
+  operation_id = "shuffle_presents",
+  path = "/games/{game_id}/presents/shuffle",
+  tag = "presents",
+  params(("game_id" = Uuid, Path)),
+  responses(
+    (status = 200, body = Vec<PresentNumber>),
+    (status = 403, body = ApiError),
+    (status = 409, body = ApiError, description = "the game moved on or the rules forbid the action"),
+  )
+)]
+pub async fn shuffle(
+  State(repo): State<Arc<dyn PresentsRepo>>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+) -> Response {
+  if user.can_edit(game_id) {
+    let res = repo.shuffle(game_id, &user.sub);
+    make_json_response(res.await)
+  } else {
+    StatusCode::FORBIDDEN.into_response()
+  }
+}
@@ -0,0 +1,230 @@
+use std::{
+  collections::HashMap,
+  net::IpAddr,
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+use axum::{
+  extract::{FromRef, FromRequestParts, Request, State},
+  http::StatusCode,
+  middleware::Next,
+  response::{IntoResponse, Response},
+};
+use uuid::Uuid;
+
+use crate::{auth::MyFirebaseUser, error_code::ErrorCode};
+
+use super::{client_ip::ClientIp, ApiError, AppState};
+
+const WINDOW: Duration = Duration::from_secs(60);
+const MAX_TRACKED: usize = 10_000;
+// per user and minute, looking up accounts by email shouldn't turn into enumerating them
+const LOOKUPS_PER_MINUTE: u32 = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Bucket {
+  Requests,
+  Events,
+}
+
+struct Window {
+  started: Instant,
+  count: u32,
+}
+
+// per-game and per-address request counters, reset every minute
+#[derive(Clone)]
+pub struct Quotas {
+  requests_per_minute: u32,
+  events_per_minute: u32,
+  counters: Arc<Mutex<HashMap<(Uuid, Bucket), Window>>>,
+  ip_requests_per_minute: u32,
+  clients: Arc<Mutex<HashMap<IpAddr, Window>>>,
+  play_interval: Duration,
+  last_play: Arc<Mutex<HashMap<Uuid, Instant>>>,
+  lookups: Arc<Mutex<HashMap<String, Window>>>,
+}
+
+impl Quotas {
+  pub fn new(
+    requests_per_minute: u32,
+    events_per_minute: u32,
+    ip_requests_per_minute: u32,
+    play_interval: Duration,
+  ) -> Self {
+    Self {
+      requests_per_minute,
+      events_per_minute,
+      counters: Arc::new(Mutex::new(HashMap::new())),
+      ip_requests_per_minute,
+      clients: Arc::new(Mutex::new(HashMap::new())),
+      play_interval,
+      last_play: Arc::new(Mutex::new(HashMap::new())),
+      lookups: Arc::new(Mutex::new(HashMap::new())),
+    }
+  }
+
+  fn limit(&self, bucket: Bucket) -> u32 {
+    match bucket {
+      Bucket::Requests => self.requests_per_minute,
+      Bucket::Events => self.events_per_minute,
+    }
+  }
+
+  // count a request, returning the seconds until the window resets when over quota
+  pub fn hit(&self, game_id: Uuid, bucket: Bucket) -> Result<u32, u64> {
+    let limit = self.limit(bucket);
+    if limit == 0 {
+      return Ok(0);
+    }
+    count(&mut self.counters.lock().unwrap(), (game_id, bucket), limit)
+  }
+
+  // count a request of a client address, returning the seconds until the window resets when over quota
+  pub fn hit_client(&self, ip: IpAddr) -> Result<u32, u64> {
+    if self.ip_requests_per_minute == 0 {
+      return Ok(0);
+    }
+    count(
+      &mut self.clients.lock().unwrap(),
+      ip,
+      self.ip_requests_per_minute,
+    )
+  }
+
+  // count a user lookup, returning the seconds until the window resets when over quota
+  pub fn hit_lookup(&self, uid: &str) -> Result<u32, u64> {
+    count(
+      &mut self.lookups.lock().unwrap(),
+      uid.to_string(),
+      LOOKUPS_PER_MINUTE,
+    )
+  }
+}
+
+fn count<K: std::hash::Hash + Eq>(
+  counters: &mut HashMap<K, Window>,
+  key: K,
+  limit: u32,
+) -> Result<u32, u64> {
+  let now = Instant::now();
+  if counters.len() > MAX_TRACKED {
+    counters.retain(|_, w| now.duration_since(w.started) < WINDOW);
+  }
+  let window = counters.entry(key).or_insert(Window {
+    started: now,
+    count: 0,
+  });
+  if now.duration_since(window.started) >= WINDOW {
+    window.started = now;
+    window.count = 0;
+  }
+  if window.count >= limit {
+    let reset = WINDOW.saturating_sub(now.duration_since(window.started));
+    return Err(reset.as_secs().max(1));
+  }
+  window.count += 1;
+  Ok(window.count)
+}
+
+impl Quotas {
+  // allow one play action per interval and game, returning the time left when too early
+  pub fn throttle_play(&self, game_id: Uuid) -> Result<(), Duration> {
+    if self.play_interval.is_zero() {
+      return Ok(());
+    }
+    let now = Instant::now();
+    let mut last_play = self.last_play.lock().unwrap();
+    if last_play.len() > MAX_TRACKED {
+      last_play.retain(|_, at| now.duration_since(*at) < self.play_interval);
+    }
+    if let Some(at) = last_play.get(&game_id) {
+      let elapsed = now.duration_since(*at);
+      if elapsed < self.play_interval {
+        return Err(self.play_interval - elapsed);
+      }
+    }
+    last_play.insert(game_id, now);
+    Ok(())
+  }
+}
+
+// response for a play action that came in before the interval passed, checked after permissions
+pub fn play_throttled(wait: Duration) -> ApiError {
+  ApiError::new(
+    StatusCode::TOO_MANY_REQUESTS,
+    ErrorCode::PlayThrottled,
+    "Play actions are coming in too fast, try again in a moment",
+  )
+  .with_retry_after(wait.as_secs_f64().ceil().max(1.0) as u64)
+  .with_details(serde_json::json!({ "retry_after_ms": wait.as_millis() }))
+}
+
+impl FromRef<AppState> for Quotas {
+  fn from_ref(state: &AppState) -> Self {
+    state.quotas.clone()
+  }
+}
+
+// game id and quota bucket for /games/:game_id/*, /play/:game_id and /accept/:game_id
+fn classify(path: &str) -> Option<(Uuid, Bucket)> {
+  let mut segments = path.trim_start_matches('/').split('/');
+  let root = segments.next()?;
+  if !matches!(root, "games" | "play" | "accept") {
+    return None;
+  }
+  let game_id = Uuid::parse_str(segments.next()?).ok()?;
+  match segments.next() {
+    Some("events") if root == "games" => Some((game_id, Bucket::Events)),
+    _ => Some((game_id, Bucket::Requests)),
+  }
+}
+
+// enforce per-address and per-game quotas
+pub async fn enforce(State(state): State<AppState>, mut req: Request, next: Next) -> Response {
+  let quotas = &state.quotas;
+  let client_ip = req.extensions().get::<ClientIp>().and_then(|ip| ip.known());
+  if let Some(ip) = client_ip {
+    if let Err(retry_after) = quotas.hit_client(ip) {
+      tracing::warn!("Client {} exceeded its request quota", ip);
+      let err = ApiError::new(
+        StatusCode::TOO_MANY_REQUESTS,
+        ErrorCode::QuotaExceeded,
+        "Too many requests from this address, slow down",
+      );
+      return err.with_retry_after(retry_after).into_response();
+    }
+  }
+  if let Some((game_id, bucket)) = classify(req.uri().path()) {
+    // only callers that may view the game use up its quota, the handlers turn away the rest
+    let (mut parts, body) = req.into_parts();
+    let viewer = match MyFirebaseUser::from_request_parts(&mut parts, &state).await {
+      Ok(user) => {
+        let can_view = user.can_view(game_id);
+        parts.extensions.insert(user);
+        can_view
+      }
+      Err(_) => false,
+    };
+    req = Request::from_parts(parts, body);
+    if !viewer {
+      return next.run(req).await;
+    }
+    if let Err(retry_after) = quotas.hit(game_id, bucket) {
+      tracing::warn!(
+        "Game {} exceeded its {:?} quota (client {:?})",
+        game_id,
+        bucket,
+        client_ip
+      );
+      let err = ApiError::new(
+        StatusCode::TOO_MANY_REQUESTS,
+        ErrorCode::QuotaExceeded,
+        "Too many requests for this game, slow down",
+      );
+      return err.with_retry_after(retry_after).into_response();
+    }
+  }
+  next.run(req).await
+}
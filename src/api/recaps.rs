@@ -0,0 +1,42 @@
+use axum::{
+  extract::{Path, State},
+  http::StatusCode,
+  response::{IntoResponse, Response},
+};
+use uuid::Uuid;
+
+use crate::{auth::MyFirebaseUser, db::recaps};
+
+use super::make_json_response;
+
+// get the recap of a finished game
+pub async fn get(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+) -> Response {
+  if !user.can_view(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  make_json_response(recaps::get(&db, game_id).await)
+}
+
+// summary of a game so far, also while it is still being played
+pub async fn summary(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+) -> Response {
+  if !user.can_view(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  // prices stay hidden from players so they can be guessed, the recap shows them once done
+  let can_edit = user.can_edit(game_id);
+  make_json_response(recaps::summary(&db, game_id).await.map(|mut summary| {
+    if !can_edit {
+      summary.total_price_cents = None;
+      summary.average_price_cents = None;
+    }
+    summary
+  }))
+}
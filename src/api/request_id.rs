@@ -0,0 +1,54 @@
+use axum::{
+  body::Body,
+  extract::Request,
+  http::{header, HeaderName, HeaderValue},
+  middleware::Next,
+  response::Response,
+};
+use uuid::Uuid;
+
+use super::ApiError;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+const MAX_LEN: usize = 128;
+
+// identifies a request across the logs, error bodies and the client's bug report
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+// ids from proxies or clients are kept when they look sane, anything else is replaced
+fn incoming(req: &Request) -> Option<String> {
+  let id = req.headers().get(&REQUEST_ID_HEADER)?.to_str().ok()?;
+  let valid = !id.is_empty()
+    && id.len() <= MAX_LEN
+    && id
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+  valid.then(|| id.to_string())
+}
+
+// runs before tracing so the request span can pick up the id
+pub async fn assign(mut req: Request, next: Next) -> Response {
+  let id = incoming(&req).unwrap_or_else(|| Uuid::new_v4().to_string());
+  req.extensions_mut().insert(RequestId(id.clone()));
+  let res = next.run(req).await;
+
+  let (mut parts, body) = res.into_parts();
+  let body = match parts.extensions.get::<ApiError>().cloned() {
+    Some(err) => {
+      let err = ApiError {
+        request_id: Some(id.clone()),
+        ..err
+      };
+      parts.headers.remove(header::CONTENT_LENGTH);
+      let body = Body::from(serde_json::to_vec(&err).unwrap_or_default());
+      parts.extensions.insert(err);
+      body
+    }
+    None => body,
+  };
+  if let Ok(value) = HeaderValue::from_str(&id) {
+    parts.headers.insert(REQUEST_ID_HEADER, value);
+  }
+  Response::from_parts(parts, body)
+}
@@ -0,0 +1,332 @@
+use axum::{
+  extract::{Path, State},
+  http::{HeaderName, HeaderValue, Method, StatusCode},
+  response::{IntoResponse, Response, Sse},
+  Json,
+};
+use chrono::{DateTime, Utc};
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio_stream::wrappers::BroadcastStream;
+use uuid::Uuid;
+
+use crate::{
+  auth::MyFirebaseUser,
+  db::{
+    self,
+    events::PlayEvent,
+    games::{self, Game, PlayStream},
+  },
+  error_code::ErrorCode,
+  i18n::Locale,
+  rules::GameRules,
+  theme::GameTheme,
+};
+
+use super::{
+  activity::ActivityStream,
+  games::{heartbeat, play_event},
+  handle_db_error, internal_error, make_json_response,
+  presence::{Presence, Viewer},
+  ApiError,
+};
+
+pub const SHARE_TOKEN_HEADER: HeaderName = HeaderName::from_static("x-share-token");
+
+// the routes under /games/:game_id a spectator may read, each one leaves out uids
+const SHARED_ROUTES: [&str; 4] = ["", "/players", "/presents", "/stream"];
+
+// whether a share token may be used on a path, e.g. /games/:game_id/presents/3
+fn shared_route(path: &str) -> bool {
+  let Some(rest) = path.strip_prefix("/games/") else {
+    return false;
+  };
+  let (game_id, rest) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+  if Uuid::parse_str(game_id).is_err() {
+    return false;
+  }
+  let item = |prefix: &str| {
+    rest
+      .strip_prefix(prefix)
+      .and_then(|id| id.strip_prefix('/'))
+      .is_some_and(|id| id.parse::<i64>().is_ok())
+  };
+  SHARED_ROUTES.contains(&rest) || item("/players") || item("/presents")
+}
+
+// the read-only user a share token acts as, unknown and revoked tokens are rejected
+pub async fn authenticate(
+  db: &sqlx::PgPool,
+  method: &Method,
+  path: &str,
+  token: &HeaderValue,
+) -> Result<MyFirebaseUser, ApiError> {
+  if !matches!(*method, Method::GET | Method::HEAD) {
+    return Err(ApiError::new(
+      StatusCode::FORBIDDEN,
+      ErrorCode::PermissionDenied,
+      "Share tokens are read-only",
+    ));
+  }
+  if !shared_route(path) {
+    return Err(ApiError::new(
+      StatusCode::FORBIDDEN,
+      ErrorCode::PermissionDenied,
+      "Share tokens can only read the game, its players and presents, and follow its stream",
+    ));
+  }
+  let unauthorized = || {
+    ApiError::new(
+      StatusCode::UNAUTHORIZED,
+      ErrorCode::Unauthorized,
+      "Unknown or revoked share token",
+    )
+  };
+  let token = token.to_str().map_err(|_| unauthorized())?;
+  match games::find_shared(db, token).await {
+    Ok(shared) => Ok(MyFirebaseUser::for_share_token(shared.id)),
+    Err(db::Error::NotFound) => Err(unauthorized()),
+    Err(err) => Err(internal_error(err)),
+  }
+}
+
+#[derive(Serialize)]
+pub struct ShareStatus {
+  share_token: Option<String>,
+  viewer_limit: Option<i32>,
+  viewers: usize,
+}
+
+// the settings together with how many share-link viewers are connected
+async fn with_viewers(
+  settings: Result<games::ShareSettings, db::Error>,
+  presence: &Presence,
+  game_id: Uuid,
+) -> Result<ShareStatus, db::Error> {
+  let settings = settings?;
+  Ok(ShareStatus {
+    share_token: settings.share_token,
+    viewer_limit: settings.viewer_limit,
+    viewers: presence.count(game_id).await?,
+  })
+}
+
+// get the share link settings of a game
+pub async fn get(
+  State(db): State<sqlx::PgPool>,
+  State(presence): State<Presence>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+) -> Response {
+  if !user.can_edit(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  let res = games::get_share(&db, game_id).await;
+  make_json_response(with_viewers(res, &presence, game_id).await)
+}
+
+#[derive(Deserialize)]
+pub struct ShareData {
+  pub viewer_limit: Option<i32>,
+}
+
+// set or clear the viewer limit
+pub async fn update(
+  State(db): State<sqlx::PgPool>,
+  State(presence): State<Presence>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+  Json(data): Json<ShareData>,
+) -> Response {
+  if !user.can_edit(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  if matches!(data.viewer_limit, Some(limit) if limit < 0) {
+    return StatusCode::BAD_REQUEST.into_response();
+  }
+  let res = games::set_viewer_limit(&db, game_id, data.viewer_limit).await;
+  make_json_response(with_viewers(res, &presence, game_id).await)
+}
+
+// create a new share token, invalidating the previous one
+pub async fn rotate(
+  State(db): State<sqlx::PgPool>,
+  State(presence): State<Presence>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+) -> Response {
+  if !user.can_edit(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  let token = Uuid::new_v4().simple().to_string();
+  let res = games::set_share_token(&db, game_id, Some(&token)).await;
+  if res.is_ok() {
+    close_shared(&presence, game_id).await;
+  }
+  make_json_response(with_viewers(res, &presence, game_id).await)
+}
+
+// revoke the share token
+pub async fn revoke(
+  State(db): State<sqlx::PgPool>,
+  State(presence): State<Presence>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+) -> Result<StatusCode, Response> {
+  if !user.can_edit(game_id) {
+    return Err(StatusCode::FORBIDDEN.into_response());
+  }
+  games::set_share_token(&db, game_id, None)
+    .await
+    .map_err(handle_db_error)?;
+  close_shared(&presence, game_id).await;
+  Ok(StatusCode::ACCEPTED)
+}
+
+// the old token no longer works, so the streams opened with it end too
+async fn close_shared(presence: &Presence, game_id: Uuid) {
+  if let Err(err) = presence.close_shared(game_id).await {
+    tracing::warn!(
+      "Failed to close the shared streams of game {}: {}",
+      game_id,
+      err
+    );
+  }
+}
+
+// what a spectator sees of a game, without the uids of its members and authors
+#[derive(Serialize)]
+pub struct SharedGame {
+  pub id: Uuid,
+  pub name: String,
+  pub description: Option<String>,
+  pub images: Vec<String>,
+  pub player_id: Option<i64>,
+  pub present_id: Option<i64>,
+  pub started_at: Option<DateTime<Utc>>,
+  pub finished_at: Option<DateTime<Utc>>,
+  pub turn: i32,
+  pub turn_deadline: Option<DateTime<Utc>>,
+  pub event_seq: i64,
+  pub rules: GameRules,
+  pub theme: GameTheme,
+  pub budget_cents: Option<i64>,
+  pub currency: Option<String>,
+  pub scheduled_at: Option<DateTime<Utc>>,
+}
+
+impl From<Game> for SharedGame {
+  fn from(game: Game) -> Self {
+    SharedGame {
+      id: game.id,
+      name: game.name,
+      description: game.description,
+      images: game.images,
+      player_id: game.player_id,
+      present_id: game.present_id,
+      started_at: game.started_at,
+      finished_at: game.finished_at,
+      turn: game.turn,
+      turn_deadline: game.turn_deadline,
+      event_seq: game.event_seq,
+      rules: game.rules,
+      theme: game.theme,
+      budget_cents: game.budget_cents,
+      currency: game.currency,
+      scheduled_at: game.scheduled_at,
+    }
+  }
+}
+
+// view a shared game without signing in
+pub async fn view(
+  State(db): State<sqlx::PgPool>,
+  locale: Locale,
+  Path(token): Path<String>,
+) -> Response {
+  match games::find_shared(&db, &token).await {
+    Ok(shared) => make_json_response(
+      games::get(&db, shared.id)
+        .await
+        .map(|g| SharedGame::from(g.localize(locale))),
+    ),
+    Err(err) => handle_db_error(err),
+  }
+}
+
+// follow the play of a shared game, counted against its viewer limit
+pub async fn stream(
+  State(db): State<sqlx::PgPool>,
+  State(presence): State<Presence>,
+  State(play_stream): State<PlayStream>,
+  State(activity): State<ActivityStream>,
+  Path(token): Path<String>,
+) -> Response {
+  let shared = match games::find_shared(&db, &token).await {
+    Ok(shared) => shared,
+    Err(err) => return handle_db_error(err),
+  };
+  let viewer = match join(&presence, shared.id).await {
+    Ok(viewer) => viewer,
+    Err(err) => return err,
+  };
+
+  let game_id = shared.id;
+  let closed = viewer.closed();
+  let receiver = BroadcastStream::new(play_stream.subscribe(game_id));
+  let stream = receiver.map(move |message| {
+    // the viewer stays counted for as long as the stream is alive
+    let _ = &viewer;
+    play_event(game_id, message.map(redact_event))
+  });
+
+  let stream = stream::select(stream, activity.subscribe(game_id));
+  let stream = stream::select(stream, presence.events(game_id));
+  Sse::new(stream::select(stream, heartbeat()).take_until(closed)).into_response()
+}
+
+// spectators see what happened, not whose account did it
+pub fn redact_event(mut event: PlayEvent) -> PlayEvent {
+  event.actor_uid = None;
+  event
+}
+
+// count a share-link viewer against the viewer limit of the game
+pub async fn join(presence: &Presence, game_id: Uuid) -> Result<Viewer, Response> {
+  match presence.join(game_id).await {
+    Ok(Some(viewer)) => Ok(viewer),
+    Ok(None) => Err(
+      ApiError::new(
+        StatusCode::FORBIDDEN,
+        ErrorCode::ViewerLimitReached,
+        "This game has reached its viewer limit",
+      )
+      .into_response(),
+    ),
+    Err(err) => Err(handle_db_error(err)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn share_tokens_only_read_listed_routes() {
+    let game = "/games/6f1c2a0e-8d3b-4c55-9a70-1f2e3d4c5b6a";
+    for path in ["", "/players", "/presents/3", "/players/12", "/stream"] {
+      assert!(shared_route(&format!("{}{}", game, path)), "{}", path);
+    }
+    for path in [
+      "/ws",
+      "/events",
+      "/export",
+      "/presents/3/guesses",
+      "/players/x",
+    ] {
+      assert!(!shared_route(&format!("{}{}", game, path)), "{}", path);
+    }
+    assert!(!shared_route("/graphql/ws"));
+    assert!(!shared_route("/games/not-a-game"));
+  }
+}
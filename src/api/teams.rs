@@ -0,0 +1,113 @@
+use axum::{
+  extract::{Path, Query, State},
+  http::StatusCode,
+  response::{IntoResponse, Response},
+  Json,
+};
+use uuid::Uuid;
+
+use crate::{
+  auth::MyFirebaseUser,
+  db::{
+    teams::{self, CreateParams, ReplaceParams, UpdateParams},
+    ListParams,
+  },
+  theme::is_hex_color,
+};
+
+use super::{handle_db_error, make_json_response, make_page_response};
+
+fn valid_color(color: &Option<String>) -> bool {
+  color.as_deref().is_none_or(is_hex_color)
+}
+
+// list teams
+pub async fn list(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Query(p): Query<ListParams>,
+  Path(game_id): Path<Uuid>,
+) -> Response {
+  if user.can_view(game_id) {
+    make_page_response(teams::list(&db, game_id, p).await)
+  } else {
+    StatusCode::FORBIDDEN.into_response()
+  }
+}
+
+// get a team
+pub async fn get(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Path((game_id, team_id)): Path<(Uuid, i64)>,
+) -> Response {
+  if user.can_view(game_id) {
+    make_json_response(teams::get(&db, game_id, team_id).await)
+  } else {
+    StatusCode::FORBIDDEN.into_response()
+  }
+}
+
+// create a team
+pub async fn create(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+  Json(p): Json<CreateParams>,
+) -> Response {
+  if !user.can_edit(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  if !valid_color(&p.color) {
+    return StatusCode::BAD_REQUEST.into_response();
+  }
+  make_json_response(teams::create(&db, game_id, p).await)
+}
+
+// update a team
+pub async fn update(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Path((game_id, team_id)): Path<(Uuid, i64)>,
+  Json(p): Json<UpdateParams>,
+) -> Response {
+  if !user.can_edit(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  if !valid_color(&p.color) {
+    return StatusCode::BAD_REQUEST.into_response();
+  }
+  make_json_response(teams::update(&db, game_id, team_id, p).await)
+}
+
+// replace a team
+pub async fn replace(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Path((game_id, team_id)): Path<(Uuid, i64)>,
+  Json(p): Json<ReplaceParams>,
+) -> Response {
+  if !user.can_edit(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  if !valid_color(&p.color) {
+    return StatusCode::BAD_REQUEST.into_response();
+  }
+  make_json_response(teams::replace(&db, game_id, team_id, p).await)
+}
+
+// delete a team
+pub async fn delete(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Path((game_id, team_id)): Path<(Uuid, i64)>,
+) -> Result<StatusCode, Response> {
+  if user.can_edit(game_id) {
+    teams::delete(&db, game_id, team_id)
+      .await
+      .map_err(handle_db_error)?;
+    Ok(StatusCode::ACCEPTED)
+  } else {
+    Err(StatusCode::FORBIDDEN.into_response())
+  }
+}
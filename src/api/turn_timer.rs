@@ -0,0 +1,45 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::db::{
+  games::{self, GameStateUpdateResult},
+  Error,
+};
+
+// keep for the rolled player once the turn deadline passes, the play events reach clients
+// through the usual notify stream
+pub fn schedule(db: PgPool, game_id: Uuid, deadline: DateTime<Utc>) {
+  tokio::spawn(async move {
+    let wait = (deadline - Utc::now()).to_std().unwrap_or_default();
+    tokio::time::sleep(wait).await;
+    match games::auto_keep(&db, game_id, deadline).await {
+      Ok(Some(_)) => tracing::info!("Turn timer ran out in game {}", game_id),
+      Ok(None) => {}
+      Err(err) => tracing::warn!("Turn timer failed in game {}: {}", game_id, err),
+    }
+  });
+}
+
+// start the timer when a roll, or an undone keep or steal, opened a timed turn
+pub fn schedule_roll(db: &PgPool, game_id: Uuid, result: &Result<GameStateUpdateResult, Error>) {
+  if let Ok(GameStateUpdateResult {
+    turn_deadline: Some(deadline),
+    ..
+  }) = result
+  {
+    schedule(db.clone(), game_id, *deadline);
+  }
+}
+
+// pick up the timers of turns that were running before a restart
+pub async fn resume(db: &PgPool) {
+  match games::turn_timers(db).await {
+    Ok(timers) => {
+      for timer in timers {
+        schedule(db.clone(), timer.game_id, timer.turn_deadline);
+      }
+    }
+    Err(err) => tracing::warn!("Could not resume turn timers: {}", err),
+  }
+}
@@ -0,0 +1,78 @@
+use std::sync::Arc;
+
+use axum::{
+  async_trait,
+  extract::{FromRef, FromRequestParts, Request},
+  http::{request::Parts, Method},
+  middleware::Next,
+  response::{IntoResponse, Response},
+};
+use sqlx::{PgConnection, PgPool, Postgres, Transaction};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+use super::{internal_error, ApiError, AppState};
+
+type Slot = Arc<Mutex<Option<Transaction<'static, Postgres>>>>;
+
+#[derive(Clone, Default)]
+struct TxSlot(Slot);
+
+// a transaction shared by everything a mutating request does, committed only on success
+pub struct Tx(OwnedMutexGuard<Option<Transaction<'static, Postgres>>>);
+
+impl Tx {
+  pub fn conn(&mut self) -> &mut PgConnection {
+    self.0.as_mut().expect("transaction already finished")
+  }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Tx
+where
+  S: Send + Sync,
+  AppState: FromRef<S>,
+{
+  type Rejection = ApiError;
+
+  async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+    let Some(TxSlot(slot)) = parts.extensions.get::<TxSlot>().cloned() else {
+      return Err(internal_error(
+        "Transactions are only available on mutating requests",
+      ));
+    };
+    let mut guard = slot.lock_owned().await;
+    if guard.is_none() {
+      let pool = PgPool::from_ref(&AppState::from_ref(state));
+      let tx = pool.begin().await.map_err(internal_error)?;
+      *guard = Some(tx);
+    }
+    Ok(Tx(guard))
+  }
+}
+
+// commit the request transaction on 2xx, roll it back otherwise
+pub async fn manage(mut req: Request, next: Next) -> Response {
+  if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+    return next.run(req).await;
+  }
+  let slot = TxSlot::default();
+  req.extensions_mut().insert(slot.clone());
+  let res = next.run(req).await;
+
+  // handlers that never asked for a transaction leave the slot empty
+  let Some(tx) = slot.0.lock().await.take() else {
+    return res;
+  };
+  if !res.status().is_success() {
+    if let Err(err) = tx.rollback().await {
+      tracing::error!("Failed to roll back request transaction: {}", err);
+    }
+    return res;
+  }
+  match tx.commit().await {
+    Ok(()) => res,
+    Err(err) => {
+      internal_error(format!("Failed to commit request transaction: {}", err)).into_response()
+    }
+  }
+}
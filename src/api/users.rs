@@ -0,0 +1,64 @@
+use axum::{
+  extract::{Query, State},
+  http::StatusCode,
+  response::{IntoResponse, Response},
+  Json,
+};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::{
+  auth::{user::UserService, MyFirebaseUser},
+  error_code::ErrorCode,
+};
+
+use super::{check_fields, games::OWNER_PERMISSION, quota::Quotas, ApiError};
+
+#[derive(Deserialize, Validate)]
+pub struct LookupParams {
+  #[validate(email)]
+  pub email: String,
+}
+
+// just enough to add the user to a game and recognize them
+#[derive(Serialize)]
+pub struct FoundUser {
+  pub uid: String,
+  pub display_name: Option<String>,
+  pub photo_url: Option<String>,
+}
+
+// find the account of an email, for owners adding members
+pub async fn lookup(
+  State(claims_service): State<UserService>,
+  State(quotas): State<Quotas>,
+  user: MyFirebaseUser,
+  Query(p): Query<LookupParams>,
+) -> Response {
+  let owns_a_game = user.games.values().any(|p| *p >= OWNER_PERMISSION);
+  if user.is_api_key() || !owns_a_game {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  if let Err(err) = check_fields(&p) {
+    return err.into_response();
+  }
+  if let Err(retry_after) = quotas.hit_lookup(&user.sub) {
+    return ApiError::new(
+      StatusCode::TOO_MANY_REQUESTS,
+      ErrorCode::QuotaExceeded,
+      "Too many user lookups, slow down",
+    )
+    .with_retry_after(retry_after)
+    .into_response();
+  }
+  match claims_service.lookup_by_email(p.email.trim()).await {
+    Ok(Some(found)) if !found.disabled => Json(FoundUser {
+      uid: found.localId,
+      display_name: found.displayName,
+      photo_url: found.photoUrl,
+    })
+    .into_response(),
+    Ok(_) => StatusCode::NOT_FOUND.into_response(),
+    Err(err) => (StatusCode::BAD_GATEWAY, err.to_string()).into_response(),
+  }
+}
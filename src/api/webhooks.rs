@@ -0,0 +1,417 @@
+use std::{net::SocketAddr, time::Duration};
+
+use axum::{
+  extract::{Path, Query, State},
+  http::StatusCode,
+  response::{IntoResponse, Response},
+  Json,
+};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::{
+  auth::MyFirebaseUser,
+  db::{
+    events::{EventNames, GameEvent, PlayEvent, PlayEventRow},
+    webhooks::{
+      self, CreateParams, DueDelivery, UpdateParams, Webhook, FORMAT_DISCORD, FORMAT_SLACK,
+      MAX_ATTEMPTS,
+    },
+    ListParams,
+  },
+  error_code::ErrorCode,
+  validation::{is_public, webhook_url},
+};
+
+use super::{
+  check_fields, handle_db_error, make_created_response, make_json_response, make_page_response,
+  ApiError,
+};
+
+const SIGNATURE_HEADER: &str = "x-webhook-signature";
+const EVENT_HEADER: &str = "x-webhook-event";
+const DELIVERY_HEADER: &str = "x-webhook-delivery";
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+const BATCH_SIZE: i64 = 20;
+
+fn check_events(events: &[String]) -> Result<(), ApiError> {
+  match events.iter().find(|e| !webhooks::is_event_type(e)) {
+    Some(unknown) => Err(ApiError::new(
+      StatusCode::BAD_REQUEST,
+      ErrorCode::InvalidWebhookEvent,
+      format!("Unknown webhook event {}", unknown),
+    )),
+    None => Ok(()),
+  }
+}
+
+// the secret is only ever returned here, receivers use it to check the signature
+#[derive(Serialize)]
+pub struct CreatedWebhook {
+  #[serde(flatten)]
+  pub webhook: Webhook,
+  pub secret: String,
+}
+
+// list webhooks
+pub async fn list(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Query(p): Query<ListParams>,
+  Path(game_id): Path<Uuid>,
+) -> Response {
+  if user.can_edit(game_id) {
+    make_page_response(webhooks::list(&db, game_id, p).await)
+  } else {
+    StatusCode::FORBIDDEN.into_response()
+  }
+}
+
+// get a webhook
+pub async fn get(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Path((game_id, webhook_id)): Path<(Uuid, i64)>,
+) -> Response {
+  if user.can_edit(game_id) {
+    make_json_response(webhooks::get(&db, game_id, webhook_id).await)
+  } else {
+    StatusCode::FORBIDDEN.into_response()
+  }
+}
+
+// create a webhook
+pub async fn create(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+  Json(p): Json<CreateParams>,
+) -> Response {
+  if !user.can_edit(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  if let Err(err) = check_fields(&p).and_then(|_| check_events(&p.events)) {
+    return err.into_response();
+  }
+  let secret = format!(
+    "whsec_{}{}",
+    Uuid::new_v4().simple(),
+    Uuid::new_v4().simple()
+  );
+  match webhooks::create(&db, game_id, &secret, p).await {
+    Ok(webhook) => make_created_response(
+      format!("/games/{}/webhooks/{}", game_id, webhook.id),
+      CreatedWebhook { webhook, secret },
+    ),
+    Err(err) => handle_db_error(err),
+  }
+}
+
+// update a webhook
+pub async fn update(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Path((game_id, webhook_id)): Path<(Uuid, i64)>,
+  Json(p): Json<UpdateParams>,
+) -> Response {
+  if !user.can_edit(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  let events = p.events.as_deref().unwrap_or_default();
+  if let Err(err) = check_fields(&p).and_then(|_| check_events(events)) {
+    return err.into_response();
+  }
+  make_json_response(webhooks::update(&db, game_id, webhook_id, p).await)
+}
+
+// delete a webhook
+pub async fn delete(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Path((game_id, webhook_id)): Path<(Uuid, i64)>,
+) -> Result<StatusCode, Response> {
+  if !user.can_edit(game_id) {
+    return Err(StatusCode::FORBIDDEN.into_response());
+  }
+  webhooks::delete(&db, game_id, webhook_id)
+    .await
+    .map_err(handle_db_error)?;
+  Ok(StatusCode::ACCEPTED)
+}
+
+// the delivery log of a webhook
+pub async fn deliveries(
+  State(db): State<sqlx::PgPool>,
+  user: MyFirebaseUser,
+  Query(p): Query<ListParams>,
+  Path((game_id, webhook_id)): Path<(Uuid, i64)>,
+) -> Response {
+  if user.can_edit(game_id) {
+    make_page_response(webhooks::list_deliveries(&db, game_id, webhook_id, p).await)
+  } else {
+    StatusCode::FORBIDDEN.into_response()
+  }
+}
+
+// queue a game lifecycle event, a failure only costs the webhooks their notification
+pub async fn notify(db: &sqlx::PgPool, game_id: Uuid, event: &str, data: Value) {
+  if let Err(err) = webhooks::enqueue(db, game_id, event, data).await {
+    tracing::warn!("Failed to queue {} webhooks of {}: {}", event, game_id, err);
+  }
+}
+
+// `t=<unix seconds>,v1=<hex hmac-sha256 of "<t>.<body>">`, the timestamp lets receivers reject replays
+fn signature(secret: &str, timestamp: i64, body: &[u8]) -> String {
+  let mut mac =
+    Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any size");
+  mac.update(timestamp.to_string().as_bytes());
+  mac.update(b".");
+  mac.update(body);
+  format!(
+    "t={},v1={}",
+    timestamp,
+    hex::encode(mac.finalize().into_bytes())
+  )
+}
+
+fn play_event(delivery: &DueDelivery) -> Option<PlayEvent> {
+  if !delivery.event.starts_with("play.") {
+    return None;
+  }
+  serde_json::from_value::<PlayEventRow>(delivery.payload.clone())
+    .ok()
+    .and_then(|row| PlayEvent::try_from(row).ok())
+}
+
+// play events are queued as their row, receivers get the same shape as the event stream
+fn data(delivery: &DueDelivery) -> Value {
+  play_event(delivery)
+    .and_then(|event| serde_json::to_value(event).ok())
+    .unwrap_or_else(|| delivery.payload.clone())
+}
+
+// a line for the channel, e.g. "Alice stole Air Fryer from Bob!", None for events not worth a message
+fn announcement(names: &EventNames, event: &GameEvent) -> Option<String> {
+  let text = match event {
+    GameEvent::Started => "🎁 The game has started!".to_string(),
+    GameEvent::Rolled { player } => format!("🎲 {} rolled the dice", names.player(player)),
+    GameEvent::Picked { player, present } => {
+      format!("{} picked {}", names.player(player), names.present(present))
+    }
+    GameEvent::Kept { player, present } => {
+      format!("{} kept {}", names.player(player), names.present(present))
+    }
+    GameEvent::Stolen {
+      player,
+      present,
+      victim,
+      swapped,
+    } => {
+      let mut text = format!(
+        "😈 {} stole {} from {}!",
+        names.player(player),
+        names.present(present),
+        names.player(victim)
+      );
+      if let Some(swapped) = swapped {
+        text.push_str(&format!(
+          " {} gets {} in return",
+          names.player(victim),
+          names.present(swapped)
+        ));
+      }
+      text
+    }
+    GameEvent::Assigned {
+      player, present, ..
+    } => format!(
+      "{} now has {}",
+      names.player(player),
+      names.present(present)
+    ),
+    GameEvent::FinalSwap { player, present } => format!(
+      "{} made the final swap for {}",
+      names.player(player),
+      names.present(present)
+    ),
+    GameEvent::Undone { .. } => "↩️ The last move was undone".to_string(),
+    GameEvent::Finished => "🎄 The game is over, everyone has a present!".to_string(),
+    GameEvent::Reset => "The game was reset".to_string(),
+    GameEvent::Nudged { .. } => return None,
+  };
+  Some(text)
+}
+
+// the incoming webhook payload, names are user input so mentions and markup are neutralised
+fn chat_body(format: &str, text: &str) -> String {
+  match format {
+    FORMAT_SLACK => json!({
+      "text": text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;"),
+    }),
+    _ => json!({
+      "content": text,
+      "allowed_mentions": { "parse": [] },
+    }),
+  }
+  .to_string()
+}
+
+// None when the delivery has nothing to tell a chat channel
+async fn chat_message(db: &sqlx::PgPool, delivery: &DueDelivery) -> Option<String> {
+  let event = play_event(delivery)?;
+  let names = EventNames::load(db, delivery.game_id)
+    .await
+    .unwrap_or_default();
+  announcement(&names, &event.event).map(|text| chat_body(&delivery.format, &text))
+}
+
+// 30s, 1m, 2m, ... between attempts, None once the delivery is given up on
+fn retry_in(attempts: i32) -> Option<i64> {
+  (attempts < MAX_ATTEMPTS).then(|| 30 * 2_i64.pow(attempts.max(1) as u32 - 1))
+}
+
+// a client that only connects to the address the webhook host resolved to, refusing hosts
+// that resolve into our network. pinning keeps the name from being pointed elsewhere after the check
+async fn pinned_client(url: &str) -> Result<reqwest::Client, String> {
+  webhook_url(url).map_err(|_| "The webhook URL is not allowed".to_string())?;
+  let url = url::Url::parse(url).map_err(|err| err.to_string())?;
+  let mut builder = reqwest::Client::builder()
+    .timeout(SEND_TIMEOUT)
+    .redirect(reqwest::redirect::Policy::none())
+    .no_proxy();
+  if let Some(url::Host::Domain(domain)) = url.host() {
+    let port = url.port_or_known_default().unwrap_or(443);
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((domain, port))
+      .await
+      .map_err(|err| format!("Could not resolve {}: {}", domain, err))?
+      .collect();
+    let Some(addr) = addrs
+      .first()
+      .filter(|_| addrs.iter().all(|a| is_public(a.ip())))
+    else {
+      return Err(format!("{} does not resolve to a public address", domain));
+    };
+    builder = builder.resolve(domain, *addr);
+  }
+  builder.build().map_err(|err| err.to_string())
+}
+
+async fn send(db: &sqlx::PgPool, delivery: DueDelivery) {
+  let body = match delivery.format.as_str() {
+    FORMAT_DISCORD | FORMAT_SLACK => match chat_message(db, &delivery).await {
+      Some(body) => body,
+      None => {
+        if let Err(err) = webhooks::record_attempt(db, delivery.id, None, None, true, None).await {
+          tracing::error!("Failed to record webhook delivery {}: {}", delivery.id, err);
+        }
+        return;
+      }
+    },
+    _ => json!({
+      "id": delivery.id,
+      "event": delivery.event,
+      "game_id": delivery.game_id,
+      "created_at": delivery.created_at,
+      "data": data(&delivery),
+    })
+    .to_string(),
+  };
+  let attempts = delivery.attempts + 1;
+  let client = match pinned_client(&delivery.url).await {
+    Ok(client) => client,
+    Err(error) => {
+      let recorded = webhooks::record_attempt(
+        db,
+        delivery.id,
+        None,
+        Some(&error),
+        false,
+        retry_in(attempts),
+      )
+      .await;
+      if let Err(err) = recorded {
+        tracing::error!("Failed to record webhook delivery {}: {}", delivery.id, err);
+      }
+      return;
+    }
+  };
+  let res = client
+    .post(&delivery.url)
+    .header(reqwest::header::CONTENT_TYPE, "application/json")
+    .header(
+      SIGNATURE_HEADER,
+      signature(&delivery.secret.0, Utc::now().timestamp(), body.as_bytes()),
+    )
+    .header(EVENT_HEADER, &delivery.event)
+    .header(DELIVERY_HEADER, delivery.id)
+    .body(body)
+    .send()
+    .await;
+
+  let recorded = match res {
+    Ok(res) if res.status().is_success() => {
+      let status = Some(res.status().as_u16() as i16);
+      webhooks::record_attempt(db, delivery.id, status, None, true, None).await
+    }
+    Ok(res) => {
+      let status = Some(res.status().as_u16() as i16);
+      webhooks::record_attempt(db, delivery.id, status, None, false, retry_in(attempts)).await
+    }
+    Err(err) => {
+      let error = err.to_string();
+      webhooks::record_attempt(
+        db,
+        delivery.id,
+        None,
+        Some(&error),
+        false,
+        retry_in(attempts),
+      )
+      .await
+    }
+  };
+  if let Err(err) = recorded {
+    tracing::error!("Failed to record webhook delivery {}: {}", delivery.id, err);
+  }
+}
+
+// send due deliveries every few seconds, leases keep instances from sending the same one
+pub fn dispatch(db: sqlx::PgPool) {
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+      interval.tick().await;
+      let due = match webhooks::claim_due(&db, BATCH_SIZE).await {
+        Ok(due) => due,
+        Err(err) => {
+          tracing::warn!("Failed to claim webhook deliveries: {}", err);
+          continue;
+        }
+      };
+      for delivery in due {
+        send(&db, delivery).await;
+      }
+    }
+  });
+}
+
+// drop old deliveries every hour
+pub fn purge_hourly(db: sqlx::PgPool) {
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+      interval.tick().await;
+      match webhooks::purge(&db).await {
+        Ok(0) => {}
+        Ok(purged) => tracing::info!("Purged {} webhook deliveries", purged),
+        Err(err) => tracing::warn!("Failed to purge webhook deliveries: {}", err),
+      }
+    }
+  });
+}
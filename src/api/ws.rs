@@ -0,0 +1,202 @@
+use axum::{
+  extract::{
+    ws::{Message, WebSocket},
+    Path, State, WebSocketUpgrade,
+  },
+  http::StatusCode,
+  response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use crate::{
+  auth::MyFirebaseUser,
+  db::games::{self, GameStateUpdateResult},
+  error_code::ErrorCode,
+};
+
+use super::{
+  db_api_error, games::PresentData, presence::Kind, quota, turn_timer, ApiError, AppState,
+};
+
+// a play action sent by the client, e.g. {"id": 1, "action": "pick", "present_id": 7}
+#[derive(Deserialize)]
+struct Request {
+  // echoed back so clients can match replies to requests
+  id: Option<u64>,
+  #[serde(flatten)]
+  action: Action,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+  Roll,
+  Pick(PresentData),
+  Keep,
+  Steal(PresentData),
+}
+
+#[derive(Serialize)]
+struct Reply {
+  id: Option<u64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  result: Option<GameStateUpdateResult>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  error: Option<ApiError>,
+}
+
+// play events out, play actions in, over one connection
+pub async fn connect(
+  State(state): State<AppState>,
+  user: MyFirebaseUser,
+  Path(game_id): Path<Uuid>,
+  ws: WebSocketUpgrade,
+) -> Response {
+  if !user.can_view(game_id) {
+    return StatusCode::FORBIDDEN.into_response();
+  }
+  ws.on_upgrade(move |socket| session(socket, state, user, game_id))
+}
+
+async fn session(mut socket: WebSocket, state: AppState, user: MyFirebaseUser, game_id: Uuid) {
+  let mut events = state.play_stream.subscribe(game_id);
+  // the socket works without being counted, presence is informational for members
+  let _viewer = match state.presence.connect(game_id, Kind::Socket).await {
+    Ok(viewer) => Some(viewer),
+    Err(err) => {
+      tracing::warn!(
+        "Could not count the WebSocket for game {}: {}",
+        game_id,
+        err
+      );
+      None
+    }
+  };
+  loop {
+    tokio::select! {
+      event = events.recv() => {
+        let event = match event {
+          Ok(event) => event,
+          Err(RecvError::Lagged(n)) => {
+            tracing::warn!("WebSocket for game {} skipped {} events", game_id, n);
+            continue;
+          }
+          Err(RecvError::Closed) => break,
+        };
+        let Ok(data) = serde_json::to_string(&event) else {
+          continue;
+        };
+        if socket.send(Message::Text(data)).await.is_err() {
+          break;
+        }
+      }
+      message = socket.recv() => {
+        let text = match message {
+          Some(Ok(Message::Text(text))) => text,
+          Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+          Some(Ok(_)) => continue,
+        };
+        let reply = handle(&state, &user, game_id, &text).await;
+        let Ok(data) = serde_json::to_string(&reply) else {
+          continue;
+        };
+        if socket.send(Message::Text(data)).await.is_err() {
+          break;
+        }
+      }
+    }
+  }
+}
+
+async fn handle(state: &AppState, user: &MyFirebaseUser, game_id: Uuid, text: &str) -> Reply {
+  let request = match serde_json::from_str::<Request>(text) {
+    Ok(request) => request,
+    Err(err) => {
+      return Reply {
+        id: None,
+        result: None,
+        error: Some(ApiError::new(
+          StatusCode::BAD_REQUEST,
+          ErrorCode::InvalidBody,
+          err.to_string(),
+        )),
+      }
+    }
+  };
+  let (result, error) = match act(state, user, game_id, request.action).await {
+    Ok(result) => (Some(result), None),
+    Err(err) => (None, Some(err)),
+  };
+  Reply {
+    id: request.id,
+    result,
+    error,
+  }
+}
+
+// sockets outlive the memberships they were opened with, look them up again for every action
+async fn current(state: &AppState, user: &MyFirebaseUser) -> Result<MyFirebaseUser, ApiError> {
+  // api keys and share tokens carry their games themselves
+  if user.is_api_key() || user.is_share_token() {
+    return Ok(user.clone());
+  }
+  let mut user = user.clone();
+  user.games = state
+    .permissions
+    .games(&user, None)
+    .await
+    .map_err(db_api_error)?;
+  Ok(user)
+}
+
+// same checks as the POST /play routes, also used by the graphql mutations
+pub async fn act(
+  state: &AppState,
+  user: &MyFirebaseUser,
+  game_id: Uuid,
+  action: Action,
+) -> Result<GameStateUpdateResult, ApiError> {
+  let user = &current(state, user).await?;
+  if !user.can_play(game_id) {
+    return Err(ApiError::new(
+      StatusCode::FORBIDDEN,
+      ErrorCode::PermissionDenied,
+      "Not allowed to play this game",
+    ));
+  }
+  let maintenance = state.maintenance.status();
+  if maintenance.enabled {
+    return Err(
+      ApiError::new(
+        StatusCode::SERVICE_UNAVAILABLE,
+        ErrorCode::Maintenance,
+        maintenance
+          .message
+          .unwrap_or(String::from("Service is in read-only maintenance mode")),
+      )
+      .with_retry_after(maintenance.retry_after),
+    );
+  }
+  if let Err(wait) = state.quotas.throttle_play(game_id) {
+    return Err(quota::play_throttled(wait));
+  }
+
+  let db = &state.pool;
+  let result = match action {
+    Action::Roll => {
+      let result = games::roll(db, game_id, &user.sub).await;
+      turn_timer::schedule_roll(db, game_id, &result);
+      result
+    }
+    Action::Pick(data) => {
+      games::pick(db, game_id, data.present_id, data.expected(), &user.sub).await
+    }
+    Action::Keep => games::keep(db, game_id, &user.sub).await,
+    Action::Steal(data) => {
+      games::steal(db, game_id, data.present_id, data.expected(), &user.sub).await
+    }
+  };
+  result.map_err(db_api_error)
+}
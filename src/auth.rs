@@ -1,9 +1,13 @@
 pub mod firebase;
+pub mod oidc;
+pub mod permissions;
 pub mod user;
 
 use std::collections::HashMap;
 
+use axum::async_trait;
 use chrono::{DateTime, Utc};
+use firebase_auth::FirebaseAuth;
 pub use firebase::ServiceAccount;
 
 use serde::{Deserialize, Serialize};
@@ -12,10 +16,28 @@ use uuid::Uuid;
 
 use crate::api::games::{PLAY_PERMISSION, VIEW_PERMISSION, OWNER_PERMISSION};
 
+// firebase rejects custom claims longer than this
+const MAX_CLAIMS_LEN: usize = 1000;
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct CustomClaims {
   #[serde(rename = "g")]
   pub games: HashMap<String, i64>,
+  // support staff, grants the /admin routes and viewing any game.
+  // "admin" is accepted too for claims set by other tooling
+  #[serde(rename = "sa", alias = "admin", default, skip_serializing_if = "std::ops::Not::not")]
+  pub superadmin: bool,
+}
+
+impl CustomClaims {
+  // the games are only a hint for clients, they are left out once they don't fit
+  pub fn within_limit(mut self) -> Self {
+    let len = serde_json::to_string(&self).map_or(usize::MAX, |claims| claims.len());
+    if len > MAX_CLAIMS_LEN {
+      self.games.clear();
+    }
+    self
+  }
 }
 
 // impl<'de> Visitor<'de> for CustomClaims {
@@ -33,6 +55,19 @@ pub struct CustomClaims {
 // }
 
 
+// verifies the bearer tokens of signed-in users, chosen with AUTH_PROVIDER
+#[async_trait]
+pub trait AuthProvider: Send + Sync {
+  async fn verify(&self, token: &str) -> anyhow::Result<MyFirebaseUser>;
+}
+
+#[async_trait]
+impl AuthProvider for FirebaseAuth<MyFirebaseUser> {
+  async fn verify(&self, token: &str) -> anyhow::Result<MyFirebaseUser> {
+    FirebaseAuth::verify(self, token).map_err(|err| anyhow::anyhow!(err.to_string()))
+  }
+}
+
 /// The Jwt claims decoded from the user token. Can also be viewed as the Firebase User
 /// information.
 #[derive(Deserialize, Clone)]
@@ -51,9 +86,54 @@ pub struct MyFirebaseUser {
   pub email_verified: Option<bool>,
   #[serde(rename = "g", default)]
   pub games: HashMap<String, i64>,
+  #[serde(rename = "sa", alias = "admin", default)]
+  pub superadmin: bool,
+  // set when the request was authenticated with an api key instead of a token
+  #[serde(skip)]
+  pub api_key_id: Option<i64>,
 }
 
 impl MyFirebaseUser {
+  // requests without a firebase account act as a user with just these games
+  fn without_account(sub: String, games: HashMap<String, i64>, api_key_id: Option<i64>) -> Self {
+    let now = Utc::now().timestamp() as u64;
+    Self {
+      provider_id: None,
+      name: None,
+      picture: None,
+      iss: String::new(),
+      aud: String::new(),
+      auth_time: now,
+      user_id: sub.clone(),
+      sub,
+      iat: now,
+      exp: now,
+      email: None,
+      email_verified: None,
+      games,
+      superadmin: false,
+      api_key_id,
+    }
+  }
+
+  pub fn for_api_key(id: i64, games: HashMap<String, i64>) -> Self {
+    Self::without_account(format!("api-key:{}", id), games, Some(id))
+  }
+
+  // a spectator with a share token may only view that game
+  pub fn for_share_token(game_id: Uuid) -> Self {
+    let games = HashMap::from([(game_id.to_string(), VIEW_PERMISSION)]);
+    Self::without_account(format!("share:{}", game_id), games, None)
+  }
+
+  pub fn is_share_token(&self) -> bool {
+    self.sub.starts_with("share:")
+  }
+
+  pub fn is_api_key(&self) -> bool {
+    self.api_key_id.is_some()
+  }
+
   pub fn can_edit(&self, game_id: Uuid) -> bool {
     matches!(self.games.get(&game_id.to_string()), Some(p) if p.ge(&OWNER_PERMISSION))
   }
@@ -62,8 +142,14 @@ impl MyFirebaseUser {
     matches!(self.games.get(&game_id.to_string()), Some(p) if p.ge(&PLAY_PERMISSION))
   }
 
+  // support staff can look at any game to help its players, but not play or edit it
   pub fn can_view(&self, game_id: Uuid) -> bool {
-    matches!(self.games.get(&game_id.to_string()), Some(p) if p.ge(&VIEW_PERMISSION))
+    self.superadmin
+      || matches!(self.games.get(&game_id.to_string()), Some(p) if p.ge(&VIEW_PERMISSION))
+  }
+
+  pub fn is_superadmin(&self) -> bool {
+    self.superadmin
   }
 
   pub fn permission_level(&self, game_id: Uuid) -> i64 {
@@ -76,6 +162,7 @@ impl MyFirebaseUser {
   pub fn custom_claims(&self) -> CustomClaims {
     CustomClaims {
       games: self.games.clone(),
+      superadmin: self.superadmin,
     }
   }
 }
@@ -1,3 +1,5 @@
+use std::str::FromStr;
+
 use chrono::prelude::*;
 use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
 use serde::{Deserialize, Serialize};
@@ -28,11 +30,15 @@ pub struct ServiceAccount {
   pub universe_domain: String,
 }
 
-impl ServiceAccount {
-  pub fn from_str(s: &str) -> Self {
-    serde_json::from_str::<Self>(s).expect("FIREBASE_SERVICE_ACCOUNT should be valid JSON")
+impl FromStr for ServiceAccount {
+  type Err = serde_json::Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    serde_json::from_str(s)
   }
+}
 
+impl ServiceAccount {
   pub fn create_access_token(
     &self,
     expiry: chrono::Duration,
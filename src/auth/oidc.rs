@@ -0,0 +1,142 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+use axum::async_trait;
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, DecodingKey, Validation};
+use serde::Deserialize;
+use tokio::sync::RwLock;
+
+use super::{AuthProvider, MyFirebaseUser};
+use crate::config::OidcConfig;
+
+// unknown key ids refetch the keys at most this often, so bogus tokens can't hammer the issuer
+const MIN_REFRESH: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize)]
+struct Discovery {
+  jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct OidcClaims {
+  iss: String,
+  sub: String,
+  iat: u64,
+  exp: u64,
+  auth_time: Option<u64>,
+  name: Option<String>,
+  picture: Option<String>,
+  email: Option<String>,
+  email_verified: Option<bool>,
+}
+
+struct Keys {
+  set: JwkSet,
+  fetched_at: Instant,
+}
+
+// verifies tokens of any OpenID Connect issuer (Keycloak, Auth0, ...) against its published keys
+pub struct OidcProvider {
+  issuer: String,
+  audience: String,
+  jwks_uri: String,
+  http_client: reqwest::Client,
+  keys: RwLock<Keys>,
+}
+
+impl OidcProvider {
+  // find the issuer's keys through its discovery document
+  pub async fn discover(config: &OidcConfig) -> Result<Self> {
+    let http_client = reqwest::Client::new();
+    let url = format!(
+      "{}/.well-known/openid-configuration",
+      config.issuer.trim_end_matches('/')
+    );
+    let discovery: Discovery = http_client
+      .get(&url)
+      .send()
+      .await?
+      .error_for_status()?
+      .json()
+      .await?;
+    let set = fetch_keys(&http_client, &discovery.jwks_uri).await?;
+    Ok(Self {
+      issuer: config.issuer.clone(),
+      audience: config.audience.clone(),
+      jwks_uri: discovery.jwks_uri,
+      http_client,
+      keys: RwLock::new(Keys {
+        set,
+        fetched_at: Instant::now(),
+      }),
+    })
+  }
+
+  // the key a token was signed with, refetching the keys once when the issuer rotated them
+  async fn key(&self, kid: &str) -> Result<DecodingKey> {
+    {
+      let keys = self.keys.read().await;
+      if let Some(jwk) = keys.set.find(kid) {
+        return Ok(DecodingKey::from_jwk(jwk)?);
+      }
+      if keys.fetched_at.elapsed() < MIN_REFRESH {
+        bail!("Unknown key {}", kid);
+      }
+    }
+    let mut keys = self.keys.write().await;
+    if keys.set.find(kid).is_none() && keys.fetched_at.elapsed() >= MIN_REFRESH {
+      keys.set = fetch_keys(&self.http_client, &self.jwks_uri).await?;
+      keys.fetched_at = Instant::now();
+    }
+    let jwk = keys
+      .set
+      .find(kid)
+      .ok_or_else(|| anyhow!("Unknown key {}", kid))?;
+    Ok(DecodingKey::from_jwk(jwk)?)
+  }
+}
+
+async fn fetch_keys(http_client: &reqwest::Client, jwks_uri: &str) -> Result<JwkSet> {
+  Ok(
+    http_client
+      .get(jwks_uri)
+      .send()
+      .await?
+      .error_for_status()?
+      .json()
+      .await?,
+  )
+}
+
+#[async_trait]
+impl AuthProvider for OidcProvider {
+  async fn verify(&self, token: &str) -> Result<MyFirebaseUser> {
+    let header = decode_header(token)?;
+    let kid = header
+      .kid
+      .ok_or_else(|| anyhow!("Token without a key id"))?;
+    let key = self.key(&kid).await?;
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[&self.issuer]);
+    validation.set_audience(&[&self.audience]);
+    let claims = decode::<OidcClaims>(token, &key, &validation)?.claims;
+    // games come from game_members, superadmins are only granted through firebase claims
+    Ok(MyFirebaseUser {
+      provider_id: None,
+      name: claims.name,
+      picture: claims.picture,
+      iss: claims.iss,
+      aud: self.audience.clone(),
+      auth_time: claims.auth_time.unwrap_or(claims.iat),
+      user_id: claims.sub.clone(),
+      sub: claims.sub,
+      iat: claims.iat,
+      exp: claims.exp,
+      email: claims.email,
+      email_verified: claims.email_verified,
+      games: Default::default(),
+      superadmin: false,
+      api_key_id: None,
+    })
+  }
+}
@@ -0,0 +1,110 @@
+use std::{
+  collections::HashMap,
+  net::IpAddr,
+  sync::{Arc, Mutex},
+};
+
+use chrono::Utc;
+
+use super::MyFirebaseUser;
+use crate::db::{
+  self,
+  audit::{self, Actor},
+  email_invites, members,
+};
+
+// seconds a user's memberships are reused before reading them again
+const TTL: i64 = 30;
+const MAX_CACHED: usize = 10_000;
+
+struct Entry {
+  loaded_at: i64,
+  games: HashMap<String, i64>,
+}
+
+// the games of each user as stored in game_members, cached briefly per sub.
+// custom claims are capped at 1KB so they are only a hint: a token issued after
+// its entry was loaded that claims more than the entry has means it is stale
+#[derive(Clone)]
+pub struct Permissions {
+  db: sqlx::PgPool,
+  cache: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl Permissions {
+  pub fn new(db: sqlx::PgPool) -> Self {
+    Self {
+      db,
+      cache: Arc::new(Mutex::new(HashMap::new())),
+    }
+  }
+
+  pub async fn games(
+    &self,
+    user: &MyFirebaseUser,
+    client_ip: Option<IpAddr>,
+  ) -> Result<HashMap<String, i64>, db::Error> {
+    let sub = user.sub.as_str();
+    let now = Utc::now().timestamp();
+    if let Some(entry) = self.cache.lock().unwrap().get(sub) {
+      let covered = (user.iat as i64) < entry.loaded_at
+        || user
+          .games
+          .iter()
+          .all(|(game_id, p)| entry.games.get(game_id).is_some_and(|c| c >= p));
+      if now - entry.loaded_at < TTL && covered {
+        return Ok(entry.games.clone());
+      }
+    }
+
+    if let Some(email) = user
+      .email
+      .as_deref()
+      .filter(|_| user.email_verified == Some(true))
+    {
+      if let Err(err) = self.join_invited(sub, email, client_ip).await {
+        tracing::warn!("Failed to redeem the email invites of {}: {}", sub, err);
+      }
+    }
+    let games = members::games_of(&self.db, sub).await?;
+    let mut cache = self.cache.lock().unwrap();
+    if cache.len() > MAX_CACHED {
+      cache.retain(|_, entry| now - entry.loaded_at < TTL);
+    }
+    cache.insert(
+      sub.to_string(),
+      Entry {
+        loaded_at: now,
+        games: games.clone(),
+      },
+    );
+    Ok(games)
+  }
+
+  // games invited to by email before the user signed up
+  async fn join_invited(
+    &self,
+    sub: &str,
+    email: &str,
+    client_ip: Option<IpAddr>,
+  ) -> Result<(), db::Error> {
+    let mut tx = self.db.begin().await?;
+    let email = email.to_lowercase();
+    let actor = Actor {
+      uid: sub,
+      client_ip,
+      source: audit::INVITE,
+    };
+    let joined = email_invites::redeem(&mut tx, &actor, sub, &email, None).await?;
+    tx.commit().await?;
+    if !joined.is_empty() {
+      tracing::info!("{} joined {} games they were invited to", sub, joined.len());
+    }
+    Ok(())
+  }
+
+  // read the user's games again on their next request
+  pub fn forget(&self, sub: &str) {
+    self.cache.lock().unwrap().remove(sub);
+  }
+}
@@ -4,8 +4,10 @@ use reqwest::StatusCode;
 use serde_with::skip_serializing_none;
 use std::fmt::Debug;
 use std::ops::Add;
+use std::sync::Arc;
 use std::time::Duration;
 use std::{collections::HashMap, time::SystemTime};
+use tokio::sync::{Mutex, RwLock};
 
 use serde::{Deserialize, Serialize};
 use serde_with::{json::JsonString, serde_as};
@@ -30,7 +32,7 @@ struct FederatedUserIdentifier<'a> {
 
 #[allow(non_snake_case)]
 #[skip_serializing_none]
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Default)]
 struct AccountsLookupPayload<'a> {
   idToken: Option<&'a str>,
   localId: Option<Vec<&'a str>>,
@@ -46,17 +48,30 @@ struct AccountsLookupPayload<'a> {
 #[derive(Debug, Deserialize)]
 pub struct GetAccountInfoResponse {
   pub kind: String,
+  // left out when nothing matched
+  #[serde(default)]
   pub users: Vec<User>,
 }
 
+// renew the access token this long before google expires it
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct AccessToken {
+  auth_header: String,
+  expiry: SystemTime,
+}
+
+// clones share the access token, so each request doesn't mint its own
 #[derive(Debug, Clone)]
 pub struct UserService {
   sa: ServiceAccount,
   update_url: String,
   lookup_url: String,
   http_client: reqwest::Client,
-  auth_header: String,
-  id_token_expiry: SystemTime,
+  token: Arc<RwLock<Option<AccessToken>>>,
+  // held while fetching a token so concurrent requests wait for it instead of fetching too
+  refresh: Arc<Mutex<()>>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -79,8 +94,8 @@ impl UserService {
         api_key
       ),
       http_client: reqwest::Client::new(),
-      auth_header: String::from(""),
-      id_token_expiry: SystemTime::now(),
+      token: Arc::new(RwLock::new(None)),
+      refresh: Arc::new(Mutex::new(())),
     }
   }
 
@@ -107,26 +122,43 @@ impl UserService {
     }
   }
 
-  async fn get_auth_header(&mut self) -> Result<String> {
-    let now = SystemTime::now();
-    if self.id_token_expiry < now || self.auth_header.is_empty() {
-      let id_token = Self::fetch_id_token(self).await?;
-      self.auth_header = format!("{} {}", &id_token.token_type, &id_token.access_token);
-      self.id_token_expiry = now.add(Duration::from_secs(id_token.expires_in));
+  async fn cached_auth_header(&self) -> Option<String> {
+    match &*self.token.read().await {
+      Some(token) if token.expiry > SystemTime::now() => Some(token.auth_header.clone()),
+      _ => None,
+    }
+  }
+
+  async fn get_auth_header(&self) -> Result<String> {
+    if let Some(header) = self.cached_auth_header().await {
+      return Ok(header);
+    }
+    let _refreshing = self.refresh.lock().await;
+    // another request may have fetched one while this one waited
+    if let Some(header) = self.cached_auth_header().await {
+      return Ok(header);
     }
-    Ok(self.auth_header.clone())
+    let now = SystemTime::now();
+    let id_token = self.fetch_id_token().await?;
+    let auth_header = format!("{} {}", &id_token.token_type, &id_token.access_token);
+    let lifetime = Duration::from_secs(id_token.expires_in).saturating_sub(EXPIRY_MARGIN);
+    *self.token.write().await = Some(AccessToken {
+      auth_header: auth_header.clone(),
+      expiry: now.add(lifetime),
+    });
+    Ok(auth_header)
   }
 
-  pub async fn set_custom_attributes(&mut self, uid: &str, attr: CustomClaims) -> Result<()> {
-    self.get_auth_header().await?;
+  pub async fn set_custom_attributes(&self, uid: &str, attr: CustomClaims) -> Result<()> {
+    let auth_header = self.get_auth_header().await?;
     let res = self
       .http_client
       .post(&self.update_url)
-      .header(AUTHORIZATION, &self.auth_header)
+      .header(AUTHORIZATION, auth_header)
       .header(CONTENT_TYPE, "application/json")
       .json(&SetCustomAttributesPayload {
         localId: uid,
-        customAttributes: attr,
+        customAttributes: attr.within_limit(),
       })
       .send()
       .await?;
@@ -137,35 +169,46 @@ impl UserService {
     }
   }
 
-  pub async fn lookup(&mut self, uid: &str) -> Result<User> {
-    self.get_auth_header().await?;
+  pub async fn lookup(&self, uid: &str) -> Result<User> {
+    self
+      .accounts_lookup(AccountsLookupPayload {
+        localId: Some(vec![uid]),
+        ..Default::default()
+      })
+      .await?
+      .ok_or(anyhow!("Not found"))
+  }
+
+  // the account signed up with this email, if any
+  pub async fn lookup_by_email(&self, email: &str) -> Result<Option<User>> {
+    self
+      .accounts_lookup(AccountsLookupPayload {
+        email: Some(vec![email]),
+        ..Default::default()
+      })
+      .await
+  }
+
+  async fn accounts_lookup(&self, payload: AccountsLookupPayload<'_>) -> Result<Option<User>> {
+    let auth_header = self.get_auth_header().await?;
     let res = self
       .http_client
       .post(&self.lookup_url)
-      .header(AUTHORIZATION, &self.auth_header)
-      .json(&AccountsLookupPayload {
-        idToken: None,
-        localId: Some(vec![uid]),
-        email: None,
-        delegatedProjectNumber: None,
-        phoneNumber: None,
-        federatedUserId: None,
-        tenantId: None,
-        targetProjectId: None,
-        initialEmail: None,
-      })
+      .header(AUTHORIZATION, auth_header)
+      .json(&payload)
       .send()
       .await?;
 
     match res.status() {
-      StatusCode::OK => res
-        .json::<GetAccountInfoResponse>()
-        .await
-        .map_err(|err| anyhow!(err))?
-        .users
-        .into_iter()
-        .nth(0)
-        .ok_or(anyhow!("Not found")),
+      StatusCode::OK => Ok(
+        res
+          .json::<GetAccountInfoResponse>()
+          .await
+          .map_err(|err| anyhow!(err))?
+          .users
+          .into_iter()
+          .nth(0),
+      ),
       status => bail!("{} {}", status, res.text().await?),
     }
   }
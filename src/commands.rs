@@ -0,0 +1,77 @@
+use std::{env, fs::File, io::BufReader};
+
+use anyhow::{bail, Context, Result};
+use sqlx::{query_scalar, PgPool};
+use uuid::Uuid;
+
+use crate::{
+  config::Config,
+  crypto,
+  db::{backup, sealed},
+  MIGRATOR,
+};
+
+// evil-santa backup <file> [game_id ...]
+pub async fn backup(args: &[String]) -> Result<()> {
+  let Some((path, ids)) = args.split_first() else {
+    bail!("Usage: evil-santa backup <file> [game_id ...]");
+  };
+  let game_ids = match ids.is_empty() {
+    true => None,
+    false => Some(
+      ids
+        .iter()
+        .map(|id| Uuid::parse_str(id).with_context(|| format!("Invalid game id {}", id)))
+        .collect::<Result<Vec<_>>>()?,
+    ),
+  };
+
+  // never migrate here, backups are taken before upgrading
+  let db = connect().await?;
+  let schema = query_scalar("SELECT COALESCE(MAX(version), 0) FROM _sqlx_migrations WHERE success")
+    .fetch_one(&db)
+    .await?;
+  let archive = backup::backup(&db, game_ids, schema).await?;
+
+  let file = File::create(path).with_context(|| format!("Error creating {}", path))?;
+  serde_json::to_writer(file, &archive)?;
+  tracing::info!("Backed up {} games to {}", archive.games.len(), path);
+  Ok(())
+}
+
+// evil-santa restore <file> [--replace]
+pub async fn restore(args: &[String]) -> Result<()> {
+  let replace = args.iter().any(|a| a == "--replace");
+  let Some(path) = args.iter().find(|a| !a.starts_with("--")) else {
+    bail!("Usage: evil-santa restore <file> [--replace]");
+  };
+
+  let file = File::open(path).with_context(|| format!("Error opening {}", path))?;
+  let archive: backup::Archive = serde_json::from_reader(BufReader::new(file))
+    .with_context(|| format!("Error reading {}", path))?;
+
+  let db = connect().await?;
+  MIGRATOR.run(&db).await?;
+  let schema = MIGRATOR.iter().map(|m| m.version).max().unwrap_or_default();
+  backup::restore(&db, &archive, schema, replace).await?;
+  tracing::info!("Restored {} games from {}", archive.games.len(), path);
+  Ok(())
+}
+
+// evil-santa rotate-keys, run after putting a new key first in ENCRYPTION_KEYS
+pub async fn rotate_keys() -> Result<()> {
+  let config = Config::from_env();
+  crypto::init(&config.encryption_keys, &config.blind_index_key)?;
+  let Some(primary) = crypto::keyring().primary() else {
+    bail!("ENCRYPTION_KEYS is empty");
+  };
+  let db = connect().await?;
+  let rotated = sealed::rotate(&db).await?;
+  tracing::info!("Re-encrypted {} values with key {}", rotated, primary);
+  Ok(())
+}
+
+async fn connect() -> Result<PgPool> {
+  let db_url = env::var("DATABASE_URL").context("DATABASE_URL is missing from env")?;
+  Ok(PgPool::connect(&db_url).await?)
+}
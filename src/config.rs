@@ -0,0 +1,267 @@
+use std::{env, str::FromStr};
+
+use http::{header, HeaderName, HeaderValue};
+use ipnet::IpNet;
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, Any, CorsLayer};
+
+use crate::{
+  api::{client_ip::parse_net, request_id::REQUEST_ID_HEADER},
+  db,
+};
+
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+  // never enable in production, responses may leak SQL
+  pub debug_responses: bool,
+  // connections kept open and warmed up at startup, and the pool ceiling
+  pub db_min_connections: u32,
+  pub db_max_connections: u32,
+  pub maintenance_mode: bool,
+  pub maintenance_retry_after: u64,
+  // per game and minute, 0 disables the quota
+  pub game_requests_per_minute: u32,
+  pub game_event_polls_per_minute: u32,
+  // per client address and minute, 0 disables the quota
+  pub ip_requests_per_minute: u32,
+  // minimum time between play actions of a game, 0 disables the throttle
+  pub play_action_interval_ms: u64,
+  // play events a stream subscriber may fall behind before it has to resync
+  pub play_channel_capacity: usize,
+  // proxies allowed to set X-Forwarded-For / Forwarded
+  pub trusted_proxies: Vec<IpNet>,
+  // client ranges allowed on /admin and /metrics, empty allows all
+  pub admin_allowed_ips: Vec<IpNet>,
+  // how long the current player must be idle before they can be nudged
+  pub nudge_idle_seconds: i64,
+  // <id>:<base64 key> pairs, the first one encrypts new values
+  pub encryption_keys: Vec<String>,
+  // keys the hashes sealed columns are looked up by, must be the same on every instance
+  pub blind_index_key: String,
+  // larger request bodies are rejected with 413
+  pub max_body_bytes: usize,
+  pub cors: CorsConfig,
+  // serve https directly, for setups without a reverse proxy
+  pub tls: Option<TlsConfig>,
+  // signs invite link tokens, must be the same on every instance
+  pub invite_secret: String,
+  // emails about invites, game starts and results, disabled when unset
+  pub email: Option<EmailConfig>,
+  // who signs the bearer tokens of users
+  pub auth: AuthConfig,
+  // unfinished games a user may own at once, 0 means no limit
+  pub max_active_games: i64,
+  // largest limit a list request may ask for
+  pub max_list_limit: i64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub enum AuthConfig {
+  #[default]
+  Firebase,
+  Oidc(OidcConfig),
+}
+
+#[derive(Clone, Debug)]
+pub struct OidcConfig {
+  // e.g. https://keycloak.example.com/realms/santa, tokens must have it as iss
+  pub issuer: String,
+  // the client id tokens are issued for
+  pub audience: String,
+}
+
+impl AuthConfig {
+  fn from_env() -> Self {
+    match env::var("AUTH_PROVIDER").unwrap_or_default().as_str() {
+      "" | "firebase" => Self::Firebase,
+      "oidc" => Self::Oidc(OidcConfig {
+        issuer: env::var("OIDC_ISSUER").expect("OIDC_ISSUER is missing from env"),
+        audience: env::var("OIDC_AUDIENCE").expect("OIDC_AUDIENCE is missing from env"),
+      }),
+      other => panic!("Unknown AUTH_PROVIDER {}", other),
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct EmailConfig {
+  pub sendgrid_api_key: String,
+  // sender address, must be verified with SendGrid
+  pub from: String,
+  // web client base url, emails link to <app_url>/games/<id>
+  pub app_url: String,
+}
+
+impl EmailConfig {
+  fn from_env() -> Option<Self> {
+    let sendgrid_api_key = env::var("SENDGRID_API_KEY")
+      .ok()
+      .filter(|s| !s.is_empty())?;
+    Some(Self {
+      sendgrid_api_key,
+      from: env::var("EMAIL_FROM").expect("EMAIL_FROM is missing from env"),
+      app_url: env::var("APP_URL")
+        .unwrap_or_default()
+        .trim_end_matches('/')
+        .to_string(),
+    })
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+  // PEM files, the certificate may include the chain
+  pub cert_path: String,
+  pub key_path: String,
+}
+
+impl TlsConfig {
+  fn from_env() -> Option<Self> {
+    let cert_path = env::var("TLS_CERT_PATH").ok().filter(|s| !s.is_empty());
+    let key_path = env::var("TLS_KEY_PATH").ok().filter(|s| !s.is_empty());
+    match (cert_path, key_path) {
+      (Some(cert_path), Some(key_path)) => Some(Self {
+        cert_path,
+        key_path,
+      }),
+      (None, None) => None,
+      _ => panic!("TLS_CERT_PATH and TLS_KEY_PATH must be set together"),
+    }
+  }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CorsConfig {
+  // exact origins or *.domain patterns, empty or * allows any origin (dev)
+  pub allowed_origins: Vec<String>,
+  pub allow_credentials: bool,
+}
+
+// headers the web client reads from responses
+const EXPOSED_HEADERS: [HeaderName; 12] = [
+  header::ETAG,
+  header::LOCATION,
+  header::RETRY_AFTER,
+  header::CONTENT_LANGUAGE,
+  header::CONTENT_DISPOSITION,
+  HeaderName::from_static("x-total-count"),
+  HeaderName::from_static("x-offset"),
+  HeaderName::from_static("x-limit"),
+  HeaderName::from_static("x-has-more"),
+  HeaderName::from_static("x-next-cursor"),
+  HeaderName::from_static("idempotent-replayed"),
+  REQUEST_ID_HEADER,
+];
+
+impl CorsConfig {
+  fn from_env() -> Self {
+    Self {
+      allowed_origins: env_list("ALLOWED_ORIGINS"),
+      allow_credentials: env_flag("CORS_ALLOW_CREDENTIALS"),
+    }
+  }
+
+  fn allows_any(&self) -> bool {
+    self.allowed_origins.is_empty() || self.allowed_origins.iter().any(|o| o == "*")
+  }
+
+  pub fn layer(&self) -> CorsLayer {
+    let layer = CorsLayer::new().expose_headers(EXPOSED_HEADERS);
+    if !self.allow_credentials {
+      let layer = layer.allow_methods(Any).allow_headers(Any);
+      return if self.allows_any() {
+        layer.allow_origin(Any)
+      } else {
+        layer.allow_origin(self.allow_origin())
+      };
+    }
+    // browsers reject wildcards on credentialed requests, echo the request instead
+    let layer = layer
+      .allow_credentials(true)
+      .allow_methods(AllowMethods::mirror_request())
+      .allow_headers(AllowHeaders::mirror_request());
+    if self.allows_any() {
+      layer.allow_origin(AllowOrigin::mirror_request())
+    } else {
+      layer.allow_origin(self.allow_origin())
+    }
+  }
+
+  fn allow_origin(&self) -> AllowOrigin {
+    let origins = self.allowed_origins.clone();
+    AllowOrigin::predicate(move |origin: &HeaderValue, _| {
+      let Ok(origin) = origin.to_str() else {
+        return false;
+      };
+      origins
+        .iter()
+        .any(|allowed| origin_matches(allowed, origin))
+    })
+  }
+}
+
+// https://*.example.com matches subdomains of example.com but not example.com itself
+fn origin_matches(allowed: &str, origin: &str) -> bool {
+  match allowed.split_once("*.") {
+    Some((scheme, domain)) => origin
+      .strip_prefix(scheme)
+      .and_then(|host| host.strip_suffix(domain))
+      .is_some_and(|sub| sub.ends_with('.') && sub.len() > 1 && !sub.contains('/')),
+    None => allowed.eq_ignore_ascii_case(origin),
+  }
+}
+
+impl Config {
+  pub fn from_env() -> Self {
+    Self {
+      debug_responses: env_flag("DEBUG_RESPONSES"),
+      db_min_connections: env_parse("DB_MIN_CONNECTIONS").unwrap_or(2),
+      db_max_connections: env_parse("DB_MAX_CONNECTIONS").unwrap_or(10),
+      maintenance_mode: env_flag("MAINTENANCE_MODE"),
+      maintenance_retry_after: env_parse("MAINTENANCE_RETRY_AFTER").unwrap_or(300),
+      game_requests_per_minute: env_parse("GAME_REQUESTS_PER_MINUTE").unwrap_or(600),
+      game_event_polls_per_minute: env_parse("GAME_EVENT_POLLS_PER_MINUTE").unwrap_or(120),
+      ip_requests_per_minute: env_parse("IP_REQUESTS_PER_MINUTE").unwrap_or(1200),
+      play_action_interval_ms: env_parse("PLAY_ACTION_INTERVAL_MS").unwrap_or(1000),
+      play_channel_capacity: env_parse("PLAY_CHANNEL_CAPACITY").unwrap_or(10),
+      trusted_proxies: env_list("TRUSTED_PROXIES")
+        .iter()
+        .map(|s| parse_net(s).unwrap_or_else(|| panic!("Invalid TRUSTED_PROXIES entry {}", s)))
+        .collect(),
+      admin_allowed_ips: env_list("ADMIN_ALLOWED_IPS")
+        .iter()
+        .map(|s| parse_net(s).unwrap_or_else(|| panic!("Invalid ADMIN_ALLOWED_IPS entry {}", s)))
+        .collect(),
+      nudge_idle_seconds: env_parse("NUDGE_IDLE_SECONDS").unwrap_or(60),
+      encryption_keys: env_list("ENCRYPTION_KEYS"),
+      blind_index_key: env::var("BLIND_INDEX_KEY").unwrap_or_default(),
+      max_body_bytes: env_parse("MAX_BODY_BYTES").unwrap_or(1024 * 1024),
+      cors: CorsConfig::from_env(),
+      tls: TlsConfig::from_env(),
+      invite_secret: env::var("INVITE_SECRET").unwrap_or_default(),
+      email: EmailConfig::from_env(),
+      auth: AuthConfig::from_env(),
+      max_active_games: env_parse("MAX_ACTIVE_GAMES").unwrap_or(0),
+      max_list_limit: env_parse("MAX_LIST_LIMIT").unwrap_or(db::DEFAULT_MAX_LIMIT),
+    }
+  }
+}
+
+fn env_flag(key: &str) -> bool {
+  matches!(
+    env::var(key).map(|v| v.to_lowercase()).as_deref(),
+    Ok("1" | "true" | "yes" | "on")
+  )
+}
+
+fn env_list(key: &str) -> Vec<String> {
+  env::var(key)
+    .unwrap_or_default()
+    .split(',')
+    .map(|s| s.trim().to_string())
+    .filter(|s| !s.is_empty())
+    .collect()
+}
+
+fn env_parse<T: FromStr>(key: &str) -> Option<T> {
+  env::var(key).ok().and_then(|v| v.parse().ok())
+}
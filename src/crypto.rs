@@ -0,0 +1,159 @@
+use std::sync::OnceLock;
+
+use aes_gcm::{
+  aead::{Aead, AeadCore, KeyInit, OsRng},
+  Aes256Gcm, Key, Nonce,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::{
+  encode::IsNull,
+  error::BoxDynError,
+  postgres::{PgArgumentBuffer, PgTypeInfo, PgValueRef},
+  Decode, Encode, Postgres, Type,
+};
+
+const PREFIX: &str = "enc";
+const NONCE_LEN: usize = 12;
+
+static KEYRING: OnceLock<Keyring> = OnceLock::new();
+
+#[derive(thiserror::Error, Debug)]
+pub enum CryptoError {
+  #[error("Invalid encryption key {0}, expected <id>:<base64 32 byte key>")]
+  InvalidKey(String),
+  #[error("Unknown encryption key {0}")]
+  UnknownKey(String),
+  #[error("Malformed encrypted value")]
+  Malformed,
+}
+
+// the first key encrypts, every key decrypts so old values stay readable while rotating
+pub struct Keyring {
+  keys: Vec<(String, Aes256Gcm)>,
+  // never rotated, blind indexes would stop matching
+  index_key: Vec<u8>,
+}
+
+impl Keyring {
+  pub fn new(entries: &[String], index_key: &str) -> Result<Self, CryptoError> {
+    let keys = entries
+      .iter()
+      .map(|entry| {
+        let invalid =
+          || CryptoError::InvalidKey(entry.split(':').next().unwrap_or_default().into());
+        let (id, key) = entry.split_once(':').ok_or_else(invalid)?;
+        let key = STANDARD.decode(key).map_err(|_| invalid())?;
+        if id.is_empty() || id.contains(':') || key.len() != 32 {
+          return Err(invalid());
+        }
+        Ok((
+          id.to_string(),
+          Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+        ))
+      })
+      .collect::<Result<_, _>>()?;
+    Ok(Self {
+      keys,
+      index_key: index_key.as_bytes().to_vec(),
+    })
+  }
+
+  pub fn primary(&self) -> Option<&str> {
+    self.keys.first().map(|(id, _)| id.as_str())
+  }
+
+  // enc:<key id>:<base64 nonce and ciphertext>, plain text when no key is configured
+  pub fn seal(&self, plain: &str) -> String {
+    let Some((id, cipher)) = self.keys.first() else {
+      return plain.to_string();
+    };
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let mut data = nonce.to_vec();
+    data.extend(
+      cipher
+        .encrypt(&nonce, plain.as_bytes())
+        .expect("AES-GCM encryption of an in-memory buffer"),
+    );
+    format!("{}:{}:{}", PREFIX, id, STANDARD.encode(data))
+  }
+
+  // values written before encryption was enabled are passed through
+  pub fn open(&self, stored: &str) -> Result<String, CryptoError> {
+    let Some(rest) = stored
+      .strip_prefix(PREFIX)
+      .and_then(|s| s.strip_prefix(':'))
+    else {
+      return Ok(stored.to_string());
+    };
+    let (id, data) = rest.split_once(':').ok_or(CryptoError::Malformed)?;
+    let (_, cipher) = self
+      .keys
+      .iter()
+      .find(|(key_id, _)| key_id == id)
+      .ok_or_else(|| CryptoError::UnknownKey(id.to_string()))?;
+    let data = STANDARD.decode(data).map_err(|_| CryptoError::Malformed)?;
+    if data.len() < NONCE_LEN {
+      return Err(CryptoError::Malformed);
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    let plain = cipher
+      .decrypt(Nonce::from_slice(nonce), ciphertext)
+      .map_err(|_| CryptoError::Malformed)?;
+    String::from_utf8(plain).map_err(|_| CryptoError::Malformed)
+  }
+
+  // hex hmac-sha256 of a value, stored next to a sealed column so rows can be found by it
+  pub fn blind_index(&self, value: &str) -> String {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.index_key)
+      .expect("hmac accepts keys of any size");
+    mac.update(value.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+  }
+
+  // whether a stored value should be re-encrypted with the primary key
+  pub fn is_stale(&self, stored: &str) -> bool {
+    match self.primary() {
+      Some(id) => !stored.starts_with(&format!("{}:{}:", PREFIX, id)),
+      None => false,
+    }
+  }
+}
+
+pub fn init(entries: &[String], index_key: &str) -> Result<(), CryptoError> {
+  let _ = KEYRING.set(Keyring::new(entries, index_key)?);
+  Ok(())
+}
+
+pub fn keyring() -> &'static Keyring {
+  KEYRING.get_or_init(|| Keyring {
+    keys: Vec::new(),
+    index_key: Vec::new(),
+  })
+}
+
+// a text column encrypted at rest, plain text everywhere else
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(transparent)]
+pub struct Sealed(pub String);
+
+impl Type<Postgres> for Sealed {
+  fn type_info() -> PgTypeInfo {
+    <String as Type<Postgres>>::type_info()
+  }
+}
+
+impl Encode<'_, Postgres> for Sealed {
+  fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> IsNull {
+    <String as Encode<Postgres>>::encode(keyring().seal(&self.0), buf)
+  }
+}
+
+impl<'r> Decode<'r, Postgres> for Sealed {
+  fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+    let stored = <&str as Decode<Postgres>>::decode(value)?;
+    Ok(Sealed(keyring().open(stored)?))
+  }
+}
@@ -1,11 +1,35 @@
-use chrono::NaiveDateTime;
+use std::{future::Future, sync::OnceLock};
+
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{Postgres, QueryBuilder};
+use sqlx::{postgres::PgRow, FromRow, Postgres, QueryBuilder, Row};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::error_code::ErrorCode;
 
+pub mod activity;
+pub mod api_keys;
+pub mod audit;
+pub mod backup;
+pub mod email_invites;
+pub mod events;
+pub mod export;
 pub mod games;
+pub mod guesses;
+pub mod idempotency;
+pub mod import;
+pub mod invites;
+pub mod members;
+pub mod notifications;
 pub mod players;
 pub mod presents;
+pub mod recaps;
+pub mod repo;
+pub mod sealed;
 pub mod sqlx_macro;
+pub mod teams;
+pub mod viewers;
+pub mod webhooks;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -13,42 +37,274 @@ pub enum Error {
   NotFound,
   #[error("Empty update set")]
   Empty,
-  #[error("Invalid order param")]
-  InvalidOrder,
+  #[error("Invalid order param: {column}")]
+  InvalidOrder {
+    column: String,
+    allowed: Vec<String>,
+  },
+  #[error("This list does not support cursors")]
+  CursorUnsupported,
+  #[error("Game has already started")]
+  GameStarted,
+  #[error("Stealing is disabled for this game")]
+  StealingDisabled,
+  #[error("Present is immune from stealing until turn {until_turn}")]
+  PresentImmune { until_turn: i32 },
+  #[error("Present was already stolen {limit} times")]
+  StealLimitReached { limit: u32 },
+  #[error("Present was just stolen from this player and cannot be stolen back")]
+  StealBackForbidden,
+  #[error("Price guessing is disabled for this game")]
+  GuessingDisabled,
+  #[error("Guessing is closed once a present is unwrapped")]
+  GuessingClosed,
+  #[error("Players can only guess for themselves")]
+  NotOwnPlayer,
+  #[error("No player is taking a turn")]
+  NoActivePlayer,
+  #[error("Player can be nudged in {retry_after} seconds")]
+  PlayerNotIdle { retry_after: i64 },
+  #[error("Player was already nudged this turn")]
+  AlreadyNudged,
+  #[error("Game state changed since it was read")]
+  StateChanged {
+    player_id: Option<i64>,
+    event_seq: i64,
+  },
+  #[error("There is no play action to undo")]
+  NothingToUndo,
+  #[error("Player already has present {present_id}")]
+  PlayerHasPresent { present_id: i64 },
+  #[error("Price is above the game budget of {budget_cents} cents")]
+  OverBudget { budget_cents: i64 },
+  #[error("Price must be in the game currency {currency}")]
+  CurrencyMismatch { currency: String },
+  #[error("Game was changed since it was read")]
+  VersionMismatch,
+  #[error("User is already a member of this game")]
+  AlreadyMember,
+  #[error("A game must keep at least one owner")]
+  LastOwner,
   #[error("Unknown error")]
   Unknown,
   #[error("Unknown sqlx error {0}")]
   Sqlx(#[from] sqlx::Error),
 }
 
-#[derive(Deserialize, Debug)]
+impl Error {
+  // stable identifier used by clients and the i18n catalog
+  pub fn code(&self) -> ErrorCode {
+    match self {
+      Error::NotFound => ErrorCode::NotFound,
+      Error::Empty => ErrorCode::EmptyUpdate,
+      Error::InvalidOrder { .. } => ErrorCode::InvalidOrder,
+      Error::CursorUnsupported => ErrorCode::CursorUnsupported,
+      Error::GameStarted => ErrorCode::GameAlreadyStarted,
+      Error::StealingDisabled => ErrorCode::StealingDisabled,
+      Error::PresentImmune { .. } => ErrorCode::PresentImmune,
+      Error::StealLimitReached { .. } => ErrorCode::StealLimitReached,
+      Error::StealBackForbidden => ErrorCode::StealBackForbidden,
+      Error::GuessingDisabled => ErrorCode::GuessingDisabled,
+      Error::GuessingClosed => ErrorCode::GuessingClosed,
+      Error::NotOwnPlayer => ErrorCode::NotOwnPlayer,
+      Error::NoActivePlayer => ErrorCode::NoActivePlayer,
+      Error::PlayerNotIdle { .. } => ErrorCode::PlayerNotIdle,
+      Error::AlreadyNudged => ErrorCode::AlreadyNudged,
+      Error::StateChanged { .. } => ErrorCode::StateChanged,
+      Error::NothingToUndo => ErrorCode::NothingToUndo,
+      Error::PlayerHasPresent { .. } => ErrorCode::PlayerHasPresent,
+      Error::OverBudget { .. } => ErrorCode::OverBudget,
+      Error::CurrencyMismatch { .. } => ErrorCode::CurrencyMismatch,
+      Error::VersionMismatch => ErrorCode::VersionMismatch,
+      Error::AlreadyMember => ErrorCode::AlreadyMember,
+      Error::LastOwner => ErrorCode::LastOwner,
+      Error::Unknown | Error::Sqlx(_) => ErrorCode::InternalError,
+    }
+  }
+}
+
+#[derive(Deserialize, Debug, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct ListParams {
   pub order: Option<String>,
   pub offset: Option<i64>,
+  // capped at MAX_LIST_LIMIT, which is also the default
   pub limit: Option<i64>,
+  // keyset pagination, only rows with a larger id in id order
+  pub after_id: Option<i64>,
+  // case-insensitive name search on lists that have names
+  pub q: Option<String>,
+}
+
+// the largest page a client can ask for, set once at startup
+static MAX_LIMIT: OnceLock<i64> = OnceLock::new();
+
+pub const DEFAULT_MAX_LIMIT: i64 = 100;
+
+pub fn set_max_limit(limit: i64) {
+  let _ = MAX_LIMIT.set(limit.max(1));
+}
+
+fn max_limit() -> i64 {
+  MAX_LIMIT.get().copied().unwrap_or(DEFAULT_MAX_LIMIT)
+}
+
+impl ListParams {
+  // negative offsets start at the beginning
+  pub fn page_offset(&self) -> i64 {
+    self.offset.unwrap_or(0).max(0)
+  }
+
+  pub fn page_limit(&self) -> i64 {
+    self.limit.unwrap_or(i64::MAX).clamp(0, max_limit())
+  }
+}
+
+// a list row together with the size of the whole list, select it with
+// `COUNT(*) OVER() AS total_count` so the count ignores offset and limit
+pub struct Counted<T> {
+  pub row: T,
+  pub total_count: i64,
+  // the bigint id of the row, if it has one
+  pub cursor: Option<i64>,
+}
+
+impl<'r, T: FromRow<'r, PgRow>> FromRow<'r, PgRow> for Counted<T> {
+  fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+    Ok(Counted {
+      row: T::from_row(row)?,
+      total_count: row.try_get("total_count")?,
+      cursor: row.try_get("id").ok(),
+    })
+  }
+}
+
+// one page of a list
+#[derive(Debug)]
+pub struct Page<T> {
+  pub items: Vec<T>,
+  // zero when the page is past the end of the list, counted from the cursor when there is one
+  pub total_count: i64,
+  pub offset: i64,
+  pub limit: i64,
+  // after_id for the next page
+  pub next_cursor: Option<i64>,
+}
+
+impl<T> Page<T> {
+  pub fn new(rows: Vec<Counted<T>>, p: &ListParams) -> Self {
+    let total_count = rows.first().map_or(0, |row| row.total_count);
+    let offset = p.page_offset();
+    let has_more = offset + (rows.len() as i64) < total_count;
+    Page {
+      next_cursor: rows.last().and_then(|row| row.cursor).filter(|_| has_more),
+      items: rows.into_iter().map(|row| row.row).collect(),
+      total_count,
+      offset,
+      limit: p.page_limit(),
+    }
+  }
+
+  pub fn has_more(&self) -> bool {
+    self.offset + (self.items.len() as i64) < self.total_count
+  }
+
+  pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+    Page {
+      items: self.items.into_iter().map(f).collect(),
+      total_count: self.total_count,
+      offset: self.offset,
+      limit: self.limit,
+      next_cursor: self.next_cursor,
+    }
+  }
+}
+
+// every row of a list in id order, a page at a time, for callers that aren't serving a client
+pub async fn list_all<T, F, Fut>(mut list: F) -> Result<Vec<T>, Error>
+where
+  F: FnMut(ListParams) -> Fut,
+  Fut: Future<Output = Result<Page<T>, Error>>,
+{
+  let mut items = Vec::new();
+  loop {
+    let page = list(ListParams {
+      order: Some("id".to_string()),
+      offset: Some(items.len() as i64),
+      ..Default::default()
+    })
+    .await?;
+    let done = page.items.is_empty() || !page.has_more();
+    items.extend(page.items);
+    if done {
+      return Ok(items);
+    }
+  }
 }
 
 pub fn apply_list_filters<'a>(
+  query: QueryBuilder<'a, Postgres>,
+  p: &'a ListParams,
+  cols: Vec<&'a str>,
+) -> Result<QueryBuilder<'a, Postgres>, Error> {
+  let mut query = apply_filters(query, p, cols)?;
+  query.push(" LIMIT ").push_bind(p.page_limit());
+  Ok(query)
+}
+
+// streamed rows are never held in memory together, so only a limit the client asked for applies
+pub fn apply_stream_filters<'a>(
+  query: QueryBuilder<'a, Postgres>,
+  p: &'a ListParams,
+  cols: Vec<&'a str>,
+) -> Result<QueryBuilder<'a, Postgres>, Error> {
+  let mut query = apply_filters(query, p, cols)?;
+  if let Some(limit) = p.limit {
+    query.push(" LIMIT ").push_bind(limit.max(0));
+  }
+  Ok(query)
+}
+
+fn apply_filters<'a>(
   mut query: QueryBuilder<'a, Postgres>,
   p: &'a ListParams,
   cols: Vec<&'a str>,
 ) -> Result<QueryBuilder<'a, Postgres>, Error> {
-  if let Some(order) = &p.order {
+  if let Some(q) = p.q.as_deref().filter(|_| cols.contains(&"name")) {
+    query
+      .push(" AND name ILIKE ")
+      .push_bind(format!("%{}%", escape_like(q)));
+  }
+  if let Some(after_id) = p.after_id {
+    // a cursor only holds up in id order
+    if let Some(order) = p.order.as_ref().filter(|order| *order != "id") {
+      return Err(Error::InvalidOrder {
+        column: order.clone(),
+        allowed: vec!["id".to_string()],
+      });
+    }
+    query.push(" AND id > ").push_bind(after_id);
+    query.push(" ORDER BY id");
+  } else if let Some(order) = &p.order {
     let order = get_order_by_sql(order, cols)?;
     query.push(" ORDER BY ");
     query.push(order);
   }
-  if let Some(offset) = p.offset {
-    query.push(" OFFSET ");
-    query.push(offset);
-  }
-  if let Some(limit) = p.limit {
-    query.push(" LIMIT ");
-    query.push(limit);
+  // bound rather than inlined, so every page shares one prepared statement
+  if p.offset.is_some() {
+    query.push(" OFFSET ").push_bind(p.page_offset());
   }
   Ok(query)
 }
 
+// match the search text literally, LIKE would treat % and _ as wildcards
+fn escape_like(value: &str) -> String {
+  value
+    .replace('\\', "\\\\")
+    .replace('%', "\\%")
+    .replace('_', "\\_")
+}
+
 fn get_order_by_sql(order: &str, cols: Vec<&str>) -> Result<String, Error> {
   let s: String;
   let sort = if order.starts_with('-') {
@@ -58,12 +314,20 @@ fn get_order_by_sql(order: &str, cols: Vec<&str>) -> Result<String, Error> {
     s = order.to_string();
     "asc"
   };
-  for c in cols {
-    if c == s {
+  for c in &cols {
+    if *c == s {
       return Ok(format!("{} {}", c, sort));
     }
   }
-  Err(Error::InvalidOrder)
+  Err(Error::InvalidOrder {
+    column: s,
+    allowed: cols.iter().map(|c| c.to_string()).collect(),
+  })
+}
+
+// ISO 4217 style code, e.g. EUR
+pub fn is_currency_code(value: &str) -> bool {
+  value.len() == 3 && value.chars().all(|c| c.is_ascii_uppercase())
 }
 
 pub fn handle_pg_error(err: sqlx::Error) -> Error {
@@ -73,15 +337,81 @@ pub fn handle_pg_error(err: sqlx::Error) -> Error {
   }
 }
 
-#[derive(sqlx::FromRow, Serialize, Debug)]
+#[derive(sqlx::FromRow, Serialize, Debug, ToSchema)]
 pub struct CreateResult<T: Serialize> {
   pub id: T,
-  pub created_at: NaiveDateTime,
+  pub created_at: DateTime<Utc>,
 }
 
-#[derive(sqlx::FromRow, Serialize, Debug)]
+#[derive(sqlx::FromRow, Serialize, Debug, ToSchema)]
 pub struct UpdateResult {
-  pub updated_at: NaiveDateTime,
+  pub updated_at: DateTime<Utc>,
+}
+
+// how bulk operations deal with failing items
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkMode {
+  // all or nothing, the first failing item aborts the batch
+  #[default]
+  Atomic,
+  // apply the valid items and report the failing ones
+  Partial,
+}
+
+#[derive(Deserialize, Default, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BulkParams {
+  #[serde(default)]
+  pub mode: BulkMode,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum BulkStatus {
+  Created { id: i64, created_at: DateTime<Utc> },
+  Deleted { id: i64 },
+  Assigned { id: i64, player_id: i64 },
+  Skipped { id: i64, reason: ErrorCode },
+  Error { code: ErrorCode, message: String },
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct BulkItem {
+  pub index: usize,
+  #[serde(flatten)]
+  pub status: BulkStatus,
+}
+
+#[derive(Serialize, Default, Debug, ToSchema)]
+pub struct BulkResult {
+  pub succeeded: usize,
+  pub skipped: usize,
+  pub failed: usize,
+  pub items: Vec<BulkItem>,
+}
+
+impl BulkResult {
+  pub fn push(&mut self, index: usize, status: BulkStatus) {
+    match status {
+      BulkStatus::Created { .. } | BulkStatus::Deleted { .. } | BulkStatus::Assigned { .. } => {
+        self.succeeded += 1
+      }
+      BulkStatus::Skipped { .. } => self.skipped += 1,
+      BulkStatus::Error { .. } => self.failed += 1,
+    }
+    self.items.push(BulkItem { index, status });
+  }
+
+  pub fn push_error(&mut self, index: usize, err: Error) {
+    self.push(
+      index,
+      BulkStatus::Error {
+        code: err.code(),
+        message: err.to_string(),
+      },
+    );
+  }
 }
 
 // check health
@@ -93,3 +423,89 @@ pub async fn health(db: &sqlx::PgPool) -> Result<(), Error> {
     _ => Err(Error::Unknown),
   }
 }
+
+// whether a migration was applied, the pod isn't ready until its schema is in place
+pub async fn migrated(db: &sqlx::PgPool, version: i64) -> Result<bool, Error> {
+  sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM _sqlx_migrations WHERE version = $1 AND success)")
+    .bind(version)
+    .fetch_one(db)
+    .await
+    .map_err(Error::Sqlx)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn build(p: &ListParams) -> Result<String, Error> {
+    let query = QueryBuilder::<Postgres>::new("SELECT id FROM games WHERE TRUE");
+    apply_list_filters(query, p, vec!["id", "name"]).map(|query| query.sql().to_string())
+  }
+
+  #[test]
+  fn offset_and_limit_are_bound() {
+    let p = ListParams {
+      offset: Some(20),
+      limit: Some(10),
+      ..Default::default()
+    };
+    assert_eq!(
+      build(&p).unwrap(),
+      "SELECT id FROM games WHERE TRUE OFFSET $1 LIMIT $2"
+    );
+  }
+
+  #[test]
+  fn cursor_is_bound() {
+    let p = ListParams {
+      after_id: Some(5),
+      limit: Some(10),
+      ..Default::default()
+    };
+    assert_eq!(
+      build(&p).unwrap(),
+      "SELECT id FROM games WHERE TRUE AND id > $1 ORDER BY id LIMIT $2"
+    );
+  }
+
+  #[test]
+  fn no_limit_gets_the_largest_page() {
+    assert_eq!(
+      build(&ListParams::default()).unwrap(),
+      "SELECT id FROM games WHERE TRUE LIMIT $1"
+    );
+    assert_eq!(ListParams::default().page_limit(), DEFAULT_MAX_LIMIT);
+  }
+
+  #[test]
+  fn limit_is_clamped() {
+    let p = |limit| ListParams {
+      limit: Some(limit),
+      ..Default::default()
+    };
+    assert_eq!(p(10).page_limit(), 10);
+    assert_eq!(p(-1).page_limit(), 0);
+    assert_eq!(p(i64::MAX).page_limit(), DEFAULT_MAX_LIMIT);
+  }
+
+  #[test]
+  fn negative_offset_starts_at_the_beginning() {
+    let p = ListParams {
+      offset: Some(-5),
+      ..Default::default()
+    };
+    assert_eq!(p.page_offset(), 0);
+    let page = Page::<i64>::new(Vec::new(), &p);
+    assert_eq!(page.offset, 0);
+  }
+
+  #[test]
+  fn cursor_rejects_other_orders() {
+    let p = ListParams {
+      after_id: Some(5),
+      order: Some("name".to_string()),
+      ..Default::default()
+    };
+    assert!(matches!(build(&p), Err(Error::InvalidOrder { .. })));
+  }
+}
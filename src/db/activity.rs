@@ -0,0 +1,16 @@
+use sqlx::{query, PgPool};
+
+use crate::api::activity::Activity;
+
+use super::{handle_pg_error, Error};
+
+// hand a signal to the listener of every replica
+pub async fn notify(db: &PgPool, activity: &Activity) -> Result<(), Error> {
+  let payload = serde_json::to_string(activity).map_err(|_| Error::Unknown)?;
+  query("SELECT pg_notify('activity', $1)")
+    .bind(payload)
+    .execute(db)
+    .await
+    .map_err(handle_pg_error)?;
+  Ok(())
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, query, query_as, types::Json, PgPool, Postgres, QueryBuilder};
+use validator::Validate;
+
+use super::{apply_list_filters, handle_pg_error, Error, ListParams, Page};
+use crate::validation::{game_permissions, MAX_NAME_LEN};
+
+#[derive(FromRow, Serialize)]
+pub struct ApiKey {
+  pub id: i64,
+  pub name: String,
+  pub prefix: String,
+  #[sqlx(json)]
+  pub games: HashMap<String, i64>,
+  pub created_by: String,
+  pub last_used_at: Option<DateTime<Utc>>,
+  pub revoked_at: Option<DateTime<Utc>>,
+  pub created_at: DateTime<Utc>,
+}
+
+// list the keys a user created
+pub async fn list(db: &PgPool, created_by: &str, p: ListParams) -> Result<Page<ApiKey>, Error> {
+  let mut query = QueryBuilder::<Postgres>::new(
+    "SELECT id, name, prefix, games, created_by, last_used_at, revoked_at, created_at, COUNT(*) OVER() AS total_count FROM api_keys WHERE created_by = ",
+  );
+  query.push_bind(created_by);
+
+  query = apply_list_filters(query, &p, vec!["id", "name", "created_at"])?;
+  let rows = query
+    .build_query_as()
+    .fetch_all(db)
+    .await
+    .map_err(Error::Sqlx)?;
+  Ok(Page::new(rows, &p))
+}
+
+#[derive(Deserialize, Validate)]
+pub struct CreateParams {
+  #[validate(length(min = 1, max = MAX_NAME_LEN))]
+  pub name: String,
+  // game id => permission
+  #[validate(length(min = 1), custom(function = game_permissions))]
+  pub games: HashMap<String, i64>,
+}
+
+// store a key by its hash, the caller generates it
+pub async fn create(
+  db: &PgPool,
+  key_hash: &str,
+  prefix: &str,
+  created_by: &str,
+  p: CreateParams,
+) -> Result<ApiKey, Error> {
+  query_as(
+    "INSERT INTO api_keys (name, key_hash, prefix, games, created_by) VALUES ($1, $2, $3, $4, $5)
+    RETURNING id, name, prefix, games, created_by, last_used_at, revoked_at, created_at",
+  )
+  .bind(p.name)
+  .bind(key_hash)
+  .bind(prefix)
+  .bind(Json(p.games))
+  .bind(created_by)
+  .fetch_one(db)
+  .await
+  .map_err(handle_pg_error)
+}
+
+// revoke a key, requests with it fail from now on
+pub async fn revoke(db: &PgPool, created_by: &str, id: i64) -> Result<(), Error> {
+  let res = query!(
+    "UPDATE api_keys SET revoked_at = COALESCE(revoked_at, NOW()) WHERE id = $1 AND created_by = $2",
+    id,
+    created_by
+  )
+  .execute(db)
+  .await
+  .map_err(handle_pg_error)?;
+  match res.rows_affected() {
+    0 => Err(Error::NotFound),
+    _ => Ok(()),
+  }
+}
+
+#[derive(FromRow)]
+pub struct ActiveKey {
+  pub id: i64,
+  #[sqlx(json)]
+  pub games: HashMap<String, i64>,
+  // last used over a minute ago
+  pub stale: bool,
+}
+
+// the key with this hash unless it was revoked, without the games that were deleted
+pub async fn find_active(db: &PgPool, key_hash: &str) -> Result<ActiveKey, Error> {
+  query_as(
+    "SELECT id,
+      (SELECT COALESCE(jsonb_object_agg(g.key, g.value), '{}') FROM jsonb_each(api_keys.games) g
+        WHERE NOT EXISTS (
+          SELECT 1 FROM games WHERE games.id::text = g.key AND games.deleted_at IS NOT NULL
+        )) AS games,
+      COALESCE(last_used_at < NOW() - INTERVAL '1 minute', TRUE) AS stale
+    FROM api_keys WHERE key_hash = $1 AND revoked_at IS NULL",
+  )
+  .bind(key_hash)
+  .fetch_one(db)
+  .await
+  .map_err(handle_pg_error)
+}
+
+pub async fn touch(db: &PgPool, id: i64) -> Result<(), Error> {
+  query!("UPDATE api_keys SET last_used_at = NOW() WHERE id = $1", id)
+    .execute(db)
+    .await
+    .map_err(handle_pg_error)?;
+  Ok(())
+}
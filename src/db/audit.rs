@@ -0,0 +1,111 @@
+use std::{
+  collections::{BTreeSet, HashMap},
+  net::IpAddr,
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{prelude::FromRow, query, PgExecutor, PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use super::{apply_list_filters, handle_pg_error, Error, ListParams, Page};
+
+pub const CREATE: &str = "create";
+pub const IMPORT: &str = "import";
+pub const UPDATE: &str = "update";
+pub const REPLACE: &str = "replace";
+pub const MEMBERS: &str = "members";
+pub const INVITE: &str = "invite";
+
+// who made a change, from which address and through which endpoint
+pub struct Actor<'a> {
+  pub uid: &'a str,
+  pub client_ip: Option<IpAddr>,
+  pub source: &'a str,
+}
+
+// one user's permission before and after, None when they weren't or aren't a member
+pub struct Change {
+  pub uid: String,
+  pub old_permission: Option<i64>,
+  pub new_permission: Option<i64>,
+}
+
+// the changes between two users maps
+pub fn diff(previous: &HashMap<String, i64>, current: &HashMap<String, i64>) -> Vec<Change> {
+  let uids: BTreeSet<&String> = previous.keys().chain(current.keys()).collect();
+  uids
+    .into_iter()
+    .filter(|uid| previous.get(*uid) != current.get(*uid))
+    .map(|uid| Change {
+      uid: uid.clone(),
+      old_permission: previous.get(uid).copied(),
+      new_permission: current.get(uid).copied(),
+    })
+    .collect()
+}
+
+// write changes to the audit log
+pub async fn record(
+  db: impl PgExecutor<'_>,
+  game_id: Uuid,
+  actor: &Actor<'_>,
+  changes: &[Change],
+) -> Result<(), Error> {
+  if changes.is_empty() {
+    return Ok(());
+  }
+  let uids: Vec<&str> = changes.iter().map(|c| c.uid.as_str()).collect();
+  let old: Vec<Option<i64>> = changes.iter().map(|c| c.old_permission).collect();
+  let new: Vec<Option<i64>> = changes.iter().map(|c| c.new_permission).collect();
+  query!(
+    "INSERT INTO permission_audit (game_id, actor_uid, source, client_ip, target_uid, old_permission, new_permission)
+    SELECT $1, $2, $3, $4::text::inet, * FROM UNNEST($5::text[], $6::bigint[], $7::bigint[])",
+    game_id,
+    actor.uid,
+    actor.source,
+    actor.client_ip.map(|ip| ip.to_string()),
+    &uids as &[&str],
+    &old as &[Option<i64>],
+    &new as &[Option<i64>]
+  )
+  .execute(db)
+  .await
+  .map_err(handle_pg_error)?;
+  Ok(())
+}
+
+#[derive(FromRow, Serialize)]
+pub struct AuditEntry {
+  pub id: i64,
+  pub actor_uid: String,
+  pub target_uid: String,
+  pub old_permission: Option<i64>,
+  pub new_permission: Option<i64>,
+  pub source: String,
+  pub client_ip: Option<String>,
+  pub created_at: DateTime<Utc>,
+}
+
+// the audit log of a game, newest first unless ordered otherwise
+pub async fn list(
+  db: &PgPool,
+  game_id: Uuid,
+  mut p: ListParams,
+) -> Result<Page<AuditEntry>, Error> {
+  if p.order.is_none() && p.after_id.is_none() {
+    p.order = Some("-id".to_string());
+  }
+  let mut query = QueryBuilder::<Postgres>::new(
+    "SELECT id, actor_uid, target_uid, old_permission, new_permission, source, host(client_ip) AS client_ip, created_at, COUNT(*) OVER() AS total_count FROM permission_audit WHERE game_id = ",
+  );
+  query.push_bind(game_id);
+
+  query = apply_list_filters(query, &p, vec!["id", "created_at"])?;
+  let rows = query
+    .build_query_as()
+    .fetch_all(db)
+    .await
+    .map_err(Error::Sqlx)?;
+  Ok(Page::new(rows, &p))
+}
@@ -0,0 +1,221 @@
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{query, query_scalar, PgPool};
+use uuid::Uuid;
+
+// bump whenever the archive layout changes, not for schema migrations
+pub const ARCHIVE_VERSION: u32 = 1;
+
+// tables in restore order, with their sort column and the condition picking the rows of the games in $1.
+// everything deleting a game cascades to has to be here, or replacing it loses those rows
+const TABLES: [(&str, &str, &str); 13] = [
+  ("games", "id", "id = ANY($1)"),
+  ("teams", "id", "game_id = ANY($1)"),
+  ("players", "id", "game_id = ANY($1)"),
+  ("presents", "id", "game_id = ANY($1)"),
+  ("play_events", "id", "game_id = ANY($1)"),
+  ("guesses", "id", "game_id = ANY($1)"),
+  ("recaps", "game_id", "game_id = ANY($1)"),
+  ("webhooks", "id", "game_id = ANY($1)"),
+  (
+    "webhook_deliveries",
+    "id",
+    "webhook_id IN (SELECT id FROM webhooks WHERE game_id = ANY($1))",
+  ),
+  ("invites", "id", "game_id = ANY($1)"),
+  ("email_invites", "id", "game_id = ANY($1)"),
+  ("permission_audit", "id", "game_id = ANY($1)"),
+  ("api_keys", "id", "games ?| $1::text[]"),
+];
+
+// keys can cover games outside the archive, so they're never deleted and only added when missing
+const SHARED_TABLE: &str = "api_keys";
+
+// serial ids to move past the restored rows
+const SEQUENCES: [(&str, &str); 11] = [
+  ("teams", "teams_id_seq"),
+  ("players", "players_id_seq"),
+  ("presents", "presents_id_seq"),
+  ("play_events", "play_events_id_seq"),
+  ("guesses", "guesses_id_seq"),
+  ("webhooks", "webhooks_id_seq"),
+  ("webhook_deliveries", "webhook_deliveries_id_seq"),
+  ("invites", "invites_id_seq"),
+  ("email_invites", "email_invites_id_seq"),
+  ("permission_audit", "permission_audit_id_seq"),
+  ("api_keys", "api_keys_id_seq"),
+];
+
+#[derive(Serialize, Deserialize)]
+pub struct Archive {
+  pub version: u32,
+  // latest migration applied to the database the backup was taken from
+  pub schema: i64,
+  pub created_at: DateTime<Utc>,
+  pub games: Vec<Uuid>,
+  pub tables: Vec<Table>,
+}
+
+// rows are kept as json objects so every column survives, including ones the api never exposes
+#[derive(Serialize, Deserialize)]
+pub struct Table {
+  pub name: String,
+  pub rows: Vec<Value>,
+}
+
+// snapshot the selected games, or every game when none are given
+pub async fn backup(db: &PgPool, game_ids: Option<Vec<Uuid>>, schema: i64) -> Result<Archive> {
+  let mut tx = db.begin().await?;
+  query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+    .execute(&mut *tx)
+    .await?;
+
+  let games: Vec<Uuid> =
+    query_scalar("SELECT id FROM games WHERE $1::uuid[] IS NULL OR id = ANY($1) ORDER BY id")
+      .bind(&game_ids)
+      .fetch_all(&mut *tx)
+      .await?;
+  if let Some(ids) = &game_ids {
+    if let Some(missing) = ids.iter().find(|id| !games.contains(id)) {
+      bail!("Game {} not found", missing);
+    }
+  }
+
+  let mut tables = Vec::new();
+  for (name, order, filter) in TABLES {
+    let rows = query_scalar(&format!(
+      "SELECT row_to_json(t) FROM {name} t WHERE {filter} ORDER BY t.{order}"
+    ))
+    .bind(&games)
+    .fetch_all(&mut *tx)
+    .await?;
+    tables.push(Table {
+      name: name.to_string(),
+      rows,
+    });
+  }
+  tx.commit().await?;
+
+  Ok(Archive {
+    version: ARCHIVE_VERSION,
+    schema,
+    created_at: Utc::now(),
+    games,
+    tables,
+  })
+}
+
+// load an archive, existing games are only overwritten with replace
+pub async fn restore(db: &PgPool, archive: &Archive, schema: i64, replace: bool) -> Result<()> {
+  if archive.version != ARCHIVE_VERSION {
+    bail!(
+      "Unsupported archive version {}, expected {}",
+      archive.version,
+      ARCHIVE_VERSION
+    );
+  }
+  if archive.schema > schema {
+    bail!(
+      "Archive was taken at migration {}, upgrade before restoring (at {})",
+      archive.schema,
+      schema
+    );
+  }
+
+  let mut tx = db.begin().await?;
+  let existing: Vec<Uuid> = query_scalar("SELECT id FROM games WHERE id = ANY($1)")
+    .bind(&archive.games)
+    .fetch_all(&mut *tx)
+    .await?;
+  if !existing.is_empty() {
+    if !replace {
+      bail!(
+        "{} of the games already exist, pass --replace to overwrite them",
+        existing.len()
+      );
+    }
+    query("UPDATE games SET player_id = NULL, present_id = NULL WHERE id = ANY($1)")
+      .bind(&existing)
+      .execute(&mut *tx)
+      .await?;
+    for (name, _, filter) in TABLES
+      .iter()
+      .rev()
+      .filter(|(name, _, _)| *name != SHARED_TABLE)
+    {
+      query(&format!("DELETE FROM {name} WHERE {filter}"))
+        .bind(&existing)
+        .execute(&mut *tx)
+        .await?;
+    }
+  }
+
+  // keep snapshots, sequence numbers and timestamps as they were and stay quiet on the play channel
+  query("ALTER TABLE play_events DISABLE TRIGGER USER")
+    .execute(&mut *tx)
+    .await?;
+
+  for (name, _, _) in TABLES {
+    let Some(table) = archive.tables.iter().find(|t| t.name == name) else {
+      continue;
+    };
+    if table.rows.is_empty() {
+      continue;
+    }
+    // only restore columns known to both sides, newer columns fall back to their defaults
+    let mut columns: Vec<String> = query_scalar(
+      "SELECT column_name::text FROM information_schema.columns
+      WHERE table_schema = current_schema() AND table_name = $1
+      ORDER BY ordinal_position",
+    )
+    .bind(name)
+    .fetch_all(&mut *tx)
+    .await?;
+    columns.retain(|c| {
+      table.rows.iter().any(|row| row.get(c).is_some())
+        && !(name == "games" && (c == "player_id" || c == "present_id"))
+    });
+    let columns = columns
+      .iter()
+      .map(|c| format!("\"{}\"", c))
+      .collect::<Vec<_>>()
+      .join(", ");
+    let conflict = if name == SHARED_TABLE {
+      " ON CONFLICT DO NOTHING"
+    } else {
+      ""
+    };
+    query(&format!(
+      "INSERT INTO {name} ({columns}) SELECT {columns} FROM jsonb_populate_recordset(NULL::{name}, $1){conflict}"
+    ))
+    .bind(Value::Array(table.rows.clone()))
+    .execute(&mut *tx)
+    .await?;
+  }
+
+  // games and their current player/present reference each other
+  if let Some(games) = archive.tables.iter().find(|t| t.name == "games") {
+    query(
+      "UPDATE games SET player_id = r.player_id, present_id = r.present_id
+      FROM jsonb_populate_recordset(NULL::games, $1) r WHERE games.id = r.id",
+    )
+    .bind(Value::Array(games.rows.clone()))
+    .execute(&mut *tx)
+    .await?;
+  }
+
+  query("ALTER TABLE play_events ENABLE TRIGGER USER")
+    .execute(&mut *tx)
+    .await?;
+  for (table, sequence) in SEQUENCES {
+    query(&format!(
+      "SELECT setval('{sequence}', COALESCE((SELECT MAX(id) FROM {table}), 0) + 1, false)"
+    ))
+    .execute(&mut *tx)
+    .await?;
+  }
+  tx.commit().await?;
+  Ok(())
+}
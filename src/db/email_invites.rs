@@ -0,0 +1,170 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{prelude::FromRow, query, query_as, PgConnection, PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::crypto::{keyring, Sealed};
+
+use super::{
+  apply_list_filters,
+  audit::{self, Actor, Change},
+  handle_pg_error, members, notifications, Error, ListParams, Page,
+};
+
+#[derive(FromRow, Serialize)]
+pub struct EmailInvite {
+  pub id: i64,
+  pub game_id: Uuid,
+  pub email: Sealed,
+  pub permission: i64,
+  pub created_by: String,
+  pub accepted_by: Option<String>,
+  pub accepted_at: Option<DateTime<Utc>>,
+  pub revoked_at: Option<DateTime<Utc>>,
+  pub created_at: DateTime<Utc>,
+}
+
+// list the email invites of a game
+pub async fn list(db: &PgPool, game_id: Uuid, p: ListParams) -> Result<Page<EmailInvite>, Error> {
+  let mut query = QueryBuilder::<Postgres>::new(
+    "SELECT id, game_id, email, permission, created_by, accepted_by, accepted_at, revoked_at, created_at, COUNT(*) OVER() AS total_count FROM email_invites WHERE game_id = ",
+  );
+  query.push_bind(game_id);
+
+  // sealed emails don't sort
+  query = apply_list_filters(query, &p, vec!["id", "created_at"])?;
+  let rows = query
+    .build_query_as()
+    .fetch_all(db)
+    .await
+    .map_err(Error::Sqlx)?;
+  Ok(Page::new(rows, &p))
+}
+
+pub async fn get(conn: &mut PgConnection, game_id: Uuid, id: i64) -> Result<EmailInvite, Error> {
+  query_as(
+    "SELECT id, game_id, email, permission, created_by, accepted_by, accepted_at, revoked_at, created_at
+    FROM email_invites WHERE id = $1 AND game_id = $2",
+  )
+  .bind(id)
+  .bind(game_id)
+  .fetch_one(&mut *conn)
+  .await
+  .map_err(handle_pg_error)
+}
+
+// what sealed emails are looked up by
+pub(super) fn email_index(email: &str) -> String {
+  keyring().blind_index(email)
+}
+
+// seal and index the emails of invites made before emails were sealed, returns how many there were
+pub async fn index_emails(db: &PgPool) -> Result<u64, Error> {
+  let rows: Vec<(i64, Sealed)> =
+    query_as("SELECT id, email FROM email_invites WHERE email_index IS NULL")
+      .fetch_all(db)
+      .await
+      .map_err(handle_pg_error)?;
+  let mut indexed = 0;
+  for (id, email) in rows {
+    indexed += query("UPDATE email_invites SET email = $1, email_index = $2 WHERE id = $3")
+      .bind(&email)
+      .bind(email_index(&email.0))
+      .bind(id)
+      .execute(db)
+      .await
+      .map_err(handle_pg_error)?
+      .rows_affected();
+  }
+  Ok(indexed)
+}
+
+// invite an email address, inviting it again while pending changes the permission
+pub async fn create(
+  conn: &mut PgConnection,
+  game_id: Uuid,
+  email: &str,
+  permission: i64,
+  created_by: &str,
+) -> Result<EmailInvite, Error> {
+  query_as(
+    "INSERT INTO email_invites (game_id, email, email_index, permission, created_by) VALUES ($1, $2, $3, $4, $5)
+    ON CONFLICT (game_id, email_index) WHERE accepted_at IS NULL AND revoked_at IS NULL
+    DO UPDATE SET permission = EXCLUDED.permission, created_by = EXCLUDED.created_by
+    RETURNING id, game_id, email, permission, created_by, accepted_by, accepted_at, revoked_at, created_at",
+  )
+  .bind(game_id)
+  .bind(Sealed(email.to_string()))
+  .bind(email_index(email))
+  .bind(permission)
+  .bind(created_by)
+  .fetch_one(&mut *conn)
+  .await
+  .map_err(handle_pg_error)
+}
+
+// revoke a pending invite
+pub async fn revoke(db: &PgPool, game_id: Uuid, id: i64) -> Result<(), Error> {
+  let res = query!(
+    "UPDATE email_invites SET revoked_at = NOW()
+    WHERE id = $1 AND game_id = $2 AND accepted_at IS NULL AND revoked_at IS NULL",
+    id,
+    game_id
+  )
+  .execute(db)
+  .await
+  .map_err(handle_pg_error)?;
+  match res.rows_affected() {
+    0 => Err(Error::NotFound),
+    _ => Ok(()),
+  }
+}
+
+// join the games a verified email was invited to, all of them unless game_id is given.
+// the grants are audited as made by the actor and the user gets an invitation email.
+// returns the games joined with the user's permission in them
+pub async fn redeem(
+  conn: &mut PgConnection,
+  actor: &Actor<'_>,
+  uid: &str,
+  email: &str,
+  game_id: Option<Uuid>,
+) -> Result<Vec<(Uuid, i64)>, Error> {
+  let pending = query!(
+    "UPDATE email_invites SET accepted_by = $1, accepted_at = NOW()
+    WHERE email_index = $2 AND ($3::uuid IS NULL OR game_id = $3)
+      AND accepted_at IS NULL AND revoked_at IS NULL
+    RETURNING game_id, permission",
+    uid,
+    email_index(email),
+    game_id
+  )
+  .fetch_all(&mut *conn)
+  .await
+  .map_err(handle_pg_error)?;
+
+  let mut joined = Vec::new();
+  for invite in pending {
+    let (previous, permission) =
+      match members::grant(conn, invite.game_id, uid, invite.permission).await {
+        Ok(granted) => granted,
+        // deleted games just drop their invites
+        Err(Error::NotFound) => continue,
+        Err(err) => return Err(err),
+      };
+    if previous == Some(permission) {
+      continue;
+    }
+    let change = Change {
+      uid: uid.to_string(),
+      old_permission: previous,
+      new_permission: Some(permission),
+    };
+    audit::record(&mut *conn, invite.game_id, actor, &[change]).await?;
+    if previous.is_none() {
+      notifications::enqueue_invites(&mut *conn, invite.game_id, &[uid.to_string()]).await?;
+    }
+    joined.push((invite.game_id, permission));
+  }
+  Ok(joined)
+}
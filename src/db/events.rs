@@ -0,0 +1,332 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgRow, FromRow, PgPool};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::{list_all, players, presents, Error};
+
+// a player as they were when the event happened
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct PlayerRef {
+  pub id: i64,
+  pub name: Option<String>,
+  pub image: Option<String>,
+}
+
+// a present as it was when the event happened
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct PresentRef {
+  pub id: i64,
+  pub name: Option<String>,
+  pub image: Option<String>,
+}
+
+// what happened in a game, the tag matches play_events.kind
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+#[serde(tag = "kind")]
+pub enum GameEvent {
+  #[serde(rename = "start")]
+  Started,
+  #[serde(rename = "roll")]
+  Rolled { player: PlayerRef },
+  #[serde(rename = "pick")]
+  Picked {
+    player: PlayerRef,
+    present: PresentRef,
+  },
+  #[serde(rename = "keep")]
+  Kept {
+    player: PlayerRef,
+    present: PresentRef,
+  },
+  // the thief takes present from victim, who gets swapped in return
+  #[serde(rename = "steal")]
+  Stolen {
+    player: PlayerRef,
+    present: PresentRef,
+    victim: PlayerRef,
+    swapped: Option<PresentRef>,
+  },
+  #[serde(rename = "assign")]
+  Assigned {
+    player: PlayerRef,
+    present: PresentRef,
+    from_player: Option<PlayerRef>,
+    reason: Option<String>,
+  },
+  #[serde(rename = "nudge")]
+  Nudged { player: PlayerRef },
+  // the first player gets a last chance to swap once everyone has a present
+  #[serde(rename = "final_swap")]
+  FinalSwap {
+    player: PlayerRef,
+    present: PresentRef,
+  },
+  // the event with undone_seq was reverted and removed from the history
+  #[serde(rename = "undo")]
+  Undone { undone_seq: i64 },
+  // every player owns a present, the game is over
+  #[serde(rename = "finish")]
+  Finished,
+  #[serde(rename = "reset")]
+  Reset,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct PlayEvent {
+  pub id: i64,
+  pub game_id: Uuid,
+  // increases by one for every event of the game, a gap means missed events
+  pub seq: i64,
+  // the signed-in user who triggered the event
+  pub actor_uid: Option<String>,
+  pub created_at: DateTime<Utc>,
+  #[serde(flatten)]
+  pub event: GameEvent,
+}
+
+pub const PLAY_EVENT_COLUMNS: &str = "id,
+  game_id,
+  seq,
+  kind,
+  player_id,
+  present_id,
+  from_player_id,
+  from_present_id,
+  reason,
+  actor_uid,
+  player_name,
+  player_image,
+  present_name,
+  present_image,
+  from_player_name,
+  from_present_name,
+  undone_seq,
+  created_at";
+
+// a play_events row as stored and as sent over NOTIFY
+#[derive(FromRow, Deserialize, Debug)]
+pub struct PlayEventRow {
+  pub id: i64,
+  pub game_id: Uuid,
+  pub seq: i64,
+  pub kind: String,
+  pub player_id: Option<i64>,
+  pub present_id: Option<i64>,
+  pub from_player_id: Option<i64>,
+  pub from_present_id: Option<i64>,
+  pub reason: Option<String>,
+  pub actor_uid: Option<String>,
+  pub player_name: Option<String>,
+  pub player_image: Option<String>,
+  pub present_name: Option<String>,
+  pub present_image: Option<String>,
+  pub from_player_name: Option<String>,
+  pub from_present_name: Option<String>,
+  pub undone_seq: Option<i64>,
+  pub created_at: DateTime<Utc>,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Malformed {kind} event {id}")]
+pub struct MalformedEvent {
+  pub id: i64,
+  pub kind: String,
+}
+
+impl TryFrom<PlayEventRow> for PlayEvent {
+  type Error = MalformedEvent;
+
+  fn try_from(row: PlayEventRow) -> Result<Self, Self::Error> {
+    let malformed = || MalformedEvent {
+      id: row.id,
+      kind: row.kind.clone(),
+    };
+    let player = || {
+      row.player_id.map(|id| PlayerRef {
+        id,
+        name: row.player_name.clone(),
+        image: row.player_image.clone(),
+      })
+    };
+    let present = || {
+      row.present_id.map(|id| PresentRef {
+        id,
+        name: row.present_name.clone(),
+        image: row.present_image.clone(),
+      })
+    };
+    let from_player = || {
+      row.from_player_id.map(|id| PlayerRef {
+        id,
+        name: row.from_player_name.clone(),
+        image: None,
+      })
+    };
+    let from_present = || {
+      row.from_present_id.map(|id| PresentRef {
+        id,
+        name: row.from_present_name.clone(),
+        image: None,
+      })
+    };
+
+    let event = match row.kind.as_str() {
+      "start" => GameEvent::Started,
+      "roll" => GameEvent::Rolled {
+        player: player().ok_or_else(malformed)?,
+      },
+      "pick" => GameEvent::Picked {
+        player: player().ok_or_else(malformed)?,
+        present: present().ok_or_else(malformed)?,
+      },
+      "keep" => GameEvent::Kept {
+        player: player().ok_or_else(malformed)?,
+        present: present().ok_or_else(malformed)?,
+      },
+      "steal" => GameEvent::Stolen {
+        player: player().ok_or_else(malformed)?,
+        present: from_present().ok_or_else(malformed)?,
+        victim: from_player().ok_or_else(malformed)?,
+        swapped: present(),
+      },
+      "assign" => GameEvent::Assigned {
+        player: player().ok_or_else(malformed)?,
+        present: present().ok_or_else(malformed)?,
+        from_player: from_player(),
+        reason: row.reason.clone(),
+      },
+      "nudge" => GameEvent::Nudged {
+        player: player().ok_or_else(malformed)?,
+      },
+      "final_swap" => GameEvent::FinalSwap {
+        player: player().ok_or_else(malformed)?,
+        present: present().ok_or_else(malformed)?,
+      },
+      "undo" => GameEvent::Undone {
+        undone_seq: row.undone_seq.ok_or_else(malformed)?,
+      },
+      "finish" => GameEvent::Finished,
+      "reset" => GameEvent::Reset,
+      _ => return Err(malformed()),
+    };
+
+    Ok(PlayEvent {
+      id: row.id,
+      game_id: row.game_id,
+      seq: row.seq,
+      actor_uid: row.actor_uid,
+      created_at: row.created_at,
+      event,
+    })
+  }
+}
+
+impl<'r> FromRow<'r, PgRow> for PlayEvent {
+  fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+    PlayEventRow::from_row(row)?
+      .try_into()
+      .map_err(|err: MalformedEvent| sqlx::Error::Decode(err.into()))
+  }
+}
+
+// game state rebuilt by replaying its play events in order
+#[derive(Serialize, Default, Debug, ToSchema)]
+pub struct ReplayState {
+  // the last event applied
+  pub event_id: Option<i64>,
+  pub event_seq: Option<i64>,
+  pub started: bool,
+  pub finished: bool,
+  pub turn: i32,
+  pub player_id: Option<i64>,
+  pub present_id: Option<i64>,
+  // present id to the player owning it
+  pub owners: BTreeMap<i64, i64>,
+}
+
+impl ReplayState {
+  pub fn apply(&mut self, event: &PlayEvent) {
+    match &event.event {
+      GameEvent::Started => self.started = true,
+      GameEvent::Rolled { player } => {
+        self.turn += 1;
+        self.player_id = Some(player.id);
+      }
+      GameEvent::Picked { present, .. } => self.present_id = Some(present.id),
+      GameEvent::Kept { player, present } => {
+        self.owners.insert(present.id, player.id);
+        self.end_turn();
+      }
+      GameEvent::Stolen {
+        player,
+        present,
+        victim,
+        swapped,
+      } => {
+        self.owners.insert(present.id, player.id);
+        if let Some(swapped) = swapped {
+          self.owners.insert(swapped.id, victim.id);
+        }
+        self.end_turn();
+      }
+      GameEvent::Assigned {
+        player, present, ..
+      } => {
+        self.owners.insert(present.id, player.id);
+      }
+      GameEvent::FinalSwap { player, present } => {
+        self.player_id = Some(player.id);
+        self.present_id = Some(present.id);
+      }
+      GameEvent::Finished => self.finished = true,
+      GameEvent::Reset => *self = ReplayState::default(),
+      // an undone event is already gone from the history
+      GameEvent::Nudged { .. } | GameEvent::Undone { .. } => {}
+    }
+    self.event_id = Some(event.id);
+    self.event_seq = Some(event.seq);
+  }
+
+  fn end_turn(&mut self) {
+    self.player_id = None;
+    self.present_id = None;
+  }
+}
+
+// events only carry names since they were snapshotted, older ones fall back to the current names
+#[derive(Default)]
+pub struct EventNames {
+  players: HashMap<i64, String>,
+  presents: HashMap<i64, String>,
+}
+
+impl EventNames {
+  pub async fn load(db: &PgPool, game_id: Uuid) -> Result<Self, Error> {
+    let players = list_all(|p| players::list(db, game_id, p)).await?;
+    let presents = list_all(|p| presents::list(db, game_id, p)).await?;
+    Ok(Self {
+      players: players.into_iter().map(|p| (p.id, p.name)).collect(),
+      presents: presents.into_iter().map(|p| (p.id, p.name)).collect(),
+    })
+  }
+
+  pub fn player(&self, player: &PlayerRef) -> String {
+    player
+      .name
+      .clone()
+      .or_else(|| self.players.get(&player.id).cloned())
+      .unwrap_or_default()
+  }
+
+  pub fn present(&self, present: &PresentRef) -> String {
+    present
+      .name
+      .clone()
+      .or_else(|| self.presents.get(&present.id).cloned())
+      .unwrap_or_default()
+  }
+}
@@ -0,0 +1,196 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{prelude::FromRow, query_as, PgPool};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::{
+  api_keys::ApiKey,
+  audit::AuditEntry,
+  email_invites::{self, EmailInvite},
+  events::{PlayEvent, PLAY_EVENT_COLUMNS},
+  games::{self, Game},
+  guesses::Guess,
+  handle_pg_error, list_all,
+  notifications::{self, Preferences},
+  players::{self, Player},
+  presents::{self, Present},
+  Error,
+};
+
+// everything stored about one user, for data portability requests.
+// uids of other users are left out, except for who changed the user's permissions
+#[derive(Serialize)]
+pub struct PersonalExport {
+  pub uid: String,
+  pub exported_at: DateTime<Utc>,
+  pub games: Vec<Game>,
+  pub players: Vec<Player>,
+  pub events: Vec<PlayEvent>,
+  pub guesses: Vec<Guess>,
+  pub api_keys: Vec<ApiKey>,
+  pub notifications: Preferences,
+  pub email_invites: Vec<EmailInvite>,
+  pub permission_changes: Vec<GameAuditEntry>,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct GameAuditEntry {
+  pub game_id: Uuid,
+  #[sqlx(flatten)]
+  #[serde(flatten)]
+  pub entry: AuditEntry,
+}
+
+// only the user's own uid, everyone else's is dropped
+fn own(uid: &str, value: Option<String>) -> Option<String> {
+  value.filter(|value| value == uid)
+}
+
+// collect what is stored about a user, email is their verified email address
+pub async fn personal(
+  db: &PgPool,
+  uid: &str,
+  email: Option<&str>,
+) -> Result<PersonalExport, Error> {
+  let games = list_all(|p| games::list(db, uid, p))
+    .await?
+    .into_iter()
+    .map(|mut game| {
+      game.users.retain(|member, _| member == uid);
+      game.created_by = own(uid, game.created_by);
+      game.updated_by = own(uid, game.updated_by);
+      game
+    })
+    .collect();
+
+  let players: Vec<Player> = query_as(
+    "SELECT id, game_id, position, team_id, name, images, uid, created_by, updated_by FROM players WHERE uid = $1 ORDER BY id",
+  )
+  .bind(uid)
+  .fetch_all(db)
+  .await
+  .map_err(handle_pg_error)?;
+  let players = players
+    .into_iter()
+    .map(|mut player| {
+      player.created_by = own(uid, player.created_by);
+      player.updated_by = own(uid, player.updated_by);
+      player
+    })
+    .collect();
+
+  let events: Vec<PlayEvent> = query_as(&format!(
+    "SELECT {} FROM play_events
+    WHERE actor_uid = $1
+      OR player_id IN (SELECT id FROM players WHERE uid = $1)
+      OR from_player_id IN (SELECT id FROM players WHERE uid = $1)
+    ORDER BY id",
+    PLAY_EVENT_COLUMNS
+  ))
+  .bind(uid)
+  .fetch_all(db)
+  .await
+  .map_err(handle_pg_error)?;
+  let events = events
+    .into_iter()
+    .map(|mut event| {
+      event.actor_uid = own(uid, event.actor_uid);
+      event
+    })
+    .collect();
+
+  let guesses = query_as(
+    "SELECT id, game_id, present_id, player_id, price_cents, created_at, updated_at FROM guesses
+    WHERE player_id IN (SELECT id FROM players WHERE uid = $1) ORDER BY id",
+  )
+  .bind(uid)
+  .fetch_all(db)
+  .await
+  .map_err(handle_pg_error)?;
+
+  let api_keys = query_as(
+    "SELECT id, name, prefix, games, created_by, last_used_at, revoked_at, created_at FROM api_keys
+    WHERE created_by = $1 ORDER BY id",
+  )
+  .bind(uid)
+  .fetch_all(db)
+  .await
+  .map_err(handle_pg_error)?;
+
+  let notifications = notifications::preferences(db, uid).await?;
+
+  // invites the user sent hold other people's emails, only the ones to the user are theirs
+  let email_invites: Vec<EmailInvite> = query_as(
+    "SELECT id, game_id, email, permission, created_by, accepted_by, accepted_at, revoked_at, created_at
+    FROM email_invites WHERE accepted_by = $1 OR email_index = $2 ORDER BY id",
+  )
+  .bind(uid)
+  .bind(email.map(|email| email_invites::email_index(&email.to_lowercase())))
+  .fetch_all(db)
+  .await
+  .map_err(handle_pg_error)?;
+  let email_invites = email_invites
+    .into_iter()
+    .map(|mut invite| {
+      invite.created_by = own(uid, Some(invite.created_by)).unwrap_or_default();
+      invite
+    })
+    .collect();
+
+  // the address a change came from is only the user's own when they made it
+  let permission_changes = query_as(
+    "SELECT game_id, id, actor_uid, target_uid, old_permission, new_permission, source,
+      CASE WHEN actor_uid = $1 THEN host(client_ip) END AS client_ip, created_at
+    FROM permission_audit WHERE target_uid = $1 ORDER BY id",
+  )
+  .bind(uid)
+  .fetch_all(db)
+  .await
+  .map_err(handle_pg_error)?;
+
+  Ok(PersonalExport {
+    uid: uid.to_string(),
+    exported_at: Utc::now(),
+    games,
+    players,
+    events,
+    guesses,
+    api_keys,
+    notifications,
+    email_invites,
+    permission_changes,
+  })
+}
+
+// one game with everything in it, an archive that outlives the hosting account
+#[derive(Serialize, ToSchema)]
+pub struct GameExport {
+  pub exported_at: DateTime<Utc>,
+  pub game: Game,
+  pub players: Vec<Player>,
+  pub presents: Vec<Present>,
+  pub events: Vec<PlayEvent>,
+}
+
+pub async fn game(db: &PgPool, game_id: Uuid) -> Result<GameExport, Error> {
+  let game = games::get(db, game_id).await?;
+  let players = list_all(|p| players::list(db, game_id, p)).await?;
+  let presents = list_all(|p| presents::list(db, game_id, p)).await?;
+  let events = query_as(&format!(
+    "SELECT {} FROM play_events WHERE game_id = $1 ORDER BY id",
+    PLAY_EVENT_COLUMNS
+  ))
+  .bind(game_id)
+  .fetch_all(db)
+  .await
+  .map_err(handle_pg_error)?;
+
+  Ok(GameExport {
+    exported_at: Utc::now(),
+    game,
+    players,
+    presents,
+    events,
+  })
+}
@@ -1,53 +1,189 @@
-use std::collections::HashMap;
+use std::{
+  collections::{BTreeMap, HashMap, HashSet},
+  sync::{Arc, Mutex},
+  time::Duration,
+};
 
+use async_graphql::SimpleObject;
 use axum::{extract::FromRef, response::IntoResponse};
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
 use is_empty::IsEmpty;
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 use sqlx::{
-  postgres::PgListener, prelude::FromRow, query, query_as, types::Json, PgPool, Postgres,
+  postgres::PgListener, prelude::FromRow, query, query_as, query_file, query_file_scalar,
+  query_scalar, types::Json, Acquire, Executor, PgConnection, PgExecutor, PgPool, Postgres,
   QueryBuilder,
 };
-use tokio::sync::broadcast::Sender;
+use tokio::sync::{
+  broadcast::{channel, Receiver, Sender},
+  mpsc,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use utoipa::ToSchema;
 use uuid::Uuid;
+use validator::Validate;
 
-use crate::api::AppState;
+use crate::{
+  api::{
+    activity::{Activity, ActivityStream},
+    changes::{Change, ChangeStream},
+    games::OWNER_PERMISSION,
+    health::Readiness,
+    AppState,
+  },
+  i18n::{self, Locale, LocalizedText, Translations},
+  rules::{GameRules, TurnMode},
+  theme::GameTheme,
+  validation::{image_urls, translation_tags, MAX_DESCRIPTION_LEN, MAX_IMAGES, MAX_NAME_LEN},
+};
 
-use super::{apply_list_filters, handle_pg_error, Error, ListParams, UpdateResult};
+use super::{
+  apply_list_filters, apply_stream_filters,
+  events::{PlayEvent, PlayEventRow, ReplayState, PLAY_EVENT_COLUMNS},
+  handle_pg_error,
+  recaps::{self, PlayerHighlight, PresentHighlight},
+  BulkMode, BulkResult, BulkStatus, Error, ListParams, Page, UpdateResult,
+};
 
-#[derive(FromRow, Serialize)]
+#[derive(FromRow, Serialize, ToSchema, SimpleObject)]
+#[graphql(complex)]
 pub struct Game {
   pub id: Uuid,
   pub name: String,
+  pub description: Option<String>,
+  #[sqlx(json)]
+  #[schema(value_type = HashMap<String, LocalizedText>)]
+  #[graphql(skip)]
+  pub translations: Translations,
   #[sqlx(json)]
   pub users: HashMap<String, i64>,
   pub images: Vec<String>,
   pub player_id: Option<i64>,
   pub present_id: Option<i64>,
-  pub started_at: Option<NaiveDateTime>,
-  pub created_at: NaiveDateTime,
-  pub updated_at: Option<NaiveDateTime>,
+  pub started_at: Option<DateTime<Utc>>,
+  // set once every player owns a present
+  pub finished_at: Option<DateTime<Utc>>,
+  pub turn: i32,
+  // when the turn timer keeps the present for the current player
+  pub turn_deadline: Option<DateTime<Utc>>,
+  // sequence number of the latest play event, doubles as the game state version
+  pub event_seq: i64,
+  #[sqlx(json)]
+  #[graphql(skip)]
+  pub rules: GameRules,
+  #[sqlx(json)]
+  #[graphql(skip)]
+  pub theme: GameTheme,
+  // upper limit for present prices, in the game currency
+  pub budget_cents: Option<i64>,
+  pub currency: Option<String>,
+  // when the party is planned, in UTC
+  pub scheduled_at: Option<DateTime<Utc>>,
+  pub created_at: DateTime<Utc>,
+  pub updated_at: Option<DateTime<Utc>>,
+  // firebase uids, None for games from before they were recorded
+  pub created_by: Option<String>,
+  pub updated_by: Option<String>,
+}
+
+impl Game {
+  // changes with every write to the game, sent as the ETag
+  pub fn version(&self) -> DateTime<Utc> {
+    self.updated_at.unwrap_or(self.created_at)
+  }
+
+  pub fn localize(mut self, locale: Locale) -> Self {
+    i18n::resolve(
+      &self.translations,
+      locale,
+      &mut self.name,
+      &mut self.description,
+    );
+    self
+  }
 }
 
 // list games
-pub async fn list(db: &PgPool, user_id: &str, p: ListParams) -> Result<Vec<Game>, Error> {
+pub async fn list(db: &PgPool, user_id: &str, p: ListParams) -> Result<Page<Game>, Error> {
+  // game ids are uuids, users have few enough games for offsets
+  if p.after_id.is_some() {
+    return Err(Error::CursorUnsupported);
+  }
   let mut query = QueryBuilder::<Postgres>::new(
-    "SELECT id, name, images, users, player_id, present_id, started_at, created_at, updated_at FROM games WHERE users ? ",
+    "SELECT id, name, description, translations, images, users, player_id, present_id, started_at, finished_at, turn, turn_deadline, event_seq, rules, theme, budget_cents, currency, scheduled_at, created_at, updated_at, created_by, updated_by, COUNT(*) OVER() AS total_count FROM games WHERE deleted_at IS NULL AND users ? ",
   );
   query.push_bind(user_id);
   query = apply_list_filters(query, &p, vec!["id", "name"])?;
 
-  query
+  let rows = query
+    .build_query_as()
+    .fetch_all(db)
+    .await
+    .map_err(Error::Sqlx)?;
+  Ok(Page::new(rows, &p))
+}
+
+#[derive(FromRow, Serialize)]
+pub struct Membership {
+  pub game_id: Uuid,
+  pub name: String,
+  pub permission: i64,
+  pub started_at: Option<DateTime<Utc>>,
+  pub finished_at: Option<DateTime<Utc>>,
+  pub scheduled_at: Option<DateTime<Utc>>,
+}
+
+// the games a user is a member of, newest first
+pub async fn memberships(db: &PgPool, uid: &str) -> Result<Vec<Membership>, Error> {
+  query_as!(
+    Membership,
+    "SELECT g.id AS game_id, g.name, m.permission, g.started_at, g.finished_at, g.scheduled_at
+    FROM game_members m JOIN games g ON g.id = m.game_id
+    WHERE m.uid = $1 AND g.deleted_at IS NULL
+    ORDER BY g.created_at DESC",
+    uid
+  )
+  .fetch_all(db)
+  .await
+  .map_err(handle_pg_error)
+}
+
+// unfinished games the user owns
+pub async fn count_active_owned(db: impl PgExecutor<'_>, uid: &str) -> Result<i64, Error> {
+  query_scalar!(
+    r#"SELECT COUNT(*) AS "count!" FROM game_members m JOIN games g ON g.id = m.game_id
+    WHERE m.uid = $1 AND m.permission >= $2 AND g.finished_at IS NULL AND g.deleted_at IS NULL"#,
+    uid,
+    OWNER_PERMISSION
+  )
+  .fetch_one(db)
+  .await
+  .map_err(handle_pg_error)
+}
+
+// every game regardless of its users, for support
+pub async fn list_all(db: &PgPool, p: ListParams) -> Result<Page<Game>, Error> {
+  if p.after_id.is_some() {
+    return Err(Error::CursorUnsupported);
+  }
+  let mut query = QueryBuilder::<Postgres>::new(
+    "SELECT id, name, description, translations, images, users, player_id, present_id, started_at, finished_at, turn, turn_deadline, event_seq, rules, theme, budget_cents, currency, scheduled_at, created_at, updated_at, created_by, updated_by, COUNT(*) OVER() AS total_count FROM games WHERE TRUE",
+  );
+  query = apply_list_filters(query, &p, vec!["id", "name", "created_at"])?;
+
+  let rows = query
     .build_query_as()
     .fetch_all(db)
     .await
-    .map_err(Error::Sqlx)
+    .map_err(Error::Sqlx)?;
+  Ok(Page::new(rows, &p))
 }
 
 // get a game
 pub async fn get(db: &PgPool, id: Uuid) -> Result<Game, Error> {
-  query_as("SELECT id, name, images, users, player_id, present_id, started_at, created_at, updated_at FROM games WHERE id = $1")
+  query_as("SELECT id, name, description, translations, images, users, player_id, present_id, started_at, finished_at, turn, turn_deadline, event_seq, rules, theme, budget_cents, currency, scheduled_at, created_at, updated_at, created_by, updated_by FROM games WHERE id = $1 AND deleted_at IS NULL")
   .bind(id)
   .fetch_one(db)
   .await
@@ -59,41 +195,56 @@ pub struct CreateParams<'a> {
   pub name: &'a str,
   pub images: Vec<String>,
   pub users: &'a HashMap<String, i64>,
-}
-
-#[derive(sqlx::FromRow, Serialize, Debug)]
-pub struct CreateResult {
-  pub created_at: NaiveDateTime,
+  pub rules: GameRules,
+  pub created_by: &'a str,
 }
 
 // create a game
-pub async fn create<'a>(db: &PgPool, p: CreateParams<'a>) -> Result<CreateResult, Error> {
+pub async fn create<'a>(db: impl PgExecutor<'_>, p: CreateParams<'a>) -> Result<Game, Error> {
   query_as(
-    "INSERT INTO games (id, name, images, users) VALUES ($1, $2, $3, $4) RETURNING created_at",
+    "INSERT INTO games (id, name, images, users, rules, created_by) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id, name, description, translations, images, users, player_id, present_id, started_at, finished_at, turn, turn_deadline, event_seq, rules, theme, budget_cents, currency, scheduled_at, created_at, updated_at, created_by, updated_by",
   )
   .bind(p.id)
   .bind(p.name)
   .bind(p.images)
   .bind(Json(p.users))
+  .bind(Json(p.rules))
+  .bind(p.created_by)
   .fetch_one(db)
   .await
   .map_err(handle_pg_error)
 }
 
-#[derive(Deserialize, IsEmpty, Default)]
+#[derive(Deserialize, IsEmpty, Default, ToSchema, Validate)]
 pub struct UpdateData {
+  #[validate(length(min = 1, max = MAX_NAME_LEN))]
   pub name: Option<String>,
+  #[validate(length(max = MAX_DESCRIPTION_LEN))]
+  pub description: Option<String>,
+  #[schema(value_type = Option<HashMap<String, LocalizedText>>)]
+  #[validate(custom(function = translation_tags))]
+  pub translations: Option<Translations>,
+  #[validate(length(max = MAX_IMAGES), custom(function = image_urls))]
   pub images: Option<Vec<String>>,
   pub users: Option<HashMap<String, i64>>,
+  pub rules: Option<GameRules>,
+  pub theme: Option<GameTheme>,
+  pub budget_cents: Option<i64>,
+  pub currency: Option<String>,
+  pub scheduled_at: Option<DateTime<Utc>>,
 }
 
 #[skip_serializing_none]
-#[derive(sqlx::FromRow, Serialize, Debug)]
+#[derive(sqlx::FromRow, Serialize, Debug, ToSchema, SimpleObject)]
 pub struct GameStateUpdateResult {
   pub player_id: Option<i64>,
   pub present_id: Option<i64>,
-  pub started_at: Option<NaiveDateTime>,
-  pub updated_at: NaiveDateTime,
+  pub started_at: Option<DateTime<Utc>>,
+  pub finished_at: Option<DateTime<Utc>>,
+  pub turn: Option<i32>,
+  #[sqlx(default)]
+  pub turn_deadline: Option<DateTime<Utc>>,
+  pub updated_at: DateTime<Utc>,
 }
 
 impl IntoResponse for GameStateUpdateResult {
@@ -102,8 +253,14 @@ impl IntoResponse for GameStateUpdateResult {
   }
 }
 
-// update a game
-pub async fn update(db: &PgPool, game_id: Uuid, data: UpdateData) -> Result<UpdateResult, Error> {
+// update a game, only when it is still at one of the expected versions
+pub async fn update(
+  db: &PgPool,
+  game_id: Uuid,
+  data: UpdateData,
+  expected: Option<&[DateTime<Utc>]>,
+  updated_by: &str,
+) -> Result<UpdateResult, Error> {
   if data.is_empty() {
     return Err(Error::Empty);
   }
@@ -114,31 +271,110 @@ pub async fn update(db: &PgPool, game_id: Uuid, data: UpdateData) -> Result<Upda
   if let Some(name) = data.name {
     sep.push(" name = ").push_bind_unseparated(name);
   }
+  if let Some(description) = data.description {
+    sep
+      .push(" description = ")
+      .push_bind_unseparated(description);
+  }
+  if let Some(translations) = data.translations {
+    sep
+      .push(" translations = ")
+      .push_bind_unseparated(Json(translations));
+  }
   if let Some(images) = data.images {
     sep.push(" images = ").push_bind_unseparated(images);
   }
   if let Some(users) = data.users {
     sep.push(" users = ").push_bind_unseparated(Json(users));
   }
+  if let Some(rules) = data.rules {
+    sep.push(" rules = ").push_bind_unseparated(Json(rules));
+  }
+  if let Some(theme) = data.theme {
+    sep.push(" theme = ").push_bind_unseparated(Json(theme));
+  }
+  if let Some(budget_cents) = data.budget_cents {
+    sep
+      .push(" budget_cents = ")
+      .push_bind_unseparated(budget_cents);
+  }
+  if let Some(currency) = data.currency {
+    sep.push(" currency = ").push_bind_unseparated(currency);
+  }
+  if let Some(scheduled_at) = data.scheduled_at {
+    sep
+      .push(" scheduled_at = ")
+      .push_bind_unseparated(scheduled_at);
+  }
   sep.push(" updated_at = NOW()");
-  query.push(" WHERE id = ").push_bind(game_id);
-  query.push(" RETURNING updated_at");
+  sep.push(" updated_by = ").push_bind_unseparated(updated_by);
   query
+    .push(" WHERE deleted_at IS NULL AND id = ")
+    .push_bind(game_id);
+  write_versioned(db, game_id, query, expected).await
+}
+
+// finish a game UPDATE with the version check, a missed row is a stale version or a missing game
+async fn write_versioned(
+  db: &PgPool,
+  game_id: Uuid,
+  mut query: QueryBuilder<'_, Postgres>,
+  expected: Option<&[DateTime<Utc>]>,
+) -> Result<UpdateResult, Error> {
+  if let Some(expected) = expected {
+    query
+      .push(" AND COALESCE(updated_at, created_at) = ANY(")
+      .push_bind(expected.to_vec())
+      .push(")");
+  }
+  query.push(" RETURNING updated_at");
+  let updated = query
     .build_query_as()
-    .fetch_one(db)
+    .fetch_optional(db)
     .await
-    .map_err(handle_pg_error)
+    .map_err(handle_pg_error)?;
+  match updated {
+    Some(updated) => Ok(updated),
+    None if expected.is_some() => {
+      let exists = query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM games WHERE id = $1 AND deleted_at IS NULL)",
+        game_id
+      )
+      .fetch_one(db)
+      .await
+      .map_err(handle_pg_error)?;
+      Err(match exists {
+        Some(true) => Error::VersionMismatch,
+        _ => Error::NotFound,
+      })
+    }
+    None => Err(Error::NotFound),
+  }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema, Validate)]
+#[schema(as = GameReplaceParams)]
 pub struct ReplaceParams {
+  #[validate(length(min = 1, max = MAX_NAME_LEN))]
   pub name: String,
+  #[validate(length(max = MAX_IMAGES), custom(function = image_urls))]
   pub images: Option<Vec<String>>,
   pub users: HashMap<String, i64>,
+  // kept as they are when omitted
+  pub rules: Option<GameRules>,
+  pub budget_cents: Option<i64>,
+  pub currency: Option<String>,
+  pub scheduled_at: Option<DateTime<Utc>>,
 }
 
-// replace a game
-pub async fn replace(db: &PgPool, id: Uuid, p: ReplaceParams) -> Result<UpdateResult, Error> {
+// replace a game, only when it is still at one of the expected versions
+pub async fn replace(
+  db: &PgPool,
+  id: Uuid,
+  p: ReplaceParams,
+  expected: Option<&[DateTime<Utc>]>,
+  updated_by: &str,
+) -> Result<UpdateResult, Error> {
   let mut query = QueryBuilder::<Postgres>::new("UPDATE games SET");
   let mut sep = query.separated(", ");
   sep.push(" name = ").push_bind_unseparated(p.name);
@@ -146,48 +382,157 @@ pub async fn replace(db: &PgPool, id: Uuid, p: ReplaceParams) -> Result<UpdateRe
     .push(" images = ")
     .push_bind_unseparated(p.images.unwrap_or_default());
   sep.push(" users = ").push_bind_unseparated(Json(p.users));
+  if let Some(rules) = p.rules {
+    sep.push(" rules = ").push_bind_unseparated(Json(rules));
+  }
+  sep
+    .push(" budget_cents = ")
+    .push_bind_unseparated(p.budget_cents);
+  sep.push(" currency = ").push_bind_unseparated(p.currency);
+  sep
+    .push(" scheduled_at = ")
+    .push_bind_unseparated(p.scheduled_at);
   sep.push(" updated_at = NOW()");
-  query.push(" WHERE id = ").push_bind(id);
-  query.push(" RETURNING updated_at");
+  sep.push(" updated_by = ").push_bind_unseparated(updated_by);
   query
-    .build_query_as()
-    .fetch_one(db)
-    .await
-    .map_err(handle_pg_error)
+    .push(" WHERE deleted_at IS NULL AND id = ")
+    .push_bind(id);
+  write_versioned(db, id, query, expected).await
 }
 
 // delete a game
 pub async fn delete(db: &PgPool, game_id: Uuid) -> Result<(), Error> {
-  match query!("DELETE FROM games WHERE id = $1", game_id)
-    .execute(db)
-    .await
+  match query!(
+    "UPDATE games SET deleted_at = NOW() WHERE id = $1 AND deleted_at IS NULL",
+    game_id
+  )
+  .execute(db)
+  .await
   {
+    Ok(res) if res.rows_affected() == 0 => Err(Error::NotFound),
     Ok(_) => Ok(()),
     Err(err) => Err(handle_pg_error(err)),
   }
 }
 
+// undo a delete while the game is still kept, only for its owners. deleted games aren't in
+// anyone's permissions, so ownership is checked on the row itself
+pub async fn restore(db: &PgPool, game_id: Uuid, uid: &str) -> Result<Game, Error> {
+  query_as("UPDATE games SET deleted_at = NULL WHERE id = $1 AND deleted_at IS NOT NULL AND (users->>$2)::bigint >= $3 RETURNING id, name, description, translations, images, users, player_id, present_id, started_at, finished_at, turn, turn_deadline, event_seq, rules, theme, budget_cents, currency, scheduled_at, created_at, updated_at, created_by, updated_by")
+  .bind(game_id)
+  .bind(uid)
+  .bind(OWNER_PERMISSION)
+  .fetch_one(db)
+  .await
+  .map_err(handle_pg_error)
+}
+
+// remove a game and everything in it for good
+pub async fn destroy(db: &PgPool, game_id: Uuid) -> Result<(), Error> {
+  let mut tx = db.begin().await.map_err(handle_pg_error)?;
+  // the current player and present point back into the game's own rows
+  query!(
+    "UPDATE games SET player_id = NULL, present_id = NULL WHERE id = $1",
+    game_id
+  )
+  .execute(&mut *tx)
+  .await
+  .map_err(handle_pg_error)?;
+  let res = query!("DELETE FROM games WHERE id = $1", game_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(handle_pg_error)?;
+  if res.rows_affected() == 0 {
+    return Err(Error::NotFound);
+  }
+  tx.commit().await.map_err(handle_pg_error)
+}
+
+// destroy games deleted more than 30 days ago, returns how many were removed
+pub async fn purge_deleted(db: &PgPool) -> Result<u64, Error> {
+  let mut tx = db.begin().await.map_err(handle_pg_error)?;
+  query!(
+    "UPDATE games SET player_id = NULL, present_id = NULL
+    WHERE deleted_at < NOW() - INTERVAL '30 days'"
+  )
+  .execute(&mut *tx)
+  .await
+  .map_err(handle_pg_error)?;
+  let res = query!("DELETE FROM games WHERE deleted_at < NOW() - INTERVAL '30 days'")
+    .execute(&mut *tx)
+    .await
+    .map_err(handle_pg_error)?;
+  tx.commit().await.map_err(handle_pg_error)?;
+  Ok(res.rows_affected())
+}
+
+// notify listeners of an event again, for clients that missed it
+pub async fn reemit_event(db: &PgPool, game_id: Uuid, event_id: i64) -> Result<(), Error> {
+  query_scalar!(
+    "SELECT e.id FROM play_events e, pg_notify('play', row_to_json(e)::text)
+    WHERE e.game_id = $1 AND e.id = $2",
+    game_id,
+    event_id
+  )
+  .fetch_one(db)
+  .await
+  .map_err(handle_pg_error)?;
+  Ok(())
+}
+
 // update a game
-pub async fn start(db: &PgPool, game_id: Uuid) -> Result<GameStateUpdateResult, Error> {
-  let game = query!("UPDATE games SET started_at = NOW() WHERE id = $1 AND started_at IS NULL RETURNING started_at, updated_at", game_id)
-    .fetch_one(db)
+pub async fn start(
+  db: &PgPool,
+  game_id: Uuid,
+  actor_uid: &str,
+) -> Result<GameStateUpdateResult, Error> {
+  let mut tx = db.begin().await.map_err(Error::Sqlx)?;
+
+  let game = query!("UPDATE games SET started_at = NOW() WHERE id = $1 AND started_at IS NULL AND deleted_at IS NULL RETURNING started_at, updated_at", game_id)
+    .fetch_one(&mut *tx)
     .await
     .map_err(handle_pg_error)?;
 
+  query!(
+    "INSERT INTO play_events (game_id, kind, actor_uid) VALUES ($1, 'start', $2)",
+    game_id,
+    actor_uid
+  )
+  .execute(&mut *tx)
+  .await
+  .map_err(handle_pg_error)?;
+
+  tx.commit().await.map_err(handle_pg_error)?;
+
   Ok(GameStateUpdateResult {
     player_id: None,
     present_id: None,
     started_at: game.started_at,
+    finished_at: None,
+    turn_deadline: None,
+    turn: None,
     updated_at: game.updated_at.unwrap_or_default(),
   })
 }
 
 // reset a game
-pub async fn reset(db: &PgPool, game_id: Uuid) -> Result<GameStateUpdateResult, Error> {
+pub async fn reset(
+  db: &PgPool,
+  game_id: Uuid,
+  actor_uid: &str,
+) -> Result<GameStateUpdateResult, Error> {
   let mut tx = db.begin().await.map_err(|err| Error::Sqlx(err))?;
 
+  query!(
+    "SELECT id FROM games WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+    game_id
+  )
+  .fetch_one(&mut *tx)
+  .await
+  .map_err(handle_pg_error)?;
+
   match query!(
-    "UPDATE presents SET player_id = NULL, updated_at = NOW() WHERE game_id = $1",
+    "UPDATE presents SET player_id = NULL, immune_until_turn = NULL, updated_at = NOW() WHERE game_id = $1",
     game_id,
   )
   .execute(&mut *tx)
@@ -200,8 +545,12 @@ pub async fn reset(db: &PgPool, game_id: Uuid) -> Result<GameStateUpdateResult,
   let game = query!(
     "UPDATE games
      SET started_at = NULL,
+       finished_at = NULL,
        player_id = NULL,
        present_id = NULL,
+       turn = 0,
+       nudged_turn = NULL,
+       turn_deadline = NULL,
        updated_at = NOW()
      WHERE id = $1
      RETURNING updated_at",
@@ -219,35 +568,88 @@ pub async fn reset(db: &PgPool, game_id: Uuid) -> Result<GameStateUpdateResult,
     Err(err) => Err(handle_pg_error(err)),
   }?;
 
+  match query!("DELETE FROM recaps WHERE game_id = $1", game_id)
+    .execute(&mut *tx)
+    .await
+  {
+    Ok(_) => Ok(()),
+    Err(err) => Err(handle_pg_error(err)),
+  }?;
+
+  match query!("DELETE FROM guesses WHERE game_id = $1", game_id)
+    .execute(&mut *tx)
+    .await
+  {
+    Ok(_) => Ok(()),
+    Err(err) => Err(handle_pg_error(err)),
+  }?;
+
+  // sequence numbers keep counting so clients notice the history was cleared
+  query!(
+    "INSERT INTO play_events (game_id, kind, actor_uid) VALUES ($1, 'reset', $2)",
+    game_id,
+    actor_uid
+  )
+  .execute(&mut *tx)
+  .await
+  .map_err(handle_pg_error)?;
+
   tx.commit().await.map_err(handle_pg_error)?;
 
   Ok(GameStateUpdateResult {
     player_id: None,
     present_id: None,
     started_at: None,
+    finished_at: None,
+    turn_deadline: None,
+    turn: None,
     updated_at: game.updated_at.unwrap_or_default(),
   })
 }
 
 // roll a dice to pick a player
-pub async fn roll(db: &PgPool, game_id: Uuid) -> Result<GameStateUpdateResult, Error> {
+pub async fn roll(
+  db: &PgPool,
+  game_id: Uuid,
+  actor_uid: &str,
+) -> Result<GameStateUpdateResult, Error> {
   let mut tx = db.begin().await.map_err(|err| Error::Sqlx(err))?;
 
-  let game = query!(
-    "UPDATE games SET player_id = (
-    SELECT players.id 
-    FROM players
-    WHERE id NOT IN (
-      SELECT player_id
-      FROM presents 
-      WHERE game_id = $1 
-      AND player_id IS NOT NULL)
-    AND game_id = $1
-    ORDER BY random() 
-    LIMIT 1) 
-  WHERE player_id IS NULL 
-  AND id = $1 RETURNING player_id, updated_at",
-    game_id
+  let current = query_file!("queries/play/roll_lock.sql", game_id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(handle_pg_error)?;
+
+  // turn counts the rolls so far, snake games flip direction after each pass
+  let order = match current.rules.turn_mode {
+    TurnMode::Random => "random",
+    TurnMode::Sequential => "asc",
+    TurnMode::Snake
+      if current.players > 0 && (i64::from(current.turn) / current.players) % 2 == 1 =>
+    {
+      "desc"
+    }
+    TurnMode::Snake => "asc",
+  };
+
+  // teams take turns in id order, wrapping around after the last one
+  let last_team = if current.rules.alternate_teams {
+    query_file_scalar!("queries/play/roll_last_team.sql", game_id)
+      .fetch_optional(&mut *tx)
+      .await
+      .map_err(handle_pg_error)?
+      .flatten()
+  } else {
+    None
+  };
+
+  let game = query_file!(
+    "queries/play/roll_next_player.sql",
+    game_id,
+    order,
+    current.rules.turn_timer_seconds.map(f64::from),
+    current.rules.alternate_teams,
+    last_team
   )
   .fetch_one(&mut *tx)
   .await
@@ -255,14 +657,10 @@ pub async fn roll(db: &PgPool, game_id: Uuid) -> Result<GameStateUpdateResult, E
 
   match game.player_id {
     Some(player_id) => {
-      query!(
-        "INSERT INTO play_events (game_id, player_id) VALUES ($1, $2)",
-        game_id,
-        player_id
-      )
-      .execute(&mut *tx)
-      .await
-      .map_err(handle_pg_error)?;
+      query_file!("queries/play/roll_event.sql", game_id, player_id, actor_uid)
+        .execute(&mut *tx)
+        .await
+        .map_err(handle_pg_error)?;
 
       tx.commit().await.map_err(handle_pg_error)?;
 
@@ -270,6 +668,9 @@ pub async fn roll(db: &PgPool, game_id: Uuid) -> Result<GameStateUpdateResult, E
         player_id: Some(player_id),
         present_id: None,
         started_at: None,
+        finished_at: None,
+        turn: Some(game.turn),
+        turn_deadline: game.turn_deadline,
         updated_at: game.updated_at.unwrap_or_default(),
       })
     }
@@ -277,33 +678,103 @@ pub async fn roll(db: &PgPool, game_id: Uuid) -> Result<GameStateUpdateResult, E
   }
 }
 
+// game state a client based its decision on
+#[derive(Deserialize, Default, Clone, Copy, Debug)]
+pub struct Expected {
+  pub player_id: Option<i64>,
+  pub event_seq: Option<i64>,
+}
+
+#[derive(FromRow)]
+struct CurrentState {
+  player_id: Option<i64>,
+  event_seq: i64,
+}
+
+// deleted games can't be played
+const CURRENT_STATE_SQL: &str =
+  "SELECT player_id, event_seq FROM games WHERE id = $1 AND deleted_at IS NULL FOR UPDATE";
+
+// lock the game and reject the action when it moved on since the client looked
+async fn check_expected(
+  conn: &mut PgConnection,
+  game_id: Uuid,
+  expected: Expected,
+) -> Result<(), Error> {
+  let current: CurrentState = query_as(CURRENT_STATE_SQL)
+    .bind(game_id)
+    .fetch_one(conn)
+    .await
+    .map_err(handle_pg_error)?;
+  let player_changed = matches!(expected.player_id, Some(id) if current.player_id != Some(id));
+  let seq_changed = matches!(expected.event_seq, Some(seq) if current.event_seq != seq);
+  if player_changed || seq_changed {
+    return Err(Error::StateChanged {
+      player_id: current.player_id,
+      event_seq: current.event_seq,
+    });
+  }
+  Ok(())
+}
+
+// the statements of a roll, pick, keep or steal. query_file! sends the file contents as they are,
+// so preparing the same text puts them in the statement cache the macros use
+const PLAY_QUERIES: [&str; 19] = [
+  CURRENT_STATE_SQL,
+  FINAL_SWAP_SQL,
+  recaps::DONE_SQL,
+  include_str!("../../queries/play/roll_lock.sql"),
+  include_str!("../../queries/play/roll_last_team.sql"),
+  include_str!("../../queries/play/roll_next_player.sql"),
+  include_str!("../../queries/play/roll_event.sql"),
+  include_str!("../../queries/play/pick_present.sql"),
+  include_str!("../../queries/play/pick_event.sql"),
+  include_str!("../../queries/play/keep_lock.sql"),
+  include_str!("../../queries/play/move_present.sql"),
+  include_str!("../../queries/play/end_turn.sql"),
+  include_str!("../../queries/play/keep_event.sql"),
+  include_str!("../../queries/play/steal_state.sql"),
+  include_str!("../../queries/play/steal_present.sql"),
+  include_str!("../../queries/play/steal_count.sql"),
+  include_str!("../../queries/play/steal_last.sql"),
+  include_str!("../../queries/play/steal_take.sql"),
+  include_str!("../../queries/play/steal_event.sql"),
+];
+
+// open the idle connections up front and prepare the statements of every turn on them
+pub async fn warmup(db: &PgPool, connections: u32) -> Result<(), Error> {
+  let conns = futures_util::future::try_join_all((0..connections).map(|_| db.acquire())).await?;
+  for mut conn in conns {
+    for sql in PLAY_QUERIES {
+      conn.prepare(sql).await?;
+    }
+  }
+  Ok(())
+}
+
 // pick a present
 pub async fn pick(
   db: &PgPool,
   game_id: Uuid,
   present_id: i64,
+  expected: Expected,
+  actor_uid: &str,
 ) -> Result<GameStateUpdateResult, Error> {
   let mut tx = db.begin().await.map_err(|err| Error::Sqlx(err))?;
 
-  let game = query!(
-    "UPDATE games SET
-      present_id = $1,
-      updated_at = NOW()
-    WHERE present_id IS NULL
-      AND id = $2
-    RETURNING player_id, updated_at",
-    present_id,
-    game_id
-  )
-  .fetch_one(&mut *tx)
-  .await
-  .map_err(handle_pg_error)?;
+  check_expected(&mut tx, game_id, expected).await?;
 
-  query!(
-    "INSERT INTO play_events (game_id, player_id, present_id) VALUES ($1, $2, $3)",
+  let game = query_file!("queries/play/pick_present.sql", present_id, game_id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(handle_pg_error)?;
+
+  query_file!(
+    "queries/play/pick_event.sql",
     game_id,
     game.player_id,
-    present_id
+    present_id,
+    actor_uid
   )
   .execute(&mut *tx)
   .await
@@ -315,94 +786,232 @@ pub async fn pick(
     player_id: None,
     present_id: Some(present_id),
     started_at: None,
+    finished_at: None,
+    turn_deadline: None,
+    turn: None,
     updated_at: game.updated_at.unwrap_or_default(),
   })
 }
 
 // keep a present
-pub async fn keep(db: &PgPool, game_id: Uuid) -> Result<GameStateUpdateResult, Error> {
+pub async fn keep(
+  db: &PgPool,
+  game_id: Uuid,
+  actor_uid: &str,
+) -> Result<GameStateUpdateResult, Error> {
   let mut tx = db.begin().await.map_err(|err| Error::Sqlx(err))?;
 
-  let game = query!(
-    "SELECT player_id, present_id FROM games WHERE id = $1",
-    game_id
-  )
-  .fetch_one(&mut *tx)
-  .await
-  .map_err(handle_pg_error)?;
+  let result = keep_held(&mut tx, game_id, Some(actor_uid)).await?;
 
-  match query!(
-    "UPDATE presents SET player_id = $1, updated_at = NOW() WHERE id = $2",
+  tx.commit().await.map_err(handle_pg_error)?;
+
+  Ok(result)
+}
+
+// give the current player the present they unwrapped and end the turn
+async fn keep_held(
+  conn: &mut PgConnection,
+  game_id: Uuid,
+  actor_uid: Option<&str>,
+) -> Result<GameStateUpdateResult, Error> {
+  let game = query_file!("queries/play/keep_lock.sql", game_id)
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(handle_pg_error)?;
+
+  match query_file!(
+    "queries/play/move_present.sql",
     game.player_id,
     game.present_id
   )
-  .execute(&mut *tx)
+  .execute(&mut *conn)
   .await
   {
     Ok(_) => Ok(()),
     Err(err) => Err(handle_pg_error(err)),
   }?;
 
-  let game_after = query!(
-    "UPDATE games SET
-      player_id = NULL,
-      present_id = NULL,
-      updated_at = NOW()
-    WHERE id = $1
-    RETURNING updated_at",
-    game_id
-  )
-  .fetch_one(&mut *tx)
-  .await
-  .map_err(handle_pg_error)?;
+  let game_after = query_file!("queries/play/end_turn.sql", game_id)
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(handle_pg_error)?;
 
-  query!(
-    "INSERT INTO play_events (game_id, player_id, present_id, from_player_id, from_present_id) VALUES ($1, $2, $3, $4, $5)",
+  query_file!(
+    "queries/play/keep_event.sql",
     game_id,
     game.player_id,
     game.present_id,
     game.player_id,
     game.present_id,
+    actor_uid,
   )
-  .execute(&mut *tx)
+  .execute(&mut *conn)
   .await
   .map_err(handle_pg_error)?;
 
-  tx.commit().await.map_err(handle_pg_error)?;
+  let final_swap = begin_final_swap(&mut *conn, game_id, actor_uid).await?;
+  let finished_at = finish_if_done(&mut *conn, game_id, actor_uid).await?;
 
   Ok(GameStateUpdateResult {
-    player_id: None,
-    present_id: None,
+    player_id: final_swap.as_ref().map(|swap| swap.player_id),
+    present_id: final_swap.as_ref().map(|swap| swap.present_id),
     started_at: None,
+    finished_at,
+    turn_deadline: None,
+    turn: None,
     updated_at: game_after.updated_at.unwrap_or_default(),
   })
 }
 
-// steal a present
-pub async fn steal(
+#[derive(FromRow, Debug)]
+pub struct TurnTimer {
+  pub game_id: Uuid,
+  pub turn_deadline: DateTime<Utc>,
+}
+
+// turns waiting on their timer, rescheduled after a restart
+pub async fn turn_timers(db: &PgPool) -> Result<Vec<TurnTimer>, Error> {
+  query_as(
+    "SELECT id AS game_id, turn_deadline FROM games
+    WHERE turn_deadline IS NOT NULL AND player_id IS NOT NULL",
+  )
+  .fetch_all(db)
+  .await
+  .map_err(handle_pg_error)
+}
+
+// the turn timer ran out, keep the unwrapped present or hand the player a random wrapped one,
+// returns None when the turn ended before the deadline
+pub async fn auto_keep(
   db: &PgPool,
   game_id: Uuid,
-  present_id: i64,
-) -> Result<GameStateUpdateResult, Error> {
-  let mut tx = db.begin().await.map_err(|err| Error::Sqlx(err))?;
+  deadline: DateTime<Utc>,
+) -> Result<Option<GameStateUpdateResult>, Error> {
+  let mut tx = db.begin().await.map_err(Error::Sqlx)?;
 
+  // a timer from an undone roll has a different deadline than the current turn
   let game = query!(
-    "SELECT player_id, present_id FROM games WHERE id = $1",
-    game_id
+    "SELECT player_id, present_id FROM games
+    WHERE id = $1 AND turn_deadline = $2 AND player_id IS NOT NULL AND deleted_at IS NULL
+    FOR UPDATE",
+    game_id,
+    deadline
   )
-  // .bind(game_id)
-  .fetch_one(&mut *tx)
+  .fetch_optional(&mut *tx)
   .await
   .map_err(handle_pg_error)?;
+  let Some(game) = game else {
+    return Ok(None);
+  };
+
+  if game.present_id.is_none() {
+    let present_id = query_scalar!(
+      "SELECT id FROM presents WHERE game_id = $1 AND player_id IS NULL ORDER BY random() LIMIT 1",
+      game_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(handle_pg_error)?;
+    let Some(present_id) = present_id else {
+      // nothing left to unwrap, the host has to assign one
+      query!(
+        "UPDATE games SET turn_deadline = NULL WHERE id = $1",
+        game_id
+      )
+      .execute(&mut *tx)
+      .await
+      .map_err(handle_pg_error)?;
+      tx.commit().await.map_err(handle_pg_error)?;
+      return Ok(None);
+    };
+
+    query!(
+      "UPDATE games SET present_id = $2, updated_at = NOW() WHERE id = $1",
+      game_id,
+      present_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(handle_pg_error)?;
+
+    query!(
+      "INSERT INTO play_events (game_id, kind, player_id, present_id) VALUES ($1, 'pick', $2, $3)",
+      game_id,
+      game.player_id,
+      present_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(handle_pg_error)?;
+  }
 
-  let present = query!("SELECT player_id FROM presents WHERE id = $1", present_id)
+  let result = keep_held(&mut tx, game_id, None).await?;
+
+  tx.commit().await.map_err(handle_pg_error)?;
+
+  Ok(Some(result))
+}
+
+// steal a present
+pub async fn steal(
+  db: &PgPool,
+  game_id: Uuid,
+  present_id: i64,
+  expected: Expected,
+  actor_uid: &str,
+) -> Result<GameStateUpdateResult, Error> {
+  let mut tx = db.begin().await.map_err(|err| Error::Sqlx(err))?;
+
+  check_expected(&mut tx, game_id, expected).await?;
+
+  let game = query_file!("queries/play/steal_state.sql", game_id)
+    // .bind(game_id)
     .fetch_one(&mut *tx)
     .await
     .map_err(handle_pg_error)?;
 
-  match query!(
-    "UPDATE presents SET player_id = $1, updated_at = NOW() WHERE id = $2",
+  let present = query_file!("queries/play/steal_present.sql", present_id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(handle_pg_error)?;
+
+  if !game.rules.allow_steals {
+    return Err(Error::StealingDisabled);
+  }
+  if game.rules.steal_immunity {
+    if let Some(until_turn) = present.immune_until_turn.filter(|t| *t >= game.turn) {
+      return Err(Error::PresentImmune { until_turn });
+    }
+  }
+  if let Some(limit) = game.rules.max_steals_per_present {
+    let steals = query_file_scalar!("queries/play/steal_count.sql", game_id, present_id)
+      .fetch_one(&mut *tx)
+      .await
+      .map_err(handle_pg_error)?;
+    if steals >= i64::from(limit) {
+      return Err(Error::StealLimitReached { limit });
+    }
+  }
+  if game.rules.no_steal_back {
+    let last_steal = query_file!("queries/play/steal_last.sql", game_id)
+      .fetch_optional(&mut *tx)
+      .await
+      .map_err(handle_pg_error)?;
+    if let Some(last_steal) = last_steal {
+      if last_steal.from_player_id.is_some()
+        && last_steal.from_player_id == game.player_id
+        && last_steal.from_present_id == Some(present_id)
+      {
+        return Err(Error::StealBackForbidden);
+      }
+    }
+  }
+  let immune_until_turn = game.rules.steal_immunity.then_some(game.turn + 1);
+
+  match query_file!(
+    "queries/play/steal_take.sql",
     game.player_id,
+    immune_until_turn,
     present_id,
   )
   .execute(&mut *tx)
@@ -412,8 +1021,8 @@ pub async fn steal(
     Err(err) => Err(handle_pg_error(err)),
   }?;
 
-  match query!(
-    "UPDATE presents SET player_id = $1, updated_at = NOW() WHERE id = $2",
+  match query_file!(
+    "queries/play/move_present.sql",
     present.player_id,
     game.present_id
   )
@@ -424,52 +1033,665 @@ pub async fn steal(
     Err(err) => Err(handle_pg_error(err)),
   }?;
 
-  let game_after = query!(
-    "UPDATE games SET
-      player_id = NULL,
-      present_id = NULL,
-      updated_at = NOW()
-    WHERE id = $1
-    RETURNING updated_at",
+  let game_after = query_file!("queries/play/end_turn.sql", game_id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(handle_pg_error)?;
+
+  query_file!(
+    "queries/play/steal_event.sql",
+    game_id,
+    game.player_id,
+    game.present_id,
+    present.player_id,
+    present_id,
+    actor_uid,
+  )
+  .execute(&mut *tx)
+  .await
+  .map_err(handle_pg_error)?;
+
+  let final_swap = begin_final_swap(&mut tx, game_id, Some(actor_uid)).await?;
+  let finished_at = finish_if_done(&mut tx, game_id, Some(actor_uid)).await?;
+
+  tx.commit().await.map_err(handle_pg_error)?;
+
+  Ok(GameStateUpdateResult {
+    started_at: None,
+    finished_at,
+    turn_deadline: None,
+    turn: None,
+    player_id: final_swap.as_ref().map(|swap| swap.player_id),
+    present_id: final_swap.as_ref().map(|swap| swap.present_id),
+    updated_at: game_after.updated_at.unwrap_or_default(),
+  })
+}
+
+#[derive(FromRow)]
+struct FinalSwap {
+  player_id: i64,
+  present_id: i64,
+}
+
+const FINAL_SWAP_SQL: &str = "SELECT first.player_id, presents.id AS present_id
+  FROM games
+  JOIN LATERAL (
+    SELECT player_id FROM play_events
+    WHERE game_id = games.id AND kind = 'roll'
+    ORDER BY seq LIMIT 1
+  ) first ON TRUE
+  JOIN presents ON presents.game_id = games.id AND presents.player_id = first.player_id
+  WHERE games.id = $1
+    AND (games.rules->>'final_swap')::boolean IS TRUE
+    AND games.player_id IS NULL
+    AND NOT EXISTS (SELECT 1 FROM play_events WHERE game_id = games.id AND kind = 'final_swap')
+    AND NOT EXISTS (
+      SELECT 1 FROM players
+      WHERE game_id = games.id
+      AND id NOT IN (SELECT player_id FROM presents WHERE game_id = games.id AND player_id IS NOT NULL))
+  ORDER BY presents.id
+  LIMIT 1";
+
+// with the final swap rule, the first player gets one more turn once everyone has a present,
+// they can steal to swap with anyone or keep what they have
+async fn begin_final_swap(
+  conn: &mut PgConnection,
+  game_id: Uuid,
+  actor_uid: Option<&str>,
+) -> Result<Option<FinalSwap>, Error> {
+  let swap: Option<FinalSwap> = query_as(FINAL_SWAP_SQL)
+    .bind(game_id)
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(handle_pg_error)?;
+  let Some(swap) = swap else {
+    return Ok(None);
+  };
+
+  query!(
+    "UPDATE games SET player_id = $2, present_id = $3, updated_at = NOW() WHERE id = $1",
+    game_id,
+    swap.player_id,
+    swap.present_id
+  )
+  .execute(&mut *conn)
+  .await
+  .map_err(handle_pg_error)?;
+
+  query!(
+    "INSERT INTO play_events (game_id, kind, player_id, present_id, actor_uid) VALUES ($1, 'final_swap', $2, $3, $4)",
+    game_id,
+    swap.player_id,
+    swap.present_id,
+    actor_uid
+  )
+  .execute(&mut *conn)
+  .await
+  .map_err(handle_pg_error)?;
+
+  Ok(Some(swap))
+}
+
+// mark the game finished and store its recap once every player owns a present,
+// returns the finish time when this action ended the game
+async fn finish_if_done(
+  conn: &mut PgConnection,
+  game_id: Uuid,
+  actor_uid: Option<&str>,
+) -> Result<Option<DateTime<Utc>>, Error> {
+  if recaps::finish_if_done(&mut *conn, game_id).await?.is_none() {
+    return Ok(None);
+  }
+
+  let finished = query!(
+    "UPDATE games SET finished_at = NOW() WHERE id = $1 AND finished_at IS NULL RETURNING finished_at",
+    game_id
+  )
+  .fetch_optional(&mut *conn)
+  .await
+  .map_err(handle_pg_error)?;
+  let Some(finished) = finished else {
+    return Ok(None);
+  };
+
+  query!(
+    "INSERT INTO play_events (game_id, kind, actor_uid) VALUES ($1, 'finish', $2)",
+    game_id,
+    actor_uid
+  )
+  .execute(&mut *conn)
+  .await
+  .map_err(handle_pg_error)?;
+
+  Ok(finished.finished_at)
+}
+
+// hand a present to a player outside of normal play
+pub async fn assign(
+  db: &PgPool,
+  game_id: Uuid,
+  present_id: i64,
+  player_id: i64,
+  reason: &str,
+  actor_uid: &str,
+) -> Result<GameStateUpdateResult, Error> {
+  let mut tx = db.begin().await.map_err(Error::Sqlx)?;
+  let result = assign_on(&mut tx, game_id, present_id, player_id, reason, actor_uid).await?;
+  tx.commit().await.map_err(handle_pg_error)?;
+  Ok(result)
+}
+
+pub struct Assignment {
+  pub present_id: i64,
+  pub player_id: i64,
+  pub reason: String,
+}
+
+// hand out many presents at once, in the order given
+pub async fn assign_many(
+  db: &PgPool,
+  game_id: Uuid,
+  items: Vec<Assignment>,
+  mode: BulkMode,
+  actor_uid: &str,
+) -> Result<BulkResult, Error> {
+  if items.is_empty() {
+    return Err(Error::Empty);
+  }
+  let mut tx = db.begin().await.map_err(Error::Sqlx)?;
+  let mut result = BulkResult::default();
+  for (index, item) in items.into_iter().enumerate() {
+    // each item gets a savepoint so a failure only undoes that item
+    let mut item_tx = Acquire::begin(&mut *tx).await.map_err(Error::Sqlx)?;
+    let assigned = assign_on(
+      &mut item_tx,
+      game_id,
+      item.present_id,
+      item.player_id,
+      &item.reason,
+      actor_uid,
+    )
+    .await;
+    match assigned {
+      Ok(_) => {
+        item_tx.commit().await.map_err(Error::Sqlx)?;
+        result.push(
+          index,
+          BulkStatus::Assigned {
+            id: item.present_id,
+            player_id: item.player_id,
+          },
+        );
+      }
+      Err(err) if mode == BulkMode::Partial => {
+        item_tx.rollback().await.map_err(Error::Sqlx)?;
+        result.push_error(index, err);
+      }
+      Err(err) => return Err(err),
+    }
+  }
+  tx.commit().await.map_err(handle_pg_error)?;
+  Ok(result)
+}
+
+async fn assign_on(
+  tx: &mut PgConnection,
+  game_id: Uuid,
+  present_id: i64,
+  player_id: i64,
+  reason: &str,
+  actor_uid: &str,
+) -> Result<GameStateUpdateResult, Error> {
+  // serializes with play actions, so the player can't be handed a present in between
+  query!(
+    "SELECT id FROM games WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+    game_id
+  )
+  .fetch_one(&mut *tx)
+  .await
+  .map_err(handle_pg_error)?;
+
+  let present = query!(
+    "SELECT player_id FROM presents WHERE id = $1 AND game_id = $2 FOR UPDATE",
+    present_id,
+    game_id
+  )
+  .fetch_one(&mut *tx)
+  .await
+  .map_err(handle_pg_error)?;
+
+  query!(
+    "SELECT id FROM players WHERE id = $1 AND game_id = $2",
+    player_id,
     game_id
   )
   .fetch_one(&mut *tx)
   .await
   .map_err(handle_pg_error)?;
 
+  // a player holds one present at a time, the owner has to move the other one first
+  let held: Option<i64> = query_scalar(
+    "SELECT id FROM presents WHERE game_id = $1 AND player_id = $2 AND id <> $3 LIMIT 1",
+  )
+  .bind(game_id)
+  .bind(player_id)
+  .bind(present_id)
+  .fetch_optional(&mut *tx)
+  .await
+  .map_err(handle_pg_error)?;
+  if let Some(present_id) = held {
+    return Err(Error::PlayerHasPresent { present_id });
+  }
+
+  let updated = query!(
+    "UPDATE presents SET player_id = $1, updated_at = NOW() WHERE id = $2 RETURNING updated_at",
+    player_id,
+    present_id
+  )
+  .fetch_one(&mut *tx)
+  .await
+  .map_err(handle_pg_error)?;
+
   query!(
-    "INSERT INTO play_events (game_id, player_id, present_id, from_player_id, from_present_id) VALUES ($1, $2, $3, $4, $5)",
+    "INSERT INTO play_events (game_id, kind, player_id, present_id, from_player_id, reason, actor_uid) VALUES ($1, 'assign', $2, $3, $4, $5, $6)",
     game_id,
-    game.player_id,
-    game.present_id,
-    present.player_id,
+    player_id,
     present_id,
+    present.player_id,
+    reason,
+    actor_uid,
   )
   .execute(&mut *tx)
   .await
   .map_err(handle_pg_error)?;
 
-  tx.commit().await.map_err(handle_pg_error)?;
+  let finished_at = finish_if_done(tx, game_id, Some(actor_uid)).await?;
 
   Ok(GameStateUpdateResult {
+    player_id: Some(player_id),
+    present_id: Some(present_id),
     started_at: None,
-    player_id: None,
-    present_id: None,
-    updated_at: game_after.updated_at.unwrap_or_default(),
+    finished_at,
+    turn_deadline: None,
+    turn: None,
+    updated_at: updated.updated_at.unwrap_or_default(),
   })
 }
 
-#[derive(FromRow, Clone, Serialize, Deserialize, Debug)]
-pub struct PlayEvent {
-  pub id: i64,
+// the player gets their turn back, with a fresh deadline when the turn timer is on
+const RESUME_TURN_SQL: &str = "UPDATE games SET player_id = $2, present_id = $3,
+  turn_deadline = NOW() + make_interval(secs => (rules->>'turn_timer_seconds')::float8)
+  WHERE id = $1";
+
+#[derive(FromRow)]
+struct LastEvent {
+  id: i64,
+  seq: i64,
+  kind: String,
+  player_id: Option<i64>,
+  present_id: Option<i64>,
+  from_player_id: Option<i64>,
+  from_present_id: Option<i64>,
+}
+
+// revert the latest play action and drop it from the history, a reset cannot be undone
+// and nudges are skipped since they don't change the game
+pub async fn undo(
+  db: &PgPool,
+  game_id: Uuid,
+  actor_uid: &str,
+) -> Result<GameStateUpdateResult, Error> {
+  let mut tx = db.begin().await.map_err(Error::Sqlx)?;
+
+  query!(
+    "SELECT id FROM games WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+    game_id
+  )
+  .fetch_one(&mut *tx)
+  .await
+  .map_err(handle_pg_error)?;
+
+  let last: Option<LastEvent> = query_as(
+    "SELECT id, seq, kind, player_id, present_id, from_player_id, from_present_id
+    FROM play_events WHERE game_id = $1 AND kind NOT IN ('undo', 'finish', 'nudge')
+    ORDER BY seq DESC LIMIT 1",
+  )
+  .bind(game_id)
+  .fetch_optional(&mut *tx)
+  .await
+  .map_err(handle_pg_error)?;
+  let Some(last) = last.filter(|last| last.kind != "reset") else {
+    return Err(Error::NothingToUndo);
+  };
+
+  // during the final swap the player already owned the present they were holding
+  let (in_final_swap,): (bool,) = query_as(
+    "SELECT EXISTS (SELECT 1 FROM play_events WHERE game_id = $1 AND kind = 'final_swap' AND seq < $2)",
+  )
+  .bind(game_id)
+  .bind(last.seq)
+  .fetch_one(&mut *tx)
+  .await
+  .map_err(handle_pg_error)?;
+  let held_by = in_final_swap.then_some(last.player_id).flatten();
+
+  let reverts: Vec<(&str, Vec<Option<i64>>)> = match last.kind.as_str() {
+    "start" => vec![("UPDATE games SET started_at = NULL WHERE id = $1", vec![])],
+    "roll" => vec![(
+      "UPDATE games SET player_id = NULL, turn = turn - 1, turn_deadline = NULL WHERE id = $1",
+      vec![],
+    )],
+    "pick" => vec![("UPDATE games SET present_id = NULL WHERE id = $1", vec![])],
+    "keep" => vec![
+      (
+        "UPDATE presents SET player_id = $2 WHERE id = $3 AND game_id = $1",
+        vec![held_by, last.present_id],
+      ),
+      (
+        RESUME_TURN_SQL,
+        vec![last.player_id, last.present_id],
+      ),
+    ],
+    "steal" => vec![
+      (
+        "UPDATE presents SET player_id = $2, immune_until_turn = NULL WHERE id = $3 AND game_id = $1",
+        vec![last.from_player_id, last.from_present_id],
+      ),
+      (
+        "UPDATE presents SET player_id = $2 WHERE id = $3 AND game_id = $1",
+        vec![held_by, last.present_id],
+      ),
+      (
+        RESUME_TURN_SQL,
+        vec![last.player_id, last.present_id],
+      ),
+    ],
+    "assign" => vec![(
+      "UPDATE presents SET player_id = $2 WHERE id = $3 AND game_id = $1",
+      vec![last.from_player_id, last.present_id],
+    )],
+    "final_swap" => vec![(
+      "UPDATE games SET player_id = NULL, present_id = NULL WHERE id = $1",
+      vec![],
+    )],
+    _ => return Err(Error::NothingToUndo),
+  };
+  for (sql, binds) in reverts {
+    let mut revert = query(sql).bind(game_id);
+    for bind in binds {
+      revert = revert.bind(bind);
+    }
+    revert.execute(&mut *tx).await.map_err(handle_pg_error)?;
+  }
+
+  query!("DELETE FROM play_events WHERE id = $1", last.id)
+    .execute(&mut *tx)
+    .await
+    .map_err(handle_pg_error)?;
+
+  query!(
+    "INSERT INTO play_events (game_id, kind, undone_seq, actor_uid) VALUES ($1, 'undo', $2, $3)",
+    game_id,
+    last.seq,
+    actor_uid
+  )
+  .execute(&mut *tx)
+  .await
+  .map_err(handle_pg_error)?;
+
+  // an undone final move reopens the game, an undone assign may still leave it done
+  query!("DELETE FROM recaps WHERE game_id = $1", game_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(handle_pg_error)?;
+  query!(
+    "DELETE FROM play_events WHERE game_id = $1 AND kind = 'finish'",
+    game_id
+  )
+  .execute(&mut *tx)
+  .await
+  .map_err(handle_pg_error)?;
+  query!("UPDATE games SET finished_at = NULL WHERE id = $1", game_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(handle_pg_error)?;
+  finish_if_done(&mut tx, game_id, Some(actor_uid)).await?;
+
+  let game = query_as(
+    "UPDATE games SET updated_at = NOW() WHERE id = $1
+    RETURNING player_id, present_id, started_at, finished_at, turn, turn_deadline, updated_at",
+  )
+  .bind(game_id)
+  .fetch_one(&mut *tx)
+  .await
+  .map_err(handle_pg_error)?;
+
+  tx.commit().await.map_err(handle_pg_error)?;
+
+  Ok(game)
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct NudgeResult {
   pub player_id: i64,
-  pub present_id: Option<i64>,
-  pub from_player_id: Option<i64>,
-  pub from_present_id: Option<i64>,
-  pub created_at: NaiveDateTime,
+  pub uid: Option<String>,
+  pub turn: i32,
+}
+
+// remind the current player to hurry up, at most once per turn
+pub async fn nudge(
+  db: &PgPool,
+  game_id: Uuid,
+  idle_seconds: i64,
+  actor_uid: &str,
+) -> Result<NudgeResult, Error> {
+  let mut tx = db.begin().await.map_err(Error::Sqlx)?;
+
+  let game = query!(
+    r#"SELECT games.player_id, games.turn, games.nudged_turn, players.uid,
+      (SELECT EXTRACT(EPOCH FROM NOW() - MAX(created_at))::BIGINT
+        FROM play_events WHERE game_id = games.id) AS "idle_seconds"
+    FROM games
+    LEFT JOIN players ON players.id = games.player_id
+    WHERE games.id = $1 AND games.deleted_at IS NULL
+    FOR UPDATE OF games"#,
+    game_id
+  )
+  .fetch_one(&mut *tx)
+  .await
+  .map_err(handle_pg_error)?;
+
+  let Some(player_id) = game.player_id else {
+    return Err(Error::NoActivePlayer);
+  };
+  if game.nudged_turn == Some(game.turn) {
+    return Err(Error::AlreadyNudged);
+  }
+  let idle = game.idle_seconds.unwrap_or_default();
+  if idle < idle_seconds {
+    return Err(Error::PlayerNotIdle {
+      retry_after: idle_seconds - idle,
+    });
+  }
+
+  query!("UPDATE games SET nudged_turn = turn WHERE id = $1", game_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(handle_pg_error)?;
+
+  query!(
+    "INSERT INTO play_events (game_id, kind, player_id, actor_uid) VALUES ($1, 'nudge', $2, $3)",
+    game_id,
+    player_id,
+    actor_uid
+  )
+  .execute(&mut *tx)
+  .await
+  .map_err(handle_pg_error)?;
+
+  tx.commit().await.map_err(handle_pg_error)?;
+
+  Ok(NudgeResult {
+    player_id,
+    uid: game.uid,
+    turn: game.turn,
+  })
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct GameStats {
+  pub most_stolen_present: Option<PresentHighlight>,
+  pub biggest_thief: Option<PlayerHighlight>,
+  // most turns in a row that ended with a steal
+  pub longest_steal_chain: i64,
+  // from roll to keep or steal, over the finished turns
+  pub average_turn_seconds: Option<f64>,
+}
+
+// steal statistics of a game so far
+pub async fn stats(db: &PgPool, game_id: Uuid) -> Result<GameStats, Error> {
+  let mut conn = db.acquire().await.map_err(Error::Sqlx)?;
+
+  let most_stolen_present = recaps::most_stolen_present(&mut conn, game_id).await?;
+  let biggest_thief = recaps::biggest_thief(&mut conn, game_id).await?;
+
+  // runs of steals among the turn endings, numbered by the gaps-and-islands trick
+  let longest_steal_chain = query_scalar!(
+    r#"SELECT COALESCE(MAX(length), 0) AS "length!" FROM (
+      SELECT COUNT(*) AS length FROM (
+        SELECT kind,
+          ROW_NUMBER() OVER (ORDER BY seq) - ROW_NUMBER() OVER (PARTITION BY kind ORDER BY seq) AS run
+        FROM play_events
+        WHERE game_id = $1 AND kind IN ('keep', 'steal')
+      ) endings
+      WHERE kind = 'steal'
+      GROUP BY run
+    ) runs"#,
+    game_id
+  )
+  .fetch_one(&mut *conn)
+  .await
+  .map_err(handle_pg_error)?;
+
+  let average_turn_seconds = query_scalar!(
+    r#"SELECT EXTRACT(EPOCH FROM AVG(ended_at - created_at))::FLOAT8 FROM (
+      SELECT kind, created_at,
+        LEAD(kind) OVER (ORDER BY seq) AS next_kind,
+        LEAD(created_at) OVER (ORDER BY seq) AS ended_at
+      FROM play_events
+      WHERE game_id = $1 AND kind IN ('roll', 'keep', 'steal')
+    ) turns
+    WHERE kind = 'roll' AND next_kind IN ('keep', 'steal')"#,
+    game_id
+  )
+  .fetch_one(&mut *conn)
+  .await
+  .map_err(handle_pg_error)?;
+
+  Ok(GameStats {
+    most_stolen_present,
+    biggest_thief,
+    longest_steal_chain,
+    average_turn_seconds,
+  })
 }
 
-pub type PlayStream = Sender<PlayEvent>;
+#[derive(FromRow, Serialize, Debug)]
+pub struct ShareSettings {
+  pub share_token: Option<String>,
+  pub viewer_limit: Option<i32>,
+}
+
+// get the share link settings of a game
+pub async fn get_share(db: &PgPool, game_id: Uuid) -> Result<ShareSettings, Error> {
+  query_as("SELECT share_token, viewer_limit FROM games WHERE id = $1")
+    .bind(game_id)
+    .fetch_one(db)
+    .await
+    .map_err(handle_pg_error)
+}
+
+// replace or clear the share token
+pub async fn set_share_token(
+  db: &PgPool,
+  game_id: Uuid,
+  token: Option<&str>,
+) -> Result<ShareSettings, Error> {
+  query_as(
+    "UPDATE games SET share_token = $2, updated_at = NOW() WHERE id = $1 RETURNING share_token, viewer_limit",
+  )
+  .bind(game_id)
+  .bind(token)
+  .fetch_one(db)
+  .await
+  .map_err(handle_pg_error)
+}
+
+// set or clear the maximum number of share-link viewers
+pub async fn set_viewer_limit(
+  db: &PgPool,
+  game_id: Uuid,
+  limit: Option<i32>,
+) -> Result<ShareSettings, Error> {
+  query_as(
+    "UPDATE games SET viewer_limit = $2, updated_at = NOW() WHERE id = $1 RETURNING share_token, viewer_limit",
+  )
+  .bind(game_id)
+  .bind(limit)
+  .fetch_one(db)
+  .await
+  .map_err(handle_pg_error)
+}
+
+#[derive(FromRow, Debug)]
+pub struct SharedGame {
+  pub id: Uuid,
+  pub viewer_limit: Option<i32>,
+}
+
+// find the game behind a share token
+pub async fn find_shared(db: &PgPool, token: &str) -> Result<SharedGame, Error> {
+  query_as("SELECT id, viewer_limit FROM games WHERE share_token = $1 AND deleted_at IS NULL")
+    .bind(token)
+    .fetch_one(db)
+    .await
+    .map_err(handle_pg_error)
+}
+
+// one broadcast channel per game, so subscribers never see events of other games
+#[derive(Clone)]
+pub struct PlayStream {
+  channels: Arc<Mutex<HashMap<Uuid, Sender<PlayEvent>>>>,
+  // events a subscriber may fall behind before it lags
+  capacity: usize,
+}
+
+impl PlayStream {
+  pub fn new(capacity: usize) -> Self {
+    Self {
+      channels: Default::default(),
+      capacity: capacity.max(1),
+    }
+  }
+
+  pub fn subscribe(&self, game_id: Uuid) -> Receiver<PlayEvent> {
+    let mut channels = self.channels.lock().unwrap();
+    channels
+      .entry(game_id)
+      .or_insert_with(|| channel(self.capacity).0)
+      .subscribe()
+  }
+
+  // returns the number of subscribers the event reached
+  pub fn send(&self, event: PlayEvent) -> usize {
+    let mut channels = self.channels.lock().unwrap();
+    channels.retain(|_, tx| tx.receiver_count() > 0);
+    channels
+      .get(&event.game_id)
+      .and_then(|tx| tx.send(event).ok())
+      .unwrap_or(0)
+  }
+}
 
 impl FromRef<AppState> for PlayStream {
   fn from_ref(state: &AppState) -> Self {
@@ -481,27 +1703,114 @@ pub async fn list_events(
   db: &PgPool,
   game_id: Uuid,
   p: ListParams,
-) -> Result<Vec<PlayEvent>, Error> {
-  let mut query = QueryBuilder::<Postgres>::new(
-    "
-    SELECT id,
-      game_id,
-      player_id,
-      present_id,
-      from_player_id,
-      from_present_id,
-      created_at
-    FROM play_events
-    WHERE game_id = ",
-  );
+) -> Result<Page<PlayEvent>, Error> {
+  let mut query = QueryBuilder::<Postgres>::new(format!(
+    "SELECT {}, COUNT(*) OVER() AS total_count FROM play_events WHERE game_id = ",
+    PLAY_EVENT_COLUMNS
+  ));
   query.push_bind(game_id);
   query = apply_list_filters(query, &p, Vec::new())?;
 
-  query
+  let rows = query
     .build_query_as()
     .fetch_all(db)
     .await
-    .map_err(Error::Sqlx)
+    .map_err(Error::Sqlx)?;
+  Ok(Page::new(rows, &p))
+}
+
+// the current state of a game, sent to subscribers before the live events
+#[derive(FromRow, Serialize)]
+pub struct Snapshot {
+  // events up to this one are included, later ones follow on the stream
+  pub event_seq: i64,
+  pub started: bool,
+  pub finished: bool,
+  pub turn: i32,
+  pub player_id: Option<i64>,
+  pub present_id: Option<i64>,
+  // present id to the player owning it
+  #[sqlx(json)]
+  pub owners: BTreeMap<i64, i64>,
+}
+
+// read in one statement, so the owners match the event_seq
+pub async fn snapshot(db: &PgPool, game_id: Uuid) -> Result<Snapshot, Error> {
+  query_as(
+    "SELECT g.event_seq, g.started_at IS NOT NULL AS started, g.finished_at IS NOT NULL AS finished,
+      g.turn, g.player_id, g.present_id,
+      COALESCE(
+        (SELECT jsonb_object_agg(p.id, p.player_id) FROM presents p WHERE p.game_id = g.id AND p.player_id IS NOT NULL),
+        '{}'
+      ) AS owners
+    FROM games g WHERE g.id = $1 AND g.deleted_at IS NULL",
+  )
+  .bind(game_id)
+  .fetch_one(db)
+  .await
+  .map_err(handle_pg_error)
+}
+
+// replay the events of a game up to and including at_event, or all of them
+pub async fn state_at(
+  db: &PgPool,
+  game_id: Uuid,
+  at_event: Option<i64>,
+) -> Result<ReplayState, Error> {
+  let mut query = QueryBuilder::<Postgres>::new(format!(
+    "SELECT {} FROM play_events WHERE game_id = ",
+    PLAY_EVENT_COLUMNS
+  ));
+  query.push_bind(game_id);
+  if let Some(at_event) = at_event {
+    query
+      .push(" AND seq <= (SELECT seq FROM play_events WHERE id = ")
+      .push_bind(at_event)
+      .push(")");
+  }
+  query.push(" ORDER BY seq");
+
+  let mut state = ReplayState::default();
+  let mut rows = query.build_query_as::<PlayEvent>().fetch(db);
+  while let Some(event) = rows.next().await {
+    state.apply(&event.map_err(Error::Sqlx)?);
+  }
+  // the event is unknown or belongs to another game
+  if at_event.is_some() && state.event_id != at_event {
+    return Err(Error::NotFound);
+  }
+  Ok(state)
+}
+
+// stream the events of a game row by row instead of buffering them
+pub fn stream_events(
+  db: PgPool,
+  game_id: Uuid,
+  p: ListParams,
+) -> ReceiverStream<Result<PlayEvent, Error>> {
+  let (tx, rx) = mpsc::channel(64);
+  tokio::spawn(async move {
+    let mut query = QueryBuilder::<Postgres>::new(format!(
+      "SELECT {} FROM play_events WHERE game_id = ",
+      PLAY_EVENT_COLUMNS
+    ));
+    query.push_bind(game_id);
+    let mut query = match apply_stream_filters(query, &p, Vec::new()) {
+      Ok(query) => query,
+      Err(err) => {
+        let _ = tx.send(Err(err)).await;
+        return;
+      }
+    };
+    let mut rows = query.build_query_as::<PlayEvent>().fetch(&db);
+    while let Some(row) = rows.next().await {
+      // stop reading once the client went away
+      if tx.send(row.map_err(Error::Sqlx)).await.is_err() {
+        break;
+      }
+    }
+  });
+  ReceiverStream::new(rx)
 }
 
 #[derive(Deserialize, Debug)]
@@ -514,26 +1823,236 @@ pub struct PlayLogPayload {
   pub created_at: DateTime<Utc>,
 }
 
-pub async fn start_listening(
-  mut listener: PgListener,
-  tx: &PlayStream,
+const RECOVERY_LIMIT: i64 = 1000;
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+// every replica listens on the same database, and postgres delivers each NOTIFY to all of them,
+// so SSE clients get the events, changes and activity of a game whichever instance they are
+// connected to.
+// runs forever, reconnecting with backoff and replaying what was missed while disconnected
+pub async fn start_listening(db: PgPool, streams: Streams, readiness: Readiness) {
+  let mut last_id = None;
+  let mut backoff = MIN_BACKOFF;
+  loop {
+    if let Err(err) = relay(&db, &streams, &readiness, &mut last_id, &mut backoff).await {
+      tracing::error!("Error listening to PG: {}", err);
+    }
+    readiness.set_listener(false);
+    tracing::warn!("Reconnecting the PG listener in {:?}", backoff);
+    tokio::time::sleep(backoff).await;
+    backoff = (backoff * 2).min(MAX_BACKOFF);
+  }
+}
+
+// what the listener relays to, shared with the handlers that subscribe to them
+#[derive(Clone)]
+pub struct Streams {
+  pub play: PlayStream,
+  pub changes: ChangeStream,
+  pub activity: ActivityStream,
+}
+
+impl Streams {
+  pub fn new(play_capacity: usize) -> Self {
+    Streams {
+      play: PlayStream::new(play_capacity),
+      changes: ChangeStream::new(),
+      activity: ActivityStream::new(),
+    }
+  }
+}
+
+// LISTEN on a fresh connection, send the events missed since last_id, then relay notifications
+// until the connection fails
+async fn relay(
+  db: &PgPool,
+  streams: &Streams,
+  readiness: &Readiness,
+  last_id: &mut Option<i64>,
+  backoff: &mut Duration,
 ) -> Result<(), anyhow::Error> {
-  listener.listen("play").await?;
+  let mut listener = PgListener::connect_with(db).await?;
+  listener.listen_all(["play", "changes", "activity"]).await?;
+
+  // events recovered here may still arrive as notifications, those are skipped once
+  let mut recovered = HashSet::new();
+  match *last_id {
+    Some(after) => {
+      let missed = missed_events(db, after).await?;
+      if !missed.is_empty() {
+        tracing::warn!(
+          "Recovering {} play events missed by the PG listener",
+          missed.len()
+        );
+      }
+      for row in missed {
+        recovered.insert(row.id);
+        *last_id = Some(row.id);
+        relay_event(&streams.play, row);
+      }
+    }
+    None => {
+      *last_id = Some(
+        query_scalar!(r#"SELECT COALESCE(MAX(id), 0) AS "id!" FROM play_events"#)
+          .fetch_one(db)
+          .await?,
+      );
+    }
+  }
+  readiness.set_listener(true);
+  *backoff = MIN_BACKOFF;
+
   loop {
-    if let Some(notif) = listener.try_recv().await? {
-      match serde_json::from_str::<PlayEvent>(notif.payload()) {
-        Ok(payload) => match tx.send(payload) {
-          Ok(n) => {
-            tracing::info!("Sent event to {} subscribers", n);
-          }
-          Err(e) => {
-            tracing::error!("Error send message to client: {}", e.to_string());
-          }
-        },
-        Err(e) => {
-          tracing::error!("Error deserialize message: {}", e.to_string());
-        }
+    // None means the connection dropped, reconnecting from the top recovers the gap
+    let Some(notif) = listener.try_recv().await? else {
+      anyhow::bail!("Connection lost");
+    };
+    // changes are only hints to refetch, missing some while disconnected is fine
+    if notif.channel() == "changes" {
+      match serde_json::from_str::<Change>(notif.payload()) {
+        Ok(change) => streams.changes.send(change),
+        Err(e) => tracing::error!("Error deserialize change: {}", e.to_string()),
       }
+      continue;
     }
+    // activity is ephemeral, it is never stored
+    if notif.channel() == "activity" {
+      match serde_json::from_str::<Activity>(notif.payload()) {
+        Ok(activity) => streams.activity.send(activity),
+        Err(e) => tracing::error!("Error deserialize activity: {}", e.to_string()),
+      }
+      continue;
+    }
+    let row = match serde_json::from_str::<PlayEventRow>(notif.payload()) {
+      Ok(row) => row,
+      Err(e) => {
+        tracing::error!("Error deserialize message: {}", e.to_string());
+        continue;
+      }
+    };
+    if recovered.remove(&row.id) {
+      continue;
+    }
+    *last_id = (*last_id).max(Some(row.id));
+    relay_event(&streams.play, row);
+  }
+}
+
+fn relay_event(tx: &PlayStream, row: PlayEventRow) {
+  match PlayEvent::try_from(row) {
+    Ok(payload) => {
+      let n = tx.send(payload);
+      tracing::info!("Sent event to {} subscribers", n);
+    }
+    Err(e) => {
+      tracing::error!("Error deserialize message: {}", e.to_string());
+    }
+  }
+}
+
+async fn missed_events(db: &PgPool, after: i64) -> Result<Vec<PlayEventRow>, sqlx::Error> {
+  query_as(&format!(
+    "SELECT {} FROM play_events WHERE id > $1 ORDER BY id LIMIT $2",
+    PLAY_EVENT_COLUMNS
+  ))
+  .bind(after)
+  .bind(RECOVERY_LIMIT)
+  .fetch_all(db)
+  .await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::{players, presents};
+
+  const UID: &str = "test";
+
+  // a started game where the first player picked a present
+  async fn played_game(db: &PgPool) -> Uuid {
+    let users = HashMap::from([(UID.to_string(), OWNER_PERMISSION)]);
+    let params = CreateParams {
+      id: Uuid::new_v4(),
+      name: "test",
+      images: Vec::new(),
+      users: &users,
+      rules: GameRules::default(),
+      created_by: UID,
+    };
+    let game_id = create(db, params).await.unwrap().id;
+    let mut conn = db.acquire().await.unwrap();
+    for i in 0..2 {
+      let player = players::CreateParams {
+        team_id: None,
+        name: format!("player {}", i),
+        images: Vec::new(),
+        uid: None,
+      };
+      players::create(&mut *conn, game_id, player, UID)
+        .await
+        .unwrap();
+      let present = presents::CreateParams {
+        name: format!("present {}", i),
+        description: None,
+        translations: None,
+        wrapped_images: None,
+        unwrapped_images: None,
+        price_cents: None,
+        currency: None,
+      };
+      presents::create(&mut conn, game_id, present, UID)
+        .await
+        .unwrap();
+    }
+    start(db, game_id, UID).await.unwrap();
+    roll(db, game_id, UID).await.unwrap();
+    let present_id: i64 = query_scalar("SELECT MIN(id) FROM presents WHERE game_id = $1")
+      .bind(game_id)
+      .fetch_one(db)
+      .await
+      .unwrap();
+    pick(db, game_id, present_id, Expected::default(), UID)
+      .await
+      .unwrap();
+    game_id
+  }
+
+  async fn rows_left(db: &PgPool, game_id: Uuid) -> i64 {
+    query_scalar(
+      "SELECT (SELECT COUNT(*) FROM games WHERE id = $1)
+        + (SELECT COUNT(*) FROM players WHERE game_id = $1)
+        + (SELECT COUNT(*) FROM presents WHERE game_id = $1)
+        + (SELECT COUNT(*) FROM play_events WHERE game_id = $1)",
+    )
+    .bind(game_id)
+    .fetch_one(db)
+    .await
+    .unwrap()
+  }
+
+  // needs DATABASE_URL, every test gets its own freshly migrated database
+  #[sqlx::test(migrator = "crate::MIGRATOR")]
+  async fn purge_removes_played_games(db: PgPool) {
+    let game_id = played_game(&db).await;
+    assert!(rows_left(&db, game_id).await > 5);
+
+    delete(&db, game_id).await.unwrap();
+    assert_eq!(purge_deleted(&db).await.unwrap(), 0);
+    query("UPDATE games SET deleted_at = NOW() - INTERVAL '31 days' WHERE id = $1")
+      .bind(game_id)
+      .execute(&db)
+      .await
+      .unwrap();
+    assert_eq!(purge_deleted(&db).await.unwrap(), 1);
+    assert_eq!(rows_left(&db, game_id).await, 0);
+  }
+
+  #[sqlx::test(migrator = "crate::MIGRATOR")]
+  async fn destroy_removes_played_games(db: PgPool) {
+    let game_id = played_game(&db).await;
+    destroy(&db, game_id).await.unwrap();
+    assert_eq!(rows_left(&db, game_id).await, 0);
+    assert!(matches!(destroy(&db, game_id).await, Err(Error::NotFound)));
   }
 }
@@ -0,0 +1,171 @@
+use std::{cmp::Reverse, collections::BTreeMap};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, query_as, query_scalar, types::Json, PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::rules::GameRules;
+
+use super::{apply_list_filters, handle_pg_error, CreateResult, Error, ListParams, Page};
+
+#[derive(FromRow, Serialize)]
+pub struct Guess {
+  pub id: i64,
+  pub game_id: Uuid,
+  pub present_id: i64,
+  pub player_id: i64,
+  pub price_cents: i64,
+  pub created_at: DateTime<Utc>,
+  pub updated_at: Option<DateTime<Utc>>,
+}
+
+// list guesses of a game
+pub async fn list(db: &PgPool, game_id: Uuid, p: ListParams) -> Result<Page<Guess>, Error> {
+  let mut query = QueryBuilder::<Postgres>::new(
+    "SELECT id, game_id, present_id, player_id, price_cents, created_at, updated_at, COUNT(*) OVER() AS total_count FROM guesses WHERE game_id = ",
+  );
+  query.push_bind(game_id);
+  query = apply_list_filters(query, &p, vec!["id", "present_id", "player_id"])?;
+
+  let rows = query
+    .build_query_as()
+    .fetch_all(db)
+    .await
+    .map_err(Error::Sqlx)?;
+  Ok(Page::new(rows, &p))
+}
+
+#[derive(Deserialize)]
+pub struct GuessParams {
+  pub player_id: i64,
+  pub price_cents: i64,
+}
+
+#[derive(FromRow)]
+struct GuessTarget {
+  rules: Json<GameRules>,
+  current_present_id: Option<i64>,
+  player_id: Option<i64>,
+}
+
+// submit or change a guess, only while the present is still wrapped.
+// with a uid the guess has to be for that user's own player
+pub async fn upsert(
+  db: &PgPool,
+  game_id: Uuid,
+  present_id: i64,
+  p: GuessParams,
+  uid: Option<&str>,
+) -> Result<CreateResult<i64>, Error> {
+  if let Some(uid) = uid {
+    let owner: Option<String> =
+      query_scalar("SELECT uid FROM players WHERE id = $1 AND game_id = $2")
+        .bind(p.player_id)
+        .bind(game_id)
+        .fetch_one(db)
+        .await
+        .map_err(handle_pg_error)?;
+    if owner.as_deref() != Some(uid) {
+      return Err(Error::NotOwnPlayer);
+    }
+  }
+
+  let target: GuessTarget = query_as(
+    "SELECT games.rules, games.present_id AS current_present_id, presents.player_id
+    FROM presents
+    JOIN games ON games.id = presents.game_id
+    WHERE presents.id = $1 AND presents.game_id = $2",
+  )
+  .bind(present_id)
+  .bind(game_id)
+  .fetch_one(db)
+  .await
+  .map_err(handle_pg_error)?;
+
+  if !target.rules.price_guessing {
+    return Err(Error::GuessingDisabled);
+  }
+  if target.player_id.is_some() || target.current_present_id == Some(present_id) {
+    return Err(Error::GuessingClosed);
+  }
+
+  query_as(
+    "INSERT INTO guesses (game_id, present_id, player_id, price_cents)
+    SELECT $1, $2, id, $4 FROM players WHERE id = $3 AND game_id = $1
+    ON CONFLICT (present_id, player_id)
+    DO UPDATE SET price_cents = EXCLUDED.price_cents, updated_at = NOW()
+    RETURNING id, created_at",
+  )
+  .bind(game_id)
+  .bind(present_id)
+  .bind(p.player_id)
+  .bind(p.price_cents)
+  .fetch_one(db)
+  .await
+  .map_err(handle_pg_error)
+}
+
+#[derive(FromRow, Serialize)]
+pub struct GuessWinner {
+  pub present_id: i64,
+  pub price_cents: i64,
+  pub player_id: i64,
+  pub guess_cents: i64,
+  pub difference_cents: i64,
+}
+
+#[derive(Serialize)]
+pub struct PlayerWins {
+  pub player_id: i64,
+  pub wins: usize,
+}
+
+#[derive(Serialize)]
+pub struct GuessScores {
+  pub winners: Vec<GuessWinner>,
+  pub leaderboard: Vec<PlayerWins>,
+}
+
+// closest guesses per unwrapped present, ties share the win
+pub async fn scores(db: &PgPool, game_id: Uuid) -> Result<GuessScores, Error> {
+  let winners: Vec<GuessWinner> = query_as(
+    "SELECT present_id, price_cents, player_id, guess_cents, difference_cents FROM (
+      SELECT guesses.present_id,
+        presents.price_cents,
+        guesses.player_id,
+        guesses.price_cents AS guess_cents,
+        ABS(guesses.price_cents - presents.price_cents) AS difference_cents,
+        RANK() OVER (
+          PARTITION BY guesses.present_id
+          ORDER BY ABS(guesses.price_cents - presents.price_cents)
+        ) AS rank
+      FROM guesses
+      JOIN presents ON presents.id = guesses.present_id
+      WHERE guesses.game_id = $1
+        AND presents.price_cents IS NOT NULL
+        AND presents.player_id IS NOT NULL
+    ) ranked
+    WHERE rank = 1
+    ORDER BY present_id, player_id",
+  )
+  .bind(game_id)
+  .fetch_all(db)
+  .await
+  .map_err(handle_pg_error)?;
+
+  let mut wins = BTreeMap::<i64, usize>::new();
+  for winner in &winners {
+    *wins.entry(winner.player_id).or_default() += 1;
+  }
+  let mut leaderboard: Vec<PlayerWins> = wins
+    .into_iter()
+    .map(|(player_id, wins)| PlayerWins { player_id, wins })
+    .collect();
+  leaderboard.sort_by_key(|p| Reverse(p.wins));
+
+  Ok(GuessScores {
+    winners,
+    leaderboard,
+  })
+}
@@ -0,0 +1,134 @@
+use sqlx::{prelude::FromRow, query, query_as, query_scalar, PgPool};
+
+use super::{handle_pg_error, Error};
+
+// a finished request, replayed for retries with the same key
+#[derive(FromRow)]
+pub struct StoredResponse {
+  pub status: i16,
+  pub content_type: Option<String>,
+  pub location: Option<String>,
+  pub body: Vec<u8>,
+}
+
+pub enum Claim {
+  // first use of the key, run the request and store its response
+  New,
+  Replay(StoredResponse),
+  // the first request with this key hasn't finished yet
+  InProgress,
+  // the key was already used for another request
+  Mismatch,
+}
+
+#[derive(FromRow)]
+struct StoredRequest {
+  path: String,
+  request_body: Vec<u8>,
+  status: Option<i16>,
+  content_type: Option<String>,
+  location: Option<String>,
+  response_body: Option<Vec<u8>>,
+}
+
+// claim a key for a request, keys expire after a day and a claim that never finished
+// (e.g. the server went down mid-request) can be taken over after a minute
+pub async fn claim(
+  db: &PgPool,
+  uid: &str,
+  key: &str,
+  path: &str,
+  body: &[u8],
+) -> Result<Claim, Error> {
+  let claimed = query_scalar!(
+    "INSERT INTO idempotency_keys (uid, key, path, request_body)
+    VALUES ($1, $2, $3, $4)
+    ON CONFLICT (uid, key) DO UPDATE
+    SET path = $3, request_body = $4, status = NULL, content_type = NULL, location = NULL, response_body = NULL, created_at = NOW()
+    WHERE idempotency_keys.created_at < NOW() - INTERVAL '1 day'
+      OR (idempotency_keys.status IS NULL AND idempotency_keys.created_at < NOW() - INTERVAL '1 minute')
+    RETURNING created_at",
+    uid,
+    key,
+    path,
+    body
+  )
+  .fetch_optional(db)
+  .await
+  .map_err(handle_pg_error)?;
+  if claimed.is_some() {
+    return Ok(Claim::New);
+  }
+
+  let stored = query_as!(
+    StoredRequest,
+    "SELECT path, request_body, status, content_type, location, response_body
+    FROM idempotency_keys WHERE uid = $1 AND key = $2",
+    uid,
+    key
+  )
+  .fetch_optional(db)
+  .await
+  .map_err(handle_pg_error)?;
+  // released between the two queries, the retry will claim it
+  let Some(stored) = stored else {
+    return Ok(Claim::InProgress);
+  };
+  if stored.path != path || stored.request_body != body {
+    return Ok(Claim::Mismatch);
+  }
+  Ok(match (stored.status, stored.response_body) {
+    (Some(status), Some(body)) => Claim::Replay(StoredResponse {
+      status,
+      content_type: stored.content_type,
+      location: stored.location,
+      body,
+    }),
+    _ => Claim::InProgress,
+  })
+}
+
+// store the response of a claimed request
+pub async fn complete(
+  db: &PgPool,
+  uid: &str,
+  key: &str,
+  response: &StoredResponse,
+) -> Result<(), Error> {
+  query!(
+    "UPDATE idempotency_keys SET status = $3, content_type = $4, location = $5, response_body = $6
+    WHERE uid = $1 AND key = $2",
+    uid,
+    key,
+    response.status,
+    response.content_type,
+    response.location,
+    response.body
+  )
+  .execute(db)
+  .await
+  .map_err(handle_pg_error)?;
+  Ok(())
+}
+
+// give up a claim so a retry runs the request again
+pub async fn release(db: &PgPool, uid: &str, key: &str) -> Result<(), Error> {
+  query!(
+    "DELETE FROM idempotency_keys WHERE uid = $1 AND key = $2",
+    uid,
+    key
+  )
+  .execute(db)
+  .await
+  .map_err(handle_pg_error)?;
+  Ok(())
+}
+
+// drop expired keys, returns how many were removed
+pub async fn purge(db: &PgPool) -> Result<u64, Error> {
+  let res = query!("DELETE FROM idempotency_keys WHERE created_at < NOW() - INTERVAL '1 day'")
+    .execute(db)
+    .await
+    .map_err(handle_pg_error)?;
+  Ok(res.rows_affected())
+}
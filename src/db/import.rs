@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use sqlx::{query_as, query_scalar, types::Json, PgConnection};
+use utoipa::ToSchema;
+use uuid::Uuid;
+use validator::Validate;
+
+use super::{games::Game, handle_pg_error, Error};
+use crate::{
+  i18n::{LocalizedText, Translations},
+  rules::GameRules,
+  theme::GameTheme,
+  validation::{image_urls, translation_tags, MAX_DESCRIPTION_LEN, MAX_IMAGES, MAX_NAME_LEN},
+};
+
+// the parts of a game export that are recreated, other fields (events, ids, state) are ignored
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct ImportData {
+  #[validate(nested)]
+  pub game: ImportGame,
+  #[serde(default)]
+  #[validate(nested)]
+  pub players: Vec<ImportPlayer>,
+  #[serde(default)]
+  #[validate(nested)]
+  pub presents: Vec<ImportPresent>,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct ImportGame {
+  #[validate(length(min = 1, max = MAX_NAME_LEN))]
+  pub name: String,
+  #[validate(length(max = MAX_DESCRIPTION_LEN))]
+  pub description: Option<String>,
+  #[serde(default)]
+  #[schema(value_type = HashMap<String, LocalizedText>)]
+  #[validate(custom(function = translation_tags))]
+  pub translations: Translations,
+  #[serde(default)]
+  #[validate(length(max = MAX_IMAGES), custom(function = image_urls))]
+  pub images: Vec<String>,
+  #[serde(default)]
+  pub rules: GameRules,
+  #[serde(default)]
+  pub theme: GameTheme,
+  pub budget_cents: Option<i64>,
+  pub currency: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct ImportPlayer {
+  pub id: i64,
+  #[serde(default)]
+  pub position: i32,
+  #[validate(length(min = 1, max = MAX_NAME_LEN))]
+  pub name: String,
+  #[serde(default)]
+  #[validate(length(max = MAX_IMAGES), custom(function = image_urls))]
+  pub images: Vec<String>,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct ImportPresent {
+  pub id: i64,
+  pub number: i32,
+  #[validate(length(min = 1, max = MAX_NAME_LEN))]
+  pub name: String,
+  #[validate(length(max = MAX_DESCRIPTION_LEN))]
+  pub description: Option<String>,
+  #[serde(default)]
+  #[schema(value_type = HashMap<String, LocalizedText>)]
+  #[validate(custom(function = translation_tags))]
+  pub translations: Translations,
+  #[serde(default)]
+  #[validate(length(max = MAX_IMAGES), custom(function = image_urls))]
+  pub wrapped_images: Vec<String>,
+  #[serde(default)]
+  #[validate(length(max = MAX_IMAGES), custom(function = image_urls))]
+  pub unwrapped_images: Vec<String>,
+  pub price_cents: Option<i64>,
+  pub currency: Option<String>,
+}
+
+// the new game and which new ids the exported player and present ids became
+#[derive(Serialize, ToSchema)]
+pub struct ImportResult {
+  pub game: Game,
+  pub players: HashMap<i64, i64>,
+  pub presents: HashMap<i64, i64>,
+}
+
+// recreate an exported game as a new, unstarted game owned by `users`,
+// players lose their account link and teams, those don't carry over between environments
+pub async fn import(
+  conn: &mut PgConnection,
+  game_id: Uuid,
+  users: &HashMap<String, i64>,
+  created_by: &str,
+  data: ImportData,
+) -> Result<ImportResult, Error> {
+  let game: Game = query_as(
+    "INSERT INTO games (id, name, description, translations, images, users, rules, theme, budget_cents, currency, created_by)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+    RETURNING id, name, description, translations, images, users, player_id, present_id, started_at, finished_at, turn, turn_deadline, event_seq, rules, theme, budget_cents, currency, scheduled_at, created_at, updated_at, created_by, updated_by",
+  )
+  .bind(game_id)
+  .bind(&data.game.name)
+  .bind(&data.game.description)
+  .bind(Json(&data.game.translations))
+  .bind(&data.game.images)
+  .bind(Json(users))
+  .bind(Json(&data.game.rules))
+  .bind(Json(&data.game.theme))
+  .bind(data.game.budget_cents)
+  .bind(&data.game.currency)
+  .bind(created_by)
+  .fetch_one(&mut *conn)
+  .await
+  .map_err(handle_pg_error)?;
+
+  let mut players = HashMap::new();
+  for player in data.players {
+    let id: i64 = query_scalar(
+      "INSERT INTO players (game_id, position, name, images, created_by) VALUES ($1, $2, $3, $4, $5) RETURNING id",
+    )
+    .bind(game_id)
+    .bind(player.position)
+    .bind(&player.name)
+    .bind(&player.images)
+    .bind(created_by)
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(handle_pg_error)?;
+    players.insert(player.id, id);
+  }
+
+  let mut presents = HashMap::new();
+  for present in data.presents {
+    let id: i64 = query_scalar(
+      "INSERT INTO presents (game_id, number, name, description, translations, wrapped_images, unwrapped_images, price_cents, currency, created_by)
+      VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING id",
+    )
+    .bind(game_id)
+    .bind(present.number)
+    .bind(&present.name)
+    .bind(&present.description)
+    .bind(Json(&present.translations))
+    .bind(&present.wrapped_images)
+    .bind(&present.unwrapped_images)
+    .bind(present.price_cents)
+    .bind(&present.currency)
+    .bind(created_by)
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(handle_pg_error)?;
+    presents.insert(present.id, id);
+  }
+
+  Ok(ImportResult {
+    game,
+    players,
+    presents,
+  })
+}
@@ -0,0 +1,98 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{
+  prelude::FromRow, query, query_as, query_scalar, PgConnection, PgPool, Postgres, QueryBuilder,
+};
+use uuid::Uuid;
+
+use super::{apply_list_filters, handle_pg_error, members, Error, ListParams, Page};
+
+#[derive(FromRow, Serialize)]
+pub struct Invite {
+  pub id: i64,
+  pub game_id: Uuid,
+  pub permission: i64,
+  pub created_by: String,
+  pub expires_at: DateTime<Utc>,
+  pub revoked_at: Option<DateTime<Utc>>,
+  pub accepted_count: i32,
+  pub created_at: DateTime<Utc>,
+}
+
+// list the invites of a game
+pub async fn list(db: &PgPool, game_id: Uuid, p: ListParams) -> Result<Page<Invite>, Error> {
+  let mut query = QueryBuilder::<Postgres>::new(
+    "SELECT id, game_id, permission, created_by, expires_at, revoked_at, accepted_count, created_at, COUNT(*) OVER() AS total_count FROM invites WHERE game_id = ",
+  );
+  query.push_bind(game_id);
+
+  query = apply_list_filters(query, &p, vec!["id", "created_at", "expires_at"])?;
+  let rows = query
+    .build_query_as()
+    .fetch_all(db)
+    .await
+    .map_err(Error::Sqlx)?;
+  Ok(Page::new(rows, &p))
+}
+
+// create an invite, the caller signs a token for it
+pub async fn create(
+  db: &PgPool,
+  game_id: Uuid,
+  permission: i64,
+  created_by: &str,
+  expires_at: DateTime<Utc>,
+) -> Result<Invite, Error> {
+  query_as(
+    "INSERT INTO invites (game_id, permission, created_by, expires_at) VALUES ($1, $2, $3, $4)
+    RETURNING id, game_id, permission, created_by, expires_at, revoked_at, accepted_count, created_at",
+  )
+  .bind(game_id)
+  .bind(permission)
+  .bind(created_by)
+  .bind(expires_at)
+  .fetch_one(db)
+  .await
+  .map_err(handle_pg_error)
+}
+
+// revoke an invite, tokens for it stop working immediately
+pub async fn revoke(db: &PgPool, game_id: Uuid, id: i64) -> Result<(), Error> {
+  let res = query!(
+    "UPDATE invites SET revoked_at = COALESCE(revoked_at, NOW()) WHERE id = $1 AND game_id = $2",
+    id,
+    game_id
+  )
+  .execute(db)
+  .await
+  .map_err(handle_pg_error)?;
+  match res.rows_affected() {
+    0 => Err(Error::NotFound),
+    _ => Ok(()),
+  }
+}
+
+// add the user to the game's users map, keeping a higher permission they already have,
+// NotFound when the invite is revoked, expired or the game is gone.
+// returns the user's permission before and after
+pub async fn redeem(
+  conn: &mut PgConnection,
+  game_id: Uuid,
+  id: i64,
+  uid: &str,
+) -> Result<(Option<i64>, i64), Error> {
+  let permission: Option<i64> = query_scalar(
+    "UPDATE invites SET accepted_count = accepted_count + 1
+    WHERE id = $1 AND game_id = $2 AND revoked_at IS NULL AND expires_at > NOW()
+    RETURNING permission",
+  )
+  .bind(id)
+  .bind(game_id)
+  .fetch_optional(&mut *conn)
+  .await
+  .map_err(handle_pg_error)?;
+  let Some(permission) = permission else {
+    return Err(Error::NotFound);
+  };
+  members::grant(conn, game_id, uid, permission).await
+}
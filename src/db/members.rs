@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use sqlx::{query, query_as, types::Json, PgConnection, PgPool};
+use uuid::Uuid;
+
+use super::{handle_pg_error, Error};
+use crate::api::games::OWNER_PERMISSION;
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct Member {
+  pub uid: String,
+  pub permission: i64,
+}
+
+// the users of a game with their permission bits
+pub async fn list(db: &PgPool, game_id: Uuid) -> Result<Vec<Member>, Error> {
+  query_as(
+    "SELECT key AS uid, value::bigint AS permission FROM games, jsonb_each_text(users)
+    WHERE id = $1 AND deleted_at IS NULL
+    ORDER BY key",
+  )
+  .bind(game_id)
+  .fetch_all(db)
+  .await
+  .map_err(handle_pg_error)
+}
+
+// the games a user is a member of with their permission bits, what requests are authorized with.
+// deleted games are left out, nothing in them can be read or changed until they're restored
+pub async fn games_of(db: &PgPool, uid: &str) -> Result<HashMap<String, i64>, Error> {
+  let rows = query!(
+    "SELECT game_id, permission FROM game_members
+    JOIN games ON games.id = game_members.game_id
+    WHERE uid = $1 AND games.deleted_at IS NULL",
+    uid
+  )
+  .fetch_all(db)
+  .await
+  .map_err(handle_pg_error)?;
+  Ok(
+    rows
+      .into_iter()
+      .map(|row| (row.game_id.to_string(), row.permission))
+      .collect(),
+  )
+}
+
+// the users map, locked until the transaction ends so concurrent edits can't drop the last owner
+async fn lock_users(conn: &mut PgConnection, game_id: Uuid) -> Result<HashMap<String, i64>, Error> {
+  let (Json(users),): (Json<HashMap<String, i64>>,) =
+    query_as("SELECT users FROM games WHERE id = $1 AND deleted_at IS NULL FOR UPDATE")
+      .bind(game_id)
+      .fetch_one(&mut *conn)
+      .await
+      .map_err(handle_pg_error)?;
+  Ok(users)
+}
+
+async fn store_users(
+  conn: &mut PgConnection,
+  game_id: Uuid,
+  users: HashMap<String, i64>,
+) -> Result<(), Error> {
+  query!(
+    "UPDATE games SET users = $2, updated_at = NOW() WHERE id = $1",
+    game_id,
+    Json(users) as _
+  )
+  .execute(&mut *conn)
+  .await
+  .map_err(handle_pg_error)?;
+  Ok(())
+}
+
+fn is_last_owner(users: &HashMap<String, i64>, uid: &str) -> bool {
+  let owners = users.values().filter(|p| **p >= OWNER_PERMISSION).count();
+  owners == 1 && users.get(uid).is_some_and(|p| *p >= OWNER_PERMISSION)
+}
+
+// add a user to a game
+pub async fn add(
+  conn: &mut PgConnection,
+  game_id: Uuid,
+  uid: &str,
+  permission: i64,
+) -> Result<Member, Error> {
+  let mut users = lock_users(conn, game_id).await?;
+  if users.contains_key(uid) {
+    return Err(Error::AlreadyMember);
+  }
+  users.insert(uid.to_string(), permission);
+  store_users(conn, game_id, users).await?;
+  Ok(Member {
+    uid: uid.to_string(),
+    permission,
+  })
+}
+
+// add the user to the game's users map, keeping a higher permission they already have.
+// returns their permission before and after
+pub async fn grant(
+  conn: &mut PgConnection,
+  game_id: Uuid,
+  uid: &str,
+  permission: i64,
+) -> Result<(Option<i64>, i64), Error> {
+  query_as(
+    "WITH previous AS (
+      SELECT (users->>$2)::bigint AS permission FROM games WHERE id = $1 AND deleted_at IS NULL FOR UPDATE
+    )
+    UPDATE games SET
+      users = users || jsonb_build_object($2::text, GREATEST(COALESCE((users->>$2)::bigint, 0), $3)),
+      updated_at = NOW()
+    FROM previous
+    WHERE id = $1 AND deleted_at IS NULL
+    RETURNING previous.permission, (users->>$2)::bigint",
+  )
+  .bind(game_id)
+  .bind(uid)
+  .bind(permission)
+  .fetch_optional(&mut *conn)
+  .await
+  .map_err(handle_pg_error)?
+  .ok_or(Error::NotFound)
+}
+
+// change the permission of a member, returns it with the previous permission
+pub async fn update(
+  conn: &mut PgConnection,
+  game_id: Uuid,
+  uid: &str,
+  permission: i64,
+) -> Result<(Member, i64), Error> {
+  let mut users = lock_users(conn, game_id).await?;
+  let Some(previous) = users.get(uid).copied() else {
+    return Err(Error::NotFound);
+  };
+  if permission < OWNER_PERMISSION && is_last_owner(&users, uid) {
+    return Err(Error::LastOwner);
+  }
+  users.insert(uid.to_string(), permission);
+  store_users(conn, game_id, users).await?;
+  let member = Member {
+    uid: uid.to_string(),
+    permission,
+  };
+  Ok((member, previous))
+}
+
+// remove a user from a game, returns the permission they had
+pub async fn remove(conn: &mut PgConnection, game_id: Uuid, uid: &str) -> Result<i64, Error> {
+  let mut users = lock_users(conn, game_id).await?;
+  if is_last_owner(&users, uid) {
+    return Err(Error::LastOwner);
+  }
+  let Some(previous) = users.remove(uid) else {
+    return Err(Error::NotFound);
+  };
+  store_users(conn, game_id, users).await?;
+  Ok(previous)
+}
@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, query, query_as, query_scalar, PgExecutor, PgPool};
+use uuid::Uuid;
+
+use super::{handle_pg_error, Error};
+
+pub const INVITED: &str = "invited";
+pub const STARTED: &str = "started";
+pub const FINISHED: &str = "finished";
+
+// an email is given up on after this many failed attempts
+pub const MAX_ATTEMPTS: i32 = 5;
+
+#[derive(Serialize, Deserialize)]
+pub struct Preferences {
+  pub email_opt_out: bool,
+}
+
+// users without a row get every email
+pub async fn preferences(db: &PgPool, uid: &str) -> Result<Preferences, Error> {
+  let email_opt_out = query_scalar!(
+    "SELECT email_opt_out FROM user_preferences WHERE uid = $1",
+    uid
+  )
+  .fetch_optional(db)
+  .await
+  .map_err(handle_pg_error)?;
+  Ok(Preferences {
+    email_opt_out: email_opt_out.unwrap_or(false),
+  })
+}
+
+pub async fn set_preferences(db: &PgPool, uid: &str, p: &Preferences) -> Result<(), Error> {
+  query!(
+    "INSERT INTO user_preferences (uid, email_opt_out) VALUES ($1, $2)
+    ON CONFLICT (uid) DO UPDATE SET email_opt_out = $2, updated_at = NOW()",
+    uid,
+    p.email_opt_out
+  )
+  .execute(db)
+  .await
+  .map_err(handle_pg_error)?;
+  Ok(())
+}
+
+// queue an invitation for users added to a game,
+// start and finish emails are queued by a trigger on play_events
+pub async fn enqueue_invites(
+  db: impl PgExecutor<'_>,
+  game_id: Uuid,
+  uids: &[String],
+) -> Result<(), Error> {
+  if uids.is_empty() {
+    return Ok(());
+  }
+  query!(
+    "INSERT INTO email_outbox (uid, game_id, kind) SELECT UNNEST($2::text[]), $1, $3",
+    game_id,
+    uids,
+    INVITED
+  )
+  .execute(db)
+  .await
+  .map_err(handle_pg_error)?;
+  Ok(())
+}
+
+// an email leased to this instance for sending
+#[derive(FromRow)]
+pub struct DueEmail {
+  pub id: i64,
+  pub uid: String,
+  pub game_id: Uuid,
+  pub kind: String,
+  pub attempts: i32,
+  pub email_opt_out: bool,
+}
+
+// lease due emails for five minutes, other instances skip them meanwhile
+pub async fn claim_due(db: &PgPool, limit: i64) -> Result<Vec<DueEmail>, Error> {
+  query_as(
+    "WITH due AS (
+      SELECT id FROM email_outbox
+      WHERE next_attempt_at <= NOW()
+      ORDER BY next_attempt_at
+      LIMIT $1
+      FOR UPDATE SKIP LOCKED
+    )
+    UPDATE email_outbox e SET next_attempt_at = NOW() + INTERVAL '5 minutes'
+    FROM due
+    WHERE e.id = due.id
+    RETURNING e.id, e.uid, e.game_id, e.kind, e.attempts,
+      COALESCE((SELECT email_opt_out FROM user_preferences p WHERE p.uid = e.uid), FALSE) AS email_opt_out",
+  )
+  .bind(limit)
+  .fetch_all(db)
+  .await
+  .map_err(handle_pg_error)
+}
+
+// record an attempt, `retry_in` seconds schedules the next one, None finishes the email
+pub async fn record_attempt(
+  db: &PgPool,
+  id: i64,
+  error: Option<&str>,
+  sent: bool,
+  retry_in: Option<i64>,
+) -> Result<(), Error> {
+  query!(
+    "UPDATE email_outbox SET
+      attempts = attempts + 1,
+      error = $2,
+      sent_at = CASE WHEN $3 THEN NOW() END,
+      next_attempt_at = NOW() + make_interval(secs => $4)
+    WHERE id = $1",
+    id,
+    error,
+    sent,
+    retry_in.map(|secs| secs as f64)
+  )
+  .execute(db)
+  .await
+  .map_err(handle_pg_error)?;
+  Ok(())
+}
+
+// drop emails older than 30 days, unsent ones are stale by then too,
+// returns how many were removed
+pub async fn purge(db: &PgPool) -> Result<u64, Error> {
+  let res = query!("DELETE FROM email_outbox WHERE created_at < NOW() - INTERVAL '30 days'")
+    .execute(db)
+    .await
+    .map_err(handle_pg_error)?;
+  Ok(res.rows_affected())
+}
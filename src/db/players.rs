@@ -1,83 +1,133 @@
+use async_graphql::SimpleObject;
 use serde::{Deserialize, Serialize};
-use sqlx::{prelude::FromRow, query_as, PgPool, Postgres, QueryBuilder};
+use sqlx::{prelude::FromRow, query_as, Acquire, PgExecutor, PgPool, Postgres, QueryBuilder};
+use utoipa::ToSchema;
 use uuid::Uuid;
+use validator::Validate;
 
-use super::{apply_list_filters, handle_pg_error, CreateResult, Error, ListParams, UpdateResult};
+use crate::validation::{image_urls, MAX_IMAGES, MAX_NAME_LEN};
 
-#[derive(FromRow, Serialize)]
+use super::{
+  apply_list_filters, handle_pg_error, BulkMode, BulkResult, BulkStatus, CreateResult, Error,
+  ListParams, Page, UpdateResult,
+};
+
+#[derive(FromRow, Serialize, ToSchema, SimpleObject)]
 pub struct Player {
   pub id: i64,
   pub game_id: Uuid,
+  // turn order for sequential and snake games
+  pub position: i32,
+  pub team_id: Option<i64>,
   pub name: String,
   pub images: Vec<String>,
+  // the signed-in user playing as this player
+  pub uid: Option<String>,
+  // firebase uids, None for players from before they were recorded
+  pub created_by: Option<String>,
+  pub updated_by: Option<String>,
 }
 
 // list players
-pub async fn list(db: &PgPool, game_id: Uuid, p: ListParams) -> Result<Vec<Player>, Error> {
+pub async fn list(db: &PgPool, game_id: Uuid, p: ListParams) -> Result<Page<Player>, Error> {
   let mut query = QueryBuilder::<Postgres>::new(
-    "SELECT id, game_id, name, images FROM players WHERE game_id = $1",
+    "SELECT id, game_id, position, team_id, name, images, uid, created_by, updated_by, COUNT(*) OVER() AS total_count FROM players WHERE game_id = ",
   );
+  query.push_bind(game_id);
 
-  query = apply_list_filters(query, &p, vec!["id", "name"])?;
-  query
+  query = apply_list_filters(query, &p, vec!["id", "position", "name"])?;
+  let rows = query
     .build_query_as()
-    .bind(game_id)
     .fetch_all(db)
     .await
-    .map_err(Error::Sqlx)
+    .map_err(Error::Sqlx)?;
+  Ok(Page::new(rows, &p))
 }
 
 // get a player
-pub async fn get(db: &PgPool, id: i64) -> Result<Player, Error> {
-  query_as("SELECT id, game_id, name, images FROM players WHERE id = $1")
+pub async fn get(db: impl PgExecutor<'_>, id: i64) -> Result<Player, Error> {
+  query_as("SELECT id, game_id, position, team_id, name, images, uid, created_by, updated_by FROM players WHERE id = $1")
     .bind(id)
     .fetch_one(db)
     .await
     .map_err(handle_pg_error)
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema, Validate)]
+#[schema(as = PlayerCreateParams)]
 pub struct CreateParams {
+  pub team_id: Option<i64>,
+  #[validate(length(min = 1, max = MAX_NAME_LEN))]
   pub name: String,
+  #[validate(length(max = MAX_IMAGES), custom(function = image_urls))]
   pub images: Vec<String>,
+  // the signed-in user playing as this player
+  pub uid: Option<String>,
 }
 
 // create a player
 pub async fn create(
-  db: &PgPool,
+  db: impl PgExecutor<'_>,
   game_id: Uuid,
   p: CreateParams,
+  created_by: &str,
 ) -> Result<CreateResult<i64>, Error> {
   // QueryBuilder::<Postgres>::new("INSERT INTO players(name, images) VALUES (?, ?, ?) RESTURNING id, created_at")
   query_as!(
     CreateResult::<i64>,
-    "INSERT INTO players (game_id, name, images) VALUES ($1, $2, $3) RETURNING id, created_at",
+    "INSERT INTO players (game_id, position, name, images, uid, team_id, created_by)
+    VALUES ($1, (SELECT COALESCE(MAX(position), 0) + 1 FROM players WHERE game_id = $1), $2, $3, $4, $5, $6)
+    RETURNING id, created_at",
     game_id,
     p.name,
-    &p.images
+    &p.images,
+    p.uid,
+    p.team_id,
+    created_by
   )
   .fetch_one(db)
   .await
   .map_err(handle_pg_error)
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema, Validate)]
+#[schema(as = PlayerUpdateParams)]
 pub struct UpdateParams {
+  pub position: Option<i32>,
+  pub team_id: Option<i64>,
+  #[validate(length(min = 1, max = MAX_NAME_LEN))]
   pub name: Option<String>,
+  #[validate(length(max = MAX_IMAGES), custom(function = image_urls))]
   pub images: Option<Vec<String>>,
+  pub uid: Option<String>,
 }
 
 // update a player
-pub async fn update(db: &PgPool, id: i64, p: UpdateParams) -> Result<UpdateResult, Error> {
+pub async fn update(
+  db: &PgPool,
+  id: i64,
+  p: UpdateParams,
+  updated_by: &str,
+) -> Result<UpdateResult, Error> {
   let mut query = QueryBuilder::<Postgres>::new("UPDATE players SET");
   let mut sep = query.separated(", ");
+  if let Some(position) = p.position {
+    sep.push(" position = ").push_bind_unseparated(position);
+  }
+  if let Some(team_id) = p.team_id {
+    sep.push(" team_id = ").push_bind_unseparated(team_id);
+  }
   if let Some(name) = p.name {
     sep.push(" name = ").push_bind_unseparated(name);
   }
   if let Some(images) = p.images {
     sep.push(" images = ").push_bind_unseparated(images);
   }
+  if let Some(uid) = p.uid {
+    sep.push(" uid = ").push_bind_unseparated(uid);
+  }
   sep.push(" updated_at = NOW()");
+  sep.push(" updated_by = ").push_bind_unseparated(updated_by);
   query.push(" WHERE id = ").push_bind(id);
   query.push(" RETURNING updated_at");
   query
@@ -87,21 +137,34 @@ pub async fn update(db: &PgPool, id: i64, p: UpdateParams) -> Result<UpdateResul
     .map_err(handle_pg_error)
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema, Validate)]
+#[schema(as = PlayerReplaceParams)]
 pub struct ReplaceParams {
+  pub team_id: Option<i64>,
+  #[validate(length(min = 1, max = MAX_NAME_LEN))]
   pub name: String,
+  #[validate(length(max = MAX_IMAGES), custom(function = image_urls))]
   pub images: Option<Vec<String>>,
+  pub uid: Option<String>,
 }
 
 // replace a player
-pub async fn replace(db: &PgPool, id: i64, p: ReplaceParams) -> Result<UpdateResult, Error> {
+pub async fn replace(
+  db: &PgPool,
+  id: i64,
+  p: ReplaceParams,
+  updated_by: &str,
+) -> Result<UpdateResult, Error> {
   let mut query = QueryBuilder::<Postgres>::new("UPDATE players SET");
   let mut sep = query.separated(", ");
+  sep.push(" team_id = ").push_bind_unseparated(p.team_id);
   sep.push(" name = ").push_bind_unseparated(p.name);
   sep
     .push(" images = ")
     .push_bind_unseparated(p.images.unwrap_or_default());
+  sep.push(" uid = ").push_bind_unseparated(p.uid);
   sep.push(" updated_at = NOW()");
+  sep.push(" updated_by = ").push_bind_unseparated(updated_by);
   query.push(" WHERE id = ").push_bind(id);
   query.push(" RETURNING updated_at");
   query
@@ -122,3 +185,41 @@ pub async fn delete(db: &PgPool, id: i64) -> Result<(), Error> {
     Err(err) => Err(handle_pg_error(err)),
   }
 }
+
+// create many players in one transaction
+pub async fn create_many(
+  db: &PgPool,
+  game_id: Uuid,
+  items: Vec<CreateParams>,
+  mode: BulkMode,
+  created_by: &str,
+) -> Result<BulkResult, Error> {
+  if items.is_empty() {
+    return Err(Error::Empty);
+  }
+  let mut tx = db.begin().await.map_err(Error::Sqlx)?;
+  let mut result = BulkResult::default();
+  for (index, p) in items.into_iter().enumerate() {
+    // each item gets a savepoint so a failure only undoes that item
+    let mut item_tx = Acquire::begin(&mut *tx).await.map_err(Error::Sqlx)?;
+    match create(&mut *item_tx, game_id, p, created_by).await {
+      Ok(created) => {
+        item_tx.commit().await.map_err(Error::Sqlx)?;
+        result.push(
+          index,
+          BulkStatus::Created {
+            id: created.id,
+            created_at: created.created_at,
+          },
+        );
+      }
+      Err(err) if mode == BulkMode::Partial => {
+        item_tx.rollback().await.map_err(Error::Sqlx)?;
+        result.push_error(index, err);
+      }
+      Err(err) => return Err(err),
+    }
+  }
+  tx.commit().await.map_err(handle_pg_error)?;
+  Ok(result)
+}
@@ -1,41 +1,81 @@
-use chrono::NaiveDateTime;
+use async_graphql::SimpleObject;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{prelude::FromRow, query_as, PgPool, Postgres, QueryBuilder};
+use sqlx::{
+  prelude::FromRow, query_as, types::Json, Acquire, PgConnection, PgExecutor, PgPool, Postgres,
+  QueryBuilder,
+};
+use utoipa::ToSchema;
 use uuid::Uuid;
+use validator::Validate;
 
-use super::{apply_list_filters, handle_pg_error, CreateResult, Error, ListParams, UpdateResult};
+use crate::{
+  error_code::ErrorCode,
+  i18n::{self, Locale, LocalizedText, Translations},
+  validation::{image_urls, translation_tags, MAX_DESCRIPTION_LEN, MAX_IMAGES, MAX_NAME_LEN},
+};
 
-#[derive(FromRow, Serialize)]
+use super::{
+  apply_list_filters, handle_pg_error, BulkMode, BulkResult, BulkStatus, CreateResult, Error,
+  ListParams, Page, UpdateResult,
+};
+
+#[derive(FromRow, Serialize, ToSchema, SimpleObject)]
 pub struct Present {
   pub id: i64,
   pub game_id: Uuid,
+  pub number: i32,
   pub name: String,
+  pub description: Option<String>,
+  #[sqlx(json)]
+  #[schema(value_type = HashMap<String, LocalizedText>)]
+  #[graphql(skip)]
+  pub translations: Translations,
   pub player_id: Option<i64>,
   pub wrapped_images: Vec<String>,
   pub unwrapped_images: Vec<String>,
-  pub created_at: NaiveDateTime,
-  pub updated_at: Option<NaiveDateTime>,
+  pub immune_until_turn: Option<i32>,
+  pub price_cents: Option<i64>,
+  pub currency: Option<String>,
+  pub created_at: DateTime<Utc>,
+  pub updated_at: Option<DateTime<Utc>>,
+  // firebase uids, None for presents from before they were recorded
+  pub created_by: Option<String>,
+  pub updated_by: Option<String>,
+}
+
+impl Present {
+  pub fn localize(mut self, locale: Locale) -> Self {
+    i18n::resolve(
+      &self.translations,
+      locale,
+      &mut self.name,
+      &mut self.description,
+    );
+    self
+  }
 }
 
 // list presents
-pub async fn list(db: &PgPool, game_id: Uuid, p: ListParams) -> Result<Vec<Present>, Error> {
+pub async fn list(db: &PgPool, game_id: Uuid, p: ListParams) -> Result<Page<Present>, Error> {
   let mut query = QueryBuilder::<Postgres>::new(
-        "SELECT id, game_id, name, wrapped_images, unwrapped_images, player_id, created_at, updated_at FROM presents WHERE game_id = $1",
+        "SELECT id, game_id, number, name, description, translations, wrapped_images, unwrapped_images, player_id, immune_until_turn, price_cents, currency, created_at, updated_at, created_by, updated_by, COUNT(*) OVER() AS total_count FROM presents WHERE game_id = ",
     );
-  query = apply_list_filters(query, &p, vec!["id", "name"])?;
+  query.push_bind(game_id);
+  query = apply_list_filters(query, &p, vec!["id", "number", "name"])?;
 
-  query
+  let rows = query
     .build_query_as()
-    .bind(game_id)
     .fetch_all(db)
     .await
-    .map_err(Error::Sqlx)
+    .map_err(Error::Sqlx)?;
+  Ok(Page::new(rows, &p))
 }
 
 // get a present
-pub async fn get(db: &PgPool, id: i64) -> Result<Present, Error> {
+pub async fn get(db: impl PgExecutor<'_>, id: i64) -> Result<Present, Error> {
   query_as(
-        "SELECT id, game_id, name, wrapped_images, unwrapped_images, player_id, created_at, updated_at FROM presents WHERE id = $1",
+        "SELECT id, game_id, number, name, description, translations, wrapped_images, unwrapped_images, player_id, immune_until_turn, price_cents, currency, created_at, updated_at, created_by, updated_by FROM presents WHERE id = $1",
     )
     .bind(id)
     .fetch_one(db)
@@ -43,46 +83,157 @@ pub async fn get(db: &PgPool, id: i64) -> Result<Present, Error> {
     .map_err(handle_pg_error)
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema, Validate)]
+#[schema(as = PresentCreateParams)]
 pub struct CreateParams {
+  #[validate(length(min = 1, max = MAX_NAME_LEN))]
   pub name: String,
+  #[validate(length(max = MAX_DESCRIPTION_LEN))]
+  pub description: Option<String>,
+  #[schema(value_type = Option<HashMap<String, LocalizedText>>)]
+  #[validate(custom(function = translation_tags))]
+  pub translations: Option<Translations>,
+  #[validate(length(max = MAX_IMAGES), custom(function = image_urls))]
   pub wrapped_images: Option<Vec<String>>,
+  #[validate(length(max = MAX_IMAGES), custom(function = image_urls))]
   pub unwrapped_images: Option<Vec<String>>,
+  pub price_cents: Option<i64>,
+  // defaults to the game currency
+  pub currency: Option<String>,
+}
+
+// reject a price above the game budget, a price can only be compared in the game currency
+async fn check_budget(
+  conn: &mut PgConnection,
+  game_id: Uuid,
+  price_cents: Option<i64>,
+  currency: Option<&str>,
+) -> Result<(), Error> {
+  let (budget_cents, game_currency): (Option<i64>, Option<String>) =
+    query_as("SELECT budget_cents, currency FROM games WHERE id = $1")
+      .bind(game_id)
+      .fetch_one(conn)
+      .await
+      .map_err(handle_pg_error)?;
+  if let (Some(currency), Some(game_currency)) = (currency, game_currency) {
+    if currency != game_currency {
+      return Err(Error::CurrencyMismatch {
+        currency: game_currency,
+      });
+    }
+  }
+  match (price_cents, budget_cents) {
+    (Some(price_cents), Some(budget_cents)) if price_cents > budget_cents => {
+      Err(Error::OverBudget { budget_cents })
+    }
+    _ => Ok(()),
+  }
+}
+
+// price, currency and game of a stored present, checked against the budget on updates
+#[derive(FromRow)]
+struct Priced {
+  game_id: Uuid,
+  price_cents: Option<i64>,
+  currency: Option<String>,
+}
+
+async fn priced(conn: &mut PgConnection, id: i64) -> Result<Priced, Error> {
+  query_as("SELECT game_id, price_cents, currency FROM presents WHERE id = $1")
+    .bind(id)
+    .fetch_one(conn)
+    .await
+    .map_err(handle_pg_error)
 }
 
-// create a present
+// create a present, on a transaction so the game stays locked until the number is committed
 pub async fn create(
-  db: &PgPool,
+  conn: &mut PgConnection,
   game_id: Uuid,
   p: CreateParams,
+  created_by: &str,
 ) -> Result<CreateResult<i64>, Error> {
+  // concurrent creates would otherwise read the same highest number
+  sqlx::query("SELECT 1 FROM games WHERE id = $1 FOR UPDATE")
+    .bind(game_id)
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(handle_pg_error)?
+    .ok_or(Error::NotFound)?;
+  check_budget(&mut *conn, game_id, p.price_cents, p.currency.as_deref()).await?;
   query_as(
-        "INSERT INTO presents (game_id, name, wrapped_images, unwrapped_images) VALUES ($1, $2, $3, $4) RETURNING id, created_at",
+        "INSERT INTO presents (game_id, number, name, description, translations, wrapped_images, unwrapped_images, price_cents, currency, created_by)
+        VALUES ($1, (SELECT COALESCE(MAX(number), 0) + 1 FROM presents WHERE game_id = $1), $2, $3, $4, $5, $6, $7,
+          COALESCE($8, (SELECT currency FROM games WHERE id = $1)), $9)
+        RETURNING id, created_at",
     )
     .bind(game_id)
     .bind(p.name)
+    .bind(p.description)
+    .bind(Json(p.translations.unwrap_or_default()))
     .bind(p.wrapped_images.unwrap_or_default())
     .bind(p.unwrapped_images.unwrap_or_default())
-    .fetch_one(db)
+    .bind(p.price_cents)
+    .bind(p.currency)
+    .bind(created_by)
+    .fetch_one(&mut *conn)
     .await
     .map_err(handle_pg_error)
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema, Validate)]
+#[schema(as = PresentUpdateParams)]
 pub struct UpdateParams {
+  #[validate(length(min = 1, max = MAX_NAME_LEN))]
   pub name: Option<String>,
+  #[validate(length(max = MAX_DESCRIPTION_LEN))]
+  pub description: Option<String>,
+  #[schema(value_type = Option<HashMap<String, LocalizedText>>)]
+  #[validate(custom(function = translation_tags))]
+  pub translations: Option<Translations>,
+  #[validate(length(max = MAX_IMAGES), custom(function = image_urls))]
   pub wrapped_images: Option<Vec<String>>,
+  #[validate(length(max = MAX_IMAGES), custom(function = image_urls))]
   pub unwrapped_images: Option<Vec<String>>,
   pub player_id: Option<i16>,
+  pub price_cents: Option<i64>,
+  pub currency: Option<String>,
 }
 
 // update a present
-pub async fn update(db: &PgPool, id: i64, p: UpdateParams) -> Result<UpdateResult, Error> {
+pub async fn update(
+  db: &PgPool,
+  id: i64,
+  p: UpdateParams,
+  updated_by: &str,
+) -> Result<UpdateResult, Error> {
+  if p.price_cents.is_some() || p.currency.is_some() {
+    let mut conn = db.acquire().await.map_err(Error::Sqlx)?;
+    let stored = priced(&mut conn, id).await?;
+    check_budget(
+      &mut conn,
+      stored.game_id,
+      p.price_cents.or(stored.price_cents),
+      p.currency.as_deref().or(stored.currency.as_deref()),
+    )
+    .await?;
+  }
+
   let mut query = QueryBuilder::<Postgres>::new("UPDATE presents SET");
   let mut sep = query.separated(", ");
   if let Some(name) = p.name {
     sep.push(" name = ").push_bind_unseparated(name);
   }
+  if let Some(description) = p.description {
+    sep
+      .push(" description = ")
+      .push_bind_unseparated(description);
+  }
+  if let Some(translations) = p.translations {
+    sep
+      .push(" translations = ")
+      .push_bind_unseparated(Json(translations));
+  }
   if let Some(wrapped_images) = p.wrapped_images {
     sep
       .push(" wrapped_images = ")
@@ -96,7 +247,16 @@ pub async fn update(db: &PgPool, id: i64, p: UpdateParams) -> Result<UpdateResul
   if let Some(player_id) = p.player_id {
     sep.push(" player_id = ").push_bind_unseparated(player_id);
   }
+  if let Some(price_cents) = p.price_cents {
+    sep
+      .push(" price_cents = ")
+      .push_bind_unseparated(price_cents);
+  }
+  if let Some(currency) = p.currency {
+    sep.push(" currency = ").push_bind_unseparated(currency);
+  }
   sep.push(" updated_at = NOW()");
+  sep.push(" updated_by = ").push_bind_unseparated(updated_by);
   query.push(" WHERE id = ").push_bind(id);
   query.push(" RETURNING updated_at");
   query
@@ -106,19 +266,51 @@ pub async fn update(db: &PgPool, id: i64, p: UpdateParams) -> Result<UpdateResul
     .map_err(handle_pg_error)
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema, Validate)]
+#[schema(as = PresentReplaceParams)]
 pub struct ReplaceParams {
+  #[validate(length(min = 1, max = MAX_NAME_LEN))]
   pub name: String,
+  #[validate(length(max = MAX_DESCRIPTION_LEN))]
+  pub description: Option<String>,
+  #[schema(value_type = Option<HashMap<String, LocalizedText>>)]
+  #[validate(custom(function = translation_tags))]
+  pub translations: Option<Translations>,
+  #[validate(length(max = MAX_IMAGES), custom(function = image_urls))]
   pub wrapped_images: Option<Vec<String>>,
+  #[validate(length(max = MAX_IMAGES), custom(function = image_urls))]
   pub unwrapped_images: Option<Vec<String>>,
   pub player_id: Option<i16>,
+  pub price_cents: Option<i64>,
+  pub currency: Option<String>,
 }
 
 // replace a present
-pub async fn replace(db: &PgPool, id: i64, p: ReplaceParams) -> Result<UpdateResult, Error> {
+pub async fn replace(
+  db: &PgPool,
+  id: i64,
+  p: ReplaceParams,
+  updated_by: &str,
+) -> Result<UpdateResult, Error> {
+  let mut conn = db.acquire().await.map_err(Error::Sqlx)?;
+  let stored = priced(&mut conn, id).await?;
+  check_budget(
+    &mut conn,
+    stored.game_id,
+    p.price_cents,
+    p.currency.as_deref(),
+  )
+  .await?;
+
   let mut query = QueryBuilder::<Postgres>::new("UPDATE presents SET");
   let mut sep = query.separated(", ");
   sep.push(" name = ").push_bind_unseparated(p.name);
+  sep
+    .push(" description = ")
+    .push_bind_unseparated(p.description);
+  sep
+    .push(" translations = ")
+    .push_bind_unseparated(Json(p.translations.unwrap_or_default()));
   sep
     .push(" wrapped_images = ")
     .push_bind_unseparated(p.wrapped_images.unwrap_or_default());
@@ -126,12 +318,17 @@ pub async fn replace(db: &PgPool, id: i64, p: ReplaceParams) -> Result<UpdateRes
     .push(" unwrapped_images = ")
     .push_bind_unseparated(p.unwrapped_images.unwrap_or_default());
   sep.push(" player_id = ").push_bind_unseparated(p.player_id);
+  sep
+    .push(" price_cents = ")
+    .push_bind_unseparated(p.price_cents);
+  sep.push(" currency = ").push_bind_unseparated(p.currency);
   sep.push(" updated_at = NOW()");
+  sep.push(" updated_by = ").push_bind_unseparated(updated_by);
   query.push(" WHERE id = ").push_bind(id);
   query.push(" RETURNING updated_at");
   query
     .build_query_as()
-    .fetch_one(db)
+    .fetch_one(&mut *conn)
     .await
     .map_err(handle_pg_error)
 }
@@ -147,3 +344,129 @@ pub async fn delete(db: &PgPool, id: i64) -> Result<(), Error> {
     Err(err) => Err(handle_pg_error(err)),
   }
 }
+
+// create many presents in one transaction
+pub async fn create_many(
+  db: &PgPool,
+  game_id: Uuid,
+  items: Vec<CreateParams>,
+  mode: BulkMode,
+  created_by: &str,
+) -> Result<BulkResult, Error> {
+  if items.is_empty() {
+    return Err(Error::Empty);
+  }
+  let mut tx = db.begin().await.map_err(Error::Sqlx)?;
+  let mut result = BulkResult::default();
+  for (index, p) in items.into_iter().enumerate() {
+    // each item gets a savepoint so a failure only undoes that item
+    let mut item_tx = Acquire::begin(&mut *tx).await.map_err(Error::Sqlx)?;
+    match create(&mut item_tx, game_id, p, created_by).await {
+      Ok(created) => {
+        item_tx.commit().await.map_err(Error::Sqlx)?;
+        result.push(
+          index,
+          BulkStatus::Created {
+            id: created.id,
+            created_at: created.created_at,
+          },
+        );
+      }
+      Err(err) if mode == BulkMode::Partial => {
+        item_tx.rollback().await.map_err(Error::Sqlx)?;
+        result.push_error(index, err);
+      }
+      Err(err) => return Err(err),
+    }
+  }
+  tx.commit().await.map_err(handle_pg_error)?;
+  Ok(result)
+}
+
+// delete many presents of a game, ids that don't exist are skipped
+pub async fn delete_many(
+  db: &PgPool,
+  game_id: Uuid,
+  ids: Vec<i64>,
+  mode: BulkMode,
+) -> Result<BulkResult, Error> {
+  if ids.is_empty() {
+    return Err(Error::Empty);
+  }
+  let mut tx = db.begin().await.map_err(Error::Sqlx)?;
+  let mut result = BulkResult::default();
+  for (index, id) in ids.into_iter().enumerate() {
+    let mut item_tx = Acquire::begin(&mut *tx).await.map_err(Error::Sqlx)?;
+    let deleted = sqlx::query("DELETE FROM presents WHERE id = $1 AND game_id = $2")
+      .bind(id)
+      .bind(game_id)
+      .execute(&mut *item_tx)
+      .await
+      .map_err(handle_pg_error);
+    match deleted {
+      Ok(done) if done.rows_affected() == 0 => {
+        item_tx.rollback().await.map_err(Error::Sqlx)?;
+        result.push(
+          index,
+          BulkStatus::Skipped {
+            id,
+            reason: ErrorCode::NotFound,
+          },
+        );
+      }
+      Ok(_) => {
+        item_tx.commit().await.map_err(Error::Sqlx)?;
+        result.push(index, BulkStatus::Deleted { id });
+      }
+      Err(err) if mode == BulkMode::Partial => {
+        item_tx.rollback().await.map_err(Error::Sqlx)?;
+        result.push_error(index, err);
+      }
+      Err(err) => return Err(err),
+    }
+  }
+  tx.commit().await.map_err(handle_pg_error)?;
+  Ok(result)
+}
+
+#[derive(FromRow, Serialize, ToSchema)]
+pub struct PresentNumber {
+  pub id: i64,
+  pub number: i32,
+}
+
+// randomize present numbers, only before the game starts
+pub async fn shuffle(
+  db: &PgPool,
+  game_id: Uuid,
+  updated_by: &str,
+) -> Result<Vec<PresentNumber>, Error> {
+  let mut tx = db.begin().await.map_err(Error::Sqlx)?;
+
+  let (started_at,): (Option<DateTime<Utc>>,) =
+    query_as("SELECT started_at FROM games WHERE id = $1 AND deleted_at IS NULL FOR UPDATE")
+      .bind(game_id)
+      .fetch_one(&mut *tx)
+      .await
+      .map_err(handle_pg_error)?;
+  if started_at.is_some() {
+    return Err(Error::GameStarted);
+  }
+
+  let numbers = query_as(
+    "UPDATE presents SET number = shuffled.n, updated_at = NOW(), updated_by = $2
+    FROM (
+      SELECT id, ROW_NUMBER() OVER (ORDER BY random())::INTEGER AS n FROM presents WHERE game_id = $1
+    ) shuffled
+    WHERE presents.id = shuffled.id
+    RETURNING presents.id, presents.number",
+  )
+  .bind(game_id)
+  .bind(updated_by)
+  .fetch_all(&mut *tx)
+  .await
+  .map_err(handle_pg_error)?;
+
+  tx.commit().await.map_err(handle_pg_error)?;
+  Ok(numbers)
+}
@@ -0,0 +1,276 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, query_as, types::Json, PgConnection, PgPool};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::{handle_pg_error, Error};
+
+// summary of a finished game, generated once every player owns a present
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Recap {
+  pub game_id: Uuid,
+  pub assignments: Vec<Assignment>,
+  // presents per team, empty when the game has no teams
+  #[serde(default)]
+  pub teams: Vec<TeamSummary>,
+  pub turns: i32,
+  pub steals: i64,
+  // every present with how often it was stolen, by present number
+  #[serde(default)]
+  pub present_steals: Vec<PresentSteals>,
+  pub duration_seconds: Option<i64>,
+  // over the presents that have a price, in the game currency
+  #[serde(default)]
+  pub total_price_cents: Option<i64>,
+  #[serde(default)]
+  pub average_price_cents: Option<i64>,
+  #[serde(default)]
+  pub currency: Option<String>,
+  pub most_stolen_present: Option<PresentHighlight>,
+  pub biggest_thief: Option<PlayerHighlight>,
+  pub most_robbed_player: Option<PlayerHighlight>,
+  pub generated_at: DateTime<Utc>,
+}
+
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
+pub struct Assignment {
+  pub player_id: i64,
+  pub player_name: String,
+  pub present_id: i64,
+  pub present_number: i32,
+  pub present_name: String,
+}
+
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
+pub struct TeamSummary {
+  pub team_id: i64,
+  pub name: String,
+  pub color: Option<String>,
+  pub presents: i64,
+  // combined price of the presents that have one
+  pub price_cents: Option<i64>,
+}
+
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
+pub struct PresentSteals {
+  pub present_id: i64,
+  pub number: i32,
+  pub name: String,
+  pub steals: i64,
+}
+
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct PresentHighlight {
+  pub present_id: i64,
+  pub name: String,
+  pub count: i64,
+}
+
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct PlayerHighlight {
+  pub player_id: i64,
+  pub name: String,
+  pub count: i64,
+}
+
+#[derive(FromRow)]
+struct Totals {
+  turns: i32,
+  steals: i64,
+  duration_seconds: Option<i64>,
+  total_price_cents: Option<i64>,
+  average_price_cents: Option<i64>,
+  currency: Option<String>,
+  generated_at: DateTime<Utc>,
+}
+
+// get the recap of a finished game
+pub async fn get(db: &PgPool, game_id: Uuid) -> Result<Recap, Error> {
+  let (Json(recap),): (Json<Recap>,) = query_as("SELECT data FROM recaps WHERE game_id = $1")
+    .bind(game_id)
+    .fetch_one(db)
+    .await
+    .map_err(handle_pg_error)?;
+  Ok(recap)
+}
+
+// summary of a game so far, generated on every call so it also covers running games
+pub async fn summary(db: &PgPool, game_id: Uuid) -> Result<Recap, Error> {
+  let mut conn = db.acquire().await.map_err(Error::Sqlx)?;
+  generate(&mut conn, game_id).await
+}
+
+// every player holds a present and no turn is open
+pub(super) const DONE_SQL: &str = "SELECT EXISTS (SELECT 1 FROM players WHERE game_id = $1)
+  AND (SELECT player_id FROM games WHERE id = $1) IS NULL
+  AND NOT EXISTS (
+    SELECT 1 FROM players
+    WHERE game_id = $1
+    AND id NOT IN (SELECT player_id FROM presents WHERE game_id = $1 AND player_id IS NOT NULL))";
+
+// store a recap when no player is left without a present and nobody is taking a turn,
+// called at the end of a play action
+pub async fn finish_if_done(
+  conn: &mut PgConnection,
+  game_id: Uuid,
+) -> Result<Option<Recap>, Error> {
+  let (done,): (bool,) = query_as(DONE_SQL)
+    .bind(game_id)
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(handle_pg_error)?;
+  if !done {
+    return Ok(None);
+  }
+
+  let recap = generate(conn, game_id).await?;
+  sqlx::query(
+    "INSERT INTO recaps (game_id, data) VALUES ($1, $2)
+    ON CONFLICT (game_id) DO UPDATE SET data = EXCLUDED.data, created_at = NOW()",
+  )
+  .bind(game_id)
+  .bind(Json(&recap))
+  .execute(&mut *conn)
+  .await
+  .map_err(handle_pg_error)?;
+  Ok(Some(recap))
+}
+
+async fn generate(conn: &mut PgConnection, game_id: Uuid) -> Result<Recap, Error> {
+  let assignments: Vec<Assignment> = query_as(
+    "SELECT players.id AS player_id, players.name AS player_name,
+      presents.id AS present_id, presents.number AS present_number, presents.name AS present_name
+    FROM presents
+    JOIN players ON players.id = presents.player_id
+    WHERE presents.game_id = $1
+    ORDER BY presents.number",
+  )
+  .bind(game_id)
+  .fetch_all(&mut *conn)
+  .await
+  .map_err(handle_pg_error)?;
+
+  let teams: Vec<TeamSummary> = query_as(
+    "SELECT teams.id AS team_id, teams.name, teams.color,
+      COUNT(presents.id) AS presents, SUM(presents.price_cents)::BIGINT AS price_cents
+    FROM teams
+    LEFT JOIN players ON players.team_id = teams.id
+    LEFT JOIN presents ON presents.player_id = players.id
+    WHERE teams.game_id = $1
+    GROUP BY teams.id
+    ORDER BY teams.id",
+  )
+  .bind(game_id)
+  .fetch_all(&mut *conn)
+  .await
+  .map_err(handle_pg_error)?;
+
+  let totals: Totals = query_as(
+    "SELECT games.turn AS turns,
+      (SELECT COUNT(*) FROM play_events WHERE game_id = $1 AND kind = 'steal') AS steals,
+      EXTRACT(EPOCH FROM COALESCE(games.finished_at, NOW()) - games.started_at)::BIGINT
+        AS duration_seconds,
+      prices.total::BIGINT AS total_price_cents,
+      ROUND(prices.average)::BIGINT AS average_price_cents,
+      games.currency,
+      NOW() AS generated_at
+    FROM games,
+      LATERAL (
+        SELECT SUM(price_cents) AS total, AVG(price_cents) AS average
+        FROM presents WHERE game_id = $1
+      ) prices
+    WHERE id = $1",
+  )
+  .bind(game_id)
+  .fetch_one(&mut *conn)
+  .await
+  .map_err(handle_pg_error)?;
+
+  let present_steals: Vec<PresentSteals> = query_as(
+    "SELECT presents.id AS present_id, presents.number, presents.name,
+      COUNT(play_events.id) AS steals
+    FROM presents
+    LEFT JOIN play_events ON play_events.from_present_id = presents.id AND play_events.kind = 'steal'
+    WHERE presents.game_id = $1
+    GROUP BY presents.id
+    ORDER BY presents.number",
+  )
+  .bind(game_id)
+  .fetch_all(&mut *conn)
+  .await
+  .map_err(handle_pg_error)?;
+
+  let most_stolen_present = most_stolen_present(&mut *conn, game_id).await?;
+  let biggest_thief = biggest_thief(&mut *conn, game_id).await?;
+
+  let most_robbed_player: Option<PlayerHighlight> = query_as(
+    "SELECT players.id AS player_id, players.name, COUNT(*) AS count
+    FROM play_events
+    JOIN players ON players.id = play_events.from_player_id
+    WHERE play_events.game_id = $1 AND play_events.kind = 'steal'
+    GROUP BY players.id
+    ORDER BY count DESC, players.id
+    LIMIT 1",
+  )
+  .bind(game_id)
+  .fetch_optional(&mut *conn)
+  .await
+  .map_err(handle_pg_error)?;
+
+  Ok(Recap {
+    game_id,
+    assignments,
+    teams,
+    turns: totals.turns,
+    steals: totals.steals,
+    present_steals,
+    duration_seconds: totals.duration_seconds,
+    total_price_cents: totals.total_price_cents,
+    average_price_cents: totals.average_price_cents,
+    currency: totals.currency,
+    most_stolen_present,
+    biggest_thief,
+    most_robbed_player,
+    generated_at: totals.generated_at,
+  })
+}
+
+// a steal event moves from_present_id from from_player_id to player_id
+pub async fn most_stolen_present(
+  conn: &mut PgConnection,
+  game_id: Uuid,
+) -> Result<Option<PresentHighlight>, Error> {
+  query_as(
+    "SELECT presents.id AS present_id, presents.name, COUNT(*) AS count
+    FROM play_events
+    JOIN presents ON presents.id = play_events.from_present_id
+    WHERE play_events.game_id = $1 AND play_events.kind = 'steal'
+    GROUP BY presents.id
+    ORDER BY count DESC, presents.number
+    LIMIT 1",
+  )
+  .bind(game_id)
+  .fetch_optional(conn)
+  .await
+  .map_err(handle_pg_error)
+}
+
+pub async fn biggest_thief(
+  conn: &mut PgConnection,
+  game_id: Uuid,
+) -> Result<Option<PlayerHighlight>, Error> {
+  query_as(
+    "SELECT players.id AS player_id, players.name, COUNT(*) AS count
+    FROM play_events
+    JOIN players ON players.id = play_events.player_id
+    WHERE play_events.game_id = $1 AND play_events.kind = 'steal'
+    GROUP BY players.id
+    ORDER BY count DESC, players.id
+    LIMIT 1",
+  )
+  .bind(game_id)
+  .fetch_optional(conn)
+  .await
+  .map_err(handle_pg_error)
+}
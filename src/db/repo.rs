@@ -0,0 +1,269 @@
+use std::sync::Arc;
+
+use axum::async_trait;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{
+  events::{PlayEvent, ReplayState},
+  games::{self, Game, GameStats, Snapshot},
+  players::{self, Player},
+  presents::{self, Present, PresentNumber},
+  BulkMode, BulkResult, Error, ListParams, Page, UpdateResult,
+};
+
+// the reads of a game, writes that span several tables stay on the Tx extractor
+#[async_trait]
+pub trait GamesRepo: Send + Sync {
+  async fn list(&self, uid: &str, p: ListParams) -> Result<Page<Game>, Error>;
+  async fn get(&self, id: Uuid) -> Result<Game, Error>;
+  async fn list_events(&self, game_id: Uuid, p: ListParams) -> Result<Page<PlayEvent>, Error>;
+  async fn state_at(&self, game_id: Uuid, at_event: Option<i64>) -> Result<ReplayState, Error>;
+  async fn snapshot(&self, game_id: Uuid) -> Result<Snapshot, Error>;
+  async fn stats(&self, game_id: Uuid) -> Result<GameStats, Error>;
+}
+
+#[async_trait]
+pub trait PlayersRepo: Send + Sync {
+  async fn list(&self, game_id: Uuid, p: ListParams) -> Result<Page<Player>, Error>;
+  async fn get(&self, id: i64) -> Result<Player, Error>;
+  // returns the player as stored
+  async fn create(
+    &self,
+    game_id: Uuid,
+    p: players::CreateParams,
+    created_by: &str,
+  ) -> Result<Player, Error>;
+  async fn create_many(
+    &self,
+    game_id: Uuid,
+    items: Vec<players::CreateParams>,
+    mode: BulkMode,
+    created_by: &str,
+  ) -> Result<BulkResult, Error>;
+  async fn update(
+    &self,
+    id: i64,
+    p: players::UpdateParams,
+    updated_by: &str,
+  ) -> Result<UpdateResult, Error>;
+  async fn replace(
+    &self,
+    id: i64,
+    p: players::ReplaceParams,
+    updated_by: &str,
+  ) -> Result<UpdateResult, Error>;
+  async fn delete(&self, id: i64) -> Result<(), Error>;
+}
+
+#[async_trait]
+pub trait PresentsRepo: Send + Sync {
+  async fn list(&self, game_id: Uuid, p: ListParams) -> Result<Page<Present>, Error>;
+  async fn get(&self, id: i64) -> Result<Present, Error>;
+  // returns the present as stored
+  async fn create(
+    &self,
+    game_id: Uuid,
+    p: presents::CreateParams,
+    created_by: &str,
+  ) -> Result<Present, Error>;
+  async fn create_many(
+    &self,
+    game_id: Uuid,
+    items: Vec<presents::CreateParams>,
+    mode: BulkMode,
+    created_by: &str,
+  ) -> Result<BulkResult, Error>;
+  async fn delete_many(
+    &self,
+    game_id: Uuid,
+    ids: Vec<i64>,
+    mode: BulkMode,
+  ) -> Result<BulkResult, Error>;
+  async fn update(
+    &self,
+    id: i64,
+    p: presents::UpdateParams,
+    updated_by: &str,
+  ) -> Result<UpdateResult, Error>;
+  async fn replace(
+    &self,
+    id: i64,
+    p: presents::ReplaceParams,
+    updated_by: &str,
+  ) -> Result<UpdateResult, Error>;
+  async fn delete(&self, id: i64) -> Result<(), Error>;
+  async fn shuffle(&self, game_id: Uuid, updated_by: &str) -> Result<Vec<PresentNumber>, Error>;
+}
+
+// the repositories handlers use, postgres unless a test swaps in fakes
+#[derive(Clone)]
+pub struct Repos {
+  pub games: Arc<dyn GamesRepo>,
+  pub players: Arc<dyn PlayersRepo>,
+  pub presents: Arc<dyn PresentsRepo>,
+}
+
+impl Repos {
+  pub fn postgres(pool: PgPool) -> Self {
+    let repo = Arc::new(PgRepo(pool));
+    Self {
+      games: repo.clone(),
+      players: repo.clone(),
+      presents: repo,
+    }
+  }
+}
+
+// delegates to the query functions of the db modules
+pub struct PgRepo(pub PgPool);
+
+#[async_trait]
+impl GamesRepo for PgRepo {
+  async fn list(&self, uid: &str, p: ListParams) -> Result<Page<Game>, Error> {
+    games::list(&self.0, uid, p).await
+  }
+
+  async fn get(&self, id: Uuid) -> Result<Game, Error> {
+    games::get(&self.0, id).await
+  }
+
+  async fn list_events(&self, game_id: Uuid, p: ListParams) -> Result<Page<PlayEvent>, Error> {
+    games::list_events(&self.0, game_id, p).await
+  }
+
+  async fn state_at(&self, game_id: Uuid, at_event: Option<i64>) -> Result<ReplayState, Error> {
+    games::state_at(&self.0, game_id, at_event).await
+  }
+
+  async fn snapshot(&self, game_id: Uuid) -> Result<Snapshot, Error> {
+    games::snapshot(&self.0, game_id).await
+  }
+
+  async fn stats(&self, game_id: Uuid) -> Result<GameStats, Error> {
+    games::stats(&self.0, game_id).await
+  }
+}
+
+#[async_trait]
+impl PlayersRepo for PgRepo {
+  async fn list(&self, game_id: Uuid, p: ListParams) -> Result<Page<Player>, Error> {
+    players::list(&self.0, game_id, p).await
+  }
+
+  async fn get(&self, id: i64) -> Result<Player, Error> {
+    players::get(&self.0, id).await
+  }
+
+  async fn create(
+    &self,
+    game_id: Uuid,
+    p: players::CreateParams,
+    created_by: &str,
+  ) -> Result<Player, Error> {
+    let created = players::create(&self.0, game_id, p, created_by).await?;
+    players::get(&self.0, created.id).await
+  }
+
+  async fn create_many(
+    &self,
+    game_id: Uuid,
+    items: Vec<players::CreateParams>,
+    mode: BulkMode,
+    created_by: &str,
+  ) -> Result<BulkResult, Error> {
+    players::create_many(&self.0, game_id, items, mode, created_by).await
+  }
+
+  async fn update(
+    &self,
+    id: i64,
+    p: players::UpdateParams,
+    updated_by: &str,
+  ) -> Result<UpdateResult, Error> {
+    players::update(&self.0, id, p, updated_by).await
+  }
+
+  async fn replace(
+    &self,
+    id: i64,
+    p: players::ReplaceParams,
+    updated_by: &str,
+  ) -> Result<UpdateResult, Error> {
+    players::replace(&self.0, id, p, updated_by).await
+  }
+
+  async fn delete(&self, id: i64) -> Result<(), Error> {
+    players::delete(&self.0, id).await
+  }
+}
+
+#[async_trait]
+impl PresentsRepo for PgRepo {
+  async fn list(&self, game_id: Uuid, p: ListParams) -> Result<Page<Present>, Error> {
+    presents::list(&self.0, game_id, p).await
+  }
+
+  async fn get(&self, id: i64) -> Result<Present, Error> {
+    presents::get(&self.0, id).await
+  }
+
+  async fn create(
+    &self,
+    game_id: Uuid,
+    p: presents::CreateParams,
+    created_by: &str,
+  ) -> Result<Present, Error> {
+    // the number is picked on this transaction, read the present back on it as well
+    let mut tx = self.0.begin().await.map_err(Error::Sqlx)?;
+    let created = presents::create(&mut tx, game_id, p, created_by).await?;
+    let present = presents::get(&mut *tx, created.id).await?;
+    tx.commit().await.map_err(Error::Sqlx)?;
+    Ok(present)
+  }
+
+  async fn create_many(
+    &self,
+    game_id: Uuid,
+    items: Vec<presents::CreateParams>,
+    mode: BulkMode,
+    created_by: &str,
+  ) -> Result<BulkResult, Error> {
+    presents::create_many(&self.0, game_id, items, mode, created_by).await
+  }
+
+  async fn delete_many(
+    &self,
+    game_id: Uuid,
+    ids: Vec<i64>,
+    mode: BulkMode,
+  ) -> Result<BulkResult, Error> {
+    presents::delete_many(&self.0, game_id, ids, mode).await
+  }
+
+  async fn update(
+    &self,
+    id: i64,
+    p: presents::UpdateParams,
+    updated_by: &str,
+  ) -> Result<UpdateResult, Error> {
+    presents::update(&self.0, id, p, updated_by).await
+  }
+
+  async fn replace(
+    &self,
+    id: i64,
+    p: presents::ReplaceParams,
+    updated_by: &str,
+  ) -> Result<UpdateResult, Error> {
+    presents::replace(&self.0, id, p, updated_by).await
+  }
+
+  async fn delete(&self, id: i64) -> Result<(), Error> {
+    presents::delete(&self.0, id).await
+  }
+
+  async fn shuffle(&self, game_id: Uuid, updated_by: &str) -> Result<Vec<PresentNumber>, Error> {
+    presents::shuffle(&self.0, game_id, updated_by).await
+  }
+}
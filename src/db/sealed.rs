@@ -0,0 +1,39 @@
+use sqlx::{query, query_as, PgPool};
+
+use crate::crypto::{keyring, Sealed};
+
+use super::Error;
+
+// (table, column) pairs holding crypto::Sealed values, rows are keyed by id
+pub const SEALED_COLUMNS: &[(&str, &str)] = &[("webhooks", "secret"), ("email_invites", "email")];
+
+// re-encrypt every value not sealed with the primary key, returns the number of values rewritten
+pub async fn rotate(db: &PgPool) -> Result<u64, Error> {
+  let keyring = keyring();
+  let mut rotated = 0;
+  for (table, column) in SEALED_COLUMNS {
+    let rows: Vec<(String, String)> = query_as(&format!(
+      "SELECT id::text, {column} FROM {table} WHERE {column} IS NOT NULL"
+    ))
+    .fetch_all(db)
+    .await?;
+    for (id, stored) in rows {
+      if !keyring.is_stale(&stored) {
+        continue;
+      }
+      let plain = keyring
+        .open(&stored)
+        .map_err(|err| Error::Sqlx(sqlx::Error::Decode(err.into())))?;
+      rotated += query(&format!(
+        "UPDATE {table} SET {column} = $1 WHERE id::text = $2 AND {column} = $3"
+      ))
+      .bind(Sealed(plain))
+      .bind(&id)
+      .bind(&stored)
+      .execute(db)
+      .await?
+      .rows_affected();
+    }
+  }
+  Ok(rotated)
+}
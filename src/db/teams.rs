@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, query_as, PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use super::{
+  apply_list_filters, handle_pg_error, CreateResult, Error, ListParams, Page, UpdateResult,
+};
+
+#[derive(FromRow, Serialize)]
+pub struct Team {
+  pub id: i64,
+  pub game_id: Uuid,
+  pub name: String,
+  // hex color the frontend uses for the team's players
+  pub color: Option<String>,
+}
+
+// list teams
+pub async fn list(db: &PgPool, game_id: Uuid, p: ListParams) -> Result<Page<Team>, Error> {
+  let mut query = QueryBuilder::<Postgres>::new(
+    "SELECT id, game_id, name, color, COUNT(*) OVER() AS total_count FROM teams WHERE game_id = ",
+  );
+  query.push_bind(game_id);
+
+  query = apply_list_filters(query, &p, vec!["id", "name"])?;
+  let rows = query
+    .build_query_as()
+    .fetch_all(db)
+    .await
+    .map_err(Error::Sqlx)?;
+  Ok(Page::new(rows, &p))
+}
+
+// get a team
+pub async fn get(db: &PgPool, game_id: Uuid, id: i64) -> Result<Team, Error> {
+  query_as("SELECT id, game_id, name, color FROM teams WHERE id = $1 AND game_id = $2")
+    .bind(id)
+    .bind(game_id)
+    .fetch_one(db)
+    .await
+    .map_err(handle_pg_error)
+}
+
+#[derive(Deserialize)]
+pub struct CreateParams {
+  pub name: String,
+  pub color: Option<String>,
+}
+
+// create a team
+pub async fn create(
+  db: &PgPool,
+  game_id: Uuid,
+  p: CreateParams,
+) -> Result<CreateResult<i64>, Error> {
+  query_as!(
+    CreateResult::<i64>,
+    "INSERT INTO teams (game_id, name, color) VALUES ($1, $2, $3) RETURNING id, created_at",
+    game_id,
+    p.name,
+    p.color
+  )
+  .fetch_one(db)
+  .await
+  .map_err(handle_pg_error)
+}
+
+#[derive(Deserialize)]
+pub struct UpdateParams {
+  pub name: Option<String>,
+  pub color: Option<String>,
+}
+
+// update a team
+pub async fn update(
+  db: &PgPool,
+  game_id: Uuid,
+  id: i64,
+  p: UpdateParams,
+) -> Result<UpdateResult, Error> {
+  let mut query = QueryBuilder::<Postgres>::new("UPDATE teams SET");
+  let mut sep = query.separated(", ");
+  if let Some(name) = p.name {
+    sep.push(" name = ").push_bind_unseparated(name);
+  }
+  if let Some(color) = p.color {
+    sep.push(" color = ").push_bind_unseparated(color);
+  }
+  sep.push(" updated_at = NOW()");
+  query.push(" WHERE id = ").push_bind(id);
+  query.push(" AND game_id = ").push_bind(game_id);
+  query.push(" RETURNING updated_at");
+  query
+    .build_query_as()
+    .fetch_one(db)
+    .await
+    .map_err(handle_pg_error)
+}
+
+#[derive(Deserialize)]
+pub struct ReplaceParams {
+  pub name: String,
+  pub color: Option<String>,
+}
+
+// replace a team
+pub async fn replace(
+  db: &PgPool,
+  game_id: Uuid,
+  id: i64,
+  p: ReplaceParams,
+) -> Result<UpdateResult, Error> {
+  query_as!(
+    UpdateResult,
+    r#"UPDATE teams SET name = $3, color = $4, updated_at = NOW()
+    WHERE id = $1 AND game_id = $2
+    RETURNING updated_at AS "updated_at!""#,
+    id,
+    game_id,
+    p.name,
+    p.color
+  )
+  .fetch_one(db)
+  .await
+  .map_err(handle_pg_error)
+}
+
+// delete a team, its players stay in the game without a team
+pub async fn delete(db: &PgPool, game_id: Uuid, id: i64) -> Result<(), Error> {
+  match sqlx::query("DELETE FROM teams WHERE id = $1 AND game_id = $2")
+    .bind(id)
+    .bind(game_id)
+    .execute(db)
+    .await
+  {
+    Ok(_) => Ok(()),
+    Err(err) => Err(handle_pg_error(err)),
+  }
+}
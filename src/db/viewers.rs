@@ -0,0 +1,117 @@
+use sqlx::{query, query_as, query_scalar, PgPool};
+use uuid::Uuid;
+
+use super::{handle_pg_error, Error};
+
+// viewers whose replica hasn't refreshed them for this long are gone
+const STALE_SECONDS: f64 = 30.0;
+
+// connections per kind, only counting fresh rows
+pub async fn counts(db: &PgPool, game_id: Uuid) -> Result<Vec<(String, i64)>, Error> {
+  query_as(
+    "SELECT kind, COUNT(*) FROM viewers
+    WHERE game_id = $1 AND seen_at > NOW() - make_interval(secs => $2)
+    GROUP BY kind",
+  )
+  .bind(game_id)
+  .bind(STALE_SECONDS)
+  .fetch_all(db)
+  .await
+  .map_err(handle_pg_error)
+}
+
+pub async fn insert(db: &PgPool, id: Uuid, game_id: Uuid, kind: &str) -> Result<(), Error> {
+  query!(
+    "INSERT INTO viewers (id, game_id, kind) VALUES ($1, $2, $3)",
+    id,
+    game_id,
+    kind
+  )
+  .execute(db)
+  .await
+  .map_err(handle_pg_error)?;
+  Ok(())
+}
+
+// insert a viewer unless the game already has `limit` of this kind, false when it's full.
+// the lock keeps two replicas from both taking the last place
+pub async fn insert_within(
+  db: &PgPool,
+  id: Uuid,
+  game_id: Uuid,
+  kind: &str,
+  limit: i64,
+) -> Result<bool, Error> {
+  let mut tx = db.begin().await.map_err(Error::Sqlx)?;
+  query("SELECT pg_advisory_xact_lock(hashtextextended($1::text, 0))")
+    .bind(game_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(handle_pg_error)?;
+  let (count,): (i64,) = query_as(
+    "SELECT COUNT(*) FROM viewers
+    WHERE game_id = $1 AND kind = $2 AND seen_at > NOW() - make_interval(secs => $3)",
+  )
+  .bind(game_id)
+  .bind(kind)
+  .bind(STALE_SECONDS)
+  .fetch_one(&mut *tx)
+  .await
+  .map_err(handle_pg_error)?;
+  if count >= limit {
+    return Ok(false);
+  }
+  query!(
+    "INSERT INTO viewers (id, game_id, kind) VALUES ($1, $2, $3)",
+    id,
+    game_id,
+    kind
+  )
+  .execute(&mut *tx)
+  .await
+  .map_err(handle_pg_error)?;
+  tx.commit().await.map_err(handle_pg_error)?;
+  Ok(true)
+}
+
+pub async fn delete(db: &PgPool, id: Uuid) -> Result<(), Error> {
+  query!("DELETE FROM viewers WHERE id = $1", id)
+    .execute(db)
+    .await
+    .map_err(handle_pg_error)?;
+  Ok(())
+}
+
+// keep the viewers of this replica fresh, returns the ones that are still there
+pub async fn touch(db: &PgPool, ids: &[Uuid]) -> Result<Vec<Uuid>, Error> {
+  query_scalar!(
+    "UPDATE viewers SET seen_at = NOW() WHERE id = ANY($1) RETURNING id",
+    ids
+  )
+  .fetch_all(db)
+  .await
+  .map_err(handle_pg_error)
+}
+
+// remove every viewer of one kind, e.g. when the share link they came through is gone
+pub async fn delete_kind(db: &PgPool, game_id: Uuid, kind: &str) -> Result<u64, Error> {
+  let res = query!(
+    "DELETE FROM viewers WHERE game_id = $1 AND kind = $2",
+    game_id,
+    kind
+  )
+  .execute(db)
+  .await
+  .map_err(handle_pg_error)?;
+  Ok(res.rows_affected())
+}
+
+// drop the rows replicas left behind when they stopped, returns how many were removed
+pub async fn purge(db: &PgPool) -> Result<u64, Error> {
+  let res = query("DELETE FROM viewers WHERE seen_at < NOW() - make_interval(secs => $1 * 2)")
+    .bind(STALE_SECONDS)
+    .execute(db)
+    .await
+    .map_err(handle_pg_error)?;
+  Ok(res.rows_affected())
+}
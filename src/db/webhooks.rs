@@ -0,0 +1,321 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{prelude::FromRow, query, query_as, PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+use validator::{Validate, ValidationError};
+
+use super::{apply_list_filters, handle_pg_error, Error, ListParams, Page};
+use crate::{crypto::Sealed, validation::webhook_url};
+
+pub const GAME_UPDATED: &str = "game.updated";
+pub const GAME_DELETED: &str = "game.deleted";
+pub const GAME_RESTORED: &str = "game.restored";
+
+// `play` subscribes to every play event, `play.<kind>` to one kind
+const EVENT_TYPES: [&str; 15] = [
+  "play",
+  "play.start",
+  "play.roll",
+  "play.pick",
+  "play.keep",
+  "play.steal",
+  "play.assign",
+  "play.nudge",
+  "play.final_swap",
+  "play.undo",
+  "play.finish",
+  "play.reset",
+  GAME_UPDATED,
+  GAME_DELETED,
+  GAME_RESTORED,
+];
+
+pub fn is_event_type(value: &str) -> bool {
+  EVENT_TYPES.contains(&value)
+}
+
+pub const FORMAT_JSON: &str = "json";
+pub const FORMAT_DISCORD: &str = "discord";
+pub const FORMAT_SLACK: &str = "slack";
+
+// json posts the signed event, discord and slack post a chat message to an incoming webhook
+fn webhook_format(value: &str) -> Result<(), ValidationError> {
+  if [FORMAT_JSON, FORMAT_DISCORD, FORMAT_SLACK].contains(&value) {
+    return Ok(());
+  }
+  let mut err = ValidationError::new("webhook_format");
+  err.add_param("value".into(), &value);
+  Err(err)
+}
+
+fn default_format() -> String {
+  FORMAT_JSON.to_string()
+}
+
+// a delivery is given up on after this many failed attempts
+pub const MAX_ATTEMPTS: i32 = 8;
+
+#[derive(FromRow, Serialize)]
+pub struct Webhook {
+  pub id: i64,
+  pub game_id: Uuid,
+  pub url: String,
+  // empty subscribes to everything
+  pub events: Vec<String>,
+  pub format: String,
+  pub active: bool,
+  pub created_at: DateTime<Utc>,
+  pub updated_at: Option<DateTime<Utc>>,
+}
+
+// list webhooks
+pub async fn list(db: &PgPool, game_id: Uuid, p: ListParams) -> Result<Page<Webhook>, Error> {
+  let mut query = QueryBuilder::<Postgres>::new(
+    "SELECT id, game_id, url, events, format, active, created_at, updated_at, COUNT(*) OVER() AS total_count FROM webhooks WHERE game_id = ",
+  );
+  query.push_bind(game_id);
+
+  query = apply_list_filters(query, &p, vec!["id", "created_at"])?;
+  let rows = query
+    .build_query_as()
+    .fetch_all(db)
+    .await
+    .map_err(Error::Sqlx)?;
+  Ok(Page::new(rows, &p))
+}
+
+// get a webhook
+pub async fn get(db: &PgPool, game_id: Uuid, id: i64) -> Result<Webhook, Error> {
+  query_as(
+    "SELECT id, game_id, url, events, format, active, created_at, updated_at FROM webhooks WHERE id = $1 AND game_id = $2",
+  )
+  .bind(id)
+  .bind(game_id)
+  .fetch_one(db)
+  .await
+  .map_err(handle_pg_error)
+}
+
+#[derive(Deserialize, Validate)]
+pub struct CreateParams {
+  #[validate(custom(function = webhook_url))]
+  pub url: String,
+  #[serde(default)]
+  pub events: Vec<String>,
+  #[serde(default = "default_format")]
+  #[validate(custom(function = webhook_format))]
+  pub format: String,
+}
+
+// create a webhook, the secret is generated by the caller
+pub async fn create(
+  db: &PgPool,
+  game_id: Uuid,
+  secret: &str,
+  p: CreateParams,
+) -> Result<Webhook, Error> {
+  query_as(
+    "INSERT INTO webhooks (game_id, url, secret, events, format) VALUES ($1, $2, $3, $4, $5)
+    RETURNING id, game_id, url, events, format, active, created_at, updated_at",
+  )
+  .bind(game_id)
+  .bind(p.url)
+  .bind(Sealed(secret.to_string()))
+  .bind(p.events)
+  .bind(p.format)
+  .fetch_one(db)
+  .await
+  .map_err(handle_pg_error)
+}
+
+#[derive(Deserialize, Validate)]
+pub struct UpdateParams {
+  #[validate(custom(function = webhook_url))]
+  pub url: Option<String>,
+  pub events: Option<Vec<String>>,
+  #[validate(custom(function = webhook_format))]
+  pub format: Option<String>,
+  pub active: Option<bool>,
+}
+
+// update a webhook
+pub async fn update(
+  db: &PgPool,
+  game_id: Uuid,
+  id: i64,
+  p: UpdateParams,
+) -> Result<Webhook, Error> {
+  let mut query = QueryBuilder::<Postgres>::new("UPDATE webhooks SET");
+  let mut sep = query.separated(", ");
+  if let Some(url) = p.url {
+    sep.push(" url = ").push_bind_unseparated(url);
+  }
+  if let Some(events) = p.events {
+    sep.push(" events = ").push_bind_unseparated(events);
+  }
+  if let Some(format) = p.format {
+    sep.push(" format = ").push_bind_unseparated(format);
+  }
+  if let Some(active) = p.active {
+    sep.push(" active = ").push_bind_unseparated(active);
+  }
+  sep.push(" updated_at = NOW()");
+  query.push(" WHERE id = ").push_bind(id);
+  query.push(" AND game_id = ").push_bind(game_id);
+  query.push(" RETURNING id, game_id, url, events, format, active, created_at, updated_at");
+  query
+    .build_query_as()
+    .fetch_one(db)
+    .await
+    .map_err(handle_pg_error)
+}
+
+// delete a webhook and its delivery log
+pub async fn delete(db: &PgPool, game_id: Uuid, id: i64) -> Result<(), Error> {
+  let res = query!(
+    "DELETE FROM webhooks WHERE id = $1 AND game_id = $2",
+    id,
+    game_id
+  )
+  .execute(db)
+  .await
+  .map_err(handle_pg_error)?;
+  match res.rows_affected() {
+    0 => Err(Error::NotFound),
+    _ => Ok(()),
+  }
+}
+
+// queue a game lifecycle event for the webhooks subscribed to it,
+// play events are queued by a trigger on play_events
+pub async fn enqueue(db: &PgPool, game_id: Uuid, event: &str, payload: Value) -> Result<(), Error> {
+  query!(
+    "INSERT INTO webhook_deliveries (webhook_id, event, payload)
+    SELECT id, $2, $3 FROM webhooks
+    WHERE game_id = $1 AND active AND (events = '{}' OR $2 = ANY(events))",
+    game_id,
+    event,
+    payload
+  )
+  .execute(db)
+  .await
+  .map_err(handle_pg_error)?;
+  Ok(())
+}
+
+#[derive(FromRow, Serialize)]
+pub struct Delivery {
+  pub id: i64,
+  pub webhook_id: i64,
+  pub event: String,
+  pub payload: Value,
+  pub attempts: i32,
+  pub status: Option<i16>,
+  pub error: Option<String>,
+  pub next_attempt_at: Option<DateTime<Utc>>,
+  pub delivered_at: Option<DateTime<Utc>>,
+  pub created_at: DateTime<Utc>,
+}
+
+// the delivery log of a webhook, newest first unless ordered otherwise
+pub async fn list_deliveries(
+  db: &PgPool,
+  game_id: Uuid,
+  webhook_id: i64,
+  mut p: ListParams,
+) -> Result<Page<Delivery>, Error> {
+  if p.order.is_none() && p.after_id.is_none() {
+    p.order = Some("-id".to_string());
+  }
+  let mut query = QueryBuilder::<Postgres>::new(
+    "SELECT id, webhook_id, event, payload, attempts, status, error, next_attempt_at, delivered_at, created_at, COUNT(*) OVER() AS total_count
+    FROM webhook_deliveries WHERE webhook_id = (SELECT id FROM webhooks WHERE id = ",
+  );
+  query.push_bind(webhook_id);
+  query.push(" AND game_id = ").push_bind(game_id);
+  query.push(")");
+
+  query = apply_list_filters(query, &p, vec!["id", "created_at"])?;
+  let rows = query
+    .build_query_as()
+    .fetch_all(db)
+    .await
+    .map_err(Error::Sqlx)?;
+  Ok(Page::new(rows, &p))
+}
+
+// a delivery leased to this instance for sending
+#[derive(FromRow)]
+pub struct DueDelivery {
+  pub id: i64,
+  pub game_id: Uuid,
+  pub url: String,
+  pub secret: Sealed,
+  pub format: String,
+  pub event: String,
+  pub payload: Value,
+  pub attempts: i32,
+  pub created_at: DateTime<Utc>,
+}
+
+// lease due deliveries for a minute, other instances skip them meanwhile
+pub async fn claim_due(db: &PgPool, limit: i64) -> Result<Vec<DueDelivery>, Error> {
+  query_as(
+    "WITH due AS (
+      SELECT id FROM webhook_deliveries
+      WHERE next_attempt_at <= NOW()
+      ORDER BY next_attempt_at
+      LIMIT $1
+      FOR UPDATE SKIP LOCKED
+    )
+    UPDATE webhook_deliveries d SET next_attempt_at = NOW() + INTERVAL '1 minute'
+    FROM due, webhooks w
+    WHERE d.id = due.id AND w.id = d.webhook_id
+    RETURNING d.id, w.game_id, w.url, w.secret, w.format, d.event, d.payload, d.attempts, d.created_at",
+  )
+  .bind(limit)
+  .fetch_all(db)
+  .await
+  .map_err(handle_pg_error)
+}
+
+// record an attempt, `retry_in` seconds schedules the next one, None finishes the delivery
+pub async fn record_attempt(
+  db: &PgPool,
+  id: i64,
+  status: Option<i16>,
+  error: Option<&str>,
+  delivered: bool,
+  retry_in: Option<i64>,
+) -> Result<(), Error> {
+  query!(
+    "UPDATE webhook_deliveries SET
+      attempts = attempts + 1,
+      status = $2,
+      error = $3,
+      delivered_at = CASE WHEN $4 THEN NOW() END,
+      next_attempt_at = NOW() + make_interval(secs => $5)
+    WHERE id = $1",
+    id,
+    status,
+    error,
+    delivered,
+    retry_in.map(|secs| secs as f64)
+  )
+  .execute(db)
+  .await
+  .map_err(handle_pg_error)?;
+  Ok(())
+}
+
+// drop deliveries older than 30 days, returns how many were removed
+pub async fn purge(db: &PgPool) -> Result<u64, Error> {
+  let res = query!(
+    "DELETE FROM webhook_deliveries WHERE created_at < NOW() - INTERVAL '30 days' AND next_attempt_at IS NULL"
+  )
+  .execute(db)
+  .await
+  .map_err(handle_pg_error)?;
+  Ok(res.rows_affected())
+}
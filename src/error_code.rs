@@ -0,0 +1,86 @@
+use axum::http::StatusCode;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+// stable identifiers sent as `code` in every error body, clients should match on these
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+  // generic, derived from the http status
+  BadRequest,
+  InvalidBody,
+  PayloadTooLarge,
+  MethodNotAllowed,
+  PermissionDenied,
+  NotFound,
+  InternalError,
+  // auth
+  MissingToken,
+  Unauthorized,
+  // request parameters
+  ValidationFailed,
+  EmptyUpdate,
+  InvalidOrder,
+  CursorUnsupported,
+  InvalidTheme,
+  InvalidRules,
+  InvalidWebhookEvent,
+  OverBudget,
+  CurrencyMismatch,
+  ReasonRequired,
+  ConfirmationRequired,
+  ConfirmationMismatch,
+  IfMatchRequired,
+  VersionMismatch,
+  // game play
+  GameAlreadyStarted,
+  StealingDisabled,
+  PresentImmune,
+  StealLimitReached,
+  StealBackForbidden,
+  GuessingDisabled,
+  GuessingClosed,
+  NotOwnPlayer,
+  NoActivePlayer,
+  PlayerNotIdle,
+  AlreadyNudged,
+  StateChanged,
+  NothingToUndo,
+  PlayerHasPresent,
+  // retries
+  IdempotencyKeyInProgress,
+  IdempotencyKeyReused,
+  // limits and availability
+  ViewerLimitReached,
+  PlayThrottled,
+  QuotaExceeded,
+  Maintenance,
+  DatabaseUnavailable,
+  NotReady,
+  GameNotScheduled,
+  InvalidInvite,
+  InviteExpired,
+  AlreadyMember,
+  LastOwner,
+  ActiveGameLimit,
+}
+
+impl ErrorCode {
+  // fallback for responses that were built from a bare status
+  pub fn from_status(status: StatusCode) -> Self {
+    match status {
+      StatusCode::UNAUTHORIZED => ErrorCode::Unauthorized,
+      StatusCode::FORBIDDEN => ErrorCode::PermissionDenied,
+      StatusCode::NOT_FOUND => ErrorCode::NotFound,
+      StatusCode::METHOD_NOT_ALLOWED => ErrorCode::MethodNotAllowed,
+      StatusCode::UNSUPPORTED_MEDIA_TYPE | StatusCode::UNPROCESSABLE_ENTITY => {
+        ErrorCode::InvalidBody
+      }
+      StatusCode::PAYLOAD_TOO_LARGE => ErrorCode::PayloadTooLarge,
+      StatusCode::TOO_MANY_REQUESTS => ErrorCode::QuotaExceeded,
+      StatusCode::SERVICE_UNAVAILABLE => ErrorCode::Maintenance,
+      s if s.is_server_error() => ErrorCode::InternalError,
+      _ => ErrorCode::BadRequest,
+    }
+  }
+}
@@ -0,0 +1,328 @@
+use std::{cmp::Ordering, collections::HashMap, convert::Infallible};
+
+use axum::{
+  async_trait,
+  body::Body,
+  extract::{FromRequestParts, Request},
+  http::{header, request::Parts, HeaderMap, HeaderValue},
+  middleware::Next,
+  response::Response,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{api::ApiError, error_code::ErrorCode};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Locale {
+  #[default]
+  En,
+  Nl,
+  De,
+}
+
+impl Locale {
+  pub fn tag(&self) -> &'static str {
+    match self {
+      Locale::En => "en",
+      Locale::Nl => "nl",
+      Locale::De => "de",
+    }
+  }
+
+  pub fn from_tag(tag: &str) -> Option<Self> {
+    let primary = tag.split('-').next().unwrap_or_default();
+    match primary.to_ascii_lowercase().as_str() {
+      "en" => Some(Locale::En),
+      "nl" => Some(Locale::Nl),
+      "de" => Some(Locale::De),
+      _ => None,
+    }
+  }
+
+  // pick the best supported locale from an Accept-Language header, e.g. "nl-NL,nl;q=0.9,en;q=0.8"
+  pub fn from_accept_language(header: &str) -> Self {
+    let mut langs: Vec<(f32, &str)> = header
+      .split(',')
+      .filter_map(|part| {
+        let mut it = part.trim().split(';');
+        let tag = it.next()?.trim();
+        let q = it
+          .find_map(|p| p.trim().strip_prefix("q="))
+          .and_then(|q| q.parse().ok())
+          .unwrap_or(1.0);
+        Some((q, tag))
+      })
+      .collect();
+    langs.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+    langs
+      .into_iter()
+      .filter(|(q, _)| *q > 0.0)
+      .find_map(|(_, tag)| Self::from_tag(tag))
+      .unwrap_or_default()
+  }
+}
+
+fn request_locale(headers: &HeaderMap) -> Locale {
+  headers
+    .get(header::ACCEPT_LANGUAGE)
+    .and_then(|v| v.to_str().ok())
+    .map(Locale::from_accept_language)
+    .unwrap_or_default()
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Locale
+where
+  S: Send + Sync,
+{
+  type Rejection = Infallible;
+
+  async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    Ok(request_locale(&parts.headers))
+  }
+}
+
+// a name/description variant of user content for one locale
+#[derive(Serialize, Deserialize, Clone, Debug, Default, ToSchema)]
+#[serde(default)]
+pub struct LocalizedText {
+  pub name: Option<String>,
+  pub description: Option<String>,
+}
+
+// variants keyed by language tag, e.g. {"nl": {"name": "Sokken"}}
+pub type Translations = HashMap<String, LocalizedText>;
+
+// replace name and description with the variants for the locale, when there are any
+pub fn resolve(
+  translations: &Translations,
+  locale: Locale,
+  name: &mut String,
+  description: &mut Option<String>,
+) {
+  let Some(text) = translations.get(locale.tag()) else {
+    return;
+  };
+  if let Some(n) = &text.name {
+    name.clone_from(n);
+  }
+  if text.description.is_some() {
+    description.clone_from(&text.description);
+  }
+}
+
+// translated message for an error code, English falls back to the original message
+pub fn message(code: ErrorCode, locale: Locale) -> Option<&'static str> {
+  match (locale, code) {
+    (Locale::Nl, ErrorCode::BadRequest) => Some("Ongeldig verzoek"),
+    (Locale::Nl, ErrorCode::InvalidBody) => Some("De inhoud van het verzoek is ongeldig"),
+    (Locale::Nl, ErrorCode::MethodNotAllowed) => Some("Deze methode is niet toegestaan"),
+    (Locale::Nl, ErrorCode::PermissionDenied) => Some("Je hebt geen toegang tot dit spel"),
+    (Locale::Nl, ErrorCode::NotFound) => Some("Niet gevonden"),
+    (Locale::Nl, ErrorCode::EmptyUpdate) => Some("Er zijn geen velden om bij te werken"),
+    (Locale::Nl, ErrorCode::InvalidOrder) => Some("Ongeldige sorteerparameter"),
+    (Locale::Nl, ErrorCode::CursorUnsupported) => Some("Deze lijst ondersteunt geen cursor"),
+    (Locale::Nl, ErrorCode::MissingToken) => Some("Authorization-header ontbreekt of is ongeldig"),
+    (Locale::Nl, ErrorCode::Unauthorized) => Some("Niet geautoriseerd"),
+    (Locale::Nl, ErrorCode::InternalError) => Some("Er is een interne fout opgetreden"),
+    (Locale::Nl, ErrorCode::Maintenance) => Some("Onderhoud bezig, probeer het later opnieuw"),
+    (Locale::Nl, ErrorCode::GameAlreadyStarted) => Some("Het spel is al begonnen"),
+    (Locale::Nl, ErrorCode::StealingDisabled) => Some("Stelen is uitgeschakeld voor dit spel"),
+    (Locale::Nl, ErrorCode::PresentImmune) => {
+      Some("Dit cadeau is net gestolen en kan deze beurt niet worden gestolen")
+    }
+    (Locale::Nl, ErrorCode::StealLimitReached) => {
+      Some("Dit cadeau is al te vaak gestolen en blijft waar het is")
+    }
+    (Locale::Nl, ErrorCode::StealBackForbidden) => {
+      Some("Je kunt een cadeau dat net van je is gestolen niet direct terugstelen")
+    }
+    (Locale::Nl, ErrorCode::GuessingDisabled) => {
+      Some("Prijzen raden is uitgeschakeld voor dit spel")
+    }
+    (Locale::Nl, ErrorCode::GuessingClosed) => {
+      Some("Raden kan niet meer, dit cadeau is al uitgepakt")
+    }
+    (Locale::Nl, ErrorCode::NotOwnPlayer) => Some("Je kunt alleen voor jezelf raden"),
+    (Locale::Nl, ErrorCode::ReasonRequired) => {
+      Some("Geef een speler en een reden op om een cadeau toe te wijzen")
+    }
+    (Locale::Nl, ErrorCode::ViewerLimitReached) => {
+      Some("Dit spel heeft het maximale aantal kijkers bereikt")
+    }
+    (Locale::Nl, ErrorCode::InvalidTheme) => Some("Ongeldige waarde in het thema"),
+    (Locale::Nl, ErrorCode::InvalidRules) => Some("Ongeldige waarde in de spelregels"),
+    (Locale::Nl, ErrorCode::InvalidWebhookEvent) => Some("Onbekend webhook-event"),
+    (Locale::Nl, ErrorCode::OverBudget) => Some("De prijs is hoger dan het budget van het spel"),
+    (Locale::Nl, ErrorCode::CurrencyMismatch) => {
+      Some("De prijs moet in de valuta van het spel zijn")
+    }
+    (Locale::Nl, ErrorCode::NoActivePlayer) => Some("Er is geen speler aan de beurt"),
+    (Locale::Nl, ErrorCode::PlayerNotIdle) => Some("Geef de speler nog even de tijd"),
+    (Locale::Nl, ErrorCode::AlreadyNudged) => Some("Deze speler is deze beurt al aangespoord"),
+    (Locale::Nl, ErrorCode::PlayThrottled) => Some("Rustig aan, probeer het zo meteen opnieuw"),
+    (Locale::Nl, ErrorCode::StateChanged) => {
+      Some("Het spel is intussen veranderd, vernieuw en probeer opnieuw")
+    }
+    (Locale::Nl, ErrorCode::NothingToUndo) => Some("Er is geen zet om ongedaan te maken"),
+    (Locale::Nl, ErrorCode::PlayerHasPresent) => Some("Deze speler heeft al een cadeau"),
+    (Locale::Nl, ErrorCode::NotReady) => Some("De server is nog niet klaar"),
+    (Locale::Nl, ErrorCode::GameNotScheduled) => Some("Het spel heeft nog geen datum"),
+    (Locale::Nl, ErrorCode::InvalidInvite) => Some("De uitnodiging is ongeldig of ingetrokken"),
+    (Locale::Nl, ErrorCode::InviteExpired) => Some("De uitnodiging is verlopen"),
+    (Locale::Nl, ErrorCode::AlreadyMember) => Some("Deze gebruiker doet al mee aan het spel"),
+    (Locale::Nl, ErrorCode::LastOwner) => Some("Een spel moet minstens één eigenaar houden"),
+    (Locale::Nl, ErrorCode::ActiveGameLimit) => {
+      Some("Je hebt het maximale aantal lopende spellen bereikt")
+    }
+    (Locale::Nl, ErrorCode::DatabaseUnavailable) => {
+      Some("De database is overbelast, probeer het zo opnieuw")
+    }
+    (Locale::Nl, ErrorCode::ConfirmationRequired) => {
+      Some("Herhaal de naam van het spel in het confirm-veld om door te gaan")
+    }
+    (Locale::Nl, ErrorCode::ConfirmationMismatch) => {
+      Some("Het confirm-veld komt niet overeen met de naam van het spel")
+    }
+    (Locale::Nl, ErrorCode::IfMatchRequired) => {
+      Some("Stuur de ETag van het spel mee in de If-Match-header")
+    }
+    (Locale::Nl, ErrorCode::VersionMismatch) => {
+      Some("Het spel is intussen door iemand anders gewijzigd, vernieuw en probeer opnieuw")
+    }
+    (Locale::Nl, ErrorCode::IdempotencyKeyInProgress) => {
+      Some("Een verzoek met deze Idempotency-Key is nog bezig")
+    }
+    (Locale::Nl, ErrorCode::IdempotencyKeyReused) => {
+      Some("Deze Idempotency-Key is al voor een ander verzoek gebruikt")
+    }
+    (Locale::Nl, ErrorCode::PayloadTooLarge) => Some("Het verzoek is te groot"),
+    (Locale::Nl, ErrorCode::ValidationFailed) => Some("Sommige velden zijn ongeldig"),
+    (Locale::Nl, ErrorCode::QuotaExceeded) => {
+      Some("Te veel verzoeken voor dit spel, probeer het zo opnieuw")
+    }
+    (Locale::De, ErrorCode::BadRequest) => Some("Ungültige Anfrage"),
+    (Locale::De, ErrorCode::InvalidBody) => Some("Der Inhalt der Anfrage ist ungültig"),
+    (Locale::De, ErrorCode::MethodNotAllowed) => Some("Diese Methode ist nicht erlaubt"),
+    (Locale::De, ErrorCode::PermissionDenied) => Some("Du hast keinen Zugriff auf dieses Spiel"),
+    (Locale::De, ErrorCode::NotFound) => Some("Nicht gefunden"),
+    (Locale::De, ErrorCode::EmptyUpdate) => Some("Keine Felder zum Aktualisieren angegeben"),
+    (Locale::De, ErrorCode::InvalidOrder) => Some("Ungültiger Sortierparameter"),
+    (Locale::De, ErrorCode::CursorUnsupported) => Some("Diese Liste unterstützt keinen Cursor"),
+    (Locale::De, ErrorCode::MissingToken) => Some("Authorization-Header fehlt oder ist ungültig"),
+    (Locale::De, ErrorCode::Unauthorized) => Some("Nicht autorisiert"),
+    (Locale::De, ErrorCode::InternalError) => Some("Ein interner Fehler ist aufgetreten"),
+    (Locale::De, ErrorCode::Maintenance) => Some("Wartungsarbeiten, bitte später erneut versuchen"),
+    (Locale::De, ErrorCode::GameAlreadyStarted) => Some("Das Spiel hat bereits begonnen"),
+    (Locale::De, ErrorCode::StealingDisabled) => Some("Stehlen ist in diesem Spiel deaktiviert"),
+    (Locale::De, ErrorCode::PresentImmune) => {
+      Some("Dieses Geschenk wurde gerade gestohlen und ist diese Runde geschützt")
+    }
+    (Locale::De, ErrorCode::StealLimitReached) => {
+      Some("Dieses Geschenk wurde schon zu oft gestohlen und bleibt, wo es ist")
+    }
+    (Locale::De, ErrorCode::StealBackForbidden) => {
+      Some("Ein gerade gestohlenes Geschenk kann nicht sofort zurückgestohlen werden")
+    }
+    (Locale::De, ErrorCode::GuessingDisabled) => Some("Preisraten ist in diesem Spiel deaktiviert"),
+    (Locale::De, ErrorCode::GuessingClosed) => {
+      Some("Raten ist nicht mehr möglich, das Geschenk ist bereits ausgepackt")
+    }
+    (Locale::De, ErrorCode::NotOwnPlayer) => Some("Du kannst nur für dich selbst raten"),
+    (Locale::De, ErrorCode::ReasonRequired) => {
+      Some("Zum Zuweisen eines Geschenks sind ein Spieler und ein Grund nötig")
+    }
+    (Locale::De, ErrorCode::ViewerLimitReached) => {
+      Some("Dieses Spiel hat die maximale Zuschauerzahl erreicht")
+    }
+    (Locale::De, ErrorCode::InvalidTheme) => Some("Ungültiger Wert im Design"),
+    (Locale::De, ErrorCode::InvalidRules) => Some("Ungültiger Wert in den Spielregeln"),
+    (Locale::De, ErrorCode::InvalidWebhookEvent) => Some("Unbekanntes Webhook-Ereignis"),
+    (Locale::De, ErrorCode::OverBudget) => Some("Der Preis liegt über dem Budget des Spiels"),
+    (Locale::De, ErrorCode::CurrencyMismatch) => {
+      Some("Der Preis muss in der Währung des Spiels angegeben werden")
+    }
+    (Locale::De, ErrorCode::NoActivePlayer) => Some("Kein Spieler ist am Zug"),
+    (Locale::De, ErrorCode::PlayerNotIdle) => Some("Gib dem Spieler noch etwas Zeit"),
+    (Locale::De, ErrorCode::AlreadyNudged) => {
+      Some("Dieser Spieler wurde in diesem Zug schon angestupst")
+    }
+    (Locale::De, ErrorCode::PlayThrottled) => {
+      Some("Nicht so schnell, versuch es gleich noch einmal")
+    }
+    (Locale::De, ErrorCode::StateChanged) => {
+      Some("Das Spiel hat sich inzwischen geändert, bitte neu laden und erneut versuchen")
+    }
+    (Locale::De, ErrorCode::NothingToUndo) => Some("Es gibt keinen Zug zum Rückgängigmachen"),
+    (Locale::De, ErrorCode::PlayerHasPresent) => Some("Dieser Spieler hat schon ein Geschenk"),
+    (Locale::De, ErrorCode::NotReady) => Some("Der Server ist noch nicht bereit"),
+    (Locale::De, ErrorCode::GameNotScheduled) => Some("Das Spiel hat noch keinen Termin"),
+    (Locale::De, ErrorCode::InvalidInvite) => {
+      Some("Die Einladung ist ungültig oder wurde widerrufen")
+    }
+    (Locale::De, ErrorCode::InviteExpired) => Some("Die Einladung ist abgelaufen"),
+    (Locale::De, ErrorCode::AlreadyMember) => Some("Dieser Benutzer ist bereits im Spiel"),
+    (Locale::De, ErrorCode::LastOwner) => Some("Ein Spiel muss mindestens einen Besitzer behalten"),
+    (Locale::De, ErrorCode::ActiveGameLimit) => {
+      Some("Du hast die maximale Anzahl laufender Spiele erreicht")
+    }
+    (Locale::De, ErrorCode::DatabaseUnavailable) => {
+      Some("Die Datenbank ist überlastet, bitte gleich erneut versuchen")
+    }
+    (Locale::De, ErrorCode::ConfirmationRequired) => {
+      Some("Wiederhole den Namen des Spiels im Feld confirm, um fortzufahren")
+    }
+    (Locale::De, ErrorCode::ConfirmationMismatch) => {
+      Some("Das Feld confirm stimmt nicht mit dem Namen des Spiels überein")
+    }
+    (Locale::De, ErrorCode::IfMatchRequired) => {
+      Some("Sende das ETag des Spiels im If-Match-Header mit")
+    }
+    (Locale::De, ErrorCode::VersionMismatch) => {
+      Some("Das Spiel wurde inzwischen von jemand anderem geändert, bitte neu laden")
+    }
+    (Locale::De, ErrorCode::IdempotencyKeyInProgress) => {
+      Some("Eine Anfrage mit diesem Idempotency-Key läuft noch")
+    }
+    (Locale::De, ErrorCode::IdempotencyKeyReused) => {
+      Some("Dieser Idempotency-Key wurde bereits für eine andere Anfrage verwendet")
+    }
+    (Locale::De, ErrorCode::PayloadTooLarge) => Some("Die Anfrage ist zu groß"),
+    (Locale::De, ErrorCode::ValidationFailed) => Some("Einige Felder sind ungültig"),
+    (Locale::De, ErrorCode::QuotaExceeded) => {
+      Some("Zu viele Anfragen für dieses Spiel, bitte kurz warten")
+    }
+    _ => None,
+  }
+}
+
+// rewrite error bodies in the language requested by the client
+pub async fn localize(req: Request, next: Next) -> Response {
+  let locale = request_locale(req.headers());
+  let res = next.run(req).await;
+  if locale == Locale::En {
+    return res;
+  }
+  let Some(err) = res.extensions().get::<ApiError>().cloned() else {
+    return res;
+  };
+  let Some(message) = message(err.code, locale) else {
+    return res;
+  };
+
+  let err = ApiError {
+    message: message.to_string(),
+    ..err
+  };
+  let body = serde_json::to_vec(&err).unwrap_or_default();
+  let (mut parts, _) = res.into_parts();
+  // outer layers rewriting the body start from the localized error
+  parts.extensions.insert(err);
+  parts.headers.remove(header::CONTENT_LENGTH);
+  parts.headers.insert(
+    header::CONTENT_LANGUAGE,
+    HeaderValue::from_static(locale.tag()),
+  );
+  Response::from_parts(parts, Body::from(body))
+}
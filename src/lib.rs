@@ -0,0 +1,16 @@
+use sqlx::migrate::Migrator;
+
+pub mod api;
+pub mod auth;
+pub mod commands;
+pub mod config;
+pub mod crypto;
+pub mod db;
+pub mod error_code;
+pub mod i18n;
+pub mod notify;
+pub mod rules;
+pub mod theme;
+pub mod validation;
+
+pub static MIGRATOR: Migrator = sqlx::migrate!();
@@ -1,37 +1,78 @@
-use std::{env, fs::File, path::Path, str::FromStr};
+use std::{env, fs::File, net::SocketAddr, path::Path, str::FromStr, sync::Arc};
+
+use axum::{body::Body, middleware};
+use axum_server::tls_rustls::RustlsConfig;
 
 use firebase_auth::FirebaseAuth;
-use sqlx::migrate::Migrator;
-use sqlx::postgres::PgListener;
-use tower_http::{
-  cors::{Any, CorsLayer},
-  trace::{DefaultMakeSpan, DefaultOnRequest, DefaultOnResponse, TraceLayer},
-};
+use sqlx::postgres::PgPoolOptions;
+use tower_http::trace::{DefaultOnRequest, DefaultOnResponse, TraceLayer};
 use tracing::{level_filters::LevelFilter, Level};
 use tracing_subscriber::{
-  prelude::__tracing_subscriber_SubscriberExt, util::SubscriberInitExt, Layer,
+  filter::filter_fn, prelude::__tracing_subscriber_SubscriberExt, util::SubscriberInitExt, Layer,
 };
 
-use crate::{
-  auth::{user::UserService, MyFirebaseUser, ServiceAccount},
-  db::games::{start_listening, PlayEvent},
+use evil_santa::{
+  api,
+  api::{
+    client_ip::{self, ClientIp, TrustedProxies},
+    debug::SqlCapture,
+    health::Readiness,
+    request_id::{self, RequestId},
+  },
+  auth::{oidc::OidcProvider, user::UserService, AuthProvider, MyFirebaseUser, ServiceAccount},
+  commands,
+  config::{AuthConfig, Config},
+  crypto,
+  db::{
+    self,
+    games::{self, start_listening, Streams},
+  },
+  notify, MIGRATOR,
 };
-use tokio::sync::broadcast::channel;
-
-mod api;
-mod auth;
-mod db;
-
-static MIGRATOR: Migrator = sqlx::migrate!();
 
 #[tokio::main]
 async fn main() {
   println!("{}", option_env!("RELEASE_VERSION").unwrap_or("v0.0.0-dev"));
 
-  run().await;
+  let args: Vec<String> = env::args().skip(1).collect();
+  let result = match args.first().map(String::as_str) {
+    Some("backup") => {
+      init_cli_logging();
+      commands::backup(&args[1..]).await
+    }
+    Some("restore") => {
+      init_cli_logging();
+      commands::restore(&args[1..]).await
+    }
+    Some("rotate-keys") => {
+      init_cli_logging();
+      commands::rotate_keys().await
+    }
+    _ => {
+      run().await;
+      Ok(())
+    }
+  };
+  if let Err(err) = result {
+    tracing::error!("{:#}", err);
+    std::process::exit(1);
+  }
+}
+
+fn init_cli_logging() {
+  tracing_subscriber::registry()
+    .with(
+      tracing_subscriber::fmt::layer()
+        .compact()
+        .without_time()
+        .with_target(false)
+        .with_filter(LevelFilter::INFO),
+    )
+    .init();
 }
 
 async fn run<'a>() {
+  let config = Config::from_env();
   let log_level = LevelFilter::from_str(&env::var("LOG_LEVEL").unwrap_or(String::from("info")))
     .unwrap_or(LevelFilter::INFO);
   tracing_subscriber::registry()
@@ -44,8 +85,23 @@ async fn run<'a>() {
         .with_target(false)
         .with_filter(log_level),
     )
+    .with(
+      config
+        .debug_responses
+        .then(|| SqlCapture.with_filter(filter_fn(|meta| meta.target() == "sqlx::query"))),
+    )
     .init();
   tracing::info!("Log level: {}", log_level);
+  crypto::init(&config.encryption_keys, &config.blind_index_key).expect("Invalid ENCRYPTION_KEYS");
+  db::set_max_limit(config.max_list_limit);
+  if config.encryption_keys.is_empty() {
+    tracing::warn!("ENCRYPTION_KEYS is empty, sensitive columns are stored unencrypted");
+  } else if config.blind_index_key.is_empty() {
+    tracing::warn!("BLIND_INDEX_KEY is empty, sealed emails can be found by hashing guesses");
+  }
+  if config.debug_responses {
+    tracing::warn!("Debug responses enabled, do not use in production!");
+  }
 
   tracing::info!("Initialising Firebase client...");
   let sa_path = env::var("FIREBASE_SERVICE_ACCOUNT_PATH")
@@ -53,7 +109,21 @@ async fn run<'a>() {
   let sa_reader = File::open(Path::new(&sa_path)).expect(&format!("Error opening {}", sa_path));
   let firebase_sa: ServiceAccount =
     serde_json::from_reader(sa_reader).expect(&format!("Error reading {}", sa_path));
-  let firebase_auth = FirebaseAuth::<MyFirebaseUser>::new(&firebase_sa.project_id).await;
+  let readiness = Readiness::default();
+  let auth_provider: Arc<dyn AuthProvider> = match &config.auth {
+    AuthConfig::Firebase => {
+      Arc::new(FirebaseAuth::<MyFirebaseUser>::new(&firebase_sa.project_id).await)
+    }
+    AuthConfig::Oidc(oidc) => {
+      tracing::info!("Verifying tokens issued by {}", oidc.issuer);
+      Arc::new(
+        OidcProvider::discover(oidc)
+          .await
+          .expect("Error loading the OIDC issuer's keys"),
+      )
+    }
+  };
+  readiness.set_firebase(true);
   let claims_service = UserService::new(
     &env::var("FIREBASE_API_KEY").expect("FIREBASE_API_KEY is missing from env"),
     firebase_sa,
@@ -61,44 +131,106 @@ async fn run<'a>() {
 
   tracing::info!("Preparing DB connection...");
   let db_url = &env::var("DATABASE_URL").expect("DATABASE_URL is missing from env");
-  let sqlx_pool = sqlx::PgPool::connect(db_url).await.unwrap();
+  let sqlx_pool = PgPoolOptions::new()
+    .min_connections(config.db_min_connections)
+    .max_connections(config.db_max_connections.max(config.db_min_connections))
+    .connect(db_url)
+    .await
+    .unwrap();
   MIGRATOR.run(&sqlx_pool).await.unwrap();
-  let listener = PgListener::connect_with(&sqlx_pool).await.unwrap();
-  let (tx, _rx) = channel::<PlayEvent>(10);
+  match db::email_invites::index_emails(&sqlx_pool).await {
+    Ok(0) => {}
+    Ok(indexed) => tracing::info!("Sealed the emails of {} invites", indexed),
+    Err(err) => tracing::warn!("Sealing invite emails failed: {}", err),
+  }
+  if let Err(err) = games::warmup(&sqlx_pool, config.db_min_connections).await {
+    tracing::warn!("DB warmup failed: {}", err);
+  }
+  api::turn_timer::resume(&sqlx_pool).await;
+  api::idempotency::purge_hourly(sqlx_pool.clone());
+  api::games::purge_deleted_hourly(sqlx_pool.clone());
+  api::webhooks::dispatch(sqlx_pool.clone());
+  api::webhooks::purge_hourly(sqlx_pool.clone());
+  notify::purge_hourly(sqlx_pool.clone());
+  match config.email.clone() {
+    Some(email) => notify::dispatch(sqlx_pool.clone(), claims_service.clone(), email),
+    None => tracing::warn!("SENDGRID_API_KEY is not set, emails are disabled"),
+  }
+  let listener_pool = sqlx_pool.clone();
+  let streams = Streams::new(config.play_channel_capacity);
 
   tracing::info!("Crating service...");
-  let server = api::Server::new(sqlx_pool, firebase_auth, claims_service, tx.clone());
+  let trusted_proxies = TrustedProxies::new(config.trusted_proxies.clone());
+  let cors = config.cors.layer();
+  let tls = config.tls.clone();
+  let server = api::Server::new(
+    sqlx_pool,
+    auth_provider,
+    claims_service,
+    streams.clone(),
+    readiness.clone(),
+    config,
+  );
 
   tracing::info!("Spawning PG => SSE worker...");
-  tokio::spawn(async move {
-    match start_listening(listener, &tx).await {
-      Ok(()) => {
-        tracing::info!("PG Listener ok")
-      }
-      Err(err) => {
-        tracing::error!("Error listening to PG: {}", err.to_string())
-      }
-    };
-  });
+  tokio::spawn(start_listening(listener_pool, streams, readiness));
 
   tracing::info!("Starting service...");
-  let cors = CorsLayer::new()
-    .allow_methods(Any)
-    .allow_origin(Any)
-    .allow_headers(Any);
   let trace = TraceLayer::new_for_http()
-    .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
+    .make_span_with(|req: &http::Request<Body>| {
+      let client_ip = req
+        .extensions()
+        .get::<ClientIp>()
+        .map(|ip| ip.0.to_string())
+        .unwrap_or_default();
+      let request_id = req
+        .extensions()
+        .get::<RequestId>()
+        .map(|id| id.0.clone())
+        .unwrap_or_default();
+      tracing::info_span!(
+        "request",
+        method = %req.method(),
+        uri = %req.uri(),
+        version = ?req.version(),
+        client_ip,
+        request_id,
+      )
+    })
     .on_request(DefaultOnRequest::new().level(Level::INFO))
     .on_response(DefaultOnResponse::new().level(Level::INFO));
-  let layers = tower::ServiceBuilder::new().layer(trace).layer(cors);
+  let layers = tower::ServiceBuilder::new()
+    .layer(middleware::from_fn_with_state(
+      trusted_proxies,
+      client_ip::resolve,
+    ))
+    .layer(middleware::from_fn(request_id::assign))
+    .layer(trace)
+    .layer(cors);
   let addr = format!(
     "{}:{}",
     env::var("HOST").unwrap_or(String::from("localhost")),
     env::var("PORT").unwrap_or(String::from("3000"))
   );
-  tracing::info!("🚀 Listening on http://{}", &addr);
-  let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-  axum::serve(listener, server.router.layer(layers).into_make_service())
-    .await
-    .unwrap();
+  let app = server
+    .router
+    .layer(layers)
+    .into_make_service_with_connect_info::<SocketAddr>();
+  let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+  match tls {
+    Some(tls) => {
+      let rustls = RustlsConfig::from_pem_file(&tls.cert_path, &tls.key_path)
+        .await
+        .unwrap_or_else(|err| panic!("Error loading {}: {}", tls.cert_path, err));
+      tracing::info!("🚀 Listening on https://{}", &addr);
+      axum_server::from_tcp_rustls(listener.into_std().unwrap(), rustls)
+        .serve(app)
+        .await
+        .unwrap();
+    }
+    None => {
+      tracing::info!("🚀 Listening on http://{}", &addr);
+      axum::serve(listener, app).await.unwrap();
+    }
+  }
 }
@@ -0,0 +1,196 @@
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+  auth::user::UserService,
+  config::EmailConfig,
+  db::{
+    self, games,
+    notifications::{self, DueEmail, FINISHED, INVITED, MAX_ATTEMPTS, STARTED},
+    recaps,
+  },
+};
+
+const SENDGRID_URL: &str = "https://api.sendgrid.com/v3/mail/send";
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+const BATCH_SIZE: i64 = 20;
+
+struct Email {
+  subject: String,
+  text: String,
+}
+
+struct Mailer {
+  client: reqwest::Client,
+  config: EmailConfig,
+}
+
+impl Mailer {
+  async fn send(&self, to: &str, email: Email) -> Result<()> {
+    let res = self
+      .client
+      .post(SENDGRID_URL)
+      .bearer_auth(&self.config.sendgrid_api_key)
+      .json(&json!({
+        "personalizations": [{ "to": [{ "email": to }] }],
+        "from": { "email": self.config.from },
+        "subject": email.subject,
+        "content": [{ "type": "text/plain", "value": email.text }],
+      }))
+      .send()
+      .await?;
+    match res.status() {
+      status if status.is_success() => Ok(()),
+      status => bail!("{} {}", status, res.text().await?),
+    }
+  }
+
+  fn game_url(&self, game_id: Uuid) -> String {
+    format!("{}/games/{}", self.config.app_url, game_id)
+  }
+}
+
+// queue an invitation for users added to a game, a failure only costs them the email
+pub async fn invite(db: &sqlx::PgPool, game_id: Uuid, uids: Vec<String>) {
+  if let Err(err) = notifications::enqueue_invites(db, game_id, &uids).await {
+    tracing::warn!("Failed to queue invitations of {}: {}", game_id, err);
+  }
+}
+
+// None when the email no longer applies, e.g. the game is gone
+async fn compose(mailer: &Mailer, db: &sqlx::PgPool, due: &DueEmail) -> Result<Option<Email>> {
+  let game = match games::get(db, due.game_id).await {
+    Ok(game) => game,
+    Err(db::Error::NotFound) => return Ok(None),
+    Err(err) => return Err(err.into()),
+  };
+  let url = mailer.game_url(game.id);
+  let email = match due.kind.as_str() {
+    INVITED => Email {
+      subject: format!("You're invited to {}", game.name),
+      text: format!(
+        "You were added to the Evil Santa game \"{}\".\n\nOpen it at {}\n",
+        game.name, url
+      ),
+    },
+    STARTED => Email {
+      subject: format!("{} has started", game.name),
+      text: format!(
+        "The Evil Santa game \"{}\" has started, follow along at {}\n",
+        game.name, url
+      ),
+    },
+    FINISHED => {
+      let mut text = format!("The Evil Santa game \"{}\" has finished.\n\n", game.name);
+      match recaps::get(db, game.id).await {
+        Ok(recap) => {
+          for a in recap.assignments {
+            text.push_str(&format!(
+              "{} got #{} {}\n",
+              a.player_name, a.present_number, a.present_name
+            ));
+          }
+          text.push_str(&format!(
+            "\n{} turns, {} steals\n",
+            recap.turns, recap.steals
+          ));
+        }
+        Err(db::Error::NotFound) => {}
+        Err(err) => return Err(err.into()),
+      }
+      text.push_str(&format!("\nSee the full recap at {}\n", url));
+      Email {
+        subject: format!("{} has finished", game.name),
+        text,
+      }
+    }
+    _ => return Ok(None),
+  };
+  Ok(Some(email))
+}
+
+async fn deliver(
+  mailer: &Mailer,
+  users: &UserService,
+  db: &sqlx::PgPool,
+  due: &DueEmail,
+) -> Result<bool> {
+  if due.email_opt_out {
+    return Ok(false);
+  }
+  let Some(email) = compose(mailer, db, due).await? else {
+    return Ok(false);
+  };
+  let user = users.lookup(&due.uid).await?;
+  if user.email.is_empty() || user.disabled {
+    return Ok(false);
+  }
+  mailer.send(&user.email, email).await?;
+  Ok(true)
+}
+
+// 1m, 2m, 4m, ... between attempts, None once the email is given up on
+fn retry_in(attempts: i32) -> Option<i64> {
+  (attempts < MAX_ATTEMPTS).then(|| 60 * 2_i64.pow(attempts.max(1) as u32 - 1))
+}
+
+async fn send(mailer: &Mailer, users: &UserService, db: &sqlx::PgPool, due: DueEmail) {
+  let recorded = match deliver(mailer, users, db, &due).await {
+    Ok(sent) => notifications::record_attempt(db, due.id, None, sent, None).await,
+    Err(err) => {
+      let error = err.to_string();
+      let retry = retry_in(due.attempts + 1);
+      notifications::record_attempt(db, due.id, Some(&error), false, retry).await
+    }
+  };
+  if let Err(err) = recorded {
+    tracing::error!("Failed to record email {}: {}", due.id, err);
+  }
+}
+
+// send due emails every few seconds, opted out users are skipped
+pub fn dispatch(db: sqlx::PgPool, users: UserService, config: EmailConfig) {
+  let mailer = Mailer {
+    client: reqwest::Client::builder()
+      .timeout(SEND_TIMEOUT)
+      .build()
+      .expect("Error building email client"),
+    config,
+  };
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+      interval.tick().await;
+      let due = match notifications::claim_due(&db, BATCH_SIZE).await {
+        Ok(due) => due,
+        Err(err) => {
+          tracing::warn!("Failed to claim emails: {}", err);
+          continue;
+        }
+      };
+      for email in due {
+        send(&mailer, &users, &db, email).await;
+      }
+    }
+  });
+}
+
+// drop old emails every hour
+pub fn purge_hourly(db: sqlx::PgPool) {
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+      interval.tick().await;
+      match notifications::purge(&db).await {
+        Ok(0) => {}
+        Ok(purged) => tracing::info!("Purged {} emails", purged),
+        Err(err) => tracing::warn!("Failed to purge emails: {}", err),
+      }
+    }
+  });
+}
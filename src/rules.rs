@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// how roll picks the next player
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TurnMode {
+  #[default]
+  Random,
+  // by player position
+  Sequential,
+  // by player position, reversing direction after every pass over all players
+  Snake,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+#[serde(default)]
+pub struct GameRules {
+  pub turn_mode: TurnMode,
+  // roll goes round the teams, picking the next player within the next team
+  pub alternate_teams: bool,
+  pub allow_steals: bool,
+  // a stolen present cannot be stolen again during the next turn
+  pub steal_immunity: bool,
+  // how often a single present can be stolen, unlimited when empty
+  pub max_steals_per_present: Option<u32>,
+  // a player cannot take back the present that was just stolen from them
+  pub no_steal_back: bool,
+  // once everyone has a present, the first player may swap one last time
+  pub final_swap: bool,
+  // seconds a player gets for their turn, no timer when empty
+  pub turn_timer_seconds: Option<u32>,
+  // side game: guess the price of wrapped presents
+  pub price_guessing: bool,
+}
+
+impl Default for GameRules {
+  fn default() -> Self {
+    Self {
+      turn_mode: TurnMode::Random,
+      alternate_teams: false,
+      allow_steals: true,
+      steal_immunity: false,
+      max_steals_per_present: None,
+      no_steal_back: false,
+      final_swap: false,
+      turn_timer_seconds: None,
+      price_guessing: false,
+    }
+  }
+}
+
+const TURN_TIMER_SECONDS: std::ops::RangeInclusive<u32> = 10..=3600;
+
+#[derive(thiserror::Error, Serialize, Debug)]
+#[error("Invalid rule value for {field}")]
+pub struct RulesError {
+  pub field: &'static str,
+  pub value: String,
+}
+
+impl GameRules {
+  pub fn validate(&self) -> Result<(), RulesError> {
+    if let Some(limit) = self.max_steals_per_present.filter(|limit| *limit == 0) {
+      return Err(RulesError {
+        field: "max_steals_per_present",
+        value: limit.to_string(),
+      });
+    }
+    if let Some(seconds) = self
+      .turn_timer_seconds
+      .filter(|seconds| !TURN_TIMER_SECONDS.contains(seconds))
+    {
+      return Err(RulesError {
+        field: "turn_timer_seconds",
+        value: seconds.to_string(),
+      });
+    }
+    Ok(())
+  }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RulePreset {
+  Classic,
+  EvilSanta,
+  Kids,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RulePresetInfo {
+  pub id: RulePreset,
+  pub name: &'static str,
+  pub description: &'static str,
+  pub rules: GameRules,
+}
+
+impl RulePreset {
+  pub const ALL: [RulePreset; 3] = [RulePreset::Classic, RulePreset::EvilSanta, RulePreset::Kids];
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      RulePreset::Classic => "Classic White Elephant",
+      RulePreset::EvilSanta => "Evil Santa",
+      RulePreset::Kids => "Kids mode",
+    }
+  }
+
+  pub fn description(&self) -> &'static str {
+    match self {
+      RulePreset::Classic => {
+        "Steal freely, but a stolen present is safe for the next turn and locked after 3 steals."
+      }
+      RulePreset::EvilSanta => "Anything goes: every present can be stolen at any time.",
+      RulePreset::Kids => "No stealing, everyone keeps the present they unwrap.",
+    }
+  }
+
+  pub fn rules(&self) -> GameRules {
+    match self {
+      RulePreset::Classic => GameRules {
+        steal_immunity: true,
+        max_steals_per_present: Some(3),
+        ..GameRules::default()
+      },
+      RulePreset::EvilSanta => GameRules {
+        allow_steals: true,
+        steal_immunity: false,
+        ..GameRules::default()
+      },
+      RulePreset::Kids => GameRules {
+        allow_steals: false,
+        ..GameRules::default()
+      },
+    }
+  }
+
+  pub fn info(&self) -> RulePresetInfo {
+    RulePresetInfo {
+      id: *self,
+      name: self.name(),
+      description: self.description(),
+      rules: self.rules(),
+    }
+  }
+}
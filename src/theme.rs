@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+const MAX_URL_LEN: usize = 2048;
+const MAX_MUSIC_CUES: usize = 20;
+const MAX_CUE_LEN: usize = 64;
+
+// how the display client styles a game
+#[derive(Serialize, Deserialize, Clone, Debug, Default, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameTheme {
+  pub colors: ThemeColors,
+  // a hex color or an https image url
+  pub background: Option<String>,
+  // ids of the music cues the display client should play
+  pub music_cues: Vec<String>,
+  pub snow: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeColors {
+  pub primary: Option<String>,
+  pub secondary: Option<String>,
+  pub accent: Option<String>,
+  pub text: Option<String>,
+}
+
+#[derive(thiserror::Error, Serialize, Debug)]
+#[error("Invalid theme value for {field}")]
+pub struct ThemeError {
+  pub field: &'static str,
+  pub value: String,
+}
+
+impl GameTheme {
+  pub fn validate(&self) -> Result<(), ThemeError> {
+    let colors = [
+      ("colors.primary", &self.colors.primary),
+      ("colors.secondary", &self.colors.secondary),
+      ("colors.accent", &self.colors.accent),
+      ("colors.text", &self.colors.text),
+    ];
+    for (field, color) in colors {
+      if let Some(color) = color {
+        if !is_hex_color(color) {
+          return Err(invalid(field, color));
+        }
+      }
+    }
+
+    if let Some(background) = &self.background {
+      if !is_hex_color(background) && !is_image_url(background) {
+        return Err(invalid("background", background));
+      }
+    }
+
+    if self.music_cues.len() > MAX_MUSIC_CUES {
+      return Err(invalid("music_cues", &self.music_cues.len().to_string()));
+    }
+    for cue in &self.music_cues {
+      if !is_cue_id(cue) {
+        return Err(invalid("music_cues", cue));
+      }
+    }
+
+    Ok(())
+  }
+}
+
+fn invalid(field: &'static str, value: &str) -> ThemeError {
+  ThemeError {
+    field,
+    value: value.to_string(),
+  }
+}
+
+// #rgb or #rrggbb
+pub fn is_hex_color(value: &str) -> bool {
+  match value.strip_prefix('#') {
+    Some(hex) => matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit()),
+    None => false,
+  }
+}
+
+fn is_image_url(value: &str) -> bool {
+  value.len() <= MAX_URL_LEN
+    && value.starts_with("https://")
+    && !value.chars().any(char::is_whitespace)
+}
+
+fn is_cue_id(value: &str) -> bool {
+  !value.is_empty()
+    && value.len() <= MAX_CUE_LEN
+    && value
+      .chars()
+      .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
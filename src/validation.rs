@@ -0,0 +1,107 @@
+use std::{collections::HashMap, net::IpAddr};
+
+use validator::{ValidateUrl, ValidationError};
+
+use crate::{
+  api::games::{OWNER_PERMISSION, PLAY_PERMISSION, VIEW_PERMISSION},
+  i18n::{Locale, Translations},
+};
+
+pub const MAX_NAME_LEN: u64 = 100;
+pub const MAX_DESCRIPTION_LEN: u64 = 2000;
+pub const MAX_IMAGES: u64 = 10;
+const MAX_URL_LEN: usize = 2048;
+
+// image lists hold http(s) urls the clients can load directly
+pub fn image_urls(urls: &[String]) -> Result<(), ValidationError> {
+  for url in urls {
+    let web = url.starts_with("https://") || url.starts_with("http://");
+    if !web || url.len() > MAX_URL_LEN || !url.validate_url() {
+      let mut err = ValidationError::new("url");
+      err.add_param("value".into(), url);
+      return Err(err);
+    }
+  }
+  Ok(())
+}
+
+// translations are looked up by the exact tag of a supported locale, any other key would never be served
+pub fn translation_tags(translations: &Translations) -> Result<(), ValidationError> {
+  for tag in translations.keys() {
+    if Locale::from_tag(tag).is_none_or(|locale| locale.tag() != tag) {
+      let mut err = ValidationError::new("locale");
+      err.add_param("value".into(), tag);
+      return Err(err);
+    }
+  }
+  Ok(())
+}
+
+// webhooks are called from inside our network, keep them off loopback and private hosts.
+// what a name resolves to is checked again before every delivery
+pub fn webhook_url(url: &str) -> Result<(), ValidationError> {
+  let parsed = url::Url::parse(url).ok();
+  let allowed = parsed.as_ref().is_some_and(|url| {
+    matches!(url.scheme(), "https" | "http")
+      && url.as_str().len() <= MAX_URL_LEN
+      && match url.host() {
+        Some(url::Host::Domain(domain)) => {
+          let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+          domain != "localhost" && !domain.ends_with(".localhost") && !domain.ends_with(".internal")
+        }
+        Some(url::Host::Ipv4(ip)) => is_public(IpAddr::V4(ip)),
+        Some(url::Host::Ipv6(ip)) => is_public(IpAddr::V6(ip)),
+        None => false,
+      }
+  });
+  if allowed {
+    return Ok(());
+  }
+  let mut err = ValidationError::new("webhook_url");
+  err.add_param("value".into(), &url);
+  Err(err)
+}
+
+pub fn is_public(ip: IpAddr) -> bool {
+  match ip {
+    IpAddr::V4(ip) => {
+      !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation())
+    }
+    IpAddr::V6(ip) => {
+      let unique_local = (ip.segments()[0] & 0xfe00) == 0xfc00;
+      let link_local = (ip.segments()[0] & 0xffc0) == 0xfe80;
+      match ip.to_ipv4_mapped() {
+        Some(mapped) => is_public(IpAddr::V4(mapped)),
+        None => !(ip.is_loopback() || ip.is_unspecified() || unique_local || link_local),
+      }
+    }
+  }
+}
+
+// game permissions are granted as one of the levels, not arbitrary bits
+pub fn permission_level(value: i64) -> Result<(), ValidationError> {
+  if [VIEW_PERMISSION, PLAY_PERMISSION, OWNER_PERMISSION].contains(&value) {
+    return Ok(());
+  }
+  let mut err = ValidationError::new("permission");
+  err.add_param("value".into(), &value);
+  Err(err)
+}
+
+// game id => permission maps, as in the custom claims
+pub fn game_permissions(games: &HashMap<String, i64>) -> Result<(), ValidationError> {
+  for (game_id, permission) in games {
+    if uuid::Uuid::parse_str(game_id).is_err() {
+      let mut err = ValidationError::new("game_id");
+      err.add_param("value".into(), game_id);
+      return Err(err);
+    }
+    permission_level(*permission)?;
+  }
+  Ok(())
+}